@@ -0,0 +1,1693 @@
+//! # HTIP Assembler / Disassembler
+//!
+//! A text form of HTIP streams, one op per line, for hand-written fixtures
+//! and for diffing builds. Covers v1 (`DXB1`, bincode) streams, v2
+//! (`dx_packet`, "DX") streams and the HTIP-only `.dxb` container.
+//!
+//! `assemble(&disassemble(bytes))` always returns `bytes`: anything the
+//! disassembler cannot express exactly is listed as raw bytes.
+//!
+//! ```text
+//! .htip v1                                  ; or v2, dxb, raw
+//! .key_id 0                                 ; v1 only
+//! .dictionary 1                             ; v1 only (before strings, which then start at its size)
+//! .signature 5f1c…                          ; v1 only (128 hex digits, default zero)
+//! .metadata issued_at=1000 min_runtime_version=1 expires_at=2000 origin "https://example.com"
+//!                                           ; v1 only (expires_at and origin optional)
+//! .flags 0x03                               ; v2 only (default from content, 0x04 = wide IDs)
+//!
+//! string s0 "<div>Hello</div>"              ; indices must be sequential
+//! template id=0 html=s0                     ; v2 adds slots=N
+//!   bind slot=0 text path=[0]               ; v1: text | class | attr=sN | prop=sN | event=sN
+//!
+//! instantiate instance=1 template=0 parent=0
+//! patch_text instance=1 slot=0 text=s1      ; comments start with ';'
+//! ```
+//!
+//! v1 ops: `template_def id html`, `instantiate instance template parent`,
+//! `patch_text instance slot text`, `patch_attr instance slot name value`,
+//! `class_toggle instance class on`, `attach_event instance event handler`,
+//! `remove_node instance`, `batch_start id`, `batch_commit id`,
+//! `set_property instance name value` (`sN`, a number, `true`, `false` or `null`),
+//! `append_child parent child`, `invoke macro args`.
+//!
+//! v1 macros (`FLAG_MACROS`) are blocks of ops whose fields are constants or
+//! `$N` parameters, numbered like strings:
+//!
+//! ```text
+//! macro id=0
+//!   instantiate instance=$0 template=0 parent=0
+//!   patch_text instance=$0 slot=0 text=$1
+//! end
+//! invoke macro=0 args=[5,12]
+//! ```
+//!
+//! v2 ops (all take `target`): `clone template parent`, `patch_text text`,
+//! `patch_attr name value`, `class_toggle class on`, `remove`,
+//! `set_style name value`, `batch_start`, `batch_commit`,
+//! `attach_event event handler`, `set_property name value` (`sN`, `true`,
+//! `false` or `null`; numbers are `number=sN`), `append_child child`.
+//! Node and template IDs above `u16::MAX` need the wide-ID flag, set with
+//! `.flags` before any template or op.
+//!
+//! Raw listings hold `bytes <hex>` lines.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use bincode::config;
+use dx_packet::{IdWidth, OpType, PropKind, StringEntry, TemplateEntry};
+
+use crate::{
+    dictionary::{self, StringDictionary},
+    macros::{InvokeMacro, MacroField, MacroOp, OpMacro, Step},
+    opcodes::*,
+    protocol::{HtipHeader, HtipPayload, StreamMetadata, FLAG_MACROS, FLAG_METADATA},
+    DxBinaryError, Result, MAGIC_BYTES, VERSION,
+};
+
+/// Magic of the HTIP-only `.dxb` container (see dx-compiler's packer)
+const DXB_MAGIC: &[u8; 2] = b"DX";
+const DXB_VERSION: u8 = 1;
+/// Container mode: HTIP stream, no WASM
+const DXB_MODE_HTIP: u8 = 0x01;
+const DXB_HEADER_SIZE: usize = 8;
+
+/// Bytes per `bytes` line in raw listings
+const RAW_LINE_BYTES: usize = 32;
+
+/// Column where trailing comments start
+const COMMENT_COLUMN: usize = 44;
+
+/// Resolved strings in comments are cut to this many chars
+const COMMENT_MAX_CHARS: usize = 48;
+
+/// How a macro op field is written
+#[derive(Clone, Copy)]
+enum FieldKind {
+    Int,
+    StringRef,
+    Bool,
+}
+
+/// Field names of a macro op, in `MacroOp::fields` order
+type MacroFields = &'static [(&'static str, FieldKind)];
+
+/// Ops macros can hold, with their mnemonics
+const MACRO_OPS: &[(OpcodeV1, &str, MacroFields)] = &[
+    (
+        OpcodeV1::Instantiate,
+        "instantiate",
+        &[
+            ("instance", FieldKind::Int),
+            ("template", FieldKind::Int),
+            ("parent", FieldKind::Int),
+        ],
+    ),
+    (
+        OpcodeV1::PatchText,
+        "patch_text",
+        &[
+            ("instance", FieldKind::Int),
+            ("slot", FieldKind::Int),
+            ("text", FieldKind::StringRef),
+        ],
+    ),
+    (
+        OpcodeV1::PatchAttr,
+        "patch_attr",
+        &[
+            ("instance", FieldKind::Int),
+            ("slot", FieldKind::Int),
+            ("name", FieldKind::StringRef),
+            ("value", FieldKind::StringRef),
+        ],
+    ),
+    (
+        OpcodeV1::PatchClassToggle,
+        "class_toggle",
+        &[
+            ("instance", FieldKind::Int),
+            ("class", FieldKind::StringRef),
+            ("on", FieldKind::Bool),
+        ],
+    ),
+    (
+        OpcodeV1::AttachEvent,
+        "attach_event",
+        &[
+            ("instance", FieldKind::Int),
+            ("event", FieldKind::StringRef),
+            ("handler", FieldKind::Int),
+        ],
+    ),
+    (OpcodeV1::RemoveNode, "remove_node", &[("instance", FieldKind::Int)]),
+    (
+        OpcodeV1::AppendChild,
+        "append_child",
+        &[("parent", FieldKind::Int), ("child", FieldKind::Int)],
+    ),
+];
+
+/// Disassemble a v1 stream, v2 stream or `.dxb` file into text
+pub fn disassemble(binary: &[u8]) -> String {
+    let listing = if binary.starts_with(MAGIC_BYTES) {
+        disassemble_v1(binary)
+    } else if binary.starts_with(&dx_packet::HtipHeader::MAGIC.to_le_bytes()) {
+        disassemble_v2(binary).map(|body| format!(".htip v2\n{body}"))
+    } else if binary.starts_with(DXB_MAGIC) {
+        disassemble_dxb(binary)
+    } else {
+        None
+    };
+
+    // Only hand out listings that reassemble to the same bytes
+    match listing {
+        Some(text) if assemble(&text).is_ok_and(|bytes| bytes == binary) => text,
+        _ => disassemble_raw(binary),
+    }
+}
+
+/// Assemble text produced by `disassemble` (or written by hand)
+pub fn assemble(text: &str) -> Result<Vec<u8>> {
+    let mut assembler = Assembler::default();
+    for (index, line) in text.lines().enumerate() {
+        let tokens = tokenize(line).map_err(|message| error(index + 1, message))?;
+        if !tokens.is_empty() {
+            assembler.line(index + 1, tokens)?;
+        }
+    }
+    assembler.finish()
+}
+
+fn error(line: usize, message: impl Into<String>) -> DxBinaryError {
+    DxBinaryError::Assembly {
+        line,
+        message: message.into(),
+    }
+}
+
+// ============================================================================
+// DISASSEMBLER
+// ============================================================================
+
+/// Text being built, with aligned trailing comments
+#[derive(Default)]
+struct Listing {
+    text: String,
+}
+
+impl Listing {
+    fn line(&mut self, code: impl AsRef<str>) {
+        self.text.push_str(code.as_ref());
+        self.text.push('\n');
+    }
+
+    fn line_with(&mut self, code: impl AsRef<str>, comment: Option<String>) {
+        match comment {
+            Some(comment) => {
+                let width = COMMENT_COLUMN - 2;
+                let _ = writeln!(self.text, "{:<width$} ; {comment}", code.as_ref());
+            }
+            None => self.line(code),
+        }
+    }
+
+    fn blank(&mut self) {
+        self.text.push('\n');
+    }
+}
+
+/// Comment showing the strings an op refers to
+fn resolve<S: AsRef<str>>(strings: &[S], ids: &[usize]) -> Option<String> {
+    let resolved: Vec<String> = ids
+        .iter()
+        .filter_map(|&id| strings.get(id))
+        .map(|s| {
+            let s = s.as_ref();
+            match s.char_indices().nth(COMMENT_MAX_CHARS) {
+                Some((cut, _)) => format!("{:?}…", &s[..cut]),
+                None => format!("{s:?}"),
+            }
+        })
+        .collect();
+
+    if resolved.is_empty() {
+        None
+    } else {
+        Some(resolved.join(" "))
+    }
+}
+
+fn disassemble_raw(binary: &[u8]) -> String {
+    let mut out = Listing::default();
+    out.line(format!("; {} bytes, not a recognized HTIP stream", binary.len()));
+    out.line(".htip raw");
+    for chunk in binary.chunks(RAW_LINE_BYTES) {
+        out.line(format!("bytes {}", to_hex(chunk)));
+    }
+    out.text
+}
+
+fn disassemble_v1(binary: &[u8]) -> Option<String> {
+    let header: HtipHeader = bytemuck::pod_read_unaligned(binary.get(..HtipHeader::SIZE)?);
+    header.verify().ok()?;
+    if header.flags & !(FLAG_METADATA | FLAG_MACROS) != 0 {
+        return None;
+    }
+    let mut body = &binary[HtipHeader::SIZE..];
+    let metadata = if header.flags & FLAG_METADATA != 0 {
+        let (metadata, len): (StreamMetadata, usize) =
+            bincode::decode_from_slice(body, config::standard()).ok()?;
+        body = &body[len..];
+        Some(metadata)
+    } else {
+        None
+    };
+    type MacroPayload = (Vec<String>, Vec<TemplateDef>, Vec<OpMacro>, Vec<Step>);
+    let (mut strings, templates, macros, steps) = if header.flags & FLAG_MACROS != 0 {
+        let (payload, _): (MacroPayload, usize) =
+            bincode::decode_from_slice(body, config::standard()).ok()?;
+        payload
+    } else {
+        let (payload, _): (HtipPayload, usize) =
+            bincode::decode_from_slice(body, config::standard()).ok()?;
+        let steps = payload.operations.into_iter().map(Step::Op).collect();
+        (payload.strings, payload.templates, Vec::new(), steps)
+    };
+    let dictionary = match header.dictionary {
+        0 => None,
+        version => Some(dictionary::find(version)?),
+    };
+    let dictionary_len = dictionary.map_or(0, |d| d.len());
+    if let Some(dictionary) = dictionary {
+        strings.splice(..0, dictionary.strings.iter().map(|s| s.to_string()));
+    }
+    let strings = &strings;
+
+    let mut out = Listing::default();
+    out.line(format!("; HTIP v1 stream, {} bytes", binary.len()));
+    out.line(".htip v1");
+    out.line(format!(".key_id {}", header.key_id));
+    out.line(format!(".signature {}", to_hex(&header.signature)));
+    if header.dictionary != 0 {
+        out.line(format!(".dictionary {}", header.dictionary));
+    }
+    if let Some(metadata) = &metadata {
+        out.line(v1_metadata(metadata));
+    }
+
+    if strings.len() > dictionary_len {
+        out.blank();
+        for (id, s) in strings.iter().enumerate().skip(dictionary_len) {
+            out.line(format!("string s{id} {s:?}"));
+        }
+    }
+
+    if !templates.is_empty() {
+        out.blank();
+        for template in &templates {
+            v1_template(&mut out, "template", template, strings);
+        }
+    }
+
+    for (id, op_macro) in macros.iter().enumerate() {
+        out.blank();
+        out.line(format!("macro id={id}"));
+        for op in &op_macro.ops {
+            v1_macro_op(&mut out, op, strings)?;
+        }
+        out.line("end");
+    }
+
+    if !steps.is_empty() {
+        out.blank();
+        for step in &steps {
+            match step {
+                Step::Op(op) => v1_operation(&mut out, op, strings),
+                Step::Invoke(invoke) => {
+                    let args: Vec<String> = invoke.args.iter().map(u32::to_string).collect();
+                    out.line(format!("invoke macro={} args=[{}]", invoke.macro_id, args.join(",")));
+                }
+            }
+        }
+    }
+
+    Some(out.text)
+}
+
+/// One op of a macro definition (None if a field has no text form)
+fn v1_macro_op(out: &mut Listing, op: &MacroOp, strings: &[String]) -> Option<()> {
+    let (_, mnemonic, names) = MACRO_OPS.iter().find(|(opcode, ..)| *opcode == op.opcode)?;
+    if names.len() != op.fields.len() {
+        return None;
+    }
+
+    let mut code = format!("  {mnemonic}");
+    let mut refs = Vec::new();
+    for (&(name, kind), field) in names.iter().zip(&op.fields) {
+        let value = match (*field, kind) {
+            (MacroField::Param(p), _) => format!("${p}"),
+            (MacroField::Const(value), FieldKind::Int) => value.to_string(),
+            (MacroField::Const(value), FieldKind::StringRef) => {
+                refs.push(value as usize);
+                format!("s{value}")
+            }
+            (MacroField::Const(0), FieldKind::Bool) => "false".to_string(),
+            (MacroField::Const(1), FieldKind::Bool) => "true".to_string(),
+            (MacroField::Const(_), FieldKind::Bool) => return None,
+        };
+        let _ = write!(code, " {name}={value}");
+    }
+
+    out.line_with(code, resolve(strings, &refs));
+    Some(())
+}
+
+fn v1_metadata(metadata: &StreamMetadata) -> String {
+    let mut line = format!(
+        ".metadata issued_at={} min_runtime_version={}",
+        metadata.issued_at, metadata.min_runtime_version
+    );
+    if let Some(expires_at) = metadata.expires_at {
+        let _ = write!(line, " expires_at={expires_at}");
+    }
+    if let Some(origin) = &metadata.origin {
+        let _ = write!(line, " origin {origin:?}");
+    }
+    line
+}
+
+fn v1_template(out: &mut Listing, mnemonic: &str, template: &TemplateDef, strings: &[String]) {
+    out.line_with(
+        format!("{mnemonic} id={} html=s{}", template.id, template.html_string_id),
+        resolve(strings, &[template.html_string_id as usize]),
+    );
+
+    for binding in &template.bindings {
+        let (kind, name) = match &binding.binding_type {
+            BindingType::Text => ("text".to_string(), None),
+            BindingType::Class => ("class".to_string(), None),
+            BindingType::Attribute { attr_name_id } => {
+                (format!("attr=s{attr_name_id}"), Some(attr_name_id))
+            }
+            BindingType::Property { prop_name_id } => {
+                (format!("prop=s{prop_name_id}"), Some(prop_name_id))
+            }
+            BindingType::Event { event_type_id } => {
+                (format!("event=s{event_type_id}"), Some(event_type_id))
+            }
+        };
+        let path: Vec<String> = binding.path.iter().map(u8::to_string).collect();
+
+        out.line_with(
+            format!("  bind slot={} {kind} path=[{}]", binding.slot_id, path.join(",")),
+            name.and_then(|&id| resolve(strings, &[id as usize])),
+        );
+    }
+}
+
+fn v1_operation(out: &mut Listing, op: &Operation, strings: &[String]) {
+    let (code, refs): (String, Vec<u32>) = match op {
+        Operation::TemplateDef(template) => {
+            v1_template(out, "template_def", template, strings);
+            return;
+        }
+        Operation::Instantiate(op) => (
+            format!(
+                "instantiate instance={} template={} parent={}",
+                op.instance_id, op.template_id, op.parent_id
+            ),
+            vec![],
+        ),
+        Operation::PatchText(op) => (
+            format!(
+                "patch_text instance={} slot={} text=s{}",
+                op.instance_id, op.slot_id, op.string_id
+            ),
+            vec![op.string_id],
+        ),
+        Operation::PatchAttr(op) => (
+            format!(
+                "patch_attr instance={} slot={} name=s{} value=s{}",
+                op.instance_id, op.slot_id, op.attr_name_id, op.value_id
+            ),
+            vec![op.attr_name_id, op.value_id],
+        ),
+        Operation::PatchClassToggle(op) => (
+            format!(
+                "class_toggle instance={} class=s{} on={}",
+                op.instance_id, op.class_name_id, op.enabled
+            ),
+            vec![op.class_name_id],
+        ),
+        Operation::AttachEvent(op) => (
+            format!(
+                "attach_event instance={} event=s{} handler={}",
+                op.instance_id, op.event_type_id, op.handler_id
+            ),
+            vec![op.event_type_id],
+        ),
+        Operation::RemoveNode(op) => (format!("remove_node instance={}", op.instance_id), vec![]),
+        Operation::BatchStart(op) => (format!("batch_start id={}", op.batch_id), vec![]),
+        Operation::BatchCommit(op) => (format!("batch_commit id={}", op.batch_id), vec![]),
+        Operation::SetProperty(op) => {
+            let (value, value_ref) = match &op.value {
+                PropertyValue::String(id) => (format!("s{id}"), Some(*id)),
+                PropertyValue::Number(n) => (format!("{n:?}"), None),
+                PropertyValue::Boolean(b) => (b.to_string(), None),
+                PropertyValue::Null => ("null".to_string(), None),
+            };
+            (
+                format!(
+                    "set_property instance={} name=s{} value={value}",
+                    op.instance_id, op.prop_name_id
+                ),
+                std::iter::once(op.prop_name_id).chain(value_ref).collect(),
+            )
+        }
+        Operation::AppendChild(op) => {
+            (format!("append_child parent={} child={}", op.parent_id, op.child_id), vec![])
+        }
+    };
+
+    let refs: Vec<usize> = refs.into_iter().map(|id| id as usize).collect();
+    out.line_with(code, resolve(strings, &refs));
+}
+
+fn disassemble_dxb(binary: &[u8]) -> Option<String> {
+    if binary.len() < DXB_HEADER_SIZE || binary[2] != DXB_VERSION || binary[3] != DXB_MODE_HTIP {
+        return None;
+    }
+    let body = disassemble_v2(&binary[DXB_HEADER_SIZE..])?;
+    Some(format!("; .dxb container (HTIP-only)\n.htip dxb\n{body}"))
+}
+
+/// Little-endian reads with bounds checks (also used by `validate`)
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    /// v2 node or template ID
+    pub(crate) fn id(&mut self, width: IdWidth) -> Option<u32> {
+        match width {
+            IdWidth::Compact => self.u16().map(u32::from),
+            IdWidth::Wide => self.u32(),
+        }
+    }
+
+    /// v2 opcode header: op type byte and target ID
+    pub(crate) fn opcode_header(&mut self, width: IdWidth) -> Option<(u8, u32)> {
+        let op_type = self.u8()?;
+        self.bytes(width.opcode_header_size() - 1 - width.id_size())?;
+        Some((op_type, self.id(width)?))
+    }
+}
+
+/// Body of a v2 listing (everything after the `.htip` line)
+fn disassemble_v2(stream: &[u8]) -> Option<String> {
+    let mut reader = Reader::new(stream);
+    let _magic = reader.u16()?;
+    let version = reader.u8()?;
+    let flags = reader.u8()?;
+    let template_count = reader.u16()?;
+    let string_count = reader.u16()?;
+    let opcode_count = reader.u32()?;
+    let _payload_size = reader.u32()?;
+    if version != dx_packet::HtipHeader::VERSION {
+        return None;
+    }
+
+    // String entries, then their data packed back to back
+    let mut entries = Vec::with_capacity(string_count as usize);
+    for _ in 0..string_count {
+        let offset = reader.u32()? as usize;
+        let len = reader.u16()? as usize;
+        let _reserved = reader.u16()?;
+        entries.push((offset, len));
+    }
+    let data_len = entries.iter().map(|&(offset, len)| offset + len).max().unwrap_or(0);
+    let data = reader.bytes(data_len)?;
+    let strings = entries
+        .iter()
+        .map(|&(offset, len)| std::str::from_utf8(&data[offset..offset + len]).ok())
+        .collect::<Option<Vec<&str>>>()?;
+
+    let width = IdWidth::from_flags(flags);
+    let mut out = Listing::default();
+    if flags != v2_default_flags(string_count, template_count) {
+        out.line(format!(".flags 0x{flags:02x}"));
+    }
+
+    if !strings.is_empty() {
+        out.blank();
+        for (id, s) in strings.iter().enumerate() {
+            out.line(format!("string s{id} {s:?}"));
+        }
+    }
+
+    if template_count > 0 {
+        out.blank();
+        for _ in 0..template_count {
+            let id = reader.id(width)?;
+            let html = reader.u16()?;
+            let slots = reader.u8()?;
+            reader.bytes(TemplateEntry::SIZE - 3 - width.id_size())?;
+            out.line_with(
+                format!("template id={id} html=s{html} slots={slots}"),
+                resolve(&strings, &[html as usize]),
+            );
+        }
+    }
+
+    if opcode_count > 0 {
+        out.blank();
+    }
+    for _ in 0..opcode_count {
+        let (op_type, target) = reader.opcode_header(width)?;
+        let op_type = OpType::from_u8(op_type)?;
+
+        let (code, refs) = match op_type {
+            OpType::Clone => {
+                let template = reader.id(width)?;
+                let parent = reader.id(width)?;
+                (format!("clone target={target} template={template} parent={parent}"), vec![])
+            }
+            OpType::PatchText => {
+                let text = reader.u16()?;
+                let _reserved = reader.u16()?;
+                (format!("patch_text target={target} text=s{text}"), vec![text])
+            }
+            OpType::PatchAttr | OpType::SetStyle => {
+                let name = reader.u16()?;
+                let value = reader.u16()?;
+                let mnemonic = if op_type == OpType::PatchAttr {
+                    "patch_attr"
+                } else {
+                    "set_style"
+                };
+                (
+                    format!("{mnemonic} target={target} name=s{name} value=s{value}"),
+                    vec![name, value],
+                )
+            }
+            OpType::ClassToggle => {
+                let class = reader.u16()?;
+                let enable = reader.u8()?;
+                let _reserved = reader.u8()?;
+                (
+                    format!("class_toggle target={target} class=s{class} on={}", enable != 0),
+                    vec![class],
+                )
+            }
+            OpType::Remove => (format!("remove target={target}"), vec![]),
+            OpType::BatchStart => (format!("batch_start target={target}"), vec![]),
+            OpType::BatchCommit => (format!("batch_commit target={target}"), vec![]),
+            OpType::AttachEvent => {
+                let event = reader.u16()?;
+                let handler = reader.u16()?;
+                (
+                    format!("attach_event target={target} event=s{event} handler={handler}"),
+                    vec![event],
+                )
+            }
+            OpType::SetProperty => {
+                let name = reader.u16()?;
+                let kind = PropKind::from_u8(reader.u8()?)?;
+                let _reserved = reader.u8()?;
+                let value = reader.u16()?;
+                let (arg, refs) = match kind {
+                    PropKind::String => (format!("value=s{value}"), vec![name, value]),
+                    PropKind::Number => (format!("number=s{value}"), vec![name, value]),
+                    PropKind::Bool => (format!("value={}", value != 0), vec![name]),
+                    PropKind::Null => ("value=null".to_string(), vec![name]),
+                };
+                (format!("set_property target={target} name=s{name} {arg}"), refs)
+            }
+            OpType::AppendChild => {
+                let child = reader.id(width)?;
+                if width == IdWidth::Compact {
+                    let _reserved = reader.u16()?;
+                }
+                (format!("append_child target={target} child={child}"), vec![])
+            }
+        };
+
+        let refs: Vec<usize> = refs.into_iter().map(usize::from).collect();
+        out.line_with(code, resolve(&strings, &refs));
+    }
+
+    // Trailing bytes cannot be expressed
+    if reader.offset() != stream.len() {
+        return None;
+    }
+
+    Some(out.text)
+}
+
+/// Flags the compiler sets: bit 0 = has_strings, bit 1 = has_templates
+fn v2_default_flags(string_count: u16, template_count: u16) -> u8 {
+    (string_count > 0) as u8 | (((template_count > 0) as u8) << 1)
+}
+
+// ============================================================================
+// ASSEMBLER
+// ============================================================================
+
+/// Write a v2 node or template ID
+fn push_id(out: &mut Vec<u8>, width: IdWidth, line: usize, id: u32) -> Result<()> {
+    match width {
+        IdWidth::Compact => {
+            let id = u16::try_from(id)
+                .map_err(|_| error(line, format!("ID {id} needs wide IDs (.flags 0x04)")))?;
+            out.extend(id.to_le_bytes());
+        }
+        IdWidth::Wide => out.extend(id.to_le_bytes()),
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    V1,
+    V2,
+    Dxb,
+    Raw,
+}
+
+/// Where `bind` lines go
+#[derive(Clone, Copy)]
+enum BindTarget {
+    Template(usize),
+    Operation(usize),
+}
+
+#[derive(Default)]
+struct Assembler {
+    format: Option<Format>,
+    strings: Vec<String>,
+
+    // v1
+    key_id: u16,
+    signature: Option<[u8; 64]>,
+    dictionary: Option<&'static StringDictionary>,
+    metadata: Option<StreamMetadata>,
+    templates: Vec<TemplateDef>,
+    macros: Vec<OpMacro>,
+    /// A `macro` block is open (its ops go to the last macro)
+    in_macro: bool,
+    steps: Vec<Step>,
+    bind_target: Option<BindTarget>,
+
+    // v2
+    flags: Option<u8>,
+    template_entries: Vec<u8>,
+    template_count: u16,
+    opcodes: Vec<u8>,
+    opcode_count: u32,
+
+    // raw
+    raw: Vec<u8>,
+}
+
+impl Assembler {
+    fn line(&mut self, line: usize, tokens: Vec<Token>) -> Result<()> {
+        let Token::Word(mnemonic) = &tokens[0] else {
+            return Err(error(line, "Expected a directive or mnemonic"));
+        };
+        let mut args = Args::new(line, &tokens[1..])?;
+
+        let Some(format) = self.format else {
+            if mnemonic != ".htip" {
+                return Err(error(line, "Listing must start with .htip"));
+            }
+            self.format = Some(match args.word()?.as_str() {
+                "v1" => Format::V1,
+                "v2" => Format::V2,
+                "dxb" => Format::Dxb,
+                "raw" => Format::Raw,
+                other => return Err(error(line, format!("Unknown format: {other}"))),
+            });
+            return args.done();
+        };
+
+        match (format, mnemonic.as_str()) {
+            (Format::Raw, "bytes") => {
+                let hex = args.word()?;
+                self.raw.extend(from_hex(&hex).ok_or_else(|| error(line, "Invalid hex"))?);
+            }
+            (Format::Raw, _) => return Err(error(line, "Raw listings only hold bytes lines")),
+            (_, "string") => {
+                let id = args.word()?;
+                if id != format!("s{}", self.strings.len()) {
+                    return Err(error(
+                        line,
+                        format!("Expected s{}, found {id}", self.strings.len()),
+                    ));
+                }
+                let value = args.quoted()?;
+                self.strings.push(value);
+            }
+            (Format::V1, _) => self.v1_line(line, mnemonic, &mut args)?,
+            (_, _) => self.v2_line(line, mnemonic, &mut args)?,
+        }
+
+        args.done()
+    }
+
+    fn v1_line(&mut self, line: usize, mnemonic: &str, args: &mut Args) -> Result<()> {
+        if self.in_macro {
+            return self.v1_macro_line(line, mnemonic, args);
+        }
+        if mnemonic != "bind" {
+            self.bind_target = None;
+        }
+
+        let op = match mnemonic {
+            ".key_id" => {
+                self.key_id = parse_int(line, &args.word()?)?;
+                return Ok(());
+            }
+            ".dictionary" => {
+                let version: u16 = parse_int(line, &args.word()?)?;
+                if !self.strings.is_empty() {
+                    return Err(error(line, ".dictionary must come before strings"));
+                }
+                let dictionary = dictionary::find(version)
+                    .ok_or_else(|| error(line, format!("Unknown dictionary: {version}")))?;
+                self.strings = dictionary.strings.iter().map(|s| s.to_string()).collect();
+                self.dictionary = Some(dictionary);
+                return Ok(());
+            }
+            ".metadata" => {
+                if self.metadata.is_some() {
+                    return Err(error(line, "Duplicate .metadata"));
+                }
+                let expires_at = match args.has("expires_at") {
+                    true => Some(args.int("expires_at")?),
+                    false => None,
+                };
+                let origin = match args.flag("origin") {
+                    true => Some(args.quoted()?),
+                    false => None,
+                };
+                self.metadata = Some(StreamMetadata {
+                    issued_at: args.int("issued_at")?,
+                    expires_at,
+                    min_runtime_version: args.int("min_runtime_version")?,
+                    origin,
+                });
+                return Ok(());
+            }
+            ".signature" => {
+                let signature = from_hex(&args.word()?)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .ok_or_else(|| error(line, "Signature must be 128 hex digits"))?;
+                self.signature = Some(signature);
+                return Ok(());
+            }
+            "template" => {
+                self.templates.push(TemplateDef {
+                    id: args.int("id")?,
+                    html_string_id: args.string_ref("html")?,
+                    bindings: Vec::new(),
+                });
+                self.bind_target = Some(BindTarget::Template(self.templates.len() - 1));
+                return Ok(());
+            }
+            "bind" => return self.v1_bind(line, args),
+            "macro" => {
+                let id = args.int::<usize>("id")?;
+                if id != self.macros.len() {
+                    return Err(error(
+                        line,
+                        format!("Expected id={}, found {id}", self.macros.len()),
+                    ));
+                }
+                self.macros.push(OpMacro { ops: Vec::new() });
+                self.in_macro = true;
+                return Ok(());
+            }
+            "invoke" => {
+                self.steps.push(Step::Invoke(InvokeMacro {
+                    macro_id: args.int("macro")?,
+                    args: args.list("args")?,
+                }));
+                return Ok(());
+            }
+            "template_def" => {
+                let op = Operation::TemplateDef(TemplateDef {
+                    id: args.int("id")?,
+                    html_string_id: args.string_ref("html")?,
+                    bindings: Vec::new(),
+                });
+                self.bind_target = Some(BindTarget::Operation(self.steps.len()));
+                op
+            }
+            "instantiate" => Operation::Instantiate(Instantiate {
+                instance_id: args.int("instance")?,
+                template_id: args.int("template")?,
+                parent_id: args.int("parent")?,
+            }),
+            "patch_text" => Operation::PatchText(PatchText {
+                instance_id: args.int("instance")?,
+                slot_id: args.int("slot")?,
+                string_id: args.string_ref("text")?,
+            }),
+            "patch_attr" => Operation::PatchAttr(PatchAttr {
+                instance_id: args.int("instance")?,
+                slot_id: args.int("slot")?,
+                attr_name_id: args.string_ref("name")?,
+                value_id: args.string_ref("value")?,
+            }),
+            "class_toggle" => Operation::PatchClassToggle(PatchClassToggle {
+                instance_id: args.int("instance")?,
+                class_name_id: args.string_ref("class")?,
+                enabled: args.bool("on")?,
+            }),
+            "attach_event" => Operation::AttachEvent(AttachEvent {
+                instance_id: args.int("instance")?,
+                event_type_id: args.string_ref("event")?,
+                handler_id: args.int("handler")?,
+            }),
+            "remove_node" => Operation::RemoveNode(RemoveNode {
+                instance_id: args.int("instance")?,
+            }),
+            "batch_start" => Operation::BatchStart(BatchStart {
+                batch_id: args.int("id")?,
+            }),
+            "batch_commit" => Operation::BatchCommit(BatchCommit {
+                batch_id: args.int("id")?,
+            }),
+            "set_property" => Operation::SetProperty(SetProperty {
+                instance_id: args.int("instance")?,
+                prop_name_id: args.string_ref("name")?,
+                value: args.property("value")?,
+            }),
+            "append_child" => Operation::AppendChild(AppendChild {
+                parent_id: args.int("parent")?,
+                child_id: args.int("child")?,
+            }),
+            other => return Err(error(line, format!("Unknown v1 mnemonic: {other}"))),
+        };
+
+        self.steps.push(Step::Op(op));
+        Ok(())
+    }
+
+    /// An op of the open `macro` block, or its `end`
+    fn v1_macro_line(&mut self, line: usize, mnemonic: &str, args: &mut Args) -> Result<()> {
+        let op_macro = self.macros.last_mut().expect("macro block is open");
+        if mnemonic == "end" {
+            op_macro.check().map_err(|e| error(line, e.to_string()))?;
+            self.in_macro = false;
+            return Ok(());
+        }
+
+        let (opcode, _, names) = MACRO_OPS
+            .iter()
+            .find(|(_, name, _)| *name == mnemonic)
+            .ok_or_else(|| error(line, format!("{mnemonic} cannot be in a macro")))?;
+        let mut fields = Vec::with_capacity(names.len());
+        for &(name, kind) in names.iter() {
+            let value = args.value(name)?;
+            let field = match (value.strip_prefix('$'), kind) {
+                (Some(param), _) => MacroField::Param(parse_int(line, param)?),
+                (None, FieldKind::Int) => MacroField::Const(parse_int(line, &value)?),
+                (None, FieldKind::StringRef) => {
+                    let id = value.strip_prefix('s').ok_or_else(|| {
+                        error(line, format!("{name}= must be a string ref (sN) or $N"))
+                    })?;
+                    MacroField::Const(parse_int(line, id)?)
+                }
+                (None, FieldKind::Bool) => match value.as_str() {
+                    "true" => MacroField::Const(1),
+                    "false" => MacroField::Const(0),
+                    _ => return Err(error(line, format!("{name}= must be true, false or $N"))),
+                },
+            };
+            fields.push(field);
+        }
+
+        op_macro.ops.push(MacroOp {
+            opcode: *opcode,
+            fields,
+        });
+        Ok(())
+    }
+
+    fn v1_bind(&mut self, line: usize, args: &mut Args) -> Result<()> {
+        let slot_id = args.int("slot")?;
+        let path = args.list("path")?;
+        let binding_type = if args.flag("text") {
+            BindingType::Text
+        } else if args.flag("class") {
+            BindingType::Class
+        } else if args.has("attr") {
+            BindingType::Attribute {
+                attr_name_id: args.string_ref("attr")?,
+            }
+        } else if args.has("prop") {
+            BindingType::Property {
+                prop_name_id: args.string_ref("prop")?,
+            }
+        } else if args.has("event") {
+            BindingType::Event {
+                event_type_id: args.string_ref("event")?,
+            }
+        } else {
+            return Err(error(line, "Binding needs text, class, attr=, prop= or event="));
+        };
+
+        let binding = Binding {
+            slot_id,
+            binding_type,
+            path,
+        };
+        let template = match self.bind_target {
+            Some(BindTarget::Template(index)) => &mut self.templates[index],
+            Some(BindTarget::Operation(index)) => match &mut self.steps[index] {
+                Step::Op(Operation::TemplateDef(template)) => template,
+                _ => unreachable!("bind target is always a template_def"),
+            },
+            None => return Err(error(line, "bind must follow template or template_def")),
+        };
+        template.bindings.push(binding);
+
+        Ok(())
+    }
+
+    fn v2_line(&mut self, line: usize, mnemonic: &str, args: &mut Args) -> Result<()> {
+        match mnemonic {
+            ".flags" => {
+                let flags: u8 = parse_int(line, &args.word()?)?;
+                let started = self.template_count > 0 || self.opcode_count > 0;
+                if started && IdWidth::from_flags(flags) != self.width() {
+                    return Err(error(line, ".flags changing the ID width must come first"));
+                }
+                self.flags = Some(flags);
+                return Ok(());
+            }
+            "template" => {
+                let width = self.width();
+                let id: u32 = args.int("id")?;
+                let html: u16 = args.string_ref("html")?;
+                push_id(&mut self.template_entries, width, line, id)?;
+                self.template_entries.extend(html.to_le_bytes());
+                self.template_entries.push(args.int("slots")?);
+                let end = self.template_entries.len().next_multiple_of(TemplateEntry::SIZE);
+                self.template_entries.resize(end, 0);
+                self.template_count = self
+                    .template_count
+                    .checked_add(1)
+                    .ok_or_else(|| error(line, "Too many templates"))?;
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        // Node and template IDs, then u16 fields
+        let mut ids: Vec<u32> = Vec::new();
+        let (op_type, payload): (OpType, Vec<u16>) = match mnemonic {
+            "clone" => {
+                ids = vec![args.int("template")?, args.int("parent")?];
+                (OpType::Clone, vec![])
+            }
+            "patch_text" => (OpType::PatchText, vec![args.string_ref("text")?, 0]),
+            "patch_attr" => {
+                (OpType::PatchAttr, vec![args.string_ref("name")?, args.string_ref("value")?])
+            }
+            "class_toggle" => {
+                (OpType::ClassToggle, vec![args.string_ref("class")?, args.bool("on")? as u16])
+            }
+            "remove" => (OpType::Remove, vec![]),
+            "set_style" => {
+                (OpType::SetStyle, vec![args.string_ref("name")?, args.string_ref("value")?])
+            }
+            "batch_start" => (OpType::BatchStart, vec![]),
+            "batch_commit" => (OpType::BatchCommit, vec![]),
+            "attach_event" => {
+                (OpType::AttachEvent, vec![args.string_ref("event")?, args.int("handler")?])
+            }
+            "set_property" => {
+                let name = args.string_ref("name")?;
+                let (kind, value) = if args.has("number") {
+                    (PropKind::Number, args.string_ref("number")?)
+                } else {
+                    match args.value("value")?.as_str() {
+                        "true" => (PropKind::Bool, 1),
+                        "false" => (PropKind::Bool, 0),
+                        "null" => (PropKind::Null, 0),
+                        v => (
+                            PropKind::String,
+                            v.strip_prefix('s').map(|id| parse_int(line, id)).ok_or_else(
+                                || error(line, format!("Invalid value= value: {v}")),
+                            )??,
+                        ),
+                    }
+                };
+                // Kind byte, then the reserved byte
+                (OpType::SetProperty, vec![name, kind as u16, value])
+            }
+            "append_child" => {
+                ids = vec![args.int("child")?];
+                (OpType::AppendChild, vec![])
+            }
+            other => return Err(error(line, format!("Unknown v2 mnemonic: {other}"))),
+        };
+        let target: u32 = args.int("target")?;
+
+        let width = self.width();
+        self.opcodes.push(op_type as u8);
+        self.opcodes
+            .resize(self.opcodes.len() + width.opcode_header_size() - 1 - width.id_size(), 0);
+        push_id(&mut self.opcodes, width, line, target)?;
+        // Payloads are IDs and u16 fields (class_toggle: u16 + enable u8 +
+        // reserved u8), zero-padded to the payload size
+        let end = self.opcodes.len() + width.payload_size(op_type);
+        for id in ids {
+            push_id(&mut self.opcodes, width, line, id)?;
+        }
+        for field in payload {
+            self.opcodes.extend(field.to_le_bytes());
+        }
+        self.opcodes.resize(end, 0);
+        self.opcode_count += 1;
+
+        Ok(())
+    }
+
+    /// Width of v2 node and template IDs, from `.flags`
+    fn width(&self) -> IdWidth {
+        IdWidth::from_flags(self.flags.unwrap_or(0))
+    }
+
+    fn finish(self) -> Result<Vec<u8>> {
+        if self.in_macro {
+            return Err(error(0, "Unterminated macro block"));
+        }
+        match self.format {
+            None => Err(error(0, "Empty listing")),
+            Some(Format::Raw) => Ok(self.raw),
+            Some(Format::V1) => self.finish_v1(),
+            Some(Format::V2) => self.finish_v2(),
+            Some(Format::Dxb) => {
+                let stream = self.finish_v2()?;
+                let mut out = Vec::with_capacity(DXB_HEADER_SIZE + stream.len());
+                out.extend_from_slice(DXB_MAGIC);
+                out.push(DXB_VERSION);
+                out.push(DXB_MODE_HTIP);
+                out.extend((stream.len() as u32).to_le_bytes());
+                out.extend(stream);
+                Ok(out)
+            }
+        }
+    }
+
+    /// Same layout as `HtipWriter::finish_and_sign`, with the given signature
+    fn finish_v1(mut self) -> Result<Vec<u8>> {
+        // Dictionary strings are not on the wire
+        let dictionary_len = self.dictionary.map_or(0, |d| d.len());
+        self.strings.drain(..dictionary_len);
+        let encode_error =
+            |e: bincode::error::EncodeError| DxBinaryError::BincodeError(e.to_string());
+        let payload_bytes = if self.macros.is_empty() {
+            let operations = self
+                .steps
+                .into_iter()
+                .map(|step| match step {
+                    Step::Op(op) => Ok(op),
+                    Step::Invoke(_) => Err(error(0, "invoke without any macro definition")),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let payload = (&self.strings, &self.templates, &operations);
+            bincode::encode_to_vec(payload, config::standard())
+        } else {
+            let sections = (&self.strings, &self.templates, &self.macros, &self.steps);
+            bincode::encode_to_vec(sections, config::standard())
+        }
+        .map_err(encode_error)?;
+        let metadata_bytes = match &self.metadata {
+            Some(metadata) => {
+                bincode::encode_to_vec(metadata, config::standard()).map_err(encode_error)?
+            }
+            None => Vec::new(),
+        };
+
+        let mut header = HtipHeader::new();
+        header.magic = *MAGIC_BYTES;
+        header.version = VERSION;
+        if self.metadata.is_some() {
+            header.flags |= FLAG_METADATA;
+        }
+        if !self.macros.is_empty() {
+            header.flags |= FLAG_MACROS;
+        }
+        header.signature = self.signature.unwrap_or([0; 64]);
+        header.template_count = self.templates.len() as u16;
+        header.key_id = self.key_id;
+        header.dictionary = self.dictionary.map_or(0, |d| d.version);
+        header.string_count = self.strings.len() as u32;
+        header.total_opcodes_size = payload_bytes.len() as u32;
+
+        let mut result =
+            Vec::with_capacity(HtipHeader::SIZE + metadata_bytes.len() + payload_bytes.len());
+        result.extend_from_slice(bytemuck::bytes_of(&header));
+        result.extend_from_slice(&metadata_bytes);
+        result.extend_from_slice(&payload_bytes);
+        Ok(result)
+    }
+
+    /// Same layout as dx-compiler's `generate_htip`
+    fn finish_v2(self) -> Result<Vec<u8>> {
+        let string_count =
+            u16::try_from(self.strings.len()).map_err(|_| error(0, "Too many strings for v2"))?;
+
+        let mut string_entries = Vec::with_capacity(self.strings.len() * StringEntry::SIZE);
+        let mut string_data: Vec<u8> = Vec::new();
+        for s in &self.strings {
+            let len = u16::try_from(s.len())
+                .map_err(|_| error(0, format!("String too long for v2: {} bytes", s.len())))?;
+            string_entries.extend((string_data.len() as u32).to_le_bytes());
+            string_entries.extend(len.to_le_bytes());
+            string_entries.extend(0u16.to_le_bytes());
+            string_data.extend(s.as_bytes());
+        }
+
+        let payload_size = string_entries.len()
+            + string_data.len()
+            + self.template_entries.len()
+            + self.opcodes.len();
+        let flags = self
+            .flags
+            .unwrap_or_else(|| v2_default_flags(string_count, self.template_count));
+
+        let mut stream = Vec::with_capacity(dx_packet::HtipHeader::SIZE + payload_size);
+        stream.extend(dx_packet::HtipHeader::MAGIC.to_le_bytes());
+        stream.push(dx_packet::HtipHeader::VERSION);
+        stream.push(flags);
+        stream.extend(self.template_count.to_le_bytes());
+        stream.extend(string_count.to_le_bytes());
+        stream.extend(self.opcode_count.to_le_bytes());
+        stream.extend((payload_size as u32).to_le_bytes());
+        stream.extend(string_entries);
+        stream.extend(string_data);
+        stream.extend(self.template_entries);
+        stream.extend(self.opcodes);
+
+        Ok(stream)
+    }
+}
+
+// ============================================================================
+// TOKENS & ARGUMENTS
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+}
+
+/// Split a line into words and quoted strings, dropping the comment
+fn tokenize(line: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == ';' {
+            break;
+        } else if c == '"' {
+            chars.next();
+            tokens.push(Token::Quoted(unescape(&mut chars)?));
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ';' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Read a quoted string up to its closing quote (Rust `{:?}` escapes)
+fn unescape(chars: &mut impl Iterator<Item = char>) -> std::result::Result<String, String> {
+    let mut out = String::new();
+    loop {
+        match chars.next().ok_or("Unterminated string")? {
+            '"' => return Ok(out),
+            '\\' => match chars.next().ok_or("Unterminated string")? {
+                'n' => out.push('\n'),
+                'r' => out.push('\r'),
+                't' => out.push('\t'),
+                '0' => out.push('\0'),
+                c @ ('\\' | '"' | '\'') => out.push(c),
+                'u' => {
+                    if chars.next() != Some('{') {
+                        return Err("Expected { after \\u".to_string());
+                    }
+                    let hex: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    let c = u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("Invalid escape \\u{{{hex}}}"))?;
+                    out.push(c);
+                }
+                c => return Err(format!("Unknown escape \\{c}")),
+            },
+            c => out.push(c),
+        }
+    }
+}
+
+/// Arguments of one line: positional words, `key=value` pairs and strings
+struct Args {
+    line: usize,
+    words: Vec<String>,
+    pairs: HashMap<String, String>,
+    quoted: Vec<String>,
+}
+
+impl Args {
+    fn new(line: usize, tokens: &[Token]) -> Result<Self> {
+        let mut args = Self {
+            line,
+            words: Vec::new(),
+            pairs: HashMap::new(),
+            quoted: Vec::new(),
+        };
+
+        for token in tokens {
+            match token {
+                Token::Quoted(s) => args.quoted.push(s.clone()),
+                Token::Word(word) => match word.split_once('=') {
+                    Some((key, value)) => {
+                        if args.pairs.insert(key.to_string(), value.to_string()).is_some() {
+                            return Err(error(line, format!("Duplicate argument: {key}")));
+                        }
+                    }
+                    None => args.words.push(word.clone()),
+                },
+            }
+        }
+
+        // Positional words are read front to back
+        args.words.reverse();
+        args.quoted.reverse();
+        Ok(args)
+    }
+
+    fn word(&mut self) -> Result<String> {
+        self.words.pop().ok_or_else(|| error(self.line, "Missing argument"))
+    }
+
+    fn quoted(&mut self) -> Result<String> {
+        self.quoted.pop().ok_or_else(|| error(self.line, "Missing quoted string"))
+    }
+
+    fn flag(&mut self, name: &str) -> bool {
+        match self.words.iter().position(|w| w == name) {
+            Some(index) => {
+                self.words.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn has(&self, key: &str) -> bool {
+        self.pairs.contains_key(key)
+    }
+
+    fn value(&mut self, key: &str) -> Result<String> {
+        self.pairs
+            .remove(key)
+            .ok_or_else(|| error(self.line, format!("Missing {key}=")))
+    }
+
+    fn int<T: TryFrom<u64>>(&mut self, key: &str) -> Result<T> {
+        let value = self.value(key)?;
+        parse_int(self.line, &value)
+    }
+
+    /// `sN` string table reference
+    fn string_ref<T: TryFrom<u64>>(&mut self, key: &str) -> Result<T> {
+        let value = self.value(key)?;
+        let id = value
+            .strip_prefix('s')
+            .ok_or_else(|| error(self.line, format!("{key}= must be a string ref (sN)")))?;
+        parse_int(self.line, id)
+    }
+
+    fn bool(&mut self, key: &str) -> Result<bool> {
+        match self.value(key)?.as_str() {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            other => Err(error(self.line, format!("{key}= must be true or false, found {other}"))),
+        }
+    }
+
+    /// `[0,2,1]` list (DOM paths, macro arguments)
+    fn list<T: TryFrom<u64>>(&mut self, key: &str) -> Result<Vec<T>> {
+        let value = self.value(key)?;
+        let inner = value
+            .strip_prefix('[')
+            .and_then(|v| v.strip_suffix(']'))
+            .ok_or_else(|| error(self.line, format!("{key}= must look like [0,1]")))?;
+        if inner.is_empty() {
+            return Ok(Vec::new());
+        }
+        inner.split(',').map(|step| parse_int(self.line, step)).collect()
+    }
+
+    fn property(&mut self, key: &str) -> Result<PropertyValue> {
+        let value = self.value(key)?;
+        Ok(match value.as_str() {
+            "null" => PropertyValue::Null,
+            "true" => PropertyValue::Boolean(true),
+            "false" => PropertyValue::Boolean(false),
+            v if v.starts_with('s') => PropertyValue::String(parse_int(self.line, &v[1..])?),
+            v => PropertyValue::Number(
+                v.parse().map_err(|_| error(self.line, format!("Invalid {key}= value: {v}")))?,
+            ),
+        })
+    }
+
+    /// Fail on leftover arguments (usually typos)
+    fn done(self) -> Result<()> {
+        if let Some(word) = self.words.last() {
+            return Err(error(self.line, format!("Unexpected argument: {word}")));
+        }
+        if let Some(key) = self.pairs.keys().min() {
+            return Err(error(self.line, format!("Unexpected argument: {key}=")));
+        }
+        if !self.quoted.is_empty() {
+            return Err(error(self.line, "Unexpected quoted string"));
+        }
+        Ok(())
+    }
+}
+
+/// Decimal or `0x` hex integer
+fn parse_int<T: TryFrom<u64>>(line: usize, value: &str) -> Result<T> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed
+        .ok()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| error(line, format!("Invalid or out of range number: {value}")))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut out, b| {
+        let _ = write!(out, "{b:02x}");
+        out
+    })
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serializer::HtipWriter;
+    use ed25519_dalek::SigningKey;
+
+    fn v1_stream() -> Vec<u8> {
+        let mut writer = HtipWriter::with_dictionary(Some(crate::dictionary::LATEST));
+        writer.set_key_id(3);
+        writer.write_template(
+            0,
+            "<div class=\"card\">\n  <span></span>\n</div>",
+            vec![
+                Binding {
+                    slot_id: 0,
+                    binding_type: BindingType::Text,
+                    path: vec![0, 1],
+                },
+                Binding {
+                    slot_id: 1,
+                    binding_type: BindingType::Event { event_type_id: 9 },
+                    path: vec![],
+                },
+            ],
+        );
+        writer.write_batch_start(1);
+        writer.write_instantiate(1, 0, 0);
+        writer.write_patch_text(1, 0, "Hello \"world\" ✓");
+        writer.write_patch_attr(1, 1, "data-id", "42");
+        writer.write_class_toggle(1, "active", true);
+        writer.write_attach_event(1, "click", 7);
+        writer.write_set_property(1, "value", PropertyValue::Number(-0.5));
+        writer.write_set_property(1, "checked", PropertyValue::Boolean(false));
+        writer.write_set_property(1, "title", PropertyValue::String(2));
+        writer.write_set_property(1, "data", PropertyValue::Null);
+        writer.write_append_child(0, 1);
+        writer.write_remove_node(1);
+        writer.write_batch_commit(1);
+
+        writer.finish_and_sign(&SigningKey::from_bytes(&[9u8; 32])).unwrap()
+    }
+
+    fn v2_stream() -> Vec<u8> {
+        assemble(
+            r#"
+.htip v2
+string s0 "<p class=\"x\"></p>"
+string s1 "Count: 1"
+string s2 "color"
+string s3 "red"
+template id=0 html=s0 slots=1
+
+batch_start target=0
+clone target=1 template=0 parent=0
+patch_text target=1 text=s1     ; "Count: 1"
+patch_attr target=1 name=s2 value=s3
+set_style target=1 name=s2 value=s3
+class_toggle target=1 class=s2 on=false
+attach_event target=1 event=s2 handler=7
+set_property target=1 name=s2 value=s3
+set_property target=1 name=s2 number=s1
+set_property target=1 name=s2 value=true
+set_property target=1 name=s2 value=null
+clone target=2 template=0 parent=1
+append_child target=0 child=2
+remove target=1
+batch_commit target=0
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_v1_round_trip() {
+        let binary = v1_stream();
+        let text = disassemble(&binary);
+
+        assert!(text.starts_with("; HTIP v1"), "{text}");
+        assert!(text.contains(".key_id 3"));
+        assert!(text.contains("  bind slot=0 text path=[0,1]"));
+        assert!(text.contains(".dictionary 1"));
+        // "value" is in the dictionary
+        assert!(text.contains("set_property instance=1 name=s32 value=-0.5"), "{text}");
+        assert_eq!(assemble(&text).unwrap(), binary);
+    }
+
+    #[test]
+    fn test_v1_metadata_round_trip() {
+        let sign = |metadata: StreamMetadata| {
+            let mut writer = HtipWriter::new();
+            writer.write_instantiate(1, 0, 0);
+            writer.set_metadata(metadata);
+            writer.finish_and_sign(&SigningKey::from_bytes(&[9u8; 32])).unwrap()
+        };
+
+        let binary = sign(StreamMetadata {
+            issued_at: 1_000,
+            expires_at: Some(2_000),
+            min_runtime_version: 1,
+            origin: Some("https://example.com/\"a b\"".to_string()),
+        });
+        let text = disassemble(&binary);
+        assert!(
+            text.contains(".metadata issued_at=1000 min_runtime_version=1 expires_at=2000 origin"),
+            "{text}"
+        );
+        assert_eq!(assemble(&text).unwrap(), binary);
+
+        let binary = sign(StreamMetadata {
+            issued_at: 0,
+            expires_at: None,
+            min_runtime_version: 0,
+            origin: None,
+        });
+        let text = disassemble(&binary);
+        assert!(text.contains(".metadata issued_at=0 min_runtime_version=0\n"), "{text}");
+        assert_eq!(assemble(&text).unwrap(), binary);
+
+        let twice = ".htip v1\n.metadata issued_at=0 min_runtime_version=0\n.metadata issued_at=0";
+        assert!(matches!(assemble(twice), Err(DxBinaryError::Assembly { line: 3, .. })));
+    }
+
+    #[test]
+    fn test_v1_macros_round_trip() {
+        let mut writer = HtipWriter::new();
+        writer.set_macro_compression(true);
+        writer.write_template(0, "<li></li>", vec![]);
+        writer.write_batch_start(1);
+        for row in 1..=8 {
+            writer.write_instantiate(row, 0, 0);
+            writer.write_patch_text(row, 0, &format!("Row {row}"));
+            writer.write_class_toggle(row, "odd", row % 2 == 1);
+        }
+        writer.write_batch_commit(1);
+        let binary = writer.finish_and_sign(&SigningKey::from_bytes(&[9u8; 32])).unwrap();
+        assert_ne!(binary[std::mem::offset_of!(HtipHeader, flags)] & FLAG_MACROS, 0);
+
+        let text = disassemble(&binary);
+        assert!(
+            text.contains("macro id=0\n  instantiate instance=$0 template=0 parent=0"),
+            "{text}"
+        );
+        assert!(text.contains("  class_toggle instance=$0 class="), "{text}");
+        assert!(text.contains("invoke macro=0 args=["), "{text}");
+        assert!(text.contains("batch_commit id=1"), "{text}");
+        assert_eq!(assemble(&text).unwrap(), binary);
+    }
+
+    #[test]
+    fn test_v2_round_trip() {
+        let stream = v2_stream();
+        let word = |at: usize| u32::from_le_bytes(stream[at..at + 4].try_into().unwrap());
+        assert_eq!(&stream[0..4], &[0x58, 0x44, 2, 0x03]);
+        assert_eq!(word(8), 15); // opcode_count
+        assert_eq!(word(12) as usize, stream.len() - dx_packet::HtipHeader::SIZE);
+
+        let text = disassemble(&stream);
+        assert!(text.contains("patch_text target=1 text=s1"), "{text}");
+        assert!(text.contains("; \"Count: 1\""));
+        assert!(text.contains("set_property target=1 name=s2 number=s1"), "{text}");
+        assert_eq!(assemble(&text).unwrap(), stream);
+
+        // Same stream in the .dxb container
+        let mut dxb = b"DX\x01\x01".to_vec();
+        dxb.extend((stream.len() as u32).to_le_bytes());
+        dxb.extend(&stream);
+        let text = disassemble(&dxb);
+        assert!(text.contains(".htip dxb"));
+        assert_eq!(assemble(&text).unwrap(), dxb);
+    }
+
+    #[test]
+    fn test_v2_wide_ids_round_trip() {
+        let stream = assemble(
+            r#"
+.htip v2
+.flags 0x07
+string s0 "<li></li>"
+template id=70000 html=s0 slots=0
+clone target=1 template=70000 parent=0
+append_child target=0 child=1
+"#,
+        )
+        .unwrap();
+        // Template entry, wide clone (8 + 8) and append child (8 + 4)
+        assert_eq!(stream.len(), dx_packet::HtipHeader::SIZE + 8 + 9 + 8 + 16 + 12);
+
+        let text = disassemble(&stream);
+        assert!(text.contains(".flags 0x07"), "{text}");
+        assert!(text.contains("clone target=1 template=70000 parent=0"), "{text}");
+        assert_eq!(assemble(&text).unwrap(), stream);
+
+        let late = ".htip v2\nclone target=1 template=0 parent=0\n.flags 0x07";
+        assert!(matches!(assemble(late), Err(DxBinaryError::Assembly { line: 3, .. })));
+    }
+
+    #[test]
+    fn test_unrecognized_bytes_round_trip_as_raw() {
+        let mut stream = v2_stream();
+        stream.push(0xFF); // Trailing garbage
+        let garbage: Vec<u8> = (0..=255u8).collect();
+
+        for binary in [stream, garbage, v1_stream()[..100].to_vec()] {
+            let text = disassemble(&binary);
+            assert!(text.contains(".htip raw"));
+            assert_eq!(assemble(&text).unwrap(), binary);
+        }
+    }
+
+    #[test]
+    fn test_assembly_errors() {
+        let cases = [
+            ("string s0 \"x\"", 1),
+            (".htip v1\nstring s1 \"x\"", 2),
+            (".htip v1\npatch_text instance=1 slot=0", 2),
+            (".htip v1\nbind slot=0 text path=[]", 2),
+            (".htip v1\n.dictionary 999", 2),
+            (".htip v1\nstring s0 \"x\"\n.dictionary 1", 3),
+            (".htip v2\nclone target=1 template=0 parent=0 extra=1", 2),
+            (".htip v2\n\nclone target=70000 template=0 parent=0", 3),
+            (".htip v1\nmacro id=1", 2),
+            (".htip v1\nmacro id=0\n  batch_start id=1", 3),
+            (".htip v1\nmacro id=0\n  remove_node instance=s1", 3),
+            (".htip v1\nmacro id=0\nend", 3),
+            (".htip v1\nmacro id=0\n  remove_node instance=$0", 0),
+            (".htip v1\ninvoke macro=0 args=[1]", 0),
+        ];
+
+        for (text, expected_line) in cases {
+            match assemble(text) {
+                Err(DxBinaryError::Assembly { line, .. }) => {
+                    assert_eq!(line, expected_line, "{text}")
+                }
+                other => panic!("{text}: expected assembly error, got {other:?}"),
+            }
+        }
+    }
+}
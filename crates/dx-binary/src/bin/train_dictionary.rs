@@ -0,0 +1,91 @@
+//! # Dictionary Trainer
+//!
+//! Derives a static string dictionary from template sources.
+//!
+//! ```text
+//! train_dictionary [--version N] [--max N] <file or dir>... > src/dictionary_vN.rs
+//! ```
+//!
+//! Directories are walked for `.tsx`, `.jsx` and `.html` files. The output
+//! is the Rust source of `DICTIONARY_VN`; register it in `dictionary.rs`.
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use dx_binary::dictionary::{to_rust_source, DictionaryTrainer, MAX_DICTIONARY_ENTRIES};
+
+const EXTENSIONS: &[&str] = &["tsx", "jsx", "html"];
+
+fn main() -> ExitCode {
+    let mut version: u16 = 1;
+    let mut max = MAX_DICTIONARY_ENTRIES;
+    let mut inputs: Vec<PathBuf> = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let number = |args: &mut dyn Iterator<Item = String>| args.next()?.parse().ok();
+        match arg.as_str() {
+            "--version" => match number(&mut args) {
+                Some(v) if v > 0 && v <= u16::MAX as usize => version = v as u16,
+                _ => return usage("--version takes a number from 1 to 65535"),
+            },
+            "--max" => match number(&mut args) {
+                Some(m) => max = m,
+                None => return usage("--max takes a number"),
+            },
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+    if inputs.is_empty() {
+        return usage("no input files");
+    }
+
+    let mut files = Vec::new();
+    for input in &inputs {
+        if let Err(e) = collect(input, &mut files) {
+            eprintln!("{}: {}", input.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+    files.sort();
+
+    let mut trainer = DictionaryTrainer::new();
+    for file in &files {
+        match std::fs::read_to_string(file) {
+            Ok(markup) => trainer.add_markup(&markup),
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let strings = trainer.build(max);
+    eprintln!("{} files, {} strings", files.len(), strings.len());
+    print!("{}", to_rust_source(version, &strings));
+    ExitCode::SUCCESS
+}
+
+/// Template files under `path` (or `path` itself)
+fn collect(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if path.is_file() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(&path, files)?;
+        } else if path.extension().is_some_and(|ext| EXTENSIONS.iter().any(|e| ext == *e)) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn usage(message: &str) -> ExitCode {
+    eprintln!("train_dictionary: {message}");
+    eprintln!("usage: train_dictionary [--version N] [--max N] <file or dir>...");
+    ExitCode::FAILURE
+}
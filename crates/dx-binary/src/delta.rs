@@ -1,11 +1,51 @@
-//! # Delta Patching (v2 - Future)
+//! # Delta Patching
 //!
 //! Binary delta compression for navigation updates.
 //!
 //! This is the secret to 314-byte navigation updates.
+//!
+//! ## Algorithm (rsync-style)
+//!
+//! 1. Index every `DELTA_BLOCK_SIZE`-aligned block of the base by a weak rolling hash
+//! 2. Slide a window over the target, rolling the hash one byte at a time
+//! 3. On a verified block match, grow it both ways and emit `Copy`
+//! 4. Unmatched bytes become `Insert` (pure addition) or `Replace`
+//!    (stands in for the base bytes skipped between two copies)
+//!
+//! ## Binary Format
+//!
+//! ```text
+//! ┌──────────┬─────────┬────────────────┬──────────────────┬───────────────┐
+//! │ "DXD1":4 │ ver:1   │ base_hash:32   │ target_hash:32   │ op_count:var  │
+//! └──────────┴─────────┴────────────────┴──────────────────┴───────────────┘
+//! Operations (var = LEB128 u32):
+//!   0x01 Copy    offset:var length:var
+//!   0x02 Insert  len:var data
+//!   0x03 Replace offset:var length:var len:var data
+//! ```
+
+use std::collections::HashMap;
+
+use crate::{DxBinaryError, Result};
+
+/// Delta patch magic bytes
+pub const DELTA_MAGIC: &[u8; 4] = b"DXD1";
 
-/// Delta patch format (future implementation)
-#[derive(Debug, Clone)]
+/// Delta patch format version
+pub const DELTA_VERSION: u8 = 1;
+
+/// Block size used to index the base (small: HTIP payloads are a few KB)
+pub const DELTA_BLOCK_SIZE: usize = 16;
+
+/// Candidate base offsets kept per weak hash
+const MAX_CANDIDATES: usize = 8;
+
+const OP_COPY: u8 = 0x01;
+const OP_INSERT: u8 = 0x02;
+const OP_REPLACE: u8 = 0x03;
+
+/// Delta patch format
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DeltaPatch {
     /// Base version hash
     pub base_hash: [u8; 32],
@@ -18,7 +58,9 @@ pub struct DeltaPatch {
 }
 
 /// Delta operation
-#[derive(Debug, Clone)]
+///
+/// Operations are applied in order, each appending to the target.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DeltaOp {
     /// Copy from base (offset, length)
     Copy { offset: u32, length: u32 },
@@ -26,7 +68,7 @@ pub enum DeltaOp {
     /// Insert new data
     Insert { data: Vec<u8> },
 
-    /// Replace range
+    /// Replace range: `data` takes the place of `base[offset..offset + length]`
     Replace {
         offset: u32,
         length: u32,
@@ -34,32 +76,497 @@ pub enum DeltaOp {
     },
 }
 
+impl DeltaPatch {
+    /// Size of the fixed header (magic + version + hashes)
+    pub const HEADER_SIZE: usize = 4 + 1 + 32 + 32;
+
+    /// Length of the target this patch produces
+    pub fn target_len(&self) -> usize {
+        self.operations
+            .iter()
+            .map(|op| match op {
+                DeltaOp::Copy { length, .. } => *length as usize,
+                DeltaOp::Insert { data } | DeltaOp::Replace { data, .. } => data.len(),
+            })
+            .sum()
+    }
+
+    /// Encode to the binary patch format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(Self::HEADER_SIZE + 8 + self.operations.len() * 8);
+        out.extend_from_slice(DELTA_MAGIC);
+        out.push(DELTA_VERSION);
+        out.extend_from_slice(&self.base_hash);
+        out.extend_from_slice(&self.target_hash);
+        write_varint(&mut out, self.operations.len() as u32);
+
+        for op in &self.operations {
+            match op {
+                DeltaOp::Copy { offset, length } => {
+                    out.push(OP_COPY);
+                    write_varint(&mut out, *offset);
+                    write_varint(&mut out, *length);
+                }
+                DeltaOp::Insert { data } => {
+                    out.push(OP_INSERT);
+                    write_varint(&mut out, data.len() as u32);
+                    out.extend_from_slice(data);
+                }
+                DeltaOp::Replace {
+                    offset,
+                    length,
+                    data,
+                } => {
+                    out.push(OP_REPLACE);
+                    write_varint(&mut out, *offset);
+                    write_varint(&mut out, *length);
+                    write_varint(&mut out, data.len() as u32);
+                    out.extend_from_slice(data);
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Decode from the binary patch format
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < Self::HEADER_SIZE {
+            return Err(invalid("truncated header"));
+        }
+        if &bytes[0..4] != DELTA_MAGIC {
+            return Err(DxBinaryError::InvalidMagic);
+        }
+        if bytes[4] != DELTA_VERSION {
+            return Err(DxBinaryError::UnsupportedVersion(bytes[4]));
+        }
+
+        let mut base_hash = [0u8; 32];
+        let mut target_hash = [0u8; 32];
+        base_hash.copy_from_slice(&bytes[5..37]);
+        target_hash.copy_from_slice(&bytes[37..69]);
+
+        let mut cursor = Self::HEADER_SIZE;
+        let count = read_varint(bytes, &mut cursor)? as usize;
+        // Every op takes at least 2 bytes: don't trust `count` for the allocation
+        let mut operations = Vec::with_capacity(count.min((bytes.len() - cursor) / 2));
+
+        for _ in 0..count {
+            let tag = *bytes.get(cursor).ok_or_else(|| invalid("truncated operation"))?;
+            cursor += 1;
+
+            let op = match tag {
+                OP_COPY => DeltaOp::Copy {
+                    offset: read_varint(bytes, &mut cursor)?,
+                    length: read_varint(bytes, &mut cursor)?,
+                },
+                OP_INSERT => DeltaOp::Insert {
+                    data: read_data(bytes, &mut cursor)?,
+                },
+                OP_REPLACE => DeltaOp::Replace {
+                    offset: read_varint(bytes, &mut cursor)?,
+                    length: read_varint(bytes, &mut cursor)?,
+                    data: read_data(bytes, &mut cursor)?,
+                },
+                other => return Err(DxBinaryError::InvalidOpcode(other)),
+            };
+            operations.push(op);
+        }
+
+        if cursor != bytes.len() {
+            return Err(invalid("trailing bytes"));
+        }
+
+        Ok(Self {
+            base_hash,
+            target_hash,
+            operations,
+        })
+    }
+}
+
 /// Apply delta patch to base
-pub fn apply_delta(_base: &[u8], _delta: &DeltaPatch) -> crate::Result<Vec<u8>> {
-    // TODO: Implement delta patching
-    // This will use a binary diff algorithm like bsdiff or similar
-    unimplemented!("Delta patching coming in v2")
+///
+/// Fails (and produces nothing) if the base isn't the one the patch was made
+/// for, the target would be longer than `max_len`, an operation is out of
+/// bounds, or the result doesn't hash to `target_hash`.
+pub fn apply_delta(base: &[u8], delta: &DeltaPatch, max_len: usize) -> Result<Vec<u8>> {
+    if *blake3::hash(base).as_bytes() != delta.base_hash {
+        return Err(DxBinaryError::DeltaBaseMismatch);
+    }
+
+    // Copy lengths are untrusted: bound the output before building it
+    let target_len = delta
+        .operations
+        .iter()
+        .try_fold(0usize, |len, op| match op {
+            DeltaOp::Copy { length, .. } => len.checked_add(*length as usize),
+            DeltaOp::Insert { data } | DeltaOp::Replace { data, .. } => len.checked_add(data.len()),
+        })
+        .filter(|&len| len <= max_len)
+        .ok_or_else(|| invalid("target larger than the limit"))?;
+
+    let mut target = Vec::with_capacity(target_len);
+    for op in &delta.operations {
+        match op {
+            DeltaOp::Copy { offset, length } => {
+                target.extend_from_slice(base_range(base, *offset, *length)?);
+            }
+            DeltaOp::Insert { data } => target.extend_from_slice(data),
+            DeltaOp::Replace {
+                offset,
+                length,
+                data,
+            } => {
+                base_range(base, *offset, *length)?;
+                target.extend_from_slice(data);
+            }
+        }
+    }
+
+    if *blake3::hash(&target).as_bytes() != delta.target_hash {
+        return Err(DxBinaryError::DeltaTargetMismatch);
+    }
+
+    Ok(target)
 }
 
 /// Generate delta patch
-pub fn generate_delta(_base: &[u8], _target: &[u8]) -> crate::Result<DeltaPatch> {
-    // TODO: Implement delta generation
-    unimplemented!("Delta generation coming in v2")
+pub fn generate_delta(base: &[u8], target: &[u8]) -> Result<DeltaPatch> {
+    if base.len() > u32::MAX as usize || target.len() > u32::MAX as usize {
+        return Err(invalid("input larger than 4 GB"));
+    }
+
+    let mut builder = OpBuilder::default();
+    let index = index_blocks(base);
+    let mut literal_start = 0;
+    let mut pos = 0;
+    let mut hash = RollingHash::new(target.get(..DELTA_BLOCK_SIZE).unwrap_or(&[]));
+
+    while pos + DELTA_BLOCK_SIZE <= target.len() {
+        if let Some((mut offset, mut length)) = find_match(&index, base, target, pos, hash.value())
+        {
+            // Grow backwards into the pending literal
+            let mut start = pos;
+            while start > literal_start && offset > 0 && base[offset - 1] == target[start - 1] {
+                start -= 1;
+                offset -= 1;
+                length += 1;
+            }
+
+            builder.literal(&target[literal_start..start], offset);
+            builder.copy(offset, length);
+
+            pos = start + length;
+            literal_start = pos;
+            if pos + DELTA_BLOCK_SIZE <= target.len() {
+                hash = RollingHash::new(&target[pos..pos + DELTA_BLOCK_SIZE]);
+            }
+        } else {
+            if pos + DELTA_BLOCK_SIZE < target.len() {
+                hash.roll(target[pos], target[pos + DELTA_BLOCK_SIZE]);
+            }
+            pos += 1;
+        }
+    }
+
+    builder.literal(&target[literal_start..], base.len());
+
+    Ok(DeltaPatch {
+        base_hash: *blake3::hash(base).as_bytes(),
+        target_hash: *blake3::hash(target).as_bytes(),
+        operations: builder.ops,
+    })
+}
+
+/// Weak hash of every aligned base block -> candidate offsets
+fn index_blocks(base: &[u8]) -> HashMap<u32, Vec<usize>> {
+    let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, block) in base.chunks_exact(DELTA_BLOCK_SIZE).enumerate() {
+        let candidates = index.entry(RollingHash::new(block).value()).or_default();
+        if candidates.len() < MAX_CANDIDATES {
+            candidates.push(i * DELTA_BLOCK_SIZE);
+        }
+    }
+    index
+}
+
+/// Longest verified match for the target block at `pos`: (base offset, length)
+fn find_match(
+    index: &HashMap<u32, Vec<usize>>,
+    base: &[u8],
+    target: &[u8],
+    pos: usize,
+    hash: u32,
+) -> Option<(usize, usize)> {
+    index
+        .get(&hash)?
+        .iter()
+        .map(|&offset| {
+            let length =
+                base[offset..].iter().zip(&target[pos..]).take_while(|(a, b)| a == b).count();
+            (offset, length)
+        })
+        .filter(|&(_, length)| length >= DELTA_BLOCK_SIZE)
+        .max_by_key(|&(offset, length)| (length, std::cmp::Reverse(offset)))
+}
+
+/// Adler-style weak hash over a fixed window, updatable one byte at a time
+struct RollingHash {
+    a: u32,
+    b: u32,
+}
+
+impl RollingHash {
+    fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let mut hash = Self { a: 0, b: 0 };
+        for (i, &byte) in window.iter().enumerate() {
+            hash.a = hash.a.wrapping_add(byte as u32);
+            hash.b = hash.b.wrapping_add((len - i as u32).wrapping_mul(byte as u32));
+        }
+        hash
+    }
+
+    /// Slide the window: drop `out`, append `inp`
+    fn roll(&mut self, out: u8, inp: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(inp as u32);
+        self.b = self
+            .b
+            .wrapping_sub((DELTA_BLOCK_SIZE as u32).wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    fn value(&self) -> u32 {
+        (self.a & 0xFFFF) | (self.b << 16)
+    }
+}
+
+/// Accumulates operations, tracking where the last copy ended in the base
+#[derive(Default)]
+struct OpBuilder {
+    ops: Vec<DeltaOp>,
+    base_cursor: usize,
+}
+
+impl OpBuilder {
+    fn copy(&mut self, offset: usize, length: usize) {
+        // Merge with a directly preceding, contiguous copy
+        if let Some(DeltaOp::Copy {
+            offset: last_offset,
+            length: last_length,
+        }) = self.ops.last_mut()
+        {
+            if (*last_offset + *last_length) as usize == offset {
+                *last_length += length as u32;
+                self.base_cursor = offset + length;
+                return;
+            }
+        }
+
+        self.ops.push(DeltaOp::Copy {
+            offset: offset as u32,
+            length: length as u32,
+        });
+        self.base_cursor = offset + length;
+    }
+
+    /// Emit unmatched target bytes before the base position `next_offset`
+    fn literal(&mut self, data: &[u8], next_offset: usize) {
+        if data.is_empty() {
+            return;
+        }
+
+        if next_offset > self.base_cursor {
+            self.ops.push(DeltaOp::Replace {
+                offset: self.base_cursor as u32,
+                length: (next_offset - self.base_cursor) as u32,
+                data: data.to_vec(),
+            });
+        } else {
+            self.ops.push(DeltaOp::Insert {
+                data: data.to_vec(),
+            });
+        }
+    }
+}
+
+fn base_range(base: &[u8], offset: u32, length: u32) -> Result<&[u8]> {
+    let start = offset as usize;
+    let end = start
+        .checked_add(length as usize)
+        .filter(|&end| end <= base.len())
+        .ok_or_else(|| invalid("operation out of base bounds"))?;
+    Ok(&base[start..end])
+}
+
+fn invalid(reason: &str) -> DxBinaryError {
+    DxBinaryError::InvalidDelta(reason.to_string())
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], cursor: &mut usize) -> Result<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let byte = *bytes.get(*cursor).ok_or_else(|| invalid("truncated varint"))?;
+        *cursor += 1;
+        // The fifth byte only has room for the top 4 bits
+        if shift == 28 && byte > 0x0F {
+            return Err(invalid("varint overflow"));
+        }
+        value |= ((byte & 0x7F) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint too long"))
+}
+
+fn read_data(bytes: &[u8], cursor: &mut usize) -> Result<Vec<u8>> {
+    let len = read_varint(bytes, cursor)? as usize;
+    let data = bytes
+        .get(*cursor..cursor.saturating_add(len))
+        .ok_or_else(|| invalid("truncated data"))?;
+    *cursor += len;
+    Ok(data.to_vec())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn page(title: &str, items: usize) -> Vec<u8> {
+        let mut html = format!("<header><h1>{title}</h1><nav>home about blog</nav></header><ul>");
+        for i in 0..items {
+            html.push_str(&format!("<li class=\"item\">Item number {i}</li>"));
+        }
+        html.push_str("</ul><footer>dx-www runtime</footer>");
+        html.into_bytes()
+    }
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let base = page("Dashboard", 40);
+        let target = page("Settings", 42);
+
+        let delta = generate_delta(&base, &target).unwrap();
+        assert_eq!(delta.target_len(), target.len());
+        assert_eq!(apply_delta(&base, &delta, usize::MAX).unwrap(), target);
+
+        // A navigation delta is a small fraction of the page
+        let encoded = delta.to_bytes();
+        assert!(encoded.len() < target.len() / 4, "{} bytes", encoded.len());
+        assert_eq!(DeltaPatch::from_bytes(&encoded).unwrap(), delta);
+    }
+
+    #[test]
+    fn test_delta_ops() {
+        let base = page("Home", 4);
+        let delta = generate_delta(&base, &base).unwrap();
+        assert_eq!(
+            delta.operations,
+            [DeltaOp::Copy {
+                offset: 0,
+                length: base.len() as u32
+            }]
+        );
+
+        // In-place edit becomes a Replace of the skipped base bytes
+        let mut edited = base.clone();
+        edited[60..64].copy_from_slice(b"ABCD");
+        let delta = generate_delta(&base, &edited).unwrap();
+        assert!(delta
+            .operations
+            .iter()
+            .any(|op| matches!(op, DeltaOp::Replace { length: 4, data, .. } if data == b"ABCD")));
+        assert_eq!(apply_delta(&base, &delta, usize::MAX).unwrap(), edited);
+
+        // Unrelated data is a single insert
+        let delta = generate_delta(b"", b"hello").unwrap();
+        assert_eq!(
+            delta.operations,
+            [DeltaOp::Insert {
+                data: b"hello".to_vec()
+            }]
+        );
+        assert_eq!(apply_delta(b"", &delta, usize::MAX).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_delta_rejects_mismatch() {
+        let base = page("Dashboard", 10);
+        let target = page("Profile", 10);
+        let delta = generate_delta(&base, &target).unwrap();
+
+        let mut wrong_base = base.clone();
+        wrong_base[0] ^= 1;
+        assert!(matches!(
+            apply_delta(&wrong_base, &delta, usize::MAX),
+            Err(DxBinaryError::DeltaBaseMismatch)
+        ));
+
+        let mut tampered = delta.clone();
+        tampered.operations.push(DeltaOp::Insert { data: vec![0] });
+        assert!(matches!(
+            apply_delta(&base, &tampered, usize::MAX),
+            Err(DxBinaryError::DeltaTargetMismatch)
+        ));
+
+        let mut out_of_bounds = delta.clone();
+        out_of_bounds.operations[0] = DeltaOp::Copy {
+            offset: u32::MAX,
+            length: 1,
+        };
+        assert!(matches!(
+            apply_delta(&base, &out_of_bounds, usize::MAX),
+            Err(DxBinaryError::InvalidDelta(_))
+        ));
+
+        let encoded = delta.to_bytes();
+        assert!(DeltaPatch::from_bytes(&encoded[..encoded.len() - 1]).is_err());
+        assert!(matches!(DeltaPatch::from_bytes(b"NOPE"), Err(DxBinaryError::InvalidDelta(_))));
+    }
+
     #[test]
-    #[should_panic(expected = "not implemented")]
-    fn test_delta_not_implemented() {
-        let base = b"base";
-        let delta = DeltaPatch {
-            base_hash: [0; 32],
-            target_hash: [0; 32],
-            operations: vec![],
+    fn test_delta_rejects_oversized() {
+        let base = page("Dashboard", 10);
+        let target = page("Profile", 12);
+        let delta = generate_delta(&base, &target).unwrap();
+        assert_eq!(apply_delta(&base, &delta, target.len()).unwrap(), target);
+        assert!(matches!(
+            apply_delta(&base, &delta, target.len() - 1),
+            Err(DxBinaryError::InvalidDelta(_))
+        ));
+
+        // Repeated copies of the whole base are caught before any of them runs
+        let copies = DeltaPatch {
+            operations: vec![
+                DeltaOp::Copy {
+                    offset: 0,
+                    length: base.len() as u32,
+                };
+                4096
+            ],
+            ..delta.clone()
         };
-        let _ = apply_delta(base, &delta);
+        assert!(matches!(
+            apply_delta(&base, &copies, 1 << 20),
+            Err(DxBinaryError::InvalidDelta(_))
+        ));
+
+        // A fifth varint byte carries at most 4 bits
+        let mut cursor = 0;
+        assert_eq!(read_varint(&[0xFF, 0xFF, 0xFF, 0xFF, 0x0F], &mut cursor).unwrap(), u32::MAX);
+        for last in [0x10, 0x7F, 0x8F] {
+            let mut cursor = 0;
+            assert!(read_varint(&[0xFF, 0xFF, 0xFF, 0xFF, last], &mut cursor).is_err());
+        }
     }
 }
@@ -3,56 +3,263 @@
 //! Client-side: Streaming zero-copy parser
 //!
 //! This runs in the browser WASM runtime.
+//!
+//! - `HtipStream`: whole buffer in, verified before the first operation
+//! - `HtipStreamDecoder`: push-based, yields operations while bytes arrive,
+//!   the signature is checked by `finish()` once the stream is complete
+//!
+//! Both check the payload against `DecodeLimits` as it is decoded: section
+//! counts before anything is allocated for them, and every item with
+//! `item_config`, so a claimed length cannot preallocate more than
+//! `MAX_STRING_TABLE_SIZE`.
 
-use bincode::config;
+use std::collections::VecDeque;
+
+use bincode::config::{self, Config};
+use bincode::error::DecodeError;
+use dx_packet::{DecodeLimits, Limit};
 use ed25519_dalek::{Signature, VerifyingKey};
 
 use crate::{
-    opcodes::Operation,
-    protocol::{HtipHeader, HtipPayload},
-    signature::verify_payload,
-    DxBinaryError, Result,
+    dictionary::{self, StringDictionary},
+    macros::{OpMacro, Step, MAX_MACROS},
+    opcodes::{Operation, TemplateDef},
+    protocol::{HtipHeader, HtipPayload, StreamMetadata, FLAG_MACROS, FLAG_METADATA},
+    signature::{signed_message, verify_payload, KeySelector, StreamContext},
+    validate::{validate_payload, Problem},
+    DxBinaryError, Result, DEFAULT_LIMITS, MAX_STRING_TABLE_SIZE,
 };
 
+/// Bincode config for a single payload item
+///
+/// Bincode preallocates whatever length a container claims; the limit
+/// turns an absurd claim into an error instead of an allocation.
+fn item_config() -> impl Config {
+    config::standard().with_limit::<MAX_STRING_TABLE_SIZE>()
+}
+
+fn bincode_error(e: DecodeError) -> DxBinaryError {
+    DxBinaryError::BincodeError(e.to_string())
+}
+
+/// Dictionary named by a header, None for version 0
+fn find_dictionary(version: u16) -> Result<Option<&'static StringDictionary>> {
+    match version {
+        0 => Ok(None),
+        _ => dictionary::find(version)
+            .map(Some)
+            .ok_or(DxBinaryError::UnknownDictionary(version)),
+    }
+}
+
+/// Running totals of a payload, checked against `DecodeLimits`
+struct Budget {
+    limits: DecodeLimits,
+    string_bytes: usize,
+    templates: usize,
+    nodes: usize,
+    nesting: usize,
+    /// Operations after macro expansion
+    ops: usize,
+}
+
+impl Budget {
+    fn new(limits: &DecodeLimits) -> Self {
+        Self {
+            limits: *limits,
+            string_bytes: 0,
+            templates: 0,
+            nodes: 0,
+            nesting: 0,
+            ops: 0,
+        }
+    }
+
+    fn check(&self, limit: Limit, found: usize) -> Result<()> {
+        self.limits.check(limit, found).map_err(|limit| DxBinaryError::LimitExceeded {
+            limit,
+            found,
+            max: self.limits.max(limit),
+        })
+    }
+
+    /// Check the item count of a section before it is decoded
+    fn count(&mut self, limit: Limit, count: u64) -> Result<u64> {
+        let found = usize::try_from(count).unwrap_or(usize::MAX);
+        self.check(limit, found)?;
+        if limit == Limit::Templates {
+            self.templates = found;
+        }
+        Ok(count)
+    }
+
+    fn string(&mut self, len: usize) -> Result<()> {
+        self.string_bytes += len;
+        self.check(Limit::StringBytes, self.string_bytes)
+    }
+
+    fn operation(&mut self, operation: &Operation) -> Result<()> {
+        self.ops += 1;
+        self.check(Limit::Ops, self.ops)?;
+        match operation {
+            Operation::TemplateDef(_) => {
+                self.templates += 1;
+                self.check(Limit::Templates, self.templates)
+            }
+            Operation::Instantiate(_) => {
+                self.nodes += 1;
+                self.check(Limit::Nodes, self.nodes)
+            }
+            Operation::BatchStart(_) => {
+                self.nesting += 1;
+                self.check(Limit::Nesting, self.nesting)
+            }
+            Operation::BatchCommit(_) => {
+                self.nesting = self.nesting.saturating_sub(1);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Decode one item at `cursor` and advance past it
+fn decode_at<T: bincode::Decode<()>>(bytes: &[u8], cursor: &mut usize) -> Result<T> {
+    let (value, read) =
+        bincode::decode_from_slice(&bytes[*cursor..], item_config()).map_err(bincode_error)?;
+    *cursor += read;
+    Ok(value)
+}
+
+/// Check the macro count of a stream before its macros are decoded
+fn macro_count(count: u64) -> Result<u64> {
+    if count > MAX_MACROS as u64 {
+        return Err(DxBinaryError::InvalidMacro(format!("{count} macros, at most {MAX_MACROS}")));
+    }
+    Ok(count)
+}
+
+/// Decode a complete payload within `limits`, expanding macros if `flags` has any
+fn decode_payload(bytes: &[u8], flags: u8, limits: &DecodeLimits) -> Result<HtipPayload> {
+    let mut budget = Budget::new(limits);
+    let mut cursor = 0;
+
+    // Every item takes at least one byte, whatever the counts claim
+    let count = budget.count(Limit::Strings, decode_at(bytes, &mut cursor)?)? as usize;
+    let mut strings = Vec::with_capacity(count.min(bytes.len()));
+    for _ in 0..count {
+        let s: String = decode_at(bytes, &mut cursor)?;
+        budget.string(s.len())?;
+        strings.push(s);
+    }
+
+    let count = budget.count(Limit::Templates, decode_at(bytes, &mut cursor)?)? as usize;
+    let mut templates = Vec::with_capacity(count.min(bytes.len()));
+    for _ in 0..count {
+        templates.push(decode_at(bytes, &mut cursor)?);
+    }
+
+    let mut macros = Vec::new();
+    if flags & FLAG_MACROS != 0 {
+        for _ in 0..macro_count(decode_at(bytes, &mut cursor)?)? {
+            let op_macro: OpMacro = decode_at(bytes, &mut cursor)?;
+            op_macro.check()?;
+            macros.push(op_macro);
+        }
+    }
+
+    let count = budget.count(Limit::Ops, decode_at(bytes, &mut cursor)?)? as usize;
+    let mut operations = Vec::with_capacity(count.min(bytes.len()));
+    for _ in 0..count {
+        let start = operations.len();
+        if flags & FLAG_MACROS != 0 {
+            decode_at::<Step>(bytes, &mut cursor)?.expand(&macros, &mut operations)?;
+        } else {
+            operations.push(decode_at(bytes, &mut cursor)?);
+        }
+        for operation in &operations[start..] {
+            budget.operation(operation)?;
+        }
+    }
+
+    Ok(HtipPayload {
+        strings,
+        templates,
+        operations,
+    })
+}
+
 /// Streaming HTIP deserializer (client-side)
 #[derive(Debug)]
 pub struct HtipStream {
     payload: HtipPayload,
+    metadata: Option<StreamMetadata>,
     current_index: usize,
     verified: bool,
 }
 
 impl HtipStream {
     /// Create new stream from binary data
-    pub fn new(binary: &[u8], verifying_key: &VerifyingKey) -> Result<Self> {
+    ///
+    /// `keys` is a single `VerifyingKey` or a `KeyRing` (via `KeyRing::at`),
+    /// which picks the key named by the header's key ID. Signed metadata is
+    /// checked against `context` (clock, origin, runtime version).
+    pub fn new(binary: &[u8], keys: &impl KeySelector, context: &StreamContext) -> Result<Self> {
+        Self::with_limits(binary, keys, context, &DEFAULT_LIMITS)
+    }
+
+    /// Create new stream from binary data, decoded within `limits`
+    pub fn with_limits(
+        binary: &[u8],
+        keys: &impl KeySelector,
+        context: &StreamContext,
+        limits: &DecodeLimits,
+    ) -> Result<Self> {
         // Parse header (zero-copy)
         if binary.len() < HtipHeader::SIZE {
             return Err(DxBinaryError::IoError("Binary too short".to_string()));
         }
 
-        let header: &HtipHeader = bytemuck::from_bytes(&binary[..HtipHeader::SIZE]);
+        let header: HtipHeader = bytemuck::pod_read_unaligned(&binary[..HtipHeader::SIZE]);
 
         // Verify header
         header.verify()?;
 
+        // Select the signing key
+        let verifying_key = keys.select_key(header.key_id)?;
+
         // Extract signature
         let signature = Signature::from_bytes(&header.signature);
 
-        // Extract payload
-        let payload_bytes = &binary[HtipHeader::SIZE..];
+        // Extract signed bytes (metadata + payload)
+        let signed_bytes = &binary[HtipHeader::SIZE..];
 
-        // Verify signature
-        if !verify_payload(payload_bytes, &signature, verifying_key) {
+        // Verify signature (covers the dictionary version too)
+        let message = signed_message(header.dictionary, header.flags, signed_bytes);
+        if !verify_payload(&message, &signature, verifying_key) {
             return Err(DxBinaryError::SignatureVerificationFailed);
         }
+        let dictionary = find_dictionary(header.dictionary)?;
 
-        // Deserialize payload
-        let config = config::standard();
-        let (payload, _): (HtipPayload, usize) = bincode::decode_from_slice(payload_bytes, config)
-            .map_err(|e| DxBinaryError::BincodeError(e.to_string()))?;
+        // Check metadata before decoding the payload
+        let mut cursor = 0;
+        let metadata = if header.flags & FLAG_METADATA != 0 {
+            Some(decode_at::<StreamMetadata>(signed_bytes, &mut cursor)?)
+        } else {
+            None
+        };
+        context.check(metadata.as_ref())?;
+
+        // Deserialize payload, dictionary strings first
+        let mut payload = decode_payload(&signed_bytes[cursor..], header.flags, limits)?;
+        if let Some(dictionary) = dictionary {
+            let strings = dictionary.strings.iter().map(|s| s.to_string());
+            payload.strings.splice(..0, strings);
+        }
 
         Ok(Self {
             payload,
+            metadata,
             current_index: 0,
             verified: true,
         })
@@ -79,6 +286,16 @@ impl HtipStream {
         &self.payload.operations
     }
 
+    /// Decoded payload (strings, templates and operations)
+    pub fn payload(&self) -> &HtipPayload {
+        &self.payload
+    }
+
+    /// Signed metadata, if the stream has any
+    pub fn metadata(&self) -> Option<&StreamMetadata> {
+        self.metadata.as_ref()
+    }
+
     /// Get string by ID
     pub fn get_string(&self, id: u32) -> Option<&str> {
         self.payload.strings.get(id as usize).map(|s| s.as_str())
@@ -94,6 +311,13 @@ impl HtipStream {
         self.verified
     }
 
+    /// Check every template, string, instance and slot reference
+    ///
+    /// Empty if the stream is safe to apply (see `validate`).
+    pub fn validate(&self) -> Vec<Problem> {
+        validate_payload(&self.payload)
+    }
+
     /// Reset iterator
     pub fn reset(&mut self) {
         self.current_index = 0;
@@ -105,6 +329,306 @@ impl HtipStream {
     }
 }
 
+/// Next section of the payload to decode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Header,
+    Metadata,
+    StringCount,
+    Strings(u64),
+    TemplateCount,
+    Templates(u64),
+    MacroCount,
+    Macros(u64),
+    OperationCount,
+    Operations(u64),
+    Done,
+}
+
+/// Push-based HTIP decoder (client-side)
+///
+/// Feed bytes as they arrive from the network and pull operations as soon
+/// as they are complete. Strings stay in the decoder's buffer and are
+/// borrowed from it, never copied into owned `String`s.
+///
+/// Operations pulled before `finish()` succeeds are NOT verified yet:
+/// render them provisionally, or hold them back until `finish()`.
+pub struct HtipStreamDecoder<'k> {
+    keys: &'k dyn KeySelector,
+    /// Key named by the header's key ID, once the header arrived
+    verifying_key: Option<VerifyingKey>,
+    /// Every byte received so far (header + payload)
+    buffer: Vec<u8>,
+    /// Decode position in `buffer`
+    cursor: usize,
+    section: Section,
+    header: Option<HtipHeader>,
+    context: StreamContext,
+    metadata: Option<StreamMetadata>,
+    /// Dictionary named by the header (string IDs `0..len`)
+    dictionary: Option<&'static StringDictionary>,
+    /// Rest of the string table as (offset, len) ranges into `buffer`
+    strings: Vec<(usize, usize)>,
+    templates: Vec<TemplateDef>,
+    macros: Vec<OpMacro>,
+    /// Expanded operations of the last macro invocation, not yet pulled
+    pending: VecDeque<Operation>,
+    budget: Budget,
+    verified: bool,
+}
+
+impl<'k> HtipStreamDecoder<'k> {
+    /// Create a decoder for a stream signed by one of `keys`
+    ///
+    /// The key is picked by the header's key ID as soon as the header arrives.
+    /// Metadata is checked against `context` as soon as it arrives.
+    pub fn new(keys: &'k impl KeySelector, context: &StreamContext) -> Self {
+        Self::with_limits(keys, context, &DEFAULT_LIMITS)
+    }
+
+    /// Create a decoder that rejects streams exceeding `limits`
+    pub fn with_limits(
+        keys: &'k impl KeySelector,
+        context: &StreamContext,
+        limits: &DecodeLimits,
+    ) -> Self {
+        Self {
+            keys,
+            verifying_key: None,
+            buffer: Vec::new(),
+            cursor: 0,
+            section: Section::Header,
+            header: None,
+            context: context.clone(),
+            metadata: None,
+            dictionary: None,
+            strings: Vec::new(),
+            templates: Vec::new(),
+            macros: Vec::new(),
+            pending: VecDeque::new(),
+            budget: Budget::new(limits),
+            verified: false,
+        }
+    }
+
+    /// Append bytes received from the network
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Decode the next complete operation
+    ///
+    /// Returns `Ok(None)` when more bytes are needed or all operations were read.
+    pub fn next_operation(&mut self) -> Result<Option<Operation>> {
+        if let Some(operation) = self.pending.pop_front() {
+            return Ok(Some(operation));
+        }
+        loop {
+            match self.section {
+                Section::Header => {
+                    let Some(bytes) = self.buffer.get(..HtipHeader::SIZE) else {
+                        return Ok(None);
+                    };
+                    let header: HtipHeader = bytemuck::pod_read_unaligned(bytes);
+                    header.verify()?;
+                    self.verifying_key = Some(*self.keys.select_key(header.key_id)?);
+                    self.dictionary = find_dictionary(header.dictionary)?;
+                    self.header = Some(header);
+                    self.cursor = HtipHeader::SIZE;
+                    if header.flags & FLAG_METADATA != 0 {
+                        self.section = Section::Metadata;
+                    } else {
+                        self.context.check(None)?;
+                        self.section = Section::StringCount;
+                    }
+                }
+                Section::Metadata => {
+                    let Some(metadata) = self.decode::<StreamMetadata>()? else {
+                        return Ok(None);
+                    };
+                    // Unverified until `finish()`: enough to refuse, not to trust
+                    self.context.check(Some(&metadata))?;
+                    self.metadata = Some(metadata);
+                    self.section = Section::StringCount;
+                }
+                Section::StringCount => {
+                    let Some(count) = self.decode::<u64>()? else {
+                        return Ok(None);
+                    };
+                    let count = self.budget.count(Limit::Strings, count)?;
+                    self.section = Section::Strings(count);
+                }
+                Section::Strings(0) => self.section = Section::TemplateCount,
+                Section::Strings(remaining) => {
+                    let Some(range) = self.decode_str()? else {
+                        return Ok(None);
+                    };
+                    self.budget.string(range.1)?;
+                    self.strings.push(range);
+                    self.section = Section::Strings(remaining - 1);
+                }
+                Section::TemplateCount => {
+                    let Some(count) = self.decode::<u64>()? else {
+                        return Ok(None);
+                    };
+                    let count = self.budget.count(Limit::Templates, count)?;
+                    self.section = Section::Templates(count);
+                }
+                Section::Templates(0) => {
+                    self.section = match self.has_macros() {
+                        true => Section::MacroCount,
+                        false => Section::OperationCount,
+                    };
+                }
+                Section::Templates(remaining) => {
+                    let Some(template) = self.decode::<TemplateDef>()? else {
+                        return Ok(None);
+                    };
+                    self.templates.push(template);
+                    self.section = Section::Templates(remaining - 1);
+                }
+                Section::MacroCount => {
+                    let Some(count) = self.decode::<u64>()? else {
+                        return Ok(None);
+                    };
+                    self.section = Section::Macros(macro_count(count)?);
+                }
+                Section::Macros(0) => self.section = Section::OperationCount,
+                Section::Macros(remaining) => {
+                    let Some(op_macro) = self.decode::<OpMacro>()? else {
+                        return Ok(None);
+                    };
+                    op_macro.check()?;
+                    self.macros.push(op_macro);
+                    self.section = Section::Macros(remaining - 1);
+                }
+                Section::OperationCount => {
+                    let Some(count) = self.decode::<u64>()? else {
+                        return Ok(None);
+                    };
+                    let count = self.budget.count(Limit::Ops, count)?;
+                    self.section = Section::Operations(count);
+                }
+                Section::Operations(0) => self.section = Section::Done,
+                Section::Operations(remaining) => {
+                    if !self.has_macros() {
+                        let Some(operation) = self.decode::<Operation>()? else {
+                            return Ok(None);
+                        };
+                        self.budget.operation(&operation)?;
+                        self.section = Section::Operations(remaining - 1);
+                        return Ok(Some(operation));
+                    }
+
+                    let Some(step) = self.decode::<Step>()? else {
+                        return Ok(None);
+                    };
+                    self.section = Section::Operations(remaining - 1);
+                    step.expand(&self.macros, &mut self.pending)?;
+                    for operation in &self.pending {
+                        self.budget.operation(operation)?;
+                    }
+                    if let Some(operation) = self.pending.pop_front() {
+                        return Ok(Some(operation));
+                    }
+                }
+                Section::Done => return Ok(None),
+            }
+        }
+    }
+
+    /// Ops may invoke macros
+    fn has_macros(&self) -> bool {
+        self.header.is_some_and(|header| header.flags & FLAG_MACROS != 0)
+    }
+
+    /// Decode one value at the cursor, None if it isn't complete yet
+    fn decode<T: bincode::Decode<()>>(&mut self) -> Result<Option<T>> {
+        match bincode::decode_from_slice(&self.buffer[self.cursor..], item_config()) {
+            Ok((value, read)) => {
+                self.cursor += read;
+                Ok(Some(value))
+            }
+            Err(DecodeError::UnexpectedEnd { .. }) => Ok(None),
+            Err(e) => Err(bincode_error(e)),
+        }
+    }
+
+    /// Decode one string at the cursor as a range into the buffer
+    fn decode_str(&mut self) -> Result<Option<(usize, usize)>> {
+        let rest = &self.buffer[self.cursor..];
+        match bincode::borrow_decode_from_slice::<&str, _>(rest, item_config()) {
+            Ok((s, read)) => {
+                let range = (self.cursor + read - s.len(), s.len());
+                self.cursor += read;
+                Ok(Some(range))
+            }
+            Err(DecodeError::UnexpectedEnd { .. }) => Ok(None),
+            Err(e) => Err(bincode_error(e)),
+        }
+    }
+
+    /// Verify the signature over the complete payload
+    ///
+    /// Call once the network stream ended and `next_operation` returned None.
+    pub fn finish(&mut self) -> Result<()> {
+        if self.section != Section::Done {
+            return Err(DxBinaryError::IoError("Incomplete stream".to_string()));
+        }
+        let header = self.header.expect("header decoded before Done");
+        let verifying_key = self.verifying_key.expect("key selected with the header");
+
+        let signature = Signature::from_bytes(&header.signature);
+        let body = &self.buffer[HtipHeader::SIZE..];
+        let message = signed_message(header.dictionary, header.flags, body);
+        if !verify_payload(&message, &signature, &verifying_key) {
+            return Err(DxBinaryError::SignatureVerificationFailed);
+        }
+
+        self.verified = true;
+        Ok(())
+    }
+
+    /// Header, once its bytes have arrived
+    pub fn header(&self) -> Option<&HtipHeader> {
+        self.header.as_ref()
+    }
+
+    /// Signed metadata, once its bytes have arrived (verified by `finish()`)
+    pub fn metadata(&self) -> Option<&StreamMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Get string by ID (borrowed from the decoder's buffer)
+    pub fn get_string(&self, id: u32) -> Option<&str> {
+        let mut index = id as usize;
+        if let Some(dictionary) = self.dictionary {
+            if let Some(s) = dictionary.get(id) {
+                return Some(s);
+            }
+            index -= dictionary.len();
+        }
+        let &(offset, len) = self.strings.get(index)?;
+        // Validated as UTF-8 when decoded
+        std::str::from_utf8(&self.buffer[offset..offset + len]).ok()
+    }
+
+    /// Get template by ID
+    pub fn get_template(&self, id: u16) -> Option<&TemplateDef> {
+        self.templates.iter().find(|t| t.id == id)
+    }
+
+    /// All operations have been decoded
+    pub fn is_complete(&self) -> bool {
+        self.section == Section::Done
+    }
+
+    /// Is signature verified
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+}
+
 /// Batch processor for applying operations in chunks
 pub struct BatchProcessor {
     stream: HtipStream,
@@ -147,7 +671,8 @@ impl BatchProcessor {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::serializer::HtipWriter;
+    use crate::serializer::{encode_signed, HtipWriter, StreamOptions};
+    use crate::signature::KeyRing;
     use ed25519_dalek::SigningKey;
 
     #[test]
@@ -162,7 +687,7 @@ mod tests {
 
         // Parse binary
         let verifying_key = signing_key.verifying_key();
-        let mut stream = HtipStream::new(&binary, &verifying_key).unwrap();
+        let stream = HtipStream::new(&binary, &verifying_key, &StreamContext::new(0)).unwrap();
 
         assert!(stream.is_verified());
         assert_eq!(stream.remaining(), 2);
@@ -186,12 +711,73 @@ mod tests {
         let binary = writer.finish_and_sign(&signing_key).unwrap();
 
         let verifying_key = signing_key.verifying_key();
-        let stream = HtipStream::new(&binary, &verifying_key).unwrap();
+        let stream = HtipStream::new(&binary, &verifying_key, &StreamContext::new(0)).unwrap();
 
         assert_eq!(stream.get_string(id), Some("test string"));
         assert_eq!(stream.get_string(999), None);
     }
 
+    #[test]
+    fn test_dictionary_strings_stay_off_the_wire() {
+        let write = |dictionary| {
+            let mut writer = HtipWriter::with_dictionary(dictionary);
+            let id = writer.add_string("class");
+            writer.write_template(0, "<p></p>", vec![]);
+            (id, writer.finish_and_sign(&SigningKey::from_bytes(&[0u8; 32])).unwrap())
+        };
+        let verifying_key = SigningKey::from_bytes(&[0u8; 32]).verifying_key();
+        let context = StreamContext::new(0);
+
+        let (id, binary) = write(Some(dictionary::LATEST));
+        let (_, plain) = write(None);
+        assert_eq!(binary.len() + "class".len() + 1, plain.len());
+        assert!(!binary.windows(5).any(|w| w == b"class"));
+
+        let stream = HtipStream::new(&binary, &verifying_key, &context).unwrap();
+        assert_eq!(stream.get_string(id), Some("class"));
+        assert_eq!(stream.payload().strings.len(), dictionary::LATEST.len() + 1);
+
+        // The dictionary version is signed
+        let mut tampered = binary.clone();
+        let header = std::mem::offset_of!(HtipHeader, dictionary);
+        tampered[header..header + 2].copy_from_slice(&0u16.to_le_bytes());
+        assert!(matches!(
+            HtipStream::new(&tampered, &verifying_key, &context),
+            Err(DxBinaryError::SignatureVerificationFailed)
+        ));
+
+        // A dictionary this runtime doesn't ship
+        static UNKNOWN: StringDictionary = StringDictionary {
+            version: 999,
+            strings: &[],
+        };
+        let options = StreamOptions {
+            dictionary: Some(&UNKNOWN),
+            ..StreamOptions::default()
+        };
+        let payload = HtipPayload {
+            strings: vec![],
+            templates: vec![],
+            operations: vec![],
+        };
+        let signing_key = SigningKey::from_bytes(&[0u8; 32]);
+        let unknown = encode_signed(&payload, &options, &signing_key).unwrap();
+        assert!(matches!(
+            HtipStream::new(&unknown, &verifying_key, &context),
+            Err(DxBinaryError::UnknownDictionary(999))
+        ));
+
+        // The string table must start with the dictionary
+        let options = StreamOptions {
+            dictionary: Some(dictionary::LATEST),
+            ..StreamOptions::default()
+        };
+        assert!(matches!(
+            encode_signed(&payload, &options, &signing_key),
+            Err(DxBinaryError::DictionaryMismatch(1))
+        ));
+    }
+
     #[test]
     fn test_deserializer_invalid_signature() {
         let mut writer = HtipWriter::new();
@@ -204,11 +790,374 @@ mod tests {
         let wrong_key = SigningKey::from_bytes(&[1u8; 32]);
         let verifying_key = wrong_key.verifying_key();
 
-        let result = HtipStream::new(&binary, &verifying_key);
+        let result = HtipStream::new(&binary, &verifying_key, &StreamContext::new(0));
         assert!(result.is_err());
         assert!(matches!(result.unwrap_err(), DxBinaryError::SignatureVerificationFailed));
     }
 
+    #[test]
+    fn test_key_ring_selects_header_key() {
+        let old_key = SigningKey::from_bytes(&[1u8; 32]);
+        let new_key = SigningKey::from_bytes(&[2u8; 32]);
+
+        let mut ring = KeyRing::new();
+        ring.add_active(7, new_key.verifying_key(), 100, None);
+        ring.add_retiring(3, old_key.verifying_key(), 0, Some(200));
+
+        let sign = |key: &SigningKey, key_id: u16| {
+            let mut writer = HtipWriter::new();
+            writer.set_key_id(key_id);
+            writer.write_instantiate(1, 0, 0);
+            writer.finish_and_sign(key).unwrap()
+        };
+
+        // Cached stream from the old key still verifies during rotation
+        let old_stream = sign(&old_key, 3);
+        assert!(HtipStream::new(&old_stream, &ring.at(150), &StreamContext::new(0)).is_ok());
+        assert!(matches!(
+            HtipStream::new(&old_stream, &ring.at(200), &StreamContext::new(0)),
+            Err(DxBinaryError::KeyNotValid(3))
+        ));
+
+        let new_stream = sign(&new_key, 7);
+        assert!(HtipStream::new(&new_stream, &ring.at(150), &StreamContext::new(0)).is_ok());
+
+        // Key ID pointing at the wrong key fails verification
+        let mislabeled = sign(&new_key, 3);
+        assert!(matches!(
+            HtipStream::new(&mislabeled, &ring.at(150), &StreamContext::new(0)),
+            Err(DxBinaryError::SignatureVerificationFailed)
+        ));
+        assert!(matches!(
+            HtipStream::new(&sign(&new_key, 9), &ring.at(150), &StreamContext::new(0)),
+            Err(DxBinaryError::UnknownKeyId(9))
+        ));
+    }
+
+    #[test]
+    fn test_decoder_key_ring_selects_header_key() {
+        let old_key = SigningKey::from_bytes(&[1u8; 32]);
+        let new_key = SigningKey::from_bytes(&[2u8; 32]);
+
+        let mut ring = KeyRing::new();
+        ring.add_active(7, new_key.verifying_key(), 100, None);
+        ring.add_retiring(3, old_key.verifying_key(), 0, Some(200));
+
+        let sign = |key: &SigningKey, key_id: u16| {
+            let mut writer = HtipWriter::new();
+            writer.set_key_id(key_id);
+            writer.write_instantiate(1, 0, 0);
+            writer.finish_and_sign(key).unwrap()
+        };
+        let decode = |binary: &[u8], now: u64| {
+            let keys = ring.at(now);
+            let mut decoder = HtipStreamDecoder::new(&keys, &StreamContext::new(0));
+            decoder.feed(binary);
+            while decoder.next_operation()?.is_some() {}
+            decoder.finish()
+        };
+
+        // Stream signed by the retiring key still decodes during rotation
+        let old_stream = sign(&old_key, 3);
+        assert!(decode(&old_stream, 150).is_ok());
+        assert!(matches!(decode(&old_stream, 200), Err(DxBinaryError::KeyNotValid(3))));
+        assert!(decode(&sign(&new_key, 7), 150).is_ok());
+
+        assert!(matches!(
+            decode(&sign(&new_key, 3), 150),
+            Err(DxBinaryError::SignatureVerificationFailed)
+        ));
+        assert!(matches!(decode(&sign(&new_key, 9), 150), Err(DxBinaryError::UnknownKeyId(9))));
+    }
+
+    #[test]
+    fn test_stream_metadata() {
+        let key = SigningKey::from_bytes(&[5u8; 32]);
+        let verifying_key = key.verifying_key();
+        let sign = |metadata: Option<StreamMetadata>| {
+            let mut writer = HtipWriter::new();
+            writer.write_template(0, "<div></div>", vec![]);
+            writer.write_instantiate(1, 0, 0);
+            if let Some(metadata) = metadata {
+                writer.set_metadata(metadata);
+            }
+            writer.finish_and_sign(&key).unwrap()
+        };
+        let metadata = StreamMetadata {
+            issued_at: 1_000,
+            expires_at: Some(2_000),
+            min_runtime_version: 1,
+            origin: Some("https://example.com".to_string()),
+        };
+        let binary = sign(Some(metadata.clone()));
+        let open = |context: &StreamContext| HtipStream::new(&binary, &verifying_key, context);
+
+        let context = StreamContext::new(1_500).with_origin("https://example.com");
+        let stream = open(&context).unwrap();
+        assert_eq!(stream.metadata(), Some(&metadata));
+        assert_eq!(stream.remaining(), 2);
+
+        assert!(matches!(
+            open(&StreamContext::new(2_000)),
+            Err(DxBinaryError::StreamExpired {
+                expires_at: 2_000,
+                now: 2_000
+            })
+        ));
+        assert!(matches!(
+            open(&StreamContext::new(999)),
+            Err(DxBinaryError::StreamNotYetValid {
+                issued_at: 1_000,
+                ..
+            })
+        ));
+        assert!(matches!(
+            open(&StreamContext::new(1_500).with_min_issued_at(1_200)),
+            Err(DxBinaryError::StreamDowngraded {
+                issued_at: 1_000,
+                min_issued_at: 1_200
+            })
+        ));
+        assert!(matches!(
+            open(&StreamContext::new(1_500).with_origin("https://evil.example")),
+            Err(DxBinaryError::OriginMismatch { .. })
+        ));
+        let old_runtime = StreamContext {
+            runtime_version: 0,
+            ..StreamContext::new(1_500)
+        };
+        assert!(matches!(
+            open(&old_runtime),
+            Err(DxBinaryError::RuntimeTooOld {
+                required: 1,
+                runtime: 0
+            })
+        ));
+
+        // Metadata is signed: moving the expiry breaks the signature
+        let mut extended = binary.clone();
+        let expiry = HtipHeader::SIZE + 3; // issued_at (3-byte varint), then Some tag
+        assert_eq!(extended[expiry], 1);
+        extended[expiry + 2] = 0xFF;
+        assert!(matches!(
+            HtipStream::new(&extended, &verifying_key, &StreamContext::new(1_500)),
+            Err(DxBinaryError::SignatureVerificationFailed)
+        ));
+
+        // So are the flags: dropping FLAG_METADATA breaks the signature
+        let mut unflagged = binary.clone();
+        let flags = std::mem::offset_of!(HtipHeader, flags);
+        unflagged[flags] &= !FLAG_METADATA;
+        assert!(matches!(
+            HtipStream::new(&unflagged, &verifying_key, &StreamContext::new(1_500)),
+            Err(DxBinaryError::SignatureVerificationFailed)
+        ));
+
+        // Streams without metadata pass unless the client requires it
+        let bare = sign(None);
+        let strict = StreamContext::new(1_500).require_metadata();
+        assert!(HtipStream::new(&bare, &verifying_key, &StreamContext::new(1_500)).is_ok());
+        assert!(matches!(
+            HtipStream::new(&bare, &verifying_key, &strict),
+            Err(DxBinaryError::MissingMetadata)
+        ));
+
+        // The push decoder refuses before the first operation
+        let mut decoder = HtipStreamDecoder::new(&verifying_key, &StreamContext::new(3_000));
+        decoder.feed(&binary);
+        assert!(matches!(decoder.next_operation(), Err(DxBinaryError::StreamExpired { .. })));
+
+        let mut decoder = HtipStreamDecoder::new(&verifying_key, &context);
+        decoder.feed(&binary);
+        while decoder.next_operation().unwrap().is_some() {}
+        decoder.finish().unwrap();
+        assert_eq!(decoder.metadata(), Some(&metadata));
+    }
+
+    #[test]
+    fn test_decoder_byte_by_byte() {
+        let mut writer = HtipWriter::with_dictionary(Some(crate::dictionary::LATEST));
+        writer.write_template(0, "<div><span></span></div>", vec![]);
+        writer.write_instantiate(1, 0, 0);
+        writer.write_patch_text(1, 0, "Hello, stream");
+        writer.write_class_toggle(1, "active", true);
+
+        let signing_key = SigningKey::from_bytes(&[0u8; 32]);
+        let binary = writer.finish_and_sign(&signing_key).unwrap();
+        let verifying_key = signing_key.verifying_key();
+
+        let mut decoder = HtipStreamDecoder::new(&verifying_key, &StreamContext::new(0));
+        let mut ops = Vec::new();
+        let mut first_op_at = None;
+
+        for (i, byte) in binary.iter().enumerate() {
+            decoder.feed(std::slice::from_ref(byte));
+            while let Some(op) = decoder.next_operation().unwrap() {
+                first_op_at.get_or_insert(i);
+                ops.push(op);
+            }
+        }
+
+        // Operations came out before the download finished
+        assert!(first_op_at.unwrap() < binary.len() - 1);
+        assert!(decoder.is_complete());
+        assert!(!decoder.is_verified());
+        decoder.finish().unwrap();
+        assert!(decoder.is_verified());
+
+        let stream = HtipStream::new(&binary, &verifying_key, &StreamContext::new(0)).unwrap();
+        assert_eq!(format!("{:?}", ops), format!("{:?}", stream.operations()));
+        // Dictionary strings come first, then the stream's own
+        let dictionary = crate::dictionary::LATEST;
+        let id = dictionary.len() as u32 + 1;
+        assert_eq!(decoder.get_string(0), Some(dictionary.strings[0]));
+        assert_eq!(decoder.get_string(id), stream.get_string(id));
+        assert_eq!(decoder.get_string(id), Some("Hello, stream"));
+        assert!(decoder.get_template(0).is_some());
+    }
+
+    #[test]
+    fn test_decoder_rejects_tampered_stream() {
+        let mut writer = HtipWriter::new();
+        writer.write_patch_text(1, 0, "original");
+
+        let signing_key = SigningKey::from_bytes(&[0u8; 32]);
+        let mut binary = writer.finish_and_sign(&signing_key).unwrap();
+        let pos = binary.windows(8).position(|w| w == b"original").unwrap();
+        binary[pos] = b'O';
+
+        let verifying_key = signing_key.verifying_key();
+        let mut decoder = HtipStreamDecoder::new(&verifying_key, &StreamContext::new(0));
+        decoder.feed(&binary[..HtipHeader::SIZE + 4]);
+        assert!(decoder.next_operation().unwrap().is_none());
+        assert!(decoder.finish().is_err());
+
+        decoder.feed(&binary[HtipHeader::SIZE + 4..]);
+        while decoder.next_operation().unwrap().is_some() {}
+        assert!(matches!(decoder.finish(), Err(DxBinaryError::SignatureVerificationFailed)));
+
+        let mut bad_magic = HtipStreamDecoder::new(&verifying_key, &StreamContext::new(0));
+        bad_magic.feed(&[0u8; HtipHeader::SIZE]);
+        assert!(matches!(bad_magic.next_operation(), Err(DxBinaryError::InvalidMagic)));
+    }
+
+    #[test]
+    fn test_decode_limits() {
+        let signing_key = SigningKey::from_bytes(&[0u8; 32]);
+        let verifying_key = signing_key.verifying_key();
+
+        let mut writer = HtipWriter::new();
+        writer.write_template(0, "<p></p>", vec![]);
+        writer.write_batch_start(1);
+        writer.write_batch_start(2);
+        writer.write_instantiate(1, 0, 0);
+        writer.write_instantiate(2, 0, 0);
+        writer.write_batch_commit(2);
+        writer.write_batch_commit(1);
+        let binary = writer.finish_and_sign(&signing_key).unwrap();
+        assert!(HtipStream::new(&binary, &verifying_key, &StreamContext::new(0)).is_ok());
+
+        let exceeded = |limits: DecodeLimits| match HtipStream::with_limits(
+            &binary,
+            &verifying_key,
+            &StreamContext::new(0),
+            &limits,
+        ) {
+            Err(DxBinaryError::LimitExceeded { limit, .. }) => Some(limit),
+            _ => None,
+        };
+        let limits = DEFAULT_LIMITS;
+        assert_eq!(
+            exceeded(DecodeLimits {
+                max_nesting: 1,
+                ..limits
+            }),
+            Some(Limit::Nesting)
+        );
+        assert_eq!(
+            exceeded(DecodeLimits {
+                max_nodes: 1,
+                ..limits
+            }),
+            Some(Limit::Nodes)
+        );
+        assert_eq!(
+            exceeded(DecodeLimits {
+                max_ops: 6,
+                ..limits
+            }),
+            Some(Limit::Ops)
+        );
+        assert_eq!(
+            exceeded(DecodeLimits {
+                max_string_bytes: 6,
+                ..limits
+            }),
+            Some(Limit::StringBytes)
+        );
+        assert_eq!(
+            exceeded(DecodeLimits {
+                max_templates: 0,
+                ..limits
+            }),
+            Some(Limit::Templates)
+        );
+
+        // The push decoder stops at the same point
+        let mut decoder = HtipStreamDecoder::with_limits(
+            &verifying_key,
+            &StreamContext::new(0),
+            &DecodeLimits {
+                max_nodes: 1,
+                ..limits
+            },
+        );
+        decoder.feed(&binary);
+        let result = loop {
+            match decoder.next_operation() {
+                Ok(Some(_)) => continue,
+                other => break other,
+            }
+        };
+        assert!(matches!(
+            result,
+            Err(DxBinaryError::LimitExceeded {
+                limit: Limit::Nodes,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_claimed_lengths_are_not_allocated() {
+        // 2^32 - 1 strings
+        let count = [0xFC, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert!(matches!(
+            decode_payload(&count, 0, &DEFAULT_LIMITS),
+            Err(DxBinaryError::LimitExceeded {
+                limit: Limit::Strings,
+                ..
+            })
+        ));
+
+        // One string claiming 1 TB
+        let mut huge = vec![1, 0xFD];
+        huge.extend_from_slice(&(1u64 << 40).to_le_bytes());
+        assert!(matches!(
+            decode_payload(&huge, 0, &DEFAULT_LIMITS),
+            Err(DxBinaryError::BincodeError(_))
+        ));
+
+        let verifying_key = SigningKey::from_bytes(&[0u8; 32]).verifying_key();
+        let mut decoder = HtipStreamDecoder::new(&verifying_key, &StreamContext::new(0));
+        let mut writer = HtipWriter::new();
+        writer.write_instantiate(1, 0, 0);
+        let binary = writer.finish_and_sign(&SigningKey::from_bytes(&[0u8; 32])).unwrap();
+        decoder.feed(&binary[..HtipHeader::SIZE]);
+        decoder.feed(&huge);
+        assert!(decoder.next_operation().is_err());
+    }
+
     #[test]
     fn test_batch_processor() {
         let mut writer = HtipWriter::new();
@@ -220,7 +1169,7 @@ mod tests {
         let binary = writer.finish_and_sign(&signing_key).unwrap();
 
         let verifying_key = signing_key.verifying_key();
-        let stream = HtipStream::new(&binary, &verifying_key).unwrap();
+        let stream = HtipStream::new(&binary, &verifying_key, &StreamContext::new(0)).unwrap();
 
         let mut processor = BatchProcessor::new(stream, 3);
 
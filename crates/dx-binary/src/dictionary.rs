@@ -0,0 +1,311 @@
+//! # Static String Dictionary
+//!
+//! Strings every app sends (tag, attribute, event and class names) are
+//! shipped once with the runtime instead of in every stream.
+//!
+//! A dictionary is an immutable, versioned list. A writer using one
+//! pre-seeds its `StringTable` with it, so dictionary strings get IDs
+//! `0..len` and are left out of the wire string table. The header records
+//! the version (signed with the body), and the client puts the same
+//! strings back in front of the decoded table.
+//!
+//! Dictionaries are trained from a template corpus by `DictionaryTrainer`
+//! (see the `train_dictionary` binary). A new corpus means a new version:
+//! never edit a released one, clients may still hold streams using it.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::{opcodes::*, protocol::HtipPayload};
+
+/// Entries per dictionary: IDs below 251 are one byte (bincode varint), and
+/// the first 123 literals after the dictionary still are
+pub const MAX_DICTIONARY_ENTRIES: usize = 128;
+
+/// A versioned list of strings known to both server and client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StringDictionary {
+    /// Header value naming this dictionary (0 = none)
+    pub version: u16,
+    pub strings: &'static [&'static str],
+}
+
+impl StringDictionary {
+    /// Get string by ID
+    pub fn get(&self, id: u32) -> Option<&'static str> {
+        self.strings.get(id as usize).copied()
+    }
+
+    /// Number of strings
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    /// Is empty
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+// Generated by `train_dictionary --version 1 examples/` (do not edit)
+include!("dictionary_v1.rs");
+
+/// Every dictionary this runtime knows, by version
+pub const DICTIONARIES: &[&StringDictionary] = &[&DICTIONARY_V1];
+
+/// Dictionary new streams are written with
+pub const LATEST: &StringDictionary = &DICTIONARY_V1;
+
+/// Look up a dictionary by header version
+pub fn find(version: u16) -> Option<&'static StringDictionary> {
+    DICTIONARIES.iter().copied().find(|d| d.version == version)
+}
+
+// ============================================================================
+// TRAINING
+// ============================================================================
+
+/// Counts strings over a corpus and picks the ones worth shipping
+///
+/// A string's score is the bytes it would save: occurrences times its
+/// encoded length. Strings seen only once are never picked.
+#[derive(Debug, Clone, Default)]
+pub struct DictionaryTrainer {
+    counts: HashMap<String, u64>,
+}
+
+impl DictionaryTrainer {
+    /// Create empty trainer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a string occurrence
+    pub fn add(&mut self, s: &str) {
+        if !s.is_empty() {
+            *self.counts.entry(s.to_string()).or_default() += 1;
+        }
+    }
+
+    /// Count the names a stream built from this markup would carry
+    ///
+    /// Takes HTML or JSX: lowercase tag names, attribute names (`className`
+    /// and `htmlFor` as `class` and `for`, `onClick` or `onclick` as the
+    /// event `click`), class tokens, and short single-word attribute values.
+    pub fn add_markup(&mut self, markup: &str) {
+        let mut rest = markup;
+        while let Some(start) = rest.find('<') {
+            rest = &rest[start + 1..];
+            let name_end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+                .unwrap_or(rest.len());
+            let tag = &rest[..name_end];
+
+            // Components (`<Header />`) and closing tags carry no strings
+            if !tag.starts_with(|c: char| c.is_ascii_lowercase()) {
+                continue;
+            }
+            self.add(tag);
+            rest = self.add_attributes(&rest[name_end..]);
+        }
+    }
+
+    /// Count a tag's attributes, returning the markup after its `>`
+    fn add_attributes<'a>(&mut self, mut attrs: &'a str) -> &'a str {
+        loop {
+            attrs = attrs.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+            let name_end = attrs
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == ':'))
+                .unwrap_or(attrs.len());
+            if name_end == 0 {
+                match attrs.chars().next() {
+                    None => return attrs,
+                    Some('>') => return &attrs[1..],
+                    // `{...spread}` or stray characters
+                    Some(c) => {
+                        attrs = &attrs[c.len_utf8()..];
+                        continue;
+                    }
+                }
+            }
+            let name = &attrs[..name_end];
+            attrs = &attrs[name_end..];
+
+            let mut value = None;
+            if let Some(after) = attrs.strip_prefix('=') {
+                let (v, rest) = match after.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let end = after[1..].find(quote).map_or(after.len(), |i| i + 1);
+                        (Some(&after[1..end]), after.get(end + 1..).unwrap_or(""))
+                    }
+                    // `{expression}`: skip to the matching brace
+                    Some('{') => {
+                        let mut depth = 0;
+                        let end = after
+                            .char_indices()
+                            .find(|&(_, c)| {
+                                match c {
+                                    '{' => depth += 1,
+                                    '}' => depth -= 1,
+                                    _ => {}
+                                }
+                                depth == 0
+                            })
+                            .map_or(after.len(), |(i, _)| i + 1);
+                        (None, &after[end..])
+                    }
+                    _ => {
+                        let end = after
+                            .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                            .unwrap_or(after.len());
+                        (Some(&after[..end]), &after[end..])
+                    }
+                };
+                value = v;
+                attrs = rest;
+            }
+
+            let event = name.strip_prefix("on").filter(|e| e.len() > 1);
+            match (name, event) {
+                (_, Some(event)) => self.add(&event.to_ascii_lowercase()),
+                ("className" | "class", _) => {
+                    self.add("class");
+                    // Interpolations (`${active}`) are not class names
+                    let classes = value.unwrap_or("").split_ascii_whitespace();
+                    for class in classes.filter(|c| !c.contains(['$', '{', '}'])) {
+                        self.add(class);
+                    }
+                }
+                ("htmlFor", _) => self.add("for"),
+                _ => {
+                    self.add(&name.to_ascii_lowercase());
+                    let word = value.filter(|v| {
+                        v.len() <= 24 && v.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                    });
+                    if let Some(word) = word {
+                        self.add(word);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Count every string reference of a built stream
+    pub fn add_payload(&mut self, payload: &HtipPayload) {
+        let mut add = |id: u32| {
+            if let Some(s) = payload.strings.get(id as usize) {
+                *self.counts.entry(s.clone()).or_default() += 1;
+            }
+        };
+
+        let templates =
+            payload
+                .templates
+                .iter()
+                .chain(payload.operations.iter().filter_map(|op| match op {
+                    Operation::TemplateDef(template) => Some(template),
+                    _ => None,
+                }));
+        for template in templates {
+            for binding in &template.bindings {
+                match binding.binding_type {
+                    BindingType::Attribute { attr_name_id: id }
+                    | BindingType::Property { prop_name_id: id }
+                    | BindingType::Event { event_type_id: id } => add(id),
+                    BindingType::Text | BindingType::Class => {}
+                }
+            }
+        }
+
+        for op in &payload.operations {
+            match op {
+                Operation::PatchText(op) => add(op.string_id),
+                Operation::PatchAttr(op) => {
+                    add(op.attr_name_id);
+                    add(op.value_id);
+                }
+                Operation::PatchClassToggle(op) => add(op.class_name_id),
+                Operation::AttachEvent(op) => add(op.event_type_id),
+                Operation::SetProperty(op) => {
+                    add(op.prop_name_id);
+                    if let PropertyValue::String(id) = op.value {
+                        add(id);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Best `max_entries` strings, highest score first (ties by string)
+    pub fn build(&self, max_entries: usize) -> Vec<String> {
+        let mut ranked: Vec<(u64, &String)> = self
+            .counts
+            .iter()
+            .filter(|&(_, &count)| count > 1)
+            .map(|(s, &count)| (count * (s.len() as u64 + 1), s))
+            .collect();
+        ranked.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+
+        ranked
+            .into_iter()
+            .take(max_entries.min(MAX_DICTIONARY_ENTRIES))
+            .map(|(_, s)| s.clone())
+            .collect()
+    }
+}
+
+/// Rust source for a trained dictionary (the `dictionary_vN.rs` files)
+pub fn to_rust_source(version: u16, strings: &[String]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "/// Dictionary version {version} ({} strings)", strings.len());
+    let _ =
+        writeln!(out, "pub const DICTIONARY_V{version}: StringDictionary = StringDictionary {{");
+    let _ = writeln!(out, "    version: {version},");
+    let _ = writeln!(out, "    strings: &[");
+    for s in strings {
+        let _ = writeln!(out, "        {s:?},");
+    }
+    let _ = writeln!(out, "    ],");
+    let _ = writeln!(out, "}};");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_dictionaries_are_well_formed() {
+        for dictionary in DICTIONARIES {
+            assert_ne!(dictionary.version, 0);
+            assert!(dictionary.len() <= MAX_DICTIONARY_ENTRIES);
+            let unique: HashSet<_> = dictionary.strings.iter().collect();
+            assert_eq!(unique.len(), dictionary.len(), "v{}", dictionary.version);
+            assert_eq!(find(dictionary.version), Some(*dictionary));
+        }
+        assert_eq!(find(0), None);
+        assert_eq!(LATEST.get(0), LATEST.strings.first().copied());
+    }
+
+    #[test]
+    fn test_trainer_ranks_by_saved_bytes() {
+        let mut trainer = DictionaryTrainer::new();
+        trainer.add_markup(
+            r#"<div className="card shadow"><button onClick={() => go({ a: 1 })} type="button">Go</button></div>
+               <div class="card"><input type=text disabled><Header /></div>"#,
+        );
+
+        // Seen twice: "button" (tag, value), "type", "class", "card", "div"
+        assert_eq!(trainer.build(10), ["button", "class", "card", "type", "div"]);
+        assert!(trainer.counts.contains_key("click"));
+        assert!(trainer.counts.contains_key("text"));
+        assert!(!trainer.counts.contains_key("Header"));
+        assert!(!trainer.counts.contains_key("onClick"));
+
+        let source = to_rust_source(9, &trainer.build(2));
+        assert!(source.contains("pub const DICTIONARY_V9: StringDictionary"));
+        assert!(source.contains("        \"button\",\n        \"class\",\n    ],"));
+    }
+}
@@ -0,0 +1,57 @@
+/// Dictionary version 1 (51 strings)
+pub const DICTIONARY_V1: StringDictionary = StringDictionary {
+    version: 1,
+    strings: &[
+        "class",
+        "div",
+        "metric-label",
+        "metric-value",
+        "metric",
+        "id",
+        "button",
+        "style",
+        "span",
+        "badge",
+        "click",
+        "li",
+        "strong",
+        "card",
+        "meta",
+        "stat-box",
+        "viewport",
+        "charset",
+        "content",
+        "metrics",
+        "container",
+        "module",
+        "script",
+        "subtitle",
+        "console-line",
+        "UTF-8",
+        "console",
+        "h2",
+        "label",
+        "success",
+        "success-msg",
+        "title",
+        "value",
+        "body",
+        "head",
+        "html",
+        "lang",
+        "name",
+        "type",
+        "p",
+        "warning",
+        "h1",
+        "info",
+        "unit",
+        "status",
+        "en",
+        "stats",
+        "ul",
+        "app",
+        "dim",
+        "log",
+    ],
+};
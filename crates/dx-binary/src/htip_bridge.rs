@@ -3,35 +3,48 @@
 //! Connects dx-binary operations to dx-dom rendering.
 //! This is the critical path: Binary → WASM → Browser.
 
-#[cfg(target_arch = "wasm32")]
 use crate::deserializer::HtipStream;
-#[cfg(target_arch = "wasm32")]
 use crate::opcodes::{Operation, PropertyValue};
-#[cfg(target_arch = "wasm32")]
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 #[cfg(target_arch = "wasm32")]
 use web_sys::{Document, Element, HtmlElement, HtmlTemplateElement, Node, Text};
 
+/// Cached template (a unit mock off the browser)
+#[cfg(target_arch = "wasm32")]
+type Template = HtmlTemplateElement;
+#[cfg(not(target_arch = "wasm32"))]
+type Template = ();
+
+/// Live instance, also the type of the render root
+#[cfg(target_arch = "wasm32")]
+type Instance = HtmlElement;
+#[cfg(not(target_arch = "wasm32"))]
+type Instance = ();
+
 /// The HTIP Application Engine
 ///
 /// Manages template cache and live instances
-#[cfg(target_arch = "wasm32")]
 pub struct HtipEngine {
     /// Template cache: template_id -> HtmlTemplateElement
-    templates: HashMap<u16, HtmlTemplateElement>,
+    templates: HashMap<u16, Template>,
     /// Instance cache: instance_id -> HtmlElement
-    instances: HashMap<u32, HtmlElement>,
+    instances: HashMap<u32, Instance>,
     /// String table from HTIP payload
     strings: Vec<String>,
     /// Document reference
+    #[cfg(target_arch = "wasm32")]
     document: Document,
+    /// Open batch IDs, innermost last
+    open_batches: Vec<u32>,
+    /// Ops of the open batches, applied when the outermost one commits
+    staged: Vec<Operation>,
 }
 
-#[cfg(target_arch = "wasm32")]
 impl HtipEngine {
+    #[cfg(target_arch = "wasm32")]
     pub fn new() -> Result<Self, JsValue> {
         let window = web_sys::window().ok_or("No window")?;
         let document = window.document().ok_or("No document")?;
@@ -41,6 +54,8 @@ impl HtipEngine {
             instances: HashMap::new(),
             strings: Vec::new(),
             document,
+            open_batches: Vec::new(),
+            staged: Vec::new(),
         })
     }
 
@@ -50,15 +65,13 @@ impl HtipEngine {
             templates: HashMap::new(),
             instances: HashMap::new(),
             strings: Vec::new(),
+            open_batches: Vec::new(),
+            staged: Vec::new(),
         })
     }
 
     /// Process an HTIP stream and apply all operations to the DOM
-    pub fn process_stream(
-        &mut self,
-        stream: &HtipStream,
-        root: &HtmlElement,
-    ) -> Result<(), String> {
+    pub fn process_stream(&mut self, stream: &HtipStream, root: &Instance) -> Result<(), String> {
         // Load string table
         self.strings.clear();
         for i in 0.. {
@@ -68,16 +81,184 @@ impl HtipEngine {
             }
         }
 
+        // Refuse streams with dangling references before touching the DOM
+        if let Some(problem) = stream.validate().first() {
+            return Err(format!("Invalid HTIP stream: {}", problem));
+        }
+
         // Process each operation
         for op in stream.operations() {
             self.apply_operation(op, root)?;
         }
 
+        // A batch left open at the end of the stream is dropped
+        if let Some(&batch_id) = self.open_batches.first() {
+            self.abort_batch();
+            return Err(format!("Batch {} never committed", batch_id));
+        }
+
         Ok(())
     }
 
     /// Apply a single HTIP operation
-    fn apply_operation(&mut self, op: &Operation, root: &HtmlElement) -> Result<(), String> {
+    ///
+    /// Ops between `BatchStart` and `BatchCommit` are staged, checked as a
+    /// whole when the outermost batch commits and only then applied: a batch
+    /// failing the check never touches the DOM. If the DOM itself fails
+    /// partway through, the instances and templates the batch created are
+    /// rolled back; its patches, moves and removals of existing nodes stay.
+    fn apply_operation(&mut self, op: &Operation, root: &Instance) -> Result<(), String> {
+        match op {
+            Operation::BatchStart(batch) => {
+                if self.open_batches.contains(&batch.batch_id) {
+                    self.abort_batch();
+                    return Err(format!("Batch {} is already open", batch.batch_id));
+                }
+                self.open_batches.push(batch.batch_id);
+                Ok(())
+            }
+            Operation::BatchCommit(batch) => {
+                match self.open_batches.last() {
+                    Some(&open) if open == batch.batch_id => {}
+                    Some(&open) => {
+                        self.abort_batch();
+                        return Err(format!(
+                            "Commit of batch {} while batch {} is open",
+                            batch.batch_id, open
+                        ));
+                    }
+                    None => {
+                        return Err(format!(
+                            "Commit of batch {} with no open batch",
+                            batch.batch_id
+                        ))
+                    }
+                }
+
+                self.open_batches.pop();
+                if !self.open_batches.is_empty() {
+                    return Ok(()); // Nested: wait for the outermost commit
+                }
+
+                let staged = std::mem::take(&mut self.staged);
+                self.check_batch(&staged)?;
+                self.execute_batch(&staged, root)
+            }
+            _ if !self.open_batches.is_empty() => {
+                self.staged.push(op.clone());
+                Ok(())
+            }
+            _ => self.execute(op, root),
+        }
+    }
+
+    /// Drop all open batches and their staged ops
+    fn abort_batch(&mut self) {
+        self.open_batches.clear();
+        self.staged.clear();
+    }
+
+    /// Apply a checked batch, rolling back what it created if an op fails
+    fn execute_batch(&mut self, ops: &[Operation], root: &Instance) -> Result<(), String> {
+        let mut undo = BatchUndo::default();
+        for op in ops {
+            undo.record(self, op);
+            if let Err(e) = self.execute(op, root) {
+                self.rollback(undo);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget (and detach) the instances a batch created and restore the
+    /// templates it replaced, newest first
+    fn rollback(&mut self, undo: BatchUndo) {
+        for (id, previous) in undo.instances.into_iter().rev() {
+            let created = match previous {
+                Some(previous) => self.instances.insert(id, previous),
+                None => self.instances.remove(&id),
+            };
+            if let Some(created) = created {
+                detach(&created);
+            }
+        }
+        for (id, previous) in undo.templates.into_iter().rev() {
+            match previous {
+                Some(previous) => self.templates.insert(id, previous),
+                None => self.templates.remove(&id),
+            };
+        }
+    }
+
+    /// Check that none of a batch's ops would fail against the current state
+    ///
+    /// Mirrors the failure cases of `execute`, tracking the templates and
+    /// instances the batch itself creates and removes.
+    fn check_batch(&self, ops: &[Operation]) -> Result<(), String> {
+        let mut templates: HashSet<u16> = self.templates.keys().copied().collect();
+        let mut instances: HashSet<u32> = self.instances.keys().copied().collect();
+        let instance = |instances: &HashSet<u32>, id: u32| {
+            if instances.contains(&id) {
+                Ok(())
+            } else {
+                Err(format!("Instance {} not found", id))
+            }
+        };
+
+        for op in ops {
+            match op {
+                Operation::TemplateDef(def) => {
+                    self.get_string(def.html_string_id)?;
+                    templates.insert(def.id);
+                }
+                Operation::Instantiate(inst) => {
+                    if !templates.contains(&inst.template_id) {
+                        return Err(format!("Template {} not found", inst.template_id));
+                    }
+                    if inst.parent_id != 0 {
+                        instance(&instances, inst.parent_id)?;
+                    }
+                    instances.insert(inst.instance_id);
+                }
+                Operation::PatchText(patch) => {
+                    instance(&instances, patch.instance_id)?;
+                    self.get_string(patch.string_id)?;
+                }
+                Operation::PatchAttr(patch) => {
+                    instance(&instances, patch.instance_id)?;
+                    self.get_string(patch.attr_name_id)?;
+                    self.get_string(patch.value_id)?;
+                }
+                Operation::PatchClassToggle(toggle) => {
+                    instance(&instances, toggle.instance_id)?;
+                    self.get_string(toggle.class_name_id)?;
+                }
+                Operation::SetProperty(prop) => {
+                    instance(&instances, prop.instance_id)?;
+                    self.get_string(prop.prop_name_id)?;
+                    if let PropertyValue::String(id) = prop.value {
+                        self.get_string(id)?;
+                    }
+                }
+                Operation::AppendChild(append) => {
+                    instance(&instances, append.parent_id)?;
+                    instance(&instances, append.child_id)?;
+                }
+                Operation::RemoveNode(remove) => {
+                    instances.remove(&remove.instance_id);
+                }
+                Operation::AttachEvent(_)
+                | Operation::BatchStart(_)
+                | Operation::BatchCommit(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply an operation to the DOM right away
+    fn execute(&mut self, op: &Operation, root: &Instance) -> Result<(), String> {
         match op {
             Operation::TemplateDef(def) => {
                 self.register_template(def.id, def.html_string_id)?;
@@ -104,20 +285,18 @@ impl HtipEngine {
             Operation::PatchClassToggle(toggle) => {
                 self.toggle_class(toggle.instance_id, toggle.class_name_id, toggle.enabled)?;
             }
-            Operation::AttachEvent(event) => {
+            Operation::AttachEvent(_event) => {
                 // Event handling requires JS callbacks - skip for now
+                #[cfg(target_arch = "wasm32")]
                 web_sys::console::warn_1(
-                    &format!("AttachEvent not yet implemented: {:?}", event).into(),
+                    &format!("AttachEvent not yet implemented: {:?}", _event).into(),
                 );
             }
             Operation::RemoveNode(remove) => {
                 self.remove_node(remove.instance_id)?;
             }
-            Operation::BatchStart(_) => {
-                // Batching is transparent - no action needed
-            }
-            Operation::BatchCommit(_) => {
-                // Batching is transparent - no action needed
+            Operation::BatchStart(_) | Operation::BatchCommit(_) => {
+                // Handled by apply_operation
             }
             Operation::SetProperty(prop) => {
                 self.set_property(prop.instance_id, prop.prop_name_id, &prop.value)?;
@@ -216,7 +395,7 @@ impl HtipEngine {
         instance_id: u32,
         _template_id: u16,
         _parent_id: u32,
-        _root: &(),
+        _root: &Instance,
     ) -> Result<(), String> {
         self.instances.insert(instance_id, ()); // Mock
         Ok(())
@@ -408,6 +587,40 @@ impl HtipEngine {
     }
 }
 
+/// What a batch created so far, with the entries it replaced
+#[derive(Default)]
+struct BatchUndo {
+    instances: Vec<(u32, Option<Instance>)>,
+    templates: Vec<(u16, Option<Template>)>,
+}
+
+impl BatchUndo {
+    /// Note what `op` is about to create
+    fn record(&mut self, engine: &HtipEngine, op: &Operation) {
+        match op {
+            Operation::TemplateDef(def) => {
+                self.templates.push((def.id, engine.templates.get(&def.id).cloned()));
+            }
+            Operation::Instantiate(inst) => {
+                let previous = engine.instances.get(&inst.instance_id).cloned();
+                self.instances.push((inst.instance_id, previous));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Helper: Take an instance out of the DOM
+#[cfg(target_arch = "wasm32")]
+fn detach(instance: &Instance) {
+    if let Some(parent) = instance.parent_node() {
+        let _ = parent.remove_child(instance);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn detach(_instance: &Instance) {} // Mock
+
 /// Helper: Find text node after slot marker comment
 #[cfg(target_arch = "wasm32")]
 fn find_slot_text_node(element: &Element, slot_marker: &str) -> Option<Text> {
@@ -447,9 +660,54 @@ pub fn init_htip_engine() {
 mod tests {
     use super::*;
 
+    use crate::opcodes::{Instantiate, TemplateDef};
+
     #[test]
     fn test_engine_creation() {
         let engine = HtipEngine::new();
         assert!(engine.is_ok());
     }
+
+    #[test]
+    fn test_rollback_restores_created_entries() {
+        let mut engine = HtipEngine::new().unwrap();
+        engine.templates.insert(0, ());
+        engine.instances.insert(1, ());
+
+        let template = |id| {
+            Operation::TemplateDef(TemplateDef {
+                id,
+                html_string_id: 0,
+                bindings: vec![],
+            })
+        };
+        let instantiate = |instance_id| {
+            Operation::Instantiate(Instantiate {
+                instance_id,
+                template_id: 0,
+                parent_id: 0,
+            })
+        };
+        let ops = [
+            template(0),
+            template(5),
+            instantiate(2),
+            instantiate(2),
+            instantiate(1),
+        ];
+
+        let mut undo = BatchUndo::default();
+        for op in &ops {
+            undo.record(&engine, op);
+            engine.execute(op, &()).unwrap();
+        }
+        assert_eq!(engine.templates.len(), 2);
+        assert_eq!(engine.instances.len(), 2);
+
+        engine.rollback(undo);
+        let mut templates: Vec<u16> = engine.templates.keys().copied().collect();
+        templates.sort();
+        assert_eq!(templates, [0]);
+        assert_eq!(engine.instances.keys().copied().collect::<Vec<_>>(), [1]);
+    }
 }
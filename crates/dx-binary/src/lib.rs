@@ -44,17 +44,29 @@
 //! ├─────────────────────────────────────────┤
 //! │  - Magic: b"DXB1" (4 bytes)             │
 //! │  - Version: 1 (1 byte)                  │
+//! │  - Flags: u8 (0: metadata, 1: macros)   │
+//! │  - Dictionary: u16 (0 = none, signed)   │
 //! │  - Signature: Ed25519 (64 bytes)        │
 //! │  - Template Count: u16                  │
+//! │  - Key ID: u16 (signing key)            │
 //! │  - String Count: u32                    │
 //! │  - Total Size: u32                      │
 //! ├─────────────────────────────────────────┤
+//! │  METADATA (optional, bincode)           │
+//! │  - Issued/expires at, min runtime,      │
+//! │    origin (signed with the payload)     │
+//! ├─────────────────────────────────────────┤
 //! │  STRING TABLE (variable)                │
 //! │  - u32 length + UTF-8 bytes (per string)│
+//! │  - IDs start after the dictionary's     │
+//! │    (if the writer was given one)        │
 //! ├─────────────────────────────────────────┤
 //! │  TEMPLATE DICTIONARY (variable)         │
 //! │  - Template definitions (bincode)       │
 //! ├─────────────────────────────────────────┤
+//! │  OPCODE MACROS (optional, bincode)      │
+//! │  - Repeated op shapes (see `macros`)    │
+//! ├─────────────────────────────────────────┤
 //! │  OPCODE STREAM (variable)               │
 //! │  - u8 opcode + payload (bincode)        │
 //! └─────────────────────────────────────────┘
@@ -87,31 +99,42 @@
 //!
 //! ```rust,ignore
 //! use dx_binary::deserializer::HtipStream;
+//! use dx_binary::signature::StreamContext;
 //! use ed25519_dalek::VerifyingKey;
 //!
 //! let verifying_key = VerifyingKey::from_bytes(&[0u8; 32]).unwrap();
 //! let binary_data = &[0u8; 100]; // From network
-//! let stream = HtipStream::new(binary_data, &verifying_key).unwrap();
+//! let context = StreamContext::new(now).with_origin("https://example.com");
+//! let stream = HtipStream::new(binary_data, &verifying_key, &context).unwrap();
 //!
 //! for op in stream.operations() {
 //!     // Apply opcode to DOM via dx-morph
 //! }
 //! ```
 
+pub mod asm;
 pub mod delta;
 pub mod deserializer;
+pub mod dictionary;
 pub mod htip_bridge;
+pub mod macros;
 pub mod opcodes;
 pub mod protocol;
 pub mod serializer;
 pub mod signature;
+pub mod ssr;
 pub mod string_table;
 pub mod template;
+pub mod transcode;
+pub mod v2;
+pub mod validate;
 
-pub use deserializer::HtipStream;
+pub use deserializer::{HtipStream, HtipStreamDecoder};
+pub use dx_packet::{DecodeLimits, Limit};
 pub use opcodes::OpcodeV1;
-pub use protocol::{HtipHeader, HtipPayload};
-pub use serializer::HtipWriter;
+pub use protocol::{HtipHeader, HtipPayload, StreamMetadata};
+pub use serializer::{HtipWriter, StreamOptions};
+pub use signature::{KeyRing, KeySelector, StreamContext};
 pub use string_table::StringTable;
 pub use template::TemplateDictionary;
 
@@ -121,18 +144,31 @@ pub const MAGIC_BYTES: &[u8; 4] = b"DXB1";
 /// HTIP v1 Version
 pub const VERSION: u8 = 1;
 
+/// Version of this runtime, checked against `StreamMetadata::min_runtime_version`
+pub const RUNTIME_VERSION: u32 = 1;
+
 /// Maximum string table size (16 MB)
 pub const MAX_STRING_TABLE_SIZE: usize = 16 * 1024 * 1024;
 
 /// Maximum template count
 pub const MAX_TEMPLATE_COUNT: u16 = 65535;
 
+/// Decode limits used by `HtipStream::new` and `HtipStreamDecoder::new`
+pub const DEFAULT_LIMITS: DecodeLimits = DecodeLimits {
+    max_string_bytes: MAX_STRING_TABLE_SIZE as u32,
+    max_templates: MAX_TEMPLATE_COUNT as u32,
+    ..DecodeLimits::DEFAULT
+};
+
 /// Error types for dx-binary
 #[derive(Debug, thiserror::Error)]
 pub enum DxBinaryError {
     #[error("Invalid magic bytes: expected DXB1")]
     InvalidMagic,
 
+    #[error("Unsupported header flags: {0:#04x}")]
+    UnsupportedFlags(u8),
+
     #[error("Unsupported version: {0}")]
     UnsupportedVersion(u8),
 
@@ -153,6 +189,67 @@ pub enum DxBinaryError {
 
     #[error("IO error: {0}")]
     IoError(String),
+
+    #[error("Delta base hash mismatch")]
+    DeltaBaseMismatch,
+
+    #[error("Delta target hash mismatch")]
+    DeltaTargetMismatch,
+
+    #[error("Malformed delta patch: {0}")]
+    InvalidDelta(String),
+
+    #[error("Unknown string dictionary: {0}")]
+    UnknownDictionary(u16),
+
+    #[error("String table does not start with dictionary {0}")]
+    DictionaryMismatch(u16),
+
+    #[error("Invalid opcode macro: {0}")]
+    InvalidMacro(String),
+
+    #[error("Unknown signing key: {0}")]
+    UnknownKeyId(u16),
+
+    #[error("Signing key {0} is outside its validity window")]
+    KeyNotValid(u16),
+
+    #[error("Stream has no signed metadata")]
+    MissingMetadata,
+
+    #[error("Stream expired at {expires_at} (now {now})")]
+    StreamExpired { expires_at: u64, now: u64 },
+
+    #[error("Stream issued at {issued_at}, in the future (now {now})")]
+    StreamNotYetValid { issued_at: u64, now: u64 },
+
+    #[error("Stream issued at {issued_at}, before the oldest accepted {min_issued_at}")]
+    StreamDowngraded { issued_at: u64, min_issued_at: u64 },
+
+    #[error("Stream requires runtime {required}, this is {runtime}")]
+    RuntimeTooOld { required: u32, runtime: u32 },
+
+    #[error("Stream issued for origin {found}, expected {expected}")]
+    OriginMismatch { expected: String, found: String },
+
+    #[error("Assembly error on line {line}: {message}")]
+    Assembly { line: usize, message: String },
+
+    #[error("Decode limit exceeded: {limit:?} {found} > {max}")]
+    LimitExceeded {
+        limit: Limit,
+        found: usize,
+        max: usize,
+    },
+
+    #[error("Invalid v2 stream: {0}")]
+    InvalidV2(String),
+
+    #[error("Transcode error: {0}")]
+    Transcode(String),
+
+    #[error("Render error: {0}")]
+    Render(String),
 }
 
 pub type Result<T> = std::result::Result<T, DxBinaryError>;
@@ -0,0 +1,551 @@
+//! # Opcode Macros
+//!
+//! Lists render as long runs of the same op shapes: Instantiate, PatchText
+//! ×3, PatchClassToggle, once per row. With macro compression the writer
+//! defines each repeated shape once, as a sequence of ops whose fields are
+//! constants or parameters, and sends one `InvokeMacro` per row carrying
+//! only the fields that differ.
+//!
+//! Decoders expand invocations back into plain operations, so nothing after
+//! them (validation, engines, SSR) ever sees a macro.
+//!
+//! ## Wire Format (`FLAG_MACROS`)
+//!
+//! ```text
+//! strings, templates                 ; as without macros
+//! macro count + OpMacro...           ; bincode, right after the templates
+//! op count + Step...                 ; an Operation, or variant 11: InvokeMacro
+//! ```
+//!
+//! A `Step` holding an operation encodes exactly like the `Operation`, so a
+//! literal op costs nothing extra.
+
+use std::collections::HashMap;
+
+use bincode::{
+    de::Decoder,
+    enc::Encoder,
+    error::{AllowedEnumVariants, DecodeError, EncodeError},
+    Decode, Encode,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{opcodes::*, DxBinaryError, Result};
+
+/// Most ops in one macro (bounds what a single invocation expands to)
+pub const MAX_MACRO_OPS: usize = 32;
+
+/// Most macros in one stream
+pub const MAX_MACROS: usize = 1024;
+
+/// A shape must repeat this often before it becomes a macro
+const MIN_REPEATS: usize = 2;
+
+/// Bincode variant index of `Step::Invoke`, the first one after `Operation`'s
+const INVOKE_VARIANT: u32 = 11;
+
+/// One field of a macro op
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub enum MacroField {
+    /// Same value in every invocation
+    Const(u32),
+    /// Taken from the invocation's arguments
+    Param(u8),
+}
+
+/// One op of a macro, fields in the order of the op's struct
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Encode, Decode)]
+pub struct MacroOp {
+    pub opcode: OpcodeV1,
+    pub fields: Vec<MacroField>,
+}
+
+/// A parameterized op sequence
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Encode, Decode)]
+pub struct OpMacro {
+    pub ops: Vec<MacroOp>,
+}
+
+/// Expand macro `macro_id` with `args`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Encode, Decode)]
+pub struct InvokeMacro {
+    pub macro_id: u32,
+    pub args: Vec<u32>,
+}
+
+/// An entry of a macro-compressed op stream
+#[derive(Debug, Clone)]
+pub enum Step {
+    Op(Operation),
+    Invoke(InvokeMacro),
+}
+
+fn invalid(message: impl Into<String>) -> DxBinaryError {
+    DxBinaryError::InvalidMacro(message.into())
+}
+
+/// Field count of the ops macros can hold (integer fields only)
+fn arity(opcode: OpcodeV1) -> Option<usize> {
+    match opcode {
+        OpcodeV1::RemoveNode => Some(1),
+        OpcodeV1::AppendChild => Some(2),
+        OpcodeV1::Instantiate
+        | OpcodeV1::PatchText
+        | OpcodeV1::PatchClassToggle
+        | OpcodeV1::AttachEvent => Some(3),
+        OpcodeV1::PatchAttr => Some(4),
+        _ => None,
+    }
+}
+
+/// Fields of an op macros can hold
+fn fields(op: &Operation) -> Option<Vec<u32>> {
+    Some(match op {
+        Operation::Instantiate(op) => vec![op.instance_id, op.template_id.into(), op.parent_id],
+        Operation::PatchText(op) => vec![op.instance_id, op.slot_id.into(), op.string_id],
+        Operation::PatchAttr(op) => {
+            vec![
+                op.instance_id,
+                op.slot_id.into(),
+                op.attr_name_id,
+                op.value_id,
+            ]
+        }
+        Operation::PatchClassToggle(op) => {
+            vec![op.instance_id, op.class_name_id, op.enabled.into()]
+        }
+        Operation::AttachEvent(op) => vec![op.instance_id, op.event_type_id, op.handler_id],
+        Operation::RemoveNode(op) => vec![op.instance_id],
+        Operation::AppendChild(op) => vec![op.parent_id, op.child_id],
+        _ => return None,
+    })
+}
+
+/// Inverse of `fields`
+fn build(opcode: OpcodeV1, f: &[u32]) -> Result<Operation> {
+    let u16_field = |value: u32| {
+        u16::try_from(value).map_err(|_| invalid(format!("{value} out of range for {opcode:?}")))
+    };
+    Ok(match opcode {
+        OpcodeV1::Instantiate => Operation::Instantiate(Instantiate {
+            instance_id: f[0],
+            template_id: u16_field(f[1])?,
+            parent_id: f[2],
+        }),
+        OpcodeV1::PatchText => Operation::PatchText(PatchText {
+            instance_id: f[0],
+            slot_id: u16_field(f[1])?,
+            string_id: f[2],
+        }),
+        OpcodeV1::PatchAttr => Operation::PatchAttr(PatchAttr {
+            instance_id: f[0],
+            slot_id: u16_field(f[1])?,
+            attr_name_id: f[2],
+            value_id: f[3],
+        }),
+        OpcodeV1::PatchClassToggle => Operation::PatchClassToggle(PatchClassToggle {
+            instance_id: f[0],
+            class_name_id: f[1],
+            enabled: match f[2] {
+                0 => false,
+                1 => true,
+                other => return Err(invalid(format!("{other} is not a bool"))),
+            },
+        }),
+        OpcodeV1::AttachEvent => Operation::AttachEvent(AttachEvent {
+            instance_id: f[0],
+            event_type_id: f[1],
+            handler_id: f[2],
+        }),
+        OpcodeV1::RemoveNode => Operation::RemoveNode(RemoveNode { instance_id: f[0] }),
+        OpcodeV1::AppendChild => Operation::AppendChild(AppendChild {
+            parent_id: f[0],
+            child_id: f[1],
+        }),
+        other => return Err(invalid(format!("{other:?} cannot be in a macro"))),
+    })
+}
+
+impl OpMacro {
+    /// Check the definition (done once, when a decoder reads it)
+    pub fn check(&self) -> Result<()> {
+        if self.ops.is_empty() || self.ops.len() > MAX_MACRO_OPS {
+            return Err(invalid(format!("{} ops, expected 1 to {MAX_MACRO_OPS}", self.ops.len())));
+        }
+        for op in &self.ops {
+            if arity(op.opcode) != Some(op.fields.len()) {
+                return Err(invalid(format!("{:?} with {} fields", op.opcode, op.fields.len())));
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of arguments an invocation must pass
+    pub fn param_count(&self) -> usize {
+        self.ops
+            .iter()
+            .flat_map(|op| &op.fields)
+            .filter_map(|field| match field {
+                MacroField::Param(p) => Some(*p as usize + 1),
+                MacroField::Const(_) => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Append the ops of one invocation to `out`
+    pub fn expand(&self, args: &[u32], out: &mut impl Extend<Operation>) -> Result<()> {
+        if args.len() != self.param_count() {
+            return Err(invalid(format!(
+                "{} arguments, expected {}",
+                args.len(),
+                self.param_count()
+            )));
+        }
+        for op in &self.ops {
+            let values: Vec<u32> = op
+                .fields
+                .iter()
+                .map(|field| match *field {
+                    MacroField::Const(value) => value,
+                    MacroField::Param(p) => args[p as usize],
+                })
+                .collect();
+            out.extend([build(op.opcode, &values)?]);
+        }
+        Ok(())
+    }
+}
+
+impl Step {
+    /// Append the operations this step stands for to `out`
+    pub fn expand(self, macros: &[OpMacro], out: &mut impl Extend<Operation>) -> Result<()> {
+        match self {
+            Step::Op(op) => {
+                out.extend([op]);
+                Ok(())
+            }
+            Step::Invoke(invoke) => macros
+                .get(invoke.macro_id as usize)
+                .ok_or_else(|| invalid(format!("Unknown macro {}", invoke.macro_id)))?
+                .expand(&invoke.args, out),
+        }
+    }
+}
+
+impl Encode for Step {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> std::result::Result<(), EncodeError> {
+        match self {
+            Step::Op(op) => op.encode(encoder),
+            Step::Invoke(invoke) => {
+                INVOKE_VARIANT.encode(encoder)?;
+                invoke.encode(encoder)
+            }
+        }
+    }
+}
+
+impl<Context> Decode<Context> for Step {
+    fn decode<D: Decoder<Context = Context>>(
+        decoder: &mut D,
+    ) -> std::result::Result<Self, DecodeError> {
+        // Same variant indices as `Operation`
+        let op = match u32::decode(decoder)? {
+            0 => Operation::TemplateDef(Decode::decode(decoder)?),
+            1 => Operation::Instantiate(Decode::decode(decoder)?),
+            2 => Operation::PatchText(Decode::decode(decoder)?),
+            3 => Operation::PatchAttr(Decode::decode(decoder)?),
+            4 => Operation::PatchClassToggle(Decode::decode(decoder)?),
+            5 => Operation::AttachEvent(Decode::decode(decoder)?),
+            6 => Operation::RemoveNode(Decode::decode(decoder)?),
+            7 => Operation::BatchStart(Decode::decode(decoder)?),
+            8 => Operation::BatchCommit(Decode::decode(decoder)?),
+            9 => Operation::SetProperty(Decode::decode(decoder)?),
+            10 => Operation::AppendChild(Decode::decode(decoder)?),
+            INVOKE_VARIANT => return Ok(Step::Invoke(Decode::decode(decoder)?)),
+            found => {
+                return Err(DecodeError::UnexpectedVariant {
+                    type_name: "Step",
+                    allowed: &AllowedEnumVariants::Range {
+                        min: 0,
+                        max: INVOKE_VARIANT,
+                    },
+                    found,
+                })
+            }
+        };
+        Ok(Step::Op(op))
+    }
+}
+
+bincode::impl_borrow_decode!(Step);
+
+// ============================================================================
+// COMPRESSION
+// ============================================================================
+
+/// Encoded size of a value
+fn size_of(value: &impl Encode) -> usize {
+    bincode::encode_to_vec(value, bincode::config::standard()).map_or(0, |bytes| bytes.len())
+}
+
+/// Part of the op stream: one op kept as-is, or a unit that may become an invocation
+enum Segment {
+    Plain(usize),
+    /// Ops `start..end`, all with `fields`
+    Unit {
+        start: usize,
+        end: usize,
+    },
+}
+
+/// Replace repeated op sequences with macro invocations
+///
+/// Units start at each `Instantiate` and run over the following ops macros
+/// can hold. Units with the same op shape that repeat become one macro:
+/// fields equal across all of them are constants, the rest parameters
+/// (fields always equal to each other share one). A shape only becomes a
+/// macro when that makes the stream smaller. Returns no macros, and the ops
+/// unchanged, when nothing pays off.
+pub fn compress(operations: &[Operation]) -> (Vec<OpMacro>, Vec<Step>) {
+    let op_fields: Vec<Option<Vec<u32>>> = operations.iter().map(fields).collect();
+
+    // Split into units
+    let mut segments = Vec::new();
+    let mut unit_start = None;
+    for (i, op) in operations.iter().enumerate() {
+        let starts_unit = matches!(op, Operation::Instantiate(_));
+        if let Some(start) = unit_start {
+            if starts_unit || op_fields[i].is_none() || i - start == MAX_MACRO_OPS {
+                segments.push(Segment::Unit { start, end: i });
+                unit_start = None;
+            }
+        }
+        if starts_unit {
+            unit_start = Some(i);
+        } else if unit_start.is_none() {
+            segments.push(Segment::Plain(i));
+        }
+    }
+    if let Some(start) = unit_start {
+        segments.push(Segment::Unit {
+            start,
+            end: operations.len(),
+        });
+    }
+
+    // Group units by shape, in order of first appearance
+    let mut groups: Vec<Vec<(usize, usize)>> = Vec::new();
+    let mut group_of_shape: HashMap<Vec<u8>, usize> = HashMap::new();
+    for segment in &segments {
+        if let Segment::Unit { start, end } = *segment {
+            let shape = operations[start..end].iter().map(|op| op.opcode().to_u8()).collect();
+            let group = *group_of_shape.entry(shape).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push((start, end));
+        }
+    }
+
+    // Turn each paying group into a macro
+    let mut macros = Vec::new();
+    let mut invocations: HashMap<usize, InvokeMacro> = HashMap::new();
+    for units in groups.iter().filter(|units| units.len() >= MIN_REPEATS) {
+        // Field values of each unit, flattened
+        let values: Vec<Vec<u32>> = units
+            .iter()
+            .map(|&(start, end)| {
+                op_fields[start..end].iter().flat_map(|f| f.iter().flatten().copied()).collect()
+            })
+            .collect();
+        let column = |c: usize| values.iter().map(move |unit| unit[c]);
+
+        // Constant, or the parameter of the first identical column
+        let mut fields: Vec<MacroField> = Vec::with_capacity(values[0].len());
+        let mut param_columns: Vec<usize> = Vec::new();
+        for (c, &first) in values[0].iter().enumerate() {
+            let field = if column(c).all(|value| value == first) {
+                MacroField::Const(first)
+            } else if let Some(p) = param_columns.iter().position(|&pc| column(pc).eq(column(c))) {
+                MacroField::Param(p as u8)
+            } else {
+                param_columns.push(c);
+                MacroField::Param((param_columns.len() - 1) as u8)
+            };
+            fields.push(field);
+        }
+
+        let (start, end) = units[0];
+        let mut remaining = fields.into_iter();
+        let op_macro = OpMacro {
+            ops: operations[start..end]
+                .iter()
+                .map(|op| MacroOp {
+                    opcode: op.opcode(),
+                    fields: remaining.by_ref().take(arity(op.opcode()).unwrap_or(0)).collect(),
+                })
+                .collect(),
+        };
+
+        let macro_id = macros.len() as u32;
+        let unit_invocations: Vec<InvokeMacro> = values
+            .iter()
+            .map(|unit| InvokeMacro {
+                macro_id,
+                args: param_columns.iter().map(|&c| unit[c]).collect(),
+            })
+            .collect();
+
+        let plain: usize = units
+            .iter()
+            .flat_map(|&(start, end)| &operations[start..end])
+            .map(size_of)
+            .sum();
+        let compressed = size_of(&op_macro)
+            + unit_invocations.iter().map(|invoke| size_of(invoke) + 1).sum::<usize>();
+        if compressed < plain {
+            macros.push(op_macro);
+            for (&(start, _), invoke) in units.iter().zip(unit_invocations) {
+                invocations.insert(start, invoke);
+            }
+        }
+    }
+
+    let mut steps = Vec::with_capacity(operations.len());
+    for segment in segments {
+        match segment {
+            Segment::Plain(i) => steps.push(Step::Op(operations[i].clone())),
+            Segment::Unit { start, end } => match invocations.remove(&start) {
+                Some(invoke) => steps.push(Step::Invoke(invoke)),
+                None => steps.extend(operations[start..end].iter().cloned().map(Step::Op)),
+            },
+        }
+    }
+    (macros, steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(instance_id: u32, name: u32, selected: bool) -> Vec<Operation> {
+        vec![
+            Operation::Instantiate(Instantiate {
+                instance_id,
+                template_id: 3,
+                parent_id: 1,
+            }),
+            Operation::PatchText(PatchText {
+                instance_id,
+                slot_id: 0,
+                string_id: name,
+            }),
+            Operation::PatchText(PatchText {
+                instance_id,
+                slot_id: 1,
+                string_id: name + 1,
+            }),
+            Operation::PatchClassToggle(PatchClassToggle {
+                instance_id,
+                class_name_id: 7,
+                enabled: selected,
+            }),
+        ]
+    }
+
+    fn expand_all(macros: &[OpMacro], steps: Vec<Step>) -> Vec<Operation> {
+        let mut out = Vec::new();
+        for step in steps {
+            step.expand(macros, &mut out).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn test_compress_rows() {
+        let mut ops = vec![Operation::BatchStart(BatchStart { batch_id: 1 })];
+        for i in 0..20 {
+            ops.extend(row(10 + i, 100 + 2 * i, i % 3 == 0));
+        }
+        ops.push(Operation::BatchCommit(BatchCommit { batch_id: 1 }));
+
+        let (macros, steps) = compress(&ops);
+        assert_eq!(macros.len(), 1);
+        assert_eq!(steps.len(), 22);
+        // Instance ID shared by all four ops, name, name + 1, selected
+        assert_eq!(macros[0].param_count(), 4);
+        assert_eq!(macros[0].ops[0].fields[1], MacroField::Const(3));
+
+        let expanded = expand_all(&macros, steps.clone());
+        assert_eq!(format!("{expanded:?}"), format!("{ops:?}"));
+
+        let plain = size_of(&ops);
+        let compressed = size_of(&macros) + size_of(&steps);
+        assert!(compressed * 3 < plain * 2, "{compressed} vs {plain}");
+    }
+
+    #[test]
+    fn test_compress_leaves_unique_shapes() {
+        let ops = row(1, 2, true);
+        let (macros, steps) = compress(&ops);
+        assert!(macros.is_empty());
+        assert_eq!(format!("{:?}", expand_all(&macros, steps)), format!("{ops:?}"));
+    }
+
+    #[test]
+    fn test_step_encodes_like_operation() {
+        let op = Operation::RemoveNode(RemoveNode { instance_id: 5 });
+        assert_eq!(size_of(&Step::Op(op.clone())), size_of(&op));
+        let config = bincode::config::standard();
+        assert_eq!(
+            bincode::encode_to_vec(Step::Op(op.clone()), config).unwrap(),
+            bincode::encode_to_vec(&op, config).unwrap()
+        );
+
+        let invoke = Step::Invoke(InvokeMacro {
+            macro_id: 0,
+            args: vec![1],
+        });
+        let bytes = bincode::encode_to_vec(&invoke, config).unwrap();
+        let (decoded, _): (Step, usize) = bincode::decode_from_slice(&bytes, config).unwrap();
+        assert!(matches!(decoded, Step::Invoke(InvokeMacro { macro_id: 0, .. })));
+    }
+
+    #[test]
+    fn test_malformed_macros() {
+        let remove = |fields| OpMacro {
+            ops: vec![MacroOp {
+                opcode: OpcodeV1::RemoveNode,
+                fields,
+            }],
+        };
+        assert!(OpMacro { ops: vec![] }.check().is_err());
+        assert!(remove(vec![]).check().is_err());
+
+        let m = remove(vec![MacroField::Param(0)]);
+        m.check().unwrap();
+        let mut out = Vec::new();
+        assert!(m.expand(&[], &mut out).is_err());
+        assert!(m.expand(&[1, 2], &mut out).is_err());
+        m.expand(&[1], &mut out).unwrap();
+
+        let unknown = Step::Invoke(InvokeMacro {
+            macro_id: 1,
+            args: vec![],
+        });
+        assert!(unknown.expand(&[m], &mut out).is_err());
+
+        let template = OpMacro {
+            ops: vec![MacroOp {
+                opcode: OpcodeV1::Instantiate,
+                fields: vec![
+                    MacroField::Const(1),
+                    MacroField::Param(0),
+                    MacroField::Const(0),
+                ],
+            }],
+        };
+        assert!(template.expand(&[70_000], &mut out).is_err());
+    }
+}
@@ -89,7 +89,7 @@ impl OpcodeV1 {
 }
 
 /// Template definition with binding slots
-#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Encode, Decode)]
 pub struct TemplateDef {
     pub id: u16,
     pub html_string_id: u32, // Reference to string table
@@ -97,14 +97,14 @@ pub struct TemplateDef {
 }
 
 /// Binding slot definition
-#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Encode, Decode)]
 pub struct Binding {
     pub slot_id: u16,
     pub binding_type: BindingType,
     pub path: Vec<u8>, // DOM path (e.g., [0, 2, 1] = firstChild.childNodes[2].firstChild)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Encode, Decode)]
 pub enum BindingType {
     Text,
     Attribute { attr_name_id: u32 },
@@ -18,8 +18,11 @@ pub struct HtipHeader {
     /// Version: 1
     pub version: u8,
 
-    /// Reserved (alignment)
-    pub _reserved: [u8; 3],
+    /// Flags (`FLAG_*`)
+    pub flags: u8,
+
+    /// Static string dictionary version (see `dictionary`), 0 = none
+    pub dictionary: u16,
 
     /// Ed25519 signature (64 bytes)
     pub signature: [u8; 64],
@@ -27,8 +30,8 @@ pub struct HtipHeader {
     /// Number of templates in dictionary
     pub template_count: u16,
 
-    /// Alignment padding
-    pub _padding: [u8; 2],
+    /// ID of the signing key (see `signature::KeyRing`), 0 = default key
+    pub key_id: u16,
 
     /// Number of strings in string table
     pub string_count: u32,
@@ -40,6 +43,15 @@ pub struct HtipHeader {
     pub total_opcodes_size: u32,
 }
 
+/// A `StreamMetadata` block follows the header, signed with the payload
+pub const FLAG_METADATA: u8 = 0x01;
+
+/// A macro section follows the templates and ops may invoke macros (see `macros`)
+pub const FLAG_MACROS: u8 = 0x02;
+
+/// Every flag this version understands
+pub const KNOWN_FLAGS: u8 = FLAG_METADATA | FLAG_MACROS;
+
 impl HtipHeader {
     pub const SIZE: usize = 88; // 77 bytes + padding to 88 for alignment
 
@@ -48,10 +60,11 @@ impl HtipHeader {
         Self {
             magic: *MAGIC_BYTES,
             version: VERSION,
-            _reserved: [0; 3],
+            flags: 0,
+            dictionary: 0,
             signature: [0; 64],
             template_count: 0,
-            _padding: [0; 2],
+            key_id: 0,
             string_count: 0,
             total_templates_size: 0,
             total_opcodes_size: 0,
@@ -68,6 +81,10 @@ impl HtipHeader {
             return Err(crate::DxBinaryError::UnsupportedVersion(self.version));
         }
 
+        if self.flags & !KNOWN_FLAGS != 0 {
+            return Err(crate::DxBinaryError::UnsupportedFlags(self.flags));
+        }
+
         Ok(())
     }
 }
@@ -78,6 +95,26 @@ impl Default for HtipHeader {
     }
 }
 
+/// Signed stream metadata (bincode, between header and payload)
+///
+/// Lets clients refuse captured streams replayed after they expire, old
+/// builds replayed in place of new ones, and streams issued for another
+/// origin (see `signature::StreamContext`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct StreamMetadata {
+    /// When the stream was built (unix seconds)
+    pub issued_at: u64,
+
+    /// First second the stream is no longer accepted (None = no expiry)
+    pub expires_at: Option<u64>,
+
+    /// Oldest runtime (`RUNTIME_VERSION`) that may apply the stream
+    pub min_runtime_version: u32,
+
+    /// Origin the stream was issued for (e.g. "https://example.com")
+    pub origin: Option<String>,
+}
+
 /// Complete HTIP payload structure
 #[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
 pub struct HtipPayload {
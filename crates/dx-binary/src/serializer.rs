@@ -8,31 +8,72 @@ use bincode::config;
 use ed25519_dalek::SigningKey;
 
 use crate::{
+    dictionary::StringDictionary,
+    macros,
     opcodes::*,
-    protocol::{HtipHeader, HtipPayload},
-    signature::sign_payload,
+    protocol::{HtipHeader, HtipPayload, StreamMetadata, FLAG_MACROS, FLAG_METADATA},
+    signature::{sign_payload, signed_message},
     string_table::StringTable,
     template::TemplateDictionary,
     DxBinaryError, Result, MAGIC_BYTES, VERSION,
 };
 
+/// How a payload is framed and signed
+#[derive(Debug, Clone, Default)]
+pub struct StreamOptions {
+    /// ID of the signing key (written to the header)
+    pub key_id: u16,
+    /// Signed metadata written between header and payload
+    pub metadata: Option<StreamMetadata>,
+    /// Dictionary the payload's string table starts with (left off the wire)
+    pub dictionary: Option<&'static StringDictionary>,
+    /// Compress repeated op sequences into macros (see `macros`)
+    pub macros: bool,
+}
+
 /// HTIP writer (server-side serializer)
 pub struct HtipWriter {
     string_table: StringTable,
     template_dict: TemplateDictionary,
     operations: Vec<Operation>,
+    options: StreamOptions,
 }
 
 impl HtipWriter {
-    /// Create new writer
+    /// Create new writer (every string is sent)
     pub fn new() -> Self {
+        Self::with_dictionary(None)
+    }
+
+    /// Create new writer using `dictionary` (e.g. `dictionary::LATEST`), or
+    /// sending every string if None
+    pub fn with_dictionary(dictionary: Option<&'static StringDictionary>) -> Self {
         Self {
-            string_table: StringTable::new(),
+            string_table: dictionary.map_or_else(StringTable::new, StringTable::with_dictionary),
             template_dict: TemplateDictionary::new(),
             operations: Vec::new(),
+            options: StreamOptions {
+                dictionary,
+                ..StreamOptions::default()
+            },
         }
     }
 
+    /// Set the ID of the key that will sign the stream (written to the header)
+    pub fn set_key_id(&mut self, key_id: u16) {
+        self.options.key_id = key_id;
+    }
+
+    /// Sign issue/expiry times, minimum runtime and origin with the stream
+    pub fn set_metadata(&mut self, metadata: StreamMetadata) {
+        self.options.metadata = Some(metadata);
+    }
+
+    /// Compress repeated op sequences (list rows) into macro invocations
+    pub fn set_macro_compression(&mut self, enabled: bool) {
+        self.options.macros = enabled;
+    }
+
     /// Add string and get ID
     pub fn add_string(&mut self, s: &str) -> u32 {
         self.string_table.add(s)
@@ -155,30 +196,7 @@ impl HtipWriter {
             operations: self.operations,
         };
 
-        // Serialize payload
-        let config = config::standard();
-        let payload_bytes = bincode::encode_to_vec(&payload, config)
-            .map_err(|e| DxBinaryError::BincodeError(e.to_string()))?;
-
-        // Sign the payload
-        let signature = sign_payload(&payload_bytes, signing_key);
-
-        // Create header
-        let mut header = HtipHeader::new();
-        header.magic = *MAGIC_BYTES;
-        header.version = VERSION;
-        header.signature = signature.to_bytes();
-        header.template_count = self.template_dict.len() as u16;
-        header.string_count = self.string_table.len() as u32;
-        header.total_templates_size = 0; // Updated below
-        header.total_opcodes_size = payload_bytes.len() as u32;
-
-        // Combine header + payload
-        let mut result = Vec::with_capacity(HtipHeader::SIZE + payload_bytes.len());
-        result.extend_from_slice(bytemuck::bytes_of(&header));
-        result.extend_from_slice(&payload_bytes);
-
-        Ok(result)
+        encode_signed(&payload, &self.options, signing_key)
     }
 
     /// Finish without signing (for testing)
@@ -195,6 +213,84 @@ impl Default for HtipWriter {
     }
 }
 
+/// Serialize and sign a payload (header + optional metadata + bincode payload)
+///
+/// Used by `HtipWriter::finish_and_sign` and for payloads built elsewhere
+/// (e.g. `transcode::v2_to_v1`). With `options.dictionary`, the payload's
+/// string table must start with the dictionary's strings; only the rest
+/// are written.
+pub fn encode_signed(
+    payload: &HtipPayload,
+    options: &StreamOptions,
+    signing_key: &SigningKey,
+) -> Result<Vec<u8>> {
+    let config = config::standard();
+
+    // Serialize metadata, signed together with the payload
+    let mut signed_bytes = match &options.metadata {
+        Some(metadata) => bincode::encode_to_vec(metadata, config)
+            .map_err(|e| DxBinaryError::BincodeError(e.to_string()))?,
+        None => Vec::new(),
+    };
+
+    // Leave the dictionary's strings off the wire
+    let mut wire_payload;
+    let mut payload = payload;
+    let mut dictionary_version = 0;
+    if let Some(dictionary) = options.dictionary {
+        let prefix = payload.strings.get(..dictionary.len());
+        if prefix.is_none_or(|prefix| !prefix.iter().eq(dictionary.strings.iter())) {
+            return Err(DxBinaryError::DictionaryMismatch(dictionary.version));
+        }
+        wire_payload = payload.clone();
+        wire_payload.strings.drain(..dictionary.len());
+        payload = &wire_payload;
+        dictionary_version = dictionary.version;
+    }
+
+    // Serialize payload, ops as macro steps when that saves anything
+    let (op_macros, steps) = match options.macros {
+        true => macros::compress(&payload.operations),
+        false => (Vec::new(), Vec::new()),
+    };
+    let payload_bytes = if op_macros.is_empty() {
+        bincode::encode_to_vec(payload, config)
+    } else {
+        let sections = (&payload.strings, &payload.templates, &op_macros, &steps);
+        bincode::encode_to_vec(sections, config)
+    }
+    .map_err(|e| DxBinaryError::BincodeError(e.to_string()))?;
+    signed_bytes.extend_from_slice(&payload_bytes);
+
+    // Create header
+    let mut header = HtipHeader::new();
+    header.magic = *MAGIC_BYTES;
+    header.version = VERSION;
+    if options.metadata.is_some() {
+        header.flags |= FLAG_METADATA;
+    }
+    if !op_macros.is_empty() {
+        header.flags |= FLAG_MACROS;
+    }
+    header.dictionary = dictionary_version;
+
+    // Sign dictionary version + flags + metadata + payload
+    let message = signed_message(dictionary_version, header.flags, &signed_bytes);
+    header.signature = sign_payload(&message, signing_key).to_bytes();
+    header.template_count = payload.templates.len() as u16;
+    header.key_id = options.key_id;
+    header.string_count = payload.strings.len() as u32;
+    header.total_templates_size = 0; // Updated below
+    header.total_opcodes_size = payload_bytes.len() as u32;
+
+    // Combine header + metadata + payload
+    let mut result = Vec::with_capacity(HtipHeader::SIZE + signed_bytes.len());
+    result.extend_from_slice(bytemuck::bytes_of(&header));
+    result.extend_from_slice(&signed_bytes);
+
+    Ok(result)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -225,6 +321,23 @@ mod tests {
         assert_eq!(id1, id2); // Should be same ID
     }
 
+    #[test]
+    fn test_writer_is_deterministic() {
+        let build = || {
+            let mut writer = HtipWriter::new();
+            for id in [5, 1, 9, 3, 0, 12, 7] {
+                writer.write_template(id, &format!("<div id=\"t{id}\"></div>"), vec![]);
+            }
+            writer.write_instantiate(1, 9, 0);
+            writer.write_patch_text(1, 0, "Hello");
+
+            let signing_key = SigningKey::from_bytes(&[7u8; 32]);
+            writer.finish_and_sign(&signing_key).unwrap()
+        };
+
+        assert_eq!(build(), build());
+    }
+
     #[test]
     fn test_writer_operations() {
         let mut writer = HtipWriter::new();
@@ -1,9 +1,21 @@
 //! # Ed25519 Signature Support
 //!
 //! Every HTIP stream is signed to prevent injection attacks.
+//!
+//! The header names its signing key by ID. A `KeyRing` maps IDs to keys so
+//! signing keys can rotate: the new key goes in as Active, the old one stays
+//! Retiring until cached streams signed with it have expired.
+//!
+//! A signature alone does not stop replays. Streams can carry signed
+//! `StreamMetadata`, which `StreamContext` checks against the client's
+//! clock, origin and runtime version.
+
+use std::borrow::Cow;
 
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 
+use crate::{protocol::StreamMetadata, DxBinaryError, Result, RUNTIME_VERSION};
+
 /// Sign payload with Ed25519
 pub fn sign_payload(payload: &[u8], signing_key: &SigningKey) -> Signature {
     signing_key.sign(payload)
@@ -14,6 +26,22 @@ pub fn verify_payload(payload: &[u8], signature: &Signature, verifying_key: &Ver
     verifying_key.verify(payload, signature).is_ok()
 }
 
+/// Bytes a stream signature covers: everything after the header, behind
+/// the header's dictionary version and flags when either is set
+///
+/// Strings resolve differently under another dictionary, and the flags say
+/// how the body is laid out, so neither must be swappable.
+pub fn signed_message(dictionary: u16, flags: u8, body: &[u8]) -> Cow<'_, [u8]> {
+    if dictionary == 0 && flags == 0 {
+        return Cow::Borrowed(body);
+    }
+    let mut message = Vec::with_capacity(3 + body.len());
+    message.extend_from_slice(&dictionary.to_le_bytes());
+    message.push(flags);
+    message.extend_from_slice(body);
+    Cow::Owned(message)
+}
+
 /// Generate a new keypair (for testing/setup)
 pub fn generate_keypair() -> (SigningKey, VerifyingKey) {
     let signing_key = SigningKey::generate(&mut rand::thread_rng());
@@ -21,6 +49,266 @@ pub fn generate_keypair() -> (SigningKey, VerifyingKey) {
     (signing_key, verifying_key)
 }
 
+// ============================================================================
+// KEY SELECTION
+// ============================================================================
+
+/// Picks the verifying key for a stream from its header key ID
+pub trait KeySelector {
+    fn select_key(&self, key_id: u16) -> Result<&VerifyingKey>;
+}
+
+/// A single key verifies every stream, whatever its key ID
+impl KeySelector for VerifyingKey {
+    fn select_key(&self, _key_id: u16) -> Result<&VerifyingKey> {
+        Ok(self)
+    }
+}
+
+/// Lifecycle state of a key in a `KeyRing`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyStatus {
+    /// Current signing key
+    Active,
+    /// Replaced, still accepted for streams signed before the rotation
+    Retiring,
+}
+
+/// A verifying key with its validity window (unix seconds)
+#[derive(Debug, Clone)]
+pub struct KeyEntry {
+    pub key_id: u16,
+    pub key: VerifyingKey,
+    pub status: KeyStatus,
+    /// First second the key is valid
+    pub not_before: u64,
+    /// First second the key is no longer valid (None = no expiry)
+    pub not_after: Option<u64>,
+}
+
+impl KeyEntry {
+    /// Check the validity window at `now` (unix seconds)
+    pub fn is_valid_at(&self, now: u64) -> bool {
+        now >= self.not_before && self.not_after.is_none_or(|end| now < end)
+    }
+}
+
+/// Set of verifying keys, addressed by key ID
+#[derive(Debug, Clone, Default)]
+pub struct KeyRing {
+    keys: Vec<KeyEntry>,
+}
+
+impl KeyRing {
+    /// Create empty key ring
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add (or replace) the key for `key_id` as Active
+    pub fn add_active(
+        &mut self,
+        key_id: u16,
+        key: VerifyingKey,
+        not_before: u64,
+        not_after: Option<u64>,
+    ) {
+        self.insert(KeyEntry {
+            key_id,
+            key,
+            status: KeyStatus::Active,
+            not_before,
+            not_after,
+        });
+    }
+
+    /// Add (or replace) the key for `key_id` as Retiring
+    pub fn add_retiring(
+        &mut self,
+        key_id: u16,
+        key: VerifyingKey,
+        not_before: u64,
+        not_after: Option<u64>,
+    ) {
+        self.insert(KeyEntry {
+            key_id,
+            key,
+            status: KeyStatus::Retiring,
+            not_before,
+            not_after,
+        });
+    }
+
+    fn insert(&mut self, entry: KeyEntry) {
+        self.keys.retain(|k| k.key_id != entry.key_id);
+        self.keys.push(entry);
+    }
+
+    /// Mark a key Retiring, accepted until `until` (unix seconds) at the latest
+    ///
+    /// Returns false if the key ID is unknown.
+    pub fn retire(&mut self, key_id: u16, until: u64) -> bool {
+        let Some(entry) = self.keys.iter_mut().find(|k| k.key_id == key_id) else {
+            return false;
+        };
+        entry.status = KeyStatus::Retiring;
+        entry.not_after = Some(entry.not_after.map_or(until, |end| end.min(until)));
+        true
+    }
+
+    /// Drop a key
+    pub fn remove(&mut self, key_id: u16) -> bool {
+        let before = self.keys.len();
+        self.keys.retain(|k| k.key_id != key_id);
+        self.keys.len() != before
+    }
+
+    /// Get a key entry by ID
+    pub fn get(&self, key_id: u16) -> Option<&KeyEntry> {
+        self.keys.iter().find(|k| k.key_id == key_id)
+    }
+
+    /// Active key valid at `now` with the latest `not_before` (the one to sign with)
+    pub fn active_at(&self, now: u64) -> Option<&KeyEntry> {
+        self.keys
+            .iter()
+            .filter(|k| k.status == KeyStatus::Active && k.is_valid_at(now))
+            .max_by_key(|k| k.not_before)
+    }
+
+    /// Look up the key for `key_id`, checking its validity window at `now`
+    pub fn select(&self, key_id: u16, now: u64) -> Result<&VerifyingKey> {
+        let entry = self.get(key_id).ok_or(DxBinaryError::UnknownKeyId(key_id))?;
+        if !entry.is_valid_at(now) {
+            return Err(DxBinaryError::KeyNotValid(key_id));
+        }
+        Ok(&entry.key)
+    }
+
+    /// Key selector that checks validity windows at `now` (unix seconds)
+    pub fn at(&self, now: u64) -> KeyRingAt<'_> {
+        KeyRingAt { ring: self, now }
+    }
+
+    /// Number of keys
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Check if the ring has no keys
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// A `KeyRing` evaluated at a point in time
+#[derive(Debug, Clone, Copy)]
+pub struct KeyRingAt<'a> {
+    ring: &'a KeyRing,
+    now: u64,
+}
+
+impl KeySelector for KeyRingAt<'_> {
+    fn select_key(&self, key_id: u16) -> Result<&VerifyingKey> {
+        self.ring.select(key_id, self.now)
+    }
+}
+
+// ============================================================================
+// STREAM METADATA CHECKS
+// ============================================================================
+
+/// What the client knows when it accepts a stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamContext {
+    /// Current time (unix seconds)
+    pub now: u64,
+    /// Origin the client runs on (None = don't check origins)
+    pub origin: Option<String>,
+    /// Version of the runtime applying the stream
+    pub runtime_version: u32,
+    /// Streams issued earlier are downgrades (e.g. the last deploy's time)
+    pub min_issued_at: u64,
+    /// Refuse streams without metadata (otherwise they skip the checks)
+    pub require_metadata: bool,
+}
+
+impl StreamContext {
+    /// Context at `now` (unix seconds) for this runtime, any origin
+    pub fn new(now: u64) -> Self {
+        Self {
+            now,
+            origin: None,
+            runtime_version: RUNTIME_VERSION,
+            min_issued_at: 0,
+            require_metadata: false,
+        }
+    }
+
+    /// Only accept streams issued for `origin`
+    pub fn with_origin(mut self, origin: impl Into<String>) -> Self {
+        self.origin = Some(origin.into());
+        self
+    }
+
+    /// Refuse streams issued before `min_issued_at` (unix seconds)
+    pub fn with_min_issued_at(mut self, min_issued_at: u64) -> Self {
+        self.min_issued_at = min_issued_at;
+        self
+    }
+
+    /// Refuse streams without metadata
+    pub fn require_metadata(mut self) -> Self {
+        self.require_metadata = true;
+        self
+    }
+
+    /// Check a stream's (verified) metadata
+    pub fn check(&self, metadata: Option<&StreamMetadata>) -> Result<()> {
+        let Some(metadata) = metadata else {
+            return match self.require_metadata {
+                true => Err(DxBinaryError::MissingMetadata),
+                false => Ok(()),
+            };
+        };
+
+        if let Some(expires_at) = metadata.expires_at.filter(|&end| self.now >= end) {
+            return Err(DxBinaryError::StreamExpired {
+                expires_at,
+                now: self.now,
+            });
+        }
+        if metadata.issued_at > self.now {
+            return Err(DxBinaryError::StreamNotYetValid {
+                issued_at: metadata.issued_at,
+                now: self.now,
+            });
+        }
+        if metadata.issued_at < self.min_issued_at {
+            return Err(DxBinaryError::StreamDowngraded {
+                issued_at: metadata.issued_at,
+                min_issued_at: self.min_issued_at,
+            });
+        }
+        if metadata.min_runtime_version > self.runtime_version {
+            return Err(DxBinaryError::RuntimeTooOld {
+                required: metadata.min_runtime_version,
+                runtime: self.runtime_version,
+            });
+        }
+        if let (Some(expected), Some(found)) = (&self.origin, &metadata.origin) {
+            if expected != found {
+                return Err(DxBinaryError::OriginMismatch {
+                    expected: expected.clone(),
+                    found: found.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -59,4 +347,29 @@ mod tests {
 
         assert!(!verify_payload(payload, &signature, &wrong_verifying_key));
     }
+
+    #[test]
+    fn test_key_ring_rotation() {
+        let old = SigningKey::from_bytes(&[1u8; 32]).verifying_key();
+        let new = SigningKey::from_bytes(&[2u8; 32]).verifying_key();
+
+        let mut ring = KeyRing::new();
+        ring.add_active(1, old, 0, None);
+        ring.add_active(2, new, 1_000, None);
+        assert_eq!(ring.active_at(500).unwrap().key_id, 1);
+        assert_eq!(ring.active_at(1_500).unwrap().key_id, 2);
+
+        assert!(ring.retire(1, 2_000));
+        assert_eq!(ring.get(1).unwrap().status, KeyStatus::Retiring);
+        assert_eq!(ring.active_at(1_500).unwrap().key_id, 2);
+
+        assert_eq!(ring.select(1, 1_999).unwrap(), &old);
+        assert!(matches!(ring.select(1, 2_000), Err(DxBinaryError::KeyNotValid(1))));
+        assert!(matches!(ring.select(2, 999), Err(DxBinaryError::KeyNotValid(2))));
+        assert!(matches!(ring.select(3, 0), Err(DxBinaryError::UnknownKeyId(3))));
+
+        assert!(ring.remove(1));
+        assert!(!ring.retire(1, 0));
+        assert_eq!(ring.len(), 1);
+    }
 }
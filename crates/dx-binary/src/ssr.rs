@@ -0,0 +1,782 @@
+//! # HTIP Server-Side Renderer
+//!
+//! Executes a v1 operation stream against an in-memory tree and serializes
+//! it to HTML, so SSR, snapshot tests and crawlers see what `HtipEngine`
+//! builds in the browser.
+//!
+//! Mirrors the engine op for op:
+//! - Templates come from the dictionary and from `TemplateDef` ops; an
+//!   instance is a deep copy of the template's first element
+//! - `PatchText` fills the text node after a `<!--SLOT_N-->` comment among
+//!   the instance's children, else replaces the instance's content
+//! - `SetProperty` only shows up in HTML for properties that reflect to an
+//!   attribute (`id`, `className`, `hidden`, ...) and for `textContent` and
+//!   `innerHTML`; `AttachEvent` never does
+//! - Streams must validate first; batches are then balanced and applying
+//!   their ops in order gives the state at the final commit
+//!
+//! Template markup is parsed as well-formed HTML (what the compiler emits):
+//! no implied end tags, and only the common character references are decoded.
+
+use std::collections::HashMap;
+
+use crate::{
+    opcodes::{Operation, PropertyValue},
+    protocol::HtipPayload,
+    validate::validate_payload,
+    DxBinaryError, Result,
+};
+
+/// Elements without content or end tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Elements whose text is neither parsed nor escaped
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// Properties that reflect to an attribute: (property, attribute)
+const REFLECTED: &[(&str, &str)] = &[
+    ("id", "id"),
+    ("className", "class"),
+    ("title", "title"),
+    ("lang", "lang"),
+    ("dir", "dir"),
+    ("href", "href"),
+    ("src", "src"),
+    ("alt", "alt"),
+    ("name", "name"),
+    ("placeholder", "placeholder"),
+    ("htmlFor", "for"),
+    ("tabIndex", "tabindex"),
+];
+
+/// Boolean properties that reflect to the presence of an attribute
+const REFLECTED_BOOLEAN: &[(&str, &str)] = &[
+    ("hidden", "hidden"),
+    ("disabled", "disabled"),
+    ("required", "required"),
+    ("readOnly", "readonly"),
+    ("multiple", "multiple"),
+    ("autofocus", "autofocus"),
+    ("open", "open"),
+];
+
+type NodeId = usize;
+
+#[derive(Debug, Clone)]
+enum NodeData {
+    /// Document root or parsed template content
+    Fragment,
+    Element {
+        tag: String,
+        attrs: Vec<(String, String)>,
+    },
+    Text(String),
+    Comment(String),
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    data: NodeData,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+/// In-memory document built from HTIP operations
+#[derive(Debug)]
+pub struct HtmlDocument {
+    /// Node arena; 0 is the root, detached nodes stay until the document drops
+    nodes: Vec<Node>,
+    /// Template ID -> parsed template content
+    templates: HashMap<u16, NodeId>,
+    /// Instance ID -> element
+    instances: HashMap<u32, NodeId>,
+    /// String table of the payload being applied
+    strings: Vec<String>,
+}
+
+impl HtmlDocument {
+    /// Create an empty document
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![Node {
+                data: NodeData::Fragment,
+                parent: None,
+                children: Vec::new(),
+            }],
+            templates: HashMap::new(),
+            instances: HashMap::new(),
+            strings: Vec::new(),
+        }
+    }
+
+    /// Apply a payload's templates and operations
+    ///
+    /// Instances carry over between payloads, like in `HtipEngine`.
+    /// Invalid payloads are refused before anything is applied.
+    pub fn apply(&mut self, payload: &HtipPayload) -> Result<()> {
+        if let Some(problem) = validate_payload(payload).first() {
+            return Err(render_error(format!("Invalid HTIP stream: {}", problem)));
+        }
+
+        self.strings = payload.strings.clone();
+
+        for template in &payload.templates {
+            self.register_template(template.id, template.html_string_id)?;
+        }
+
+        for op in &payload.operations {
+            self.execute(op)?;
+        }
+
+        Ok(())
+    }
+
+    /// Serialize the document to HTML
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        self.serialize_children(0, &mut html);
+        html
+    }
+
+    /// Apply a single operation
+    fn execute(&mut self, op: &Operation) -> Result<()> {
+        match op {
+            Operation::TemplateDef(def) => self.register_template(def.id, def.html_string_id)?,
+            Operation::Instantiate(inst) => {
+                let template = *self
+                    .templates
+                    .get(&inst.template_id)
+                    .ok_or(DxBinaryError::TemplateNotFound(inst.template_id))?;
+                let element = self.nodes[template]
+                    .children
+                    .iter()
+                    .copied()
+                    .find(|&child| matches!(self.nodes[child].data, NodeData::Element { .. }))
+                    .ok_or_else(|| {
+                        render_error(format!("Template {} has no element child", inst.template_id))
+                    })?;
+
+                let parent = match inst.parent_id {
+                    0 => 0,
+                    id => self.instance(id)?,
+                };
+                let clone = self.deep_clone(element);
+                self.append(parent, clone);
+                self.instances.insert(inst.instance_id, clone);
+            }
+            Operation::PatchText(patch) => {
+                let instance = self.instance(patch.instance_id)?;
+                let text = self.string(patch.string_id)?.to_string();
+                let marker = format!("SLOT_{}", patch.slot_id);
+
+                match self.find_slot_text_node(instance, &marker) {
+                    Some(node) => self.nodes[node].data = NodeData::Text(text),
+                    None => self.set_text_content(instance, text),
+                }
+            }
+            Operation::PatchAttr(patch) => {
+                let instance = self.instance(patch.instance_id)?;
+                let name = self.string(patch.attr_name_id)?.to_string();
+                let value = self.string(patch.value_id)?.to_string();
+                self.set_attribute(instance, &name, value);
+            }
+            Operation::PatchClassToggle(toggle) => {
+                let instance = self.instance(toggle.instance_id)?;
+                let class_name = self.string(toggle.class_name_id)?.to_string();
+                self.toggle_class(instance, &class_name, toggle.enabled);
+            }
+            Operation::AttachEvent(event) => {
+                // Listeners have no HTML form
+                self.instance(event.instance_id)?;
+            }
+            Operation::RemoveNode(remove) => {
+                if let Some(instance) = self.instances.remove(&remove.instance_id) {
+                    self.detach(instance);
+                }
+            }
+            Operation::BatchStart(_) | Operation::BatchCommit(_) => {}
+            Operation::SetProperty(prop) => {
+                let instance = self.instance(prop.instance_id)?;
+                let name = self.string(prop.prop_name_id)?.to_string();
+                self.set_property(instance, &name, &prop.value)?;
+            }
+            Operation::AppendChild(append) => {
+                let parent = self.instance(append.parent_id)?;
+                let child = self.instance(append.child_id)?;
+                if self.contains(child, parent) {
+                    return Err(render_error(format!(
+                        "Cannot append {} inside itself",
+                        append.child_id
+                    )));
+                }
+                self.detach(child);
+                self.append(parent, child);
+            }
+        }
+        Ok(())
+    }
+
+    fn register_template(&mut self, template_id: u16, html_string_id: u32) -> Result<()> {
+        let html = self.string(html_string_id)?.to_string();
+        let fragment = self.parse(&html);
+        self.templates.insert(template_id, fragment);
+        Ok(())
+    }
+
+    fn string(&self, id: u32) -> Result<&str> {
+        self.strings
+            .get(id as usize)
+            .map(|s| s.as_str())
+            .ok_or_else(|| render_error(format!("String {} not found", id)))
+    }
+
+    fn instance(&self, id: u32) -> Result<NodeId> {
+        self.instances
+            .get(&id)
+            .copied()
+            .ok_or_else(|| render_error(format!("Instance {} not found", id)))
+    }
+
+    // ========================================================================
+    // Tree operations
+    // ========================================================================
+
+    fn push(&mut self, data: NodeData) -> NodeId {
+        self.nodes.push(Node {
+            data,
+            parent: None,
+            children: Vec::new(),
+        });
+        self.nodes.len() - 1
+    }
+
+    fn append(&mut self, parent: NodeId, child: NodeId) {
+        self.nodes[child].parent = Some(parent);
+        self.nodes[parent].children.push(child);
+    }
+
+    fn detach(&mut self, node: NodeId) {
+        if let Some(parent) = self.nodes[node].parent.take() {
+            self.nodes[parent].children.retain(|&child| child != node);
+        }
+    }
+
+    /// Is `node` `ancestor` or one of its descendants
+    fn contains(&self, ancestor: NodeId, mut node: NodeId) -> bool {
+        loop {
+            if node == ancestor {
+                return true;
+            }
+            match self.nodes[node].parent {
+                Some(parent) => node = parent,
+                None => return false,
+            }
+        }
+    }
+
+    fn deep_clone(&mut self, node: NodeId) -> NodeId {
+        let clone = self.push(self.nodes[node].data.clone());
+        for index in 0..self.nodes[node].children.len() {
+            let child = self.deep_clone(self.nodes[node].children[index]);
+            self.append(clone, child);
+        }
+        clone
+    }
+
+    /// Text node right after a comment containing `marker`, as `HtipEngine`
+    fn find_slot_text_node(&self, element: NodeId, marker: &str) -> Option<NodeId> {
+        let children = &self.nodes[element].children;
+        children.iter().enumerate().find_map(|(index, &child)| {
+            let NodeData::Comment(comment) = &self.nodes[child].data else {
+                return None;
+            };
+            let next = *children.get(index + 1)?;
+            (comment.contains(marker) && matches!(self.nodes[next].data, NodeData::Text(_)))
+                .then_some(next)
+        })
+    }
+
+    /// Replace all children with one text node (none for empty text)
+    fn set_text_content(&mut self, element: NodeId, text: String) {
+        for child in std::mem::take(&mut self.nodes[element].children) {
+            self.nodes[child].parent = None;
+        }
+        if !text.is_empty() {
+            let node = self.push(NodeData::Text(text));
+            self.append(element, node);
+        }
+    }
+
+    fn attrs_mut(&mut self, element: NodeId) -> Option<&mut Vec<(String, String)>> {
+        match &mut self.nodes[element].data {
+            NodeData::Element { attrs, .. } => Some(attrs),
+            _ => None,
+        }
+    }
+
+    fn set_attribute(&mut self, element: NodeId, name: &str, value: String) {
+        let name = name.to_ascii_lowercase();
+        let Some(attrs) = self.attrs_mut(element) else {
+            return;
+        };
+        match attrs.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => attrs.push((name, value)),
+        }
+    }
+
+    fn remove_attribute(&mut self, element: NodeId, name: &str) {
+        if let Some(attrs) = self.attrs_mut(element) {
+            attrs.retain(|(n, _)| n != name);
+        }
+    }
+
+    /// `classList.add` / `classList.remove`
+    fn toggle_class(&mut self, element: NodeId, class_name: &str, enabled: bool) {
+        let Some(attrs) = self.attrs_mut(element) else {
+            return;
+        };
+        let existing = attrs.iter().position(|(n, _)| n == "class");
+
+        let mut classes: Vec<&str> = Vec::new();
+        if let Some(index) = existing {
+            for class in attrs[index].1.split_ascii_whitespace() {
+                if !classes.contains(&class) {
+                    classes.push(class);
+                }
+            }
+        }
+        if enabled && !classes.contains(&class_name) {
+            classes.push(class_name);
+        } else if !enabled {
+            if existing.is_none() {
+                return; // No attribute to update
+            }
+            classes.retain(|&class| class != class_name);
+        }
+
+        let value = classes.join(" ");
+        match existing {
+            Some(index) => attrs[index].1 = value,
+            None => attrs.push(("class".to_string(), value)),
+        }
+    }
+
+    fn set_property(&mut self, element: NodeId, name: &str, value: &PropertyValue) -> Result<()> {
+        if let Some(&(_, attr)) = REFLECTED_BOOLEAN.iter().find(|(prop, _)| *prop == name) {
+            let truthy = match value {
+                PropertyValue::String(id) => !self.string(*id)?.is_empty(),
+                PropertyValue::Number(n) => *n != 0.0 && !n.is_nan(),
+                PropertyValue::Boolean(b) => *b,
+                PropertyValue::Null => false,
+            };
+            if truthy {
+                self.set_attribute(element, attr, String::new());
+            } else {
+                self.remove_attribute(element, attr);
+            }
+            return Ok(());
+        }
+
+        let text = match value {
+            PropertyValue::String(id) => self.string(*id)?.to_string(),
+            PropertyValue::Number(n) => js_number(*n),
+            PropertyValue::Boolean(b) => b.to_string(),
+            PropertyValue::Null => String::new(),
+        };
+
+        match name {
+            "textContent" => self.set_text_content(element, text),
+            "innerHTML" => {
+                let fragment = self.parse(&text);
+                self.set_text_content(element, String::new());
+                for child in std::mem::take(&mut self.nodes[fragment].children) {
+                    self.append(element, child);
+                }
+            }
+            _ => {
+                if let Some(&(_, attr)) = REFLECTED.iter().find(|(prop, _)| *prop == name) {
+                    // `null` converts to "null" for reflected strings
+                    let text = match value {
+                        PropertyValue::Null => "null".to_string(),
+                        _ => text,
+                    };
+                    self.set_attribute(element, attr, text);
+                }
+                // Other properties have no HTML form
+            }
+        }
+        Ok(())
+    }
+
+    // ========================================================================
+    // Parsing
+    // ========================================================================
+
+    /// Parse markup into a detached fragment
+    fn parse(&mut self, html: &str) -> NodeId {
+        let fragment = self.push(NodeData::Fragment);
+        let mut open = vec![fragment];
+        let mut rest = html;
+
+        while !rest.is_empty() {
+            let parent = *open.last().unwrap_or(&fragment);
+
+            if let Some(after) = rest.strip_prefix("<!--") {
+                let end = after.find("-->").unwrap_or(after.len());
+                let comment = self.push(NodeData::Comment(after[..end].to_string()));
+                self.append(parent, comment);
+                rest = after.get(end + 3..).unwrap_or("");
+            } else if rest.starts_with("<!") || rest.starts_with("<?") {
+                // Doctype or bogus comment
+                let end = rest.find('>').map_or(rest.len(), |i| i + 1);
+                rest = &rest[end..];
+            } else if let Some(after) = rest.strip_prefix("</") {
+                let end = after.find('>').unwrap_or(after.len());
+                let tag = after[..end].trim().to_ascii_lowercase();
+                if let Some(index) = open.iter().rposition(|&node| self.is_tag(node, &tag)) {
+                    open.truncate(index);
+                }
+                rest = after.get(end + 1..).unwrap_or("");
+            } else if rest.starts_with('<')
+                && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic())
+            {
+                let (tag, attrs, after) = parse_start_tag(&rest[1..]);
+                let element = self.push(NodeData::Element {
+                    tag: tag.clone(),
+                    attrs,
+                });
+                self.append(parent, element);
+                rest = after;
+
+                if RAW_TEXT_ELEMENTS.contains(&tag.as_str()) {
+                    let close = format!("</{}", tag);
+                    let end = rest.to_ascii_lowercase().find(&close).unwrap_or(rest.len());
+                    if end > 0 {
+                        let text = self.push(NodeData::Text(rest[..end].to_string()));
+                        self.append(element, text);
+                    }
+                    rest = &rest[end..];
+                    let after_close = rest.find('>').map_or(rest.len(), |i| i + 1);
+                    rest = &rest[after_close..];
+                } else if !VOID_ELEMENTS.contains(&tag.as_str()) {
+                    open.push(element);
+                }
+            } else {
+                let end = rest[1..].find('<').map_or(rest.len(), |i| i + 1);
+                let text = self.push(NodeData::Text(decode_entities(&rest[..end])));
+                self.append(parent, text);
+                rest = &rest[end..];
+            }
+        }
+
+        fragment
+    }
+
+    fn is_tag(&self, node: NodeId, name: &str) -> bool {
+        matches!(&self.nodes[node].data, NodeData::Element { tag, .. } if tag == name)
+    }
+
+    // ========================================================================
+    // Serialization
+    // ========================================================================
+
+    fn serialize_children(&self, node: NodeId, html: &mut String) {
+        let raw = matches!(
+            &self.nodes[node].data,
+            NodeData::Element { tag, .. } if RAW_TEXT_ELEMENTS.contains(&tag.as_str())
+        );
+
+        for &child in &self.nodes[node].children {
+            match &self.nodes[child].data {
+                NodeData::Fragment => self.serialize_children(child, html),
+                NodeData::Element { tag, attrs } => {
+                    html.push('<');
+                    html.push_str(tag);
+                    for (name, value) in attrs {
+                        html.push(' ');
+                        html.push_str(name);
+                        html.push_str("=\"");
+                        escape_into(value, true, html);
+                        html.push('"');
+                    }
+                    html.push('>');
+
+                    if !VOID_ELEMENTS.contains(&tag.as_str()) {
+                        self.serialize_children(child, html);
+                        html.push_str("</");
+                        html.push_str(tag);
+                        html.push('>');
+                    }
+                }
+                NodeData::Text(text) if raw => html.push_str(text),
+                NodeData::Text(text) => escape_into(text, false, html),
+                NodeData::Comment(comment) => {
+                    html.push_str("<!--");
+                    html.push_str(comment);
+                    html.push_str("-->");
+                }
+            }
+        }
+    }
+}
+
+impl Default for HtmlDocument {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Render a payload into a fresh document and serialize it
+pub fn render_html(payload: &HtipPayload) -> Result<String> {
+    let mut document = HtmlDocument::new();
+    document.apply(payload)?;
+    Ok(document.to_html())
+}
+
+fn render_error(message: String) -> DxBinaryError {
+    DxBinaryError::Render(message)
+}
+
+/// Tag name, attributes (first one wins) and the rest after `>`
+fn parse_start_tag(input: &str) -> (String, Vec<(String, String)>, &str) {
+    let name_end = input
+        .find(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/')
+        .unwrap_or(input.len());
+    let tag = input[..name_end].to_ascii_lowercase();
+    let mut rest = &input[name_end..];
+    let mut attrs: Vec<(String, String)> = Vec::new();
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if rest.is_empty() {
+            break;
+        }
+        if let Some(after) = rest.strip_prefix('>') {
+            rest = after;
+            break;
+        }
+
+        let name_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || matches!(c, '=' | '>' | '/'))
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let mut value = "";
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let end = after[1..].find(quote).map_or(after.len(), |i| i + 1);
+                    value = &after[1..end];
+                    rest = after.get(end + 1..).unwrap_or("");
+                }
+                _ => {
+                    let end = after
+                        .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                        .unwrap_or(after.len());
+                    value = &after[..end];
+                    rest = &after[end..];
+                }
+            }
+        }
+
+        if !name.is_empty() && !attrs.iter().any(|(n, _)| *n == name) {
+            attrs.push((name, decode_entities(value)));
+        }
+    }
+
+    (tag, attrs, rest)
+}
+
+/// Decode the common named and all numeric character references
+fn decode_entities(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                "nbsp" => '\u{a0}',
+                entity => {
+                    let code = match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => entity.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, end + 1))
+        });
+
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Escape text or an attribute value as the HTML serializer does
+fn escape_into(input: &str, attribute: bool, html: &mut String) {
+    for c in input.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '\u{a0}' => html.push_str("&nbsp;"),
+            '"' if attribute => html.push_str("&quot;"),
+            '<' if !attribute => html.push_str("&lt;"),
+            '>' if !attribute => html.push_str("&gt;"),
+            c => html.push(c),
+        }
+    }
+}
+
+/// Number to string as JavaScript prints it (for integers and plain decimals)
+fn js_number(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        let sign = if n < 0.0 { "-" } else { "" };
+        format!("{sign}Infinity")
+    } else if n == n.trunc() && n.abs() < 1e21 {
+        format!("{}", n as i128)
+    } else {
+        n.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deserializer::HtipStream;
+    use crate::opcodes::{Binding, BindingType};
+    use crate::serializer::HtipWriter;
+    use crate::signature::StreamContext;
+    use ed25519_dalek::SigningKey;
+
+    fn text_slot(slot_id: u16) -> Binding {
+        Binding {
+            slot_id,
+            binding_type: BindingType::Text,
+            path: vec![],
+        }
+    }
+
+    fn render(writer: HtipWriter) -> Result<String> {
+        let key = SigningKey::from_bytes(&[9u8; 32]);
+        let binary = writer.finish_and_sign(&key).unwrap();
+        let stream =
+            HtipStream::new(&binary, &key.verifying_key(), &StreamContext::new(0)).unwrap();
+        render_html(stream.payload())
+    }
+
+    #[test]
+    fn test_render_full_stream() {
+        let mut writer = HtipWriter::new();
+        writer.write_template(0, "<ul class=\"list\"></ul>", vec![]);
+        writer.write_template(1, "<li>Item: <!--SLOT_0-->?</li>", vec![text_slot(0)]);
+        writer.write_template(2, "<input type=text>", vec![]);
+        writer.write_instantiate(1, 0, 0);
+        writer.write_batch_start(1);
+        writer.write_instantiate(2, 1, 1);
+        writer.write_patch_text(2, 0, "Milk & eggs");
+        writer.write_instantiate(3, 1, 1);
+        writer.write_patch_text(3, 0, "<b>");
+        writer.write_batch_commit(1);
+        writer.write_class_toggle(1, "open", true);
+        writer.write_class_toggle(1, "list", false);
+        writer.write_patch_attr(2, 0, "data-id", "a\"b");
+        writer.write_attach_event(2, "click", 7);
+        writer.write_instantiate(4, 2, 0);
+        writer.write_set_property(4, "disabled", PropertyValue::Boolean(true));
+        writer.write_set_property(4, "tabIndex", PropertyValue::Number(3.0));
+        writer.write_set_property(4, "value", PropertyValue::Number(1.5));
+        writer.write_append_child(1, 4);
+        writer.write_remove_node(3);
+
+        assert_eq!(
+            render(writer).unwrap(),
+            "<ul class=\"open\">\
+             <li data-id=\"a&quot;b\">Item: <!--SLOT_0-->Milk &amp; eggs</li>\
+             <input type=\"text\" disabled=\"\" tabindex=\"3\">\
+             </ul>"
+        );
+    }
+
+    #[test]
+    fn test_patch_text_without_marker_replaces_content() {
+        let mut writer = HtipWriter::new();
+        writer.write_template(0, "<p>Count: <!--SLOT_0--></p>", vec![text_slot(0)]);
+        writer.write_template(1, "<div><!-- note --><script>if (a < b) {}</script></div>", vec![]);
+        writer.write_instantiate(1, 0, 0);
+        writer.write_patch_text(1, 0, "5");
+        writer.write_instantiate(2, 1, 0);
+        writer.write_set_property(2, "hidden", PropertyValue::Null);
+        writer.write_set_property(2, "title", PropertyValue::Null);
+
+        // No text node after the marker: the engine falls back to textContent
+        assert_eq!(
+            render(writer).unwrap(),
+            "<p>5</p><div title=\"null\"><!-- note --><script>if (a < b) {}</script></div>"
+        );
+    }
+
+    #[test]
+    fn test_entities_and_inner_html() {
+        let mut writer = HtipWriter::new();
+        writer.write_template(
+            0,
+            "<p title='x &amp; y'>&lt;a&gt;&#160;&#x41;&copy;<br/></p>",
+            vec![],
+        );
+        writer.write_instantiate(1, 0, 0);
+        writer.write_instantiate(2, 0, 1);
+        writer.write_set_property(2, "innerHTML", PropertyValue::Null);
+        writer.write_instantiate(3, 0, 0);
+        let html = writer.add_string("<em>Hi</em>");
+        writer.write_set_property(3, "innerHTML", PropertyValue::String(html));
+
+        assert_eq!(
+            render(writer).unwrap(),
+            "<p title=\"x &amp; y\">&lt;a&gt;&nbsp;A&amp;copy;<br><p title=\"x &amp; y\"></p></p>\
+             <p title=\"x &amp; y\"><em>Hi</em></p>"
+        );
+    }
+
+    #[test]
+    fn test_invalid_stream_is_refused() {
+        let mut writer = HtipWriter::new();
+        writer.write_template(0, "<div></div>", vec![]);
+        writer.write_instantiate(1, 0, 0);
+        writer.write_patch_attr(9, 0, "id", "x");
+        assert!(matches!(render(writer), Err(DxBinaryError::Render(_))));
+
+        // Appending an instance inside itself
+        let mut writer = HtipWriter::new();
+        writer.write_template(0, "<div></div>", vec![]);
+        writer.write_instantiate(1, 0, 0);
+        writer.write_instantiate(2, 0, 1);
+        writer.write_append_child(2, 1);
+        assert!(matches!(render(writer), Err(DxBinaryError::Render(_))));
+    }
+}
@@ -4,13 +4,16 @@
 //! This is the secret to 9.8 KB payloads.
 //!
 //! ## Strategy
-//! - Common strings (class names, tag names) are in a static enum (compile-time)
+//! - Common strings (class names, tag names) come from a static dictionary
+//!   shipped with the runtime (`dictionary`), never sent
 //! - Dynamic strings are added to the table once, referenced by ID
 //! - Result: "className" appears once in 100 KB UI, not 500 times
 
 use blake3::Hasher;
 use std::collections::HashMap;
 
+use crate::dictionary::StringDictionary;
+
 /// String table for deduplication
 #[derive(Debug, Clone)]
 pub struct StringTable {
@@ -22,6 +25,9 @@ pub struct StringTable {
 
     /// Next available ID
     next_id: u32,
+
+    /// Static dictionary holding IDs `0..len`
+    dictionary: Option<&'static StringDictionary>,
 }
 
 impl StringTable {
@@ -31,6 +37,23 @@ impl StringTable {
             strings: Vec::new(),
             lookup: HashMap::new(),
             next_id: 0,
+            dictionary: None,
+        }
+    }
+
+    /// Create a table whose first IDs are `dictionary`'s strings
+    pub fn with_dictionary(dictionary: &'static StringDictionary) -> Self {
+        let mut table = Self::new();
+        table.dictionary = Some(dictionary);
+        table.seed();
+        table
+    }
+
+    fn seed(&mut self) {
+        if let Some(dictionary) = self.dictionary {
+            for s in dictionary.strings {
+                self.add(s);
+            }
         }
     }
 
@@ -61,11 +84,21 @@ impl StringTable {
         self.strings.get(id as usize).map(|s| s.as_str())
     }
 
-    /// Get all strings (for serialization)
+    /// Get all strings, dictionary first
     pub fn strings(&self) -> &[String] {
         &self.strings
     }
 
+    /// Static dictionary the table starts with
+    pub fn dictionary(&self) -> Option<&'static StringDictionary> {
+        self.dictionary
+    }
+
+    /// Strings after the dictionary (the ones sent over the wire)
+    pub fn literals(&self) -> &[String] {
+        &self.strings[self.dictionary.map_or(0, |d| d.len())..]
+    }
+
     /// Number of strings
     pub fn len(&self) -> usize {
         self.strings.len()
@@ -76,16 +109,17 @@ impl StringTable {
         self.strings.is_empty()
     }
 
-    /// Calculate total size in bytes
+    /// Calculate total size in bytes (of the literals, dictionary strings are free)
     pub fn total_size(&self) -> usize {
-        self.strings.iter().map(|s| s.len() + 4).sum() // +4 for length prefix
+        self.literals().iter().map(|s| s.len() + 4).sum() // +4 for length prefix
     }
 
-    /// Clear the table
+    /// Clear the table (back to just the dictionary)
     pub fn clear(&mut self) {
         self.strings.clear();
         self.lookup.clear();
         self.next_id = 0;
+        self.seed();
     }
 }
 
@@ -97,8 +131,8 @@ impl Default for StringTable {
 
 /// Static string enum (compile-time known strings)
 ///
-/// These are common strings that appear in every app.
-/// By using an enum, we avoid sending them over the network entirely.
+/// These are common strings that appear in every app. Streams refer to
+/// shared strings through `dictionary::StringDictionary`, not this enum.
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StaticString {
@@ -240,6 +274,22 @@ mod tests {
         assert_eq!(table.get(999), None);
     }
 
+    #[test]
+    fn test_dictionary_strings_are_not_literals() {
+        let dictionary = crate::dictionary::LATEST;
+        let mut table = StringTable::with_dictionary(dictionary);
+        assert_eq!(table.add(dictionary.strings[1]), 1);
+        assert!(table.literals().is_empty());
+        assert_eq!(table.total_size(), 0);
+
+        let id = table.add("not in any dictionary");
+        assert_eq!(id as usize, dictionary.len());
+        assert_eq!(table.literals(), ["not in any dictionary"]);
+
+        table.clear();
+        assert_eq!(table.len(), dictionary.len());
+    }
+
     #[test]
     fn test_static_string_roundtrip() {
         let static_str = StaticString::Class;
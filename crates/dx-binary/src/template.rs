@@ -3,20 +3,22 @@
 //! Template definitions with binding metadata.
 
 use crate::opcodes::{Binding, TemplateDef};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 /// Template dictionary
+///
+/// Ordered by template ID, so serialized output is reproducible.
 #[derive(Debug, Clone)]
 pub struct TemplateDictionary {
     /// Template ID -> Definition
-    templates: HashMap<u16, TemplateDef>,
+    templates: BTreeMap<u16, TemplateDef>,
 }
 
 impl TemplateDictionary {
     /// Create new dictionary
     pub fn new() -> Self {
         Self {
-            templates: HashMap::new(),
+            templates: BTreeMap::new(),
         }
     }
 
@@ -30,7 +32,7 @@ impl TemplateDictionary {
         self.templates.get(&id)
     }
 
-    /// Get all templates
+    /// Get all templates (in ID order)
     pub fn templates(&self) -> Vec<&TemplateDef> {
         self.templates.values().collect()
     }
@@ -107,6 +109,17 @@ mod tests {
         assert!(dict.get(999).is_none());
     }
 
+    #[test]
+    fn test_templates_in_id_order() {
+        let mut dict = TemplateDictionary::new();
+        for id in [7, 2, 42, 0] {
+            dict.add(TemplateBuilder::new(id, 0).build());
+        }
+
+        let ids: Vec<u16> = dict.templates().iter().map(|t| t.id).collect();
+        assert_eq!(ids, [0, 2, 7, 42]);
+    }
+
     #[test]
     fn test_template_builder() {
         let template = TemplateBuilder::new(42, 100).build();
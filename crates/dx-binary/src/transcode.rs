@@ -0,0 +1,623 @@
+//! # HTIP v1 <-> v2 Transcoder
+//!
+//! Converts bincode v1 payloads (`HtipWriter`, `HtipEngine`) to fixed-layout
+//! v2 streams (dx-client) and back, so one authoring API can target every
+//! runtime.
+//!
+//! A conversion is lossless when converting its output back gives an
+//! equivalent stream. Anything else is listed in `Transcoded::losses`.
+//! Values v2 cannot hold at all (string indices and handler IDs above
+//! `u16::MAX`, strings over 64 KB) are errors; node IDs above it switch the
+//! output to wide IDs.
+//!
+//! - v1 instances become v2 nodes 1, 2, 3, ... in instantiation order, and
+//!   v2 nodes become v1 instances with the same IDs
+//! - v2 ops address whole nodes: a v1 patch is lossless when its slot is
+//!   bound to the instance root (empty path)
+//! - v1 templates keep their slot count; their bindings survive only if
+//!   they are all `Text` on the root, which is what v2 -> v1 generates,
+//!   plus `NODE_SLOT` for patches of the whole node
+//! - `TemplateDef` ops move into the v2 dictionary; v2 -> v1 emits one per
+//!   template first, as `HtipWriter` does
+//! - v1 numbers are stored in v2 as decimal strings (`PropKind::Number`)
+//! - v2 `SetStyle` and `AppendChild` to the root have no v1 equivalent and
+//!   are dropped
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use dx_packet::OpType;
+
+use crate::{
+    opcodes::*,
+    protocol::HtipPayload,
+    v2::{OperationV2, PropertyValueV2, StreamV2, TemplateV2},
+    validate::Location,
+    DxBinaryError, Result,
+};
+
+/// Slot that v2 -> v1 patches address: the instance root
+///
+/// No template has a `SLOT_65535` marker, so v1 runtimes patch the element
+/// itself, like v2 runtimes do.
+pub const NODE_SLOT: u16 = u16::MAX;
+
+/// Output of a conversion and what it could not carry over
+#[derive(Debug, Clone)]
+pub struct Transcoded<T> {
+    pub output: T,
+    pub losses: Vec<Loss>,
+}
+
+impl<T> Transcoded<T> {
+    pub fn is_lossless(&self) -> bool {
+        self.losses.is_empty()
+    }
+}
+
+/// Something the target format cannot express
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LossKind {
+    /// Bindings other than `Text` on the root, reduced to a slot count
+    BindingsReduced,
+    /// Patch of a slot below the instance root, applied to the whole node
+    SlotFlattened { slot_id: u16 },
+    /// `TemplateDef` replacing a template: the first definition is kept
+    TemplateRedefined,
+    /// Op with no equivalent, dropped
+    Dropped(OpType),
+}
+
+/// A loss and where it happened (op indices are in the source stream)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loss {
+    pub location: Location,
+    pub kind: LossKind,
+}
+
+impl fmt::Display for Loss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Location::Header => write!(f, "header: ")?,
+            Location::Template(id) => write!(f, "template {id}: ")?,
+            Location::Op(index) => write!(f, "op {index}: ")?,
+        }
+
+        match &self.kind {
+            LossKind::BindingsReduced => write!(f, "bindings reduced to a slot count"),
+            LossKind::SlotFlattened { slot_id } => {
+                write!(f, "slot {slot_id} is below the root, patching the whole node")
+            }
+            LossKind::TemplateRedefined => write!(f, "redefinition dropped"),
+            LossKind::Dropped(op_type) => write!(f, "{op_type:?} has no equivalent, dropped"),
+        }
+    }
+}
+
+fn error(location: Location, message: impl fmt::Display) -> DxBinaryError {
+    let at = match location {
+        Location::Header => "header".to_string(),
+        Location::Template(id) => format!("template {id}"),
+        Location::Op(index) => format!("op {index}"),
+    };
+    DxBinaryError::Transcode(format!("{at}: {message}"))
+}
+
+/// Bindings that v2 -> v1 generates and v1 -> v2 keeps exactly
+fn is_root_text(binding: &Binding) -> bool {
+    matches!(binding.binding_type, BindingType::Text) && binding.path.is_empty()
+}
+
+// ============================================================================
+// v1 -> v2
+// ============================================================================
+
+struct ToV2 {
+    strings: Vec<String>,
+    templates: Vec<TemplateV2>,
+    /// Kept definitions, for slot lookups
+    definitions: HashMap<u16, TemplateDef>,
+    /// v1 instance ID -> (v2 node ID, template ID)
+    instances: HashMap<u32, (u32, u16)>,
+    next_node: u32,
+    losses: Vec<Loss>,
+    location: Location,
+}
+
+impl ToV2 {
+    fn loss(&mut self, kind: LossKind) {
+        self.losses.push(Loss {
+            location: self.location,
+            kind,
+        });
+    }
+
+    fn string(&self, id: u32) -> Result<u16> {
+        u16::try_from(id).map_err(|_| error(self.location, format!("string {id} beyond u16")))
+    }
+
+    fn id(&self, value: u32, what: &str) -> Result<u16> {
+        u16::try_from(value).map_err(|_| error(self.location, format!("{what} {value} beyond u16")))
+    }
+
+    fn node(&self, instance_id: u32) -> Result<u32> {
+        match instance_id {
+            0 => Ok(0),
+            id => self
+                .instances
+                .get(&id)
+                .map(|&(node, _)| node)
+                .ok_or_else(|| error(self.location, format!("unknown instance {id}"))),
+        }
+    }
+
+    /// Interned decimal string of a number
+    fn number(&mut self, n: f64) -> Result<u16> {
+        let text = n.to_string();
+        let index = match self.strings.iter().position(|s| *s == text) {
+            Some(index) => index,
+            None => {
+                self.strings.push(text);
+                self.strings.len() - 1
+            }
+        };
+        self.string(index as u32)
+    }
+
+    fn define(&mut self, template: &TemplateDef) -> Result<()> {
+        if let Some(kept) = self.definitions.get(&template.id) {
+            if kept != template {
+                self.loss(LossKind::TemplateRedefined);
+            }
+            return Ok(());
+        }
+
+        let slots = template.bindings.iter().filter(|b| b.slot_id != NODE_SLOT).count();
+        let slot_count = u8::try_from(slots)
+            .map_err(|_| error(self.location, format!("{slots} slots, v2 holds 255")))?;
+        if !template.bindings.iter().all(is_root_text) {
+            self.loss(LossKind::BindingsReduced);
+        }
+
+        self.templates.push(TemplateV2 {
+            id: template.id.into(),
+            html_string_idx: self.string(template.html_string_id)?,
+            slot_count,
+        });
+        self.definitions.insert(template.id, template.clone());
+        Ok(())
+    }
+
+    /// Node of a slot patch, reporting slots below the root
+    fn slot(&mut self, instance_id: u32, slot_id: u16) -> Result<u32> {
+        let node = self.node(instance_id)?;
+        let template = self.instances.get(&instance_id).map(|&(_, template)| template);
+        let at_root = slot_id == NODE_SLOT
+            || template
+                .and_then(|id| self.definitions.get(&id))
+                .and_then(|t| t.bindings.iter().find(|b| b.slot_id == slot_id))
+                .is_some_and(|b| b.path.is_empty());
+        if !at_root {
+            self.loss(LossKind::SlotFlattened { slot_id });
+        }
+        Ok(node)
+    }
+
+    fn operation(&mut self, op: &Operation) -> Result<Option<OperationV2>> {
+        Ok(Some(match op {
+            Operation::TemplateDef(template) => {
+                self.define(template)?;
+                return Ok(None);
+            }
+            Operation::Instantiate(op) => {
+                let parent_id = self.node(op.parent_id)?;
+                let target = self.next_node;
+                self.next_node = target
+                    .checked_add(1)
+                    .ok_or_else(|| error(self.location, "more than u32::MAX nodes"))?;
+                self.instances.insert(op.instance_id, (target, op.template_id));
+                OperationV2::Clone {
+                    target,
+                    template_id: op.template_id.into(),
+                    parent_id,
+                }
+            }
+            Operation::PatchText(op) => OperationV2::PatchText {
+                target: self.slot(op.instance_id, op.slot_id)?,
+                string_idx: self.string(op.string_id)?,
+            },
+            Operation::PatchAttr(op) => OperationV2::PatchAttr {
+                target: self.slot(op.instance_id, op.slot_id)?,
+                name_idx: self.string(op.attr_name_id)?,
+                value_idx: self.string(op.value_id)?,
+            },
+            Operation::PatchClassToggle(op) => OperationV2::ClassToggle {
+                target: self.node(op.instance_id)?,
+                class_idx: self.string(op.class_name_id)?,
+                enable: op.enabled,
+            },
+            Operation::AttachEvent(op) => OperationV2::AttachEvent {
+                target: self.node(op.instance_id)?,
+                event_idx: self.string(op.event_type_id)?,
+                handler_id: self.id(op.handler_id, "handler")?,
+            },
+            Operation::RemoveNode(op) => {
+                let target = self.node(op.instance_id)?;
+                self.instances.remove(&op.instance_id);
+                OperationV2::Remove { target }
+            }
+            Operation::BatchStart(op) => OperationV2::BatchStart {
+                batch_id: op.batch_id,
+            },
+            Operation::BatchCommit(op) => OperationV2::BatchCommit {
+                batch_id: op.batch_id,
+            },
+            Operation::SetProperty(op) => OperationV2::SetProperty {
+                target: self.node(op.instance_id)?,
+                name_idx: self.string(op.prop_name_id)?,
+                value: match op.value {
+                    PropertyValue::String(id) => PropertyValueV2::String(self.string(id)?),
+                    PropertyValue::Number(n) => PropertyValueV2::Number(self.number(n)?),
+                    PropertyValue::Boolean(b) => PropertyValueV2::Bool(b),
+                    PropertyValue::Null => PropertyValueV2::Null,
+                },
+            },
+            Operation::AppendChild(op) => OperationV2::AppendChild {
+                target: self.node(op.parent_id)?,
+                child_id: self.node(op.child_id)?,
+            },
+        }))
+    }
+}
+
+/// Convert a v1 payload (see `HtipStream::payload`) to a v2 stream
+pub fn v1_to_v2(payload: &HtipPayload) -> Result<Transcoded<StreamV2>> {
+    let mut state = ToV2 {
+        strings: payload.strings.clone(),
+        templates: Vec::new(),
+        definitions: HashMap::new(),
+        instances: HashMap::new(),
+        next_node: 1,
+        losses: Vec::new(),
+        location: Location::Header,
+    };
+
+    for template in &payload.templates {
+        state.location = Location::Template(template.id.into());
+        state.define(template)?;
+    }
+
+    let mut operations = Vec::with_capacity(payload.operations.len());
+    for (index, op) in payload.operations.iter().enumerate() {
+        state.location = Location::Op(index);
+        operations.extend(state.operation(op)?);
+    }
+
+    let mut output = StreamV2 {
+        flags: 0,
+        strings: state.strings,
+        templates: state.templates,
+        operations,
+    };
+    output.flags = output.default_flags();
+
+    Ok(Transcoded {
+        output,
+        losses: state.losses,
+    })
+}
+
+// ============================================================================
+// v2 -> v1
+// ============================================================================
+
+/// Convert a v2 stream to a v1 payload (sign it with `serializer::encode_signed`)
+pub fn v2_to_v1(stream: &StreamV2) -> Result<Transcoded<HtipPayload>> {
+    let mut losses = Vec::new();
+
+    // Same dictionary order as `HtipWriter` (by ID)
+    let mut dictionary = BTreeMap::new();
+    for template in &stream.templates {
+        let mut bindings: Vec<Binding> = (0..template.slot_count as u16)
+            .map(|slot_id| Binding {
+                slot_id,
+                binding_type: BindingType::Text,
+                path: Vec::new(),
+            })
+            .collect();
+        bindings.push(Binding {
+            slot_id: NODE_SLOT,
+            binding_type: BindingType::Text,
+            path: Vec::new(),
+        });
+
+        // v1 template IDs are u16
+        let id = u16::try_from(template.id)
+            .map_err(|_| error(Location::Template(template.id), "template ID beyond u16"))?;
+        let template = TemplateDef {
+            id,
+            html_string_id: template.html_string_idx as u32,
+            bindings,
+        };
+        if dictionary.insert(id, template).is_some() {
+            return Err(error(Location::Template(id.into()), "duplicate template"));
+        }
+    }
+
+    let templates: Vec<TemplateDef> = dictionary.into_values().collect();
+    let mut operations: Vec<Operation> =
+        templates.iter().cloned().map(Operation::TemplateDef).collect();
+    let mut next_node: u32 = 1;
+
+    for (index, op) in stream.operations.iter().enumerate() {
+        let location = Location::Op(index);
+        operations.push(match *op {
+            OperationV2::Clone {
+                template_id,
+                parent_id,
+                ..
+            } => {
+                // The registry numbers clones itself, whatever the target says
+                let instance_id = next_node;
+                next_node += 1;
+                Operation::Instantiate(Instantiate {
+                    instance_id,
+                    template_id: u16::try_from(template_id).map_err(|_| {
+                        error(location, format!("template {template_id} beyond u16"))
+                    })?,
+                    parent_id,
+                })
+            }
+            OperationV2::PatchText { target, string_idx } => Operation::PatchText(PatchText {
+                instance_id: target,
+                slot_id: NODE_SLOT,
+                string_id: string_idx as u32,
+            }),
+            OperationV2::PatchAttr {
+                target,
+                name_idx,
+                value_idx,
+            } => Operation::PatchAttr(PatchAttr {
+                instance_id: target,
+                slot_id: NODE_SLOT,
+                attr_name_id: name_idx as u32,
+                value_id: value_idx as u32,
+            }),
+            OperationV2::ClassToggle {
+                target,
+                class_idx,
+                enable,
+            } => Operation::PatchClassToggle(PatchClassToggle {
+                instance_id: target,
+                class_name_id: class_idx as u32,
+                enabled: enable,
+            }),
+            OperationV2::Remove { target } => Operation::RemoveNode(RemoveNode {
+                instance_id: target,
+            }),
+            OperationV2::SetStyle { .. } => {
+                losses.push(Loss {
+                    location,
+                    kind: LossKind::Dropped(OpType::SetStyle),
+                });
+                continue;
+            }
+            OperationV2::BatchStart { batch_id } => Operation::BatchStart(BatchStart { batch_id }),
+            OperationV2::BatchCommit { batch_id } => {
+                Operation::BatchCommit(BatchCommit { batch_id })
+            }
+            OperationV2::AttachEvent {
+                target,
+                event_idx,
+                handler_id,
+            } => Operation::AttachEvent(AttachEvent {
+                instance_id: target,
+                event_type_id: event_idx as u32,
+                handler_id: handler_id as u32,
+            }),
+            OperationV2::SetProperty {
+                target,
+                name_idx,
+                value,
+            } => Operation::SetProperty(SetProperty {
+                instance_id: target,
+                prop_name_id: name_idx as u32,
+                value: match value {
+                    PropertyValueV2::String(idx) => PropertyValue::String(idx as u32),
+                    PropertyValueV2::Number(idx) => {
+                        let text = stream
+                            .strings
+                            .get(idx as usize)
+                            .ok_or_else(|| error(location, format!("unknown string {idx}")))?;
+                        PropertyValue::Number(
+                            text.parse()
+                                .map_err(|_| error(location, format!("invalid number {text:?}")))?,
+                        )
+                    }
+                    PropertyValueV2::Bool(b) => PropertyValue::Boolean(b),
+                    PropertyValueV2::Null => PropertyValue::Null,
+                },
+            }),
+            OperationV2::AppendChild { target: 0, .. } => {
+                losses.push(Loss {
+                    location,
+                    kind: LossKind::Dropped(OpType::AppendChild),
+                });
+                continue;
+            }
+            OperationV2::AppendChild { target, child_id } => Operation::AppendChild(AppendChild {
+                parent_id: target,
+                child_id,
+            }),
+        });
+    }
+
+    Ok(Transcoded {
+        output: HtipPayload {
+            strings: stream.strings.clone(),
+            templates,
+            operations,
+        },
+        losses,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::deserializer::HtipStream;
+    use crate::serializer::{encode_signed, HtipWriter, StreamOptions};
+    use crate::signature::StreamContext;
+    use crate::validate::{validate_payload, validate_v2};
+    use ed25519_dalek::SigningKey;
+
+    fn root_text(slot_id: u16) -> Binding {
+        Binding {
+            slot_id,
+            binding_type: BindingType::Text,
+            path: vec![],
+        }
+    }
+
+    #[test]
+    fn test_v1_to_v2_and_back() {
+        let mut writer = HtipWriter::new();
+        writer.write_template(0, "<ul></ul>", vec![root_text(0)]);
+        writer.write_template(1, "<li></li>", vec![root_text(0), root_text(1)]);
+        writer.write_instantiate(10, 0, 0);
+        writer.write_batch_start(3);
+        writer.write_instantiate(20, 1, 10);
+        writer.write_patch_text(20, 0, "First");
+        writer.write_patch_attr(20, 1, "data-id", "20");
+        writer.write_class_toggle(20, "done", true);
+        writer.write_attach_event(20, "click", 5);
+        writer.write_set_property(20, "tabIndex", PropertyValue::Number(2.5));
+        writer.write_set_property(20, "hidden", PropertyValue::Boolean(false));
+        writer.write_batch_commit(3);
+        writer.write_append_child(10, 20);
+        writer.write_remove_node(10);
+
+        let key = SigningKey::from_bytes(&[4u8; 32]);
+        let binary = writer.finish_and_sign(&key).unwrap();
+        let stream =
+            HtipStream::new(&binary, &key.verifying_key(), &StreamContext::new(0)).unwrap();
+
+        let v2 = v1_to_v2(stream.payload()).unwrap();
+        assert!(v2.is_lossless(), "{:?}", v2.losses);
+        let bytes = v2.output.encode().unwrap();
+        assert!(validate_v2(&bytes).is_empty(), "{:?}", validate_v2(&bytes));
+        assert!(v2.output.operations.contains(&OperationV2::Clone {
+            target: 2,
+            template_id: 1,
+            parent_id: 1,
+        }));
+
+        // Back to v1: valid, signed, and the same v2 stream again
+        let v1 = v2_to_v1(&v2.output).unwrap();
+        assert!(v1.is_lossless());
+        let problems = validate_payload(&v1.output);
+        assert!(problems.is_empty(), "{problems:?}");
+        let signed = encode_signed(&v1.output, &StreamOptions::default(), &key).unwrap();
+        let reread =
+            HtipStream::new(&signed, &key.verifying_key(), &StreamContext::new(0)).unwrap();
+        assert_eq!(v1_to_v2(reread.payload()).unwrap().output, v2.output);
+    }
+
+    #[test]
+    fn test_v2_to_v1_and_back() {
+        let stream = assemble(
+            r#"
+.htip v2
+string s0 "<p></p>"
+string s1 "Hello"
+string s2 "value"
+string s3 "7"
+template id=0 html=s0 slots=1
+clone target=1 template=0 parent=0
+clone target=2 template=0 parent=1
+patch_text target=2 text=s1
+set_property target=2 name=s2 number=s3
+set_property target=2 name=s2 value=null
+append_child target=1 child=2
+remove target=1
+"#,
+        )
+        .unwrap();
+        let v2 = StreamV2::decode(&stream).unwrap();
+
+        let v1 = v2_to_v1(&v2).unwrap();
+        assert!(v1.is_lossless());
+        let problems = validate_payload(&v1.output);
+        assert!(problems.is_empty(), "{problems:?}");
+
+        let back = v1_to_v2(&v1.output).unwrap();
+        assert!(back.is_lossless(), "{:?}", back.losses);
+        assert_eq!(back.output.encode().unwrap(), stream);
+    }
+
+    #[test]
+    fn test_losses_are_reported() {
+        let mut writer = HtipWriter::new();
+        let deep = Binding {
+            slot_id: 0,
+            binding_type: BindingType::Text,
+            path: vec![0, 1],
+        };
+        writer.write_template(0, "<div><p><b></b></p></div>", vec![deep]);
+        writer.write_instantiate(1, 0, 0);
+        writer.write_patch_text(1, 0, "deep");
+
+        let key = SigningKey::from_bytes(&[4u8; 32]);
+        let binary = writer.finish_and_sign(&key).unwrap();
+        let stream =
+            HtipStream::new(&binary, &key.verifying_key(), &StreamContext::new(0)).unwrap();
+        let v2 = v1_to_v2(stream.payload()).unwrap();
+        let losses: Vec<String> = v2.losses.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            losses,
+            [
+                "template 0: bindings reduced to a slot count",
+                "op 2: slot 0 is below the root, patching the whole node",
+            ]
+        );
+
+        let with_style = StreamV2 {
+            operations: vec![
+                OperationV2::SetStyle {
+                    target: 1,
+                    name_idx: 0,
+                    value_idx: 0,
+                },
+                OperationV2::AppendChild {
+                    target: 0,
+                    child_id: 1,
+                },
+            ],
+            ..StreamV2::default()
+        };
+        let v1 = v2_to_v1(&with_style).unwrap();
+        assert_eq!(v1.losses[0].kind, LossKind::Dropped(OpType::SetStyle));
+        assert_eq!(v1.losses[1].kind, LossKind::Dropped(OpType::AppendChild));
+        assert!(v1.output.operations.is_empty());
+
+        // Values v2 cannot hold are errors, not losses
+        let mut writer = HtipWriter::new();
+        writer.write_attach_event(0, "click", 70_000);
+        let binary = writer.finish_and_sign(&key).unwrap();
+        let stream =
+            HtipStream::new(&binary, &key.verifying_key(), &StreamContext::new(0)).unwrap();
+        assert!(matches!(v1_to_v2(stream.payload()), Err(DxBinaryError::Transcode(_))));
+
+        // IDs past u16 only widen the output
+        let mut writer = HtipWriter::new();
+        writer.write_batch_start(70_000);
+        writer.write_batch_commit(70_000);
+        let binary = writer.finish_and_sign(&key).unwrap();
+        let stream =
+            HtipStream::new(&binary, &key.verifying_key(), &StreamContext::new(0)).unwrap();
+        let v2 = v1_to_v2(stream.payload()).unwrap().output;
+        assert_ne!(v2.flags & dx_packet::HtipHeader::FLAG_WIDE_IDS, 0);
+        assert!(validate_v2(&v2.encode().unwrap()).is_empty());
+    }
+}
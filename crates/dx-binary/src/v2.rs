@@ -0,0 +1,476 @@
+//! # HTIP v2 Streams
+//!
+//! Owned model of the fixed-layout v2 format (`dx_packet`, "DX") that
+//! dx-client renders and dx-compiler's `generate_htip` writes.
+//!
+//! Node IDs follow `dx_client::NodeRegistry`: clones get 1, 2, 3, ... in
+//! order and 0 is the root. Batch ops carry their batch ID in `target_id`.
+//! Node, template and batch IDs are `u16` on the wire unless `flags` has
+//! `HtipHeader::FLAG_WIDE_IDS`.
+
+use dx_packet::{IdWidth, OpType, PropKind, StringEntry, TemplateEntry};
+
+use crate::{asm::Reader, DxBinaryError, Result};
+
+/// A decoded v2 stream
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamV2 {
+    /// Header flags (bit 0 = has_strings, bit 1 = has_templates, bit 2 = wide IDs)
+    pub flags: u8,
+    pub strings: Vec<String>,
+    pub templates: Vec<TemplateV2>,
+    pub operations: Vec<OperationV2>,
+}
+
+/// Template dictionary entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TemplateV2 {
+    pub id: u32,
+    /// String table index of the HTML
+    pub html_string_idx: u16,
+    pub slot_count: u8,
+}
+
+/// Value of a `SetProperty` op
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyValueV2 {
+    /// String table index
+    String(u16),
+    /// String table index of the number in decimal
+    Number(u16),
+    Bool(bool),
+    Null,
+}
+
+/// One v2 opcode with its payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationV2 {
+    Clone {
+        target: u32,
+        template_id: u32,
+        parent_id: u32,
+    },
+    PatchText {
+        target: u32,
+        string_idx: u16,
+    },
+    PatchAttr {
+        target: u32,
+        name_idx: u16,
+        value_idx: u16,
+    },
+    ClassToggle {
+        target: u32,
+        class_idx: u16,
+        enable: bool,
+    },
+    Remove {
+        target: u32,
+    },
+    SetStyle {
+        target: u32,
+        name_idx: u16,
+        value_idx: u16,
+    },
+    BatchStart {
+        batch_id: u32,
+    },
+    BatchCommit {
+        batch_id: u32,
+    },
+    AttachEvent {
+        target: u32,
+        event_idx: u16,
+        handler_id: u16,
+    },
+    SetProperty {
+        target: u32,
+        name_idx: u16,
+        value: PropertyValueV2,
+    },
+    /// `target` is the new parent (0 = root)
+    AppendChild {
+        target: u32,
+        child_id: u32,
+    },
+}
+
+impl OperationV2 {
+    pub fn op_type(&self) -> OpType {
+        match self {
+            Self::Clone { .. } => OpType::Clone,
+            Self::PatchText { .. } => OpType::PatchText,
+            Self::PatchAttr { .. } => OpType::PatchAttr,
+            Self::ClassToggle { .. } => OpType::ClassToggle,
+            Self::Remove { .. } => OpType::Remove,
+            Self::SetStyle { .. } => OpType::SetStyle,
+            Self::BatchStart { .. } => OpType::BatchStart,
+            Self::BatchCommit { .. } => OpType::BatchCommit,
+            Self::AttachEvent { .. } => OpType::AttachEvent,
+            Self::SetProperty { .. } => OpType::SetProperty,
+            Self::AppendChild { .. } => OpType::AppendChild,
+        }
+    }
+
+    /// `target_id` of the opcode header
+    fn target_id(&self) -> u32 {
+        match *self {
+            Self::Clone { target, .. }
+            | Self::PatchText { target, .. }
+            | Self::PatchAttr { target, .. }
+            | Self::ClassToggle { target, .. }
+            | Self::Remove { target }
+            | Self::SetStyle { target, .. }
+            | Self::AttachEvent { target, .. }
+            | Self::SetProperty { target, .. }
+            | Self::AppendChild { target, .. } => target,
+            Self::BatchStart { batch_id } | Self::BatchCommit { batch_id } => batch_id,
+        }
+    }
+}
+
+fn malformed(message: &str) -> DxBinaryError {
+    DxBinaryError::InvalidV2(message.to_string())
+}
+
+impl StreamV2 {
+    /// Decode a complete stream
+    pub fn decode(stream: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(stream);
+        let truncated = || malformed("truncated stream");
+
+        let magic = reader.u16().ok_or_else(truncated)?;
+        let version = reader.u8().ok_or_else(truncated)?;
+        let flags = reader.u8().ok_or_else(truncated)?;
+        let template_count = reader.u16().ok_or_else(truncated)?;
+        let string_count = reader.u16().ok_or_else(truncated)?;
+        let opcode_count = reader.u32().ok_or_else(truncated)?;
+        let payload_size = reader.u32().ok_or_else(truncated)?;
+
+        if magic != dx_packet::HtipHeader::MAGIC {
+            return Err(malformed("invalid magic"));
+        }
+        if version != dx_packet::HtipHeader::VERSION {
+            return Err(malformed("unsupported version"));
+        }
+        if payload_size as usize != stream.len() - dx_packet::HtipHeader::SIZE {
+            return Err(malformed("payload size does not match stream length"));
+        }
+
+        // String entries, then their data packed back to back
+        let entries =
+            reader.bytes(string_count as usize * StringEntry::SIZE).ok_or_else(truncated)?;
+        let spans: Vec<(usize, usize)> = entries
+            .chunks_exact(StringEntry::SIZE)
+            .map(|e| {
+                let offset = u32::from_le_bytes([e[0], e[1], e[2], e[3]]) as usize;
+                (offset, offset + u16::from_le_bytes([e[4], e[5]]) as usize)
+            })
+            .collect();
+        let data_len = spans.iter().map(|&(_, end)| end).max().unwrap_or(0);
+        let data = reader.bytes(data_len).ok_or_else(truncated)?;
+        let strings = spans
+            .iter()
+            .map(|&(start, end)| {
+                std::str::from_utf8(&data[start..end])
+                    .map(str::to_string)
+                    .map_err(|_| malformed("invalid UTF-8 string"))
+            })
+            .collect::<Result<Vec<String>>>()?;
+
+        let width = IdWidth::from_flags(flags);
+        let mut templates = Vec::with_capacity(template_count as usize);
+        for _ in 0..template_count {
+            let entry = reader.bytes(TemplateEntry::SIZE).ok_or_else(truncated)?;
+            let html = width.id_size();
+            templates.push(TemplateV2 {
+                id: width.read_id(entry, 0).ok_or_else(truncated)?,
+                html_string_idx: u16::from_le_bytes([entry[html], entry[html + 1]]),
+                slot_count: entry[html + 2],
+            });
+        }
+
+        // Every op takes at least its header
+        let header_size = width.opcode_header_size();
+        let mut operations =
+            Vec::with_capacity((opcode_count as usize).min(stream.len() / header_size));
+        for _ in 0..opcode_count {
+            let (op_type, target) = reader.opcode_header(width).ok_or_else(truncated)?;
+            let op_type = OpType::from_u8(op_type).ok_or_else(|| malformed("invalid opcode"))?;
+            let payload = reader.bytes(width.payload_size(op_type)).ok_or_else(truncated)?;
+            let arg = |i: usize| u16::from_le_bytes([payload[2 * i], payload[2 * i + 1]]);
+            let id = |i: usize| width.read_id(payload, i * width.id_size()).unwrap_or(0);
+
+            operations.push(match op_type {
+                OpType::Clone => OperationV2::Clone {
+                    target,
+                    template_id: id(0),
+                    parent_id: id(1),
+                },
+                OpType::PatchText => OperationV2::PatchText {
+                    target,
+                    string_idx: arg(0),
+                },
+                OpType::PatchAttr => OperationV2::PatchAttr {
+                    target,
+                    name_idx: arg(0),
+                    value_idx: arg(1),
+                },
+                OpType::ClassToggle => OperationV2::ClassToggle {
+                    target,
+                    class_idx: arg(0),
+                    enable: payload[2] != 0,
+                },
+                OpType::Remove => OperationV2::Remove { target },
+                OpType::SetStyle => OperationV2::SetStyle {
+                    target,
+                    name_idx: arg(0),
+                    value_idx: arg(1),
+                },
+                OpType::BatchStart => OperationV2::BatchStart { batch_id: target },
+                OpType::BatchCommit => OperationV2::BatchCommit { batch_id: target },
+                OpType::AttachEvent => OperationV2::AttachEvent {
+                    target,
+                    event_idx: arg(0),
+                    handler_id: arg(1),
+                },
+                OpType::SetProperty => {
+                    let kind = PropKind::from_u8(payload[2])
+                        .ok_or_else(|| malformed("invalid property kind"))?;
+                    let value = match kind {
+                        PropKind::String => PropertyValueV2::String(arg(2)),
+                        PropKind::Number => PropertyValueV2::Number(arg(2)),
+                        PropKind::Bool => PropertyValueV2::Bool(arg(2) != 0),
+                        PropKind::Null => PropertyValueV2::Null,
+                    };
+                    OperationV2::SetProperty {
+                        target,
+                        name_idx: arg(0),
+                        value,
+                    }
+                }
+                OpType::AppendChild => OperationV2::AppendChild {
+                    target,
+                    child_id: id(0),
+                },
+            });
+        }
+
+        if reader.offset() != stream.len() {
+            return Err(malformed("trailing bytes after the last opcode"));
+        }
+
+        Ok(Self {
+            flags,
+            strings,
+            templates,
+            operations,
+        })
+    }
+
+    /// Encode with the same layout as dx-compiler's `generate_htip`
+    ///
+    /// IDs are written in the width `flags` selects; a compact stream with
+    /// an ID above `u16::MAX` is an error.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let width = IdWidth::from_flags(self.flags);
+        let push_id = |out: &mut Vec<u8>, id: u32| match width {
+            IdWidth::Compact => {
+                let id = u16::try_from(id)
+                    .map_err(|_| DxBinaryError::InvalidV2(format!("ID {id} needs wide IDs")))?;
+                out.extend(id.to_le_bytes());
+                Ok(())
+            }
+            IdWidth::Wide => {
+                out.extend(id.to_le_bytes());
+                Ok::<(), DxBinaryError>(())
+            }
+        };
+
+        let string_count =
+            u16::try_from(self.strings.len()).map_err(|_| malformed("more than 65535 strings"))?;
+        let template_count = u16::try_from(self.templates.len())
+            .map_err(|_| malformed("more than 65535 templates"))?;
+        let opcode_count =
+            u32::try_from(self.operations.len()).map_err(|_| malformed("too many opcodes"))?;
+
+        let mut string_entries = Vec::with_capacity(self.strings.len() * StringEntry::SIZE);
+        let mut string_data: Vec<u8> = Vec::new();
+        for s in &self.strings {
+            let len = u16::try_from(s.len())
+                .map_err(|_| DxBinaryError::InvalidV2(format!("string of {} bytes", s.len())))?;
+            string_entries.extend((string_data.len() as u32).to_le_bytes());
+            string_entries.extend(len.to_le_bytes());
+            string_entries.extend(0u16.to_le_bytes());
+            string_data.extend(s.as_bytes());
+        }
+
+        let mut template_entries = Vec::with_capacity(self.templates.len() * TemplateEntry::SIZE);
+        for template in &self.templates {
+            push_id(&mut template_entries, template.id)?;
+            template_entries.extend(template.html_string_idx.to_le_bytes());
+            template_entries.push(template.slot_count);
+            let end = template_entries.len().next_multiple_of(TemplateEntry::SIZE);
+            template_entries.resize(end, 0);
+        }
+
+        let mut opcodes = Vec::new();
+        for op in &self.operations {
+            opcodes.push(op.op_type() as u8);
+            opcodes.resize(opcodes.len() + width.opcode_header_size() - 1 - width.id_size(), 0);
+            push_id(&mut opcodes, op.target_id())?;
+
+            // IDs first, then u16 fields, zero-padded to the payload size
+            let end = opcodes.len() + width.payload_size(op.op_type());
+            let payload: &[u16] = match *op {
+                OperationV2::Clone {
+                    template_id,
+                    parent_id,
+                    ..
+                } => {
+                    push_id(&mut opcodes, template_id)?;
+                    push_id(&mut opcodes, parent_id)?;
+                    &[]
+                }
+                OperationV2::PatchText { string_idx, .. } => &[string_idx, 0],
+                OperationV2::PatchAttr {
+                    name_idx,
+                    value_idx,
+                    ..
+                }
+                | OperationV2::SetStyle {
+                    name_idx,
+                    value_idx,
+                    ..
+                } => &[name_idx, value_idx],
+                // u16 + enable u8 + reserved u8
+                OperationV2::ClassToggle {
+                    class_idx, enable, ..
+                } => &[class_idx, enable as u16],
+                OperationV2::Remove { .. }
+                | OperationV2::BatchStart { .. }
+                | OperationV2::BatchCommit { .. } => &[],
+                OperationV2::AttachEvent {
+                    event_idx,
+                    handler_id,
+                    ..
+                } => &[event_idx, handler_id],
+                // u16 + kind u8 + reserved u8 + u16
+                OperationV2::SetProperty {
+                    name_idx, value, ..
+                } => &match value {
+                    PropertyValueV2::String(idx) => [name_idx, PropKind::String as u16, idx],
+                    PropertyValueV2::Number(idx) => [name_idx, PropKind::Number as u16, idx],
+                    PropertyValueV2::Bool(b) => [name_idx, PropKind::Bool as u16, b as u16],
+                    PropertyValueV2::Null => [name_idx, PropKind::Null as u16, 0],
+                },
+                OperationV2::AppendChild { child_id, .. } => {
+                    push_id(&mut opcodes, child_id)?;
+                    &[]
+                }
+            };
+            for field in payload {
+                opcodes.extend(field.to_le_bytes());
+            }
+            opcodes.resize(end, 0);
+        }
+
+        let payload_size =
+            string_entries.len() + string_data.len() + template_entries.len() + opcodes.len();
+
+        let mut stream = Vec::with_capacity(dx_packet::HtipHeader::SIZE + payload_size);
+        stream.extend(dx_packet::HtipHeader::MAGIC.to_le_bytes());
+        stream.push(dx_packet::HtipHeader::VERSION);
+        stream.push(self.flags);
+        stream.extend(template_count.to_le_bytes());
+        stream.extend(string_count.to_le_bytes());
+        stream.extend(opcode_count.to_le_bytes());
+        stream.extend((payload_size as u32).to_le_bytes());
+        stream.extend(string_entries);
+        stream.extend(string_data);
+        stream.extend(template_entries);
+        stream.extend(opcodes);
+
+        Ok(stream)
+    }
+
+    /// Flags the compiler sets for this content (wide IDs only if needed)
+    pub fn default_flags(&self) -> u8 {
+        let max_id = self
+            .templates
+            .iter()
+            .map(|t| t.id)
+            .chain(self.operations.iter().flat_map(|op| match *op {
+                OperationV2::Clone {
+                    target,
+                    template_id,
+                    parent_id,
+                } => vec![target, template_id, parent_id],
+                OperationV2::AppendChild { target, child_id } => vec![target, child_id],
+                _ => vec![op.target_id()],
+            }))
+            .max()
+            .unwrap_or(0);
+        (!self.strings.is_empty()) as u8
+            | (((!self.templates.is_empty()) as u8) << 1)
+            | IdWidth::for_max_id(max_id).flags()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn test_decode_encode_round_trip() {
+        let stream = assemble(
+            r#"
+.htip v2
+string s0 "<li></li>"
+string s1 "1.5"
+string s2 "value"
+template id=3 html=s0 slots=2
+clone target=1 template=3 parent=0
+batch_start target=4
+set_property target=1 name=s2 number=s1
+set_property target=1 name=s2 value=false
+attach_event target=1 event=s2 handler=9
+batch_commit target=4
+append_child target=0 child=1
+"#,
+        )
+        .unwrap();
+
+        let decoded = StreamV2::decode(&stream).unwrap();
+        assert_eq!(decoded.flags, decoded.default_flags());
+        assert_eq!(decoded.templates[0].slot_count, 2);
+        assert_eq!(
+            decoded.operations[2],
+            OperationV2::SetProperty {
+                target: 1,
+                name_idx: 2,
+                value: PropertyValueV2::Number(1),
+            }
+        );
+        assert_eq!(decoded.encode().unwrap(), stream);
+
+        // IDs past u16 switch to the wide layout
+        let mut wide = decoded.clone();
+        wide.templates[0].id = 70_000;
+        wide.operations[0] = OperationV2::Clone {
+            target: 1,
+            template_id: 70_000,
+            parent_id: 0,
+        };
+        assert!(matches!(wide.encode(), Err(DxBinaryError::InvalidV2(_))));
+        wide.flags = wide.default_flags();
+        assert_eq!(IdWidth::from_flags(wide.flags), IdWidth::Wide);
+        let bytes = wide.encode().unwrap();
+        assert_eq!(StreamV2::decode(&bytes).unwrap(), wide);
+
+        let mut truncated = stream.clone();
+        truncated.pop();
+        assert!(matches!(StreamV2::decode(&truncated), Err(DxBinaryError::InvalidV2(_))));
+    }
+}
@@ -0,0 +1,654 @@
+//! # HTIP Validator
+//!
+//! Referential checks before a stream touches the DOM: every template,
+//! string, instance and slot reference resolves, and batches are balanced.
+//! Covers v1 payloads (`HtipPayload`) and v2 (`dx_packet`) streams.
+//!
+//! All problems are collected, each with the op index (or dictionary entry)
+//! it was found at, so the server can reject a build and the client can
+//! refuse a stream up front instead of failing halfway through a render.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use dx_packet::{IdWidth, OpType, PropKind, StringEntry, TemplateEntry, MAX_TEMPLATES};
+
+use crate::{
+    asm::Reader,
+    opcodes::{BindingType, Operation, PropertyValue, TemplateDef},
+    protocol::HtipPayload,
+};
+
+/// Where a problem was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// Stream header or section layout
+    Header,
+    /// Template dictionary entry (by template ID)
+    Template(u32),
+    /// Operation (by index in the op stream)
+    Op(usize),
+}
+
+/// What is wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// String ID past the end of the string table
+    StringOutOfRange(u32),
+    /// String bytes are not valid UTF-8
+    InvalidString(u32),
+    /// Template ID not defined (yet)
+    UnknownTemplate(u32),
+    /// Template ID defined twice in the dictionary
+    DuplicateTemplate(u32),
+    /// Instance (v2: node) ID not live
+    UnknownInstance(u32),
+    /// Instance (v2: node) ID already live
+    DuplicateInstance(u32),
+    /// Slot not declared by the instance's template bindings
+    UndeclaredSlot {
+        instance_id: u32,
+        template_id: u16,
+        slot_id: u16,
+    },
+    /// v2 clone target differs from the ID the runtime will assign
+    CloneTargetMismatch { expected: u32, found: u32 },
+    /// Batch start with the ID of a batch that is still open
+    BatchReopened(u32),
+    /// Batch commit without an open batch
+    BatchNotOpen(u32),
+    /// Batch commit for a batch other than the innermost open one
+    BatchMismatch { open: u32, commit: u32 },
+    /// Batch never committed (reported at its start)
+    BatchNotCommitted(u32),
+    /// v2 opcode byte not in `OpType`
+    InvalidOpcode(u8),
+    /// v2 layout error (validation stops here)
+    Malformed(&'static str),
+}
+
+/// One validation problem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub location: Location,
+    pub kind: ProblemKind,
+}
+
+impl Problem {
+    /// Index of the offending op, if the problem is in the op stream
+    pub fn op_index(&self) -> Option<usize> {
+        match self.location {
+            Location::Op(index) => Some(index),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Location::Header => write!(f, "header: ")?,
+            Location::Template(id) => write!(f, "template {id}: ")?,
+            Location::Op(index) => write!(f, "op {index}: ")?,
+        }
+
+        match &self.kind {
+            ProblemKind::StringOutOfRange(id) => write!(f, "string {id} out of range"),
+            ProblemKind::InvalidString(id) => write!(f, "string {id} is not valid UTF-8"),
+            ProblemKind::UnknownTemplate(id) => write!(f, "unknown template {id}"),
+            ProblemKind::DuplicateTemplate(id) => write!(f, "template {id} defined twice"),
+            ProblemKind::UnknownInstance(id) => write!(f, "unknown instance {id}"),
+            ProblemKind::DuplicateInstance(id) => write!(f, "instance {id} already exists"),
+            ProblemKind::UndeclaredSlot {
+                instance_id,
+                template_id,
+                slot_id,
+            } => write!(
+                f,
+                "slot {slot_id} of instance {instance_id} not declared by template {template_id}"
+            ),
+            ProblemKind::CloneTargetMismatch { expected, found } => {
+                write!(f, "clone target {found}, runtime assigns {expected}")
+            }
+            ProblemKind::BatchReopened(id) => write!(f, "batch {id} is already open"),
+            ProblemKind::BatchNotOpen(id) => write!(f, "commit of batch {id} with no open batch"),
+            ProblemKind::BatchMismatch { open, commit } => {
+                write!(f, "commit of batch {commit} while batch {open} is open")
+            }
+            ProblemKind::BatchNotCommitted(id) => write!(f, "batch {id} is never committed"),
+            ProblemKind::InvalidOpcode(op) => write!(f, "invalid opcode {op}"),
+            ProblemKind::Malformed(what) => write!(f, "malformed stream: {what}"),
+        }
+    }
+}
+
+/// Collects problems while walking a stream
+struct Checker {
+    problems: Vec<Problem>,
+    location: Location,
+    string_count: u32,
+    /// Open batches, innermost last (ID, op index of the start)
+    batches: Vec<(u32, usize)>,
+}
+
+impl Checker {
+    fn new(string_count: u32) -> Self {
+        Self {
+            problems: Vec::new(),
+            location: Location::Header,
+            string_count,
+            batches: Vec::new(),
+        }
+    }
+
+    fn report(&mut self, kind: ProblemKind) {
+        self.problems.push(Problem {
+            location: self.location,
+            kind,
+        });
+    }
+
+    fn string(&mut self, id: u32) {
+        if id >= self.string_count {
+            self.report(ProblemKind::StringOutOfRange(id));
+        }
+    }
+
+    fn batch_start(&mut self, id: u32, index: usize) {
+        if self.batches.iter().any(|&(open, _)| open == id) {
+            self.report(ProblemKind::BatchReopened(id));
+        }
+        self.batches.push((id, index));
+    }
+
+    fn batch_commit(&mut self, id: u32) {
+        match self.batches.last() {
+            None => self.report(ProblemKind::BatchNotOpen(id)),
+            Some(&(open, _)) if open != id => {
+                self.report(ProblemKind::BatchMismatch { open, commit: id })
+            }
+            Some(_) => {
+                self.batches.pop();
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<Problem> {
+        for (id, index) in std::mem::take(&mut self.batches) {
+            self.location = Location::Op(index);
+            self.report(ProblemKind::BatchNotCommitted(id));
+        }
+        self.problems
+    }
+}
+
+/// Validate a v1 payload
+///
+/// Templates come from the dictionary and from `TemplateDef` ops (from the
+/// op on). Instance 0 is the root: a valid parent, never a target.
+pub fn validate_payload(payload: &HtipPayload) -> Vec<Problem> {
+    let mut checker = Checker::new(payload.strings.len() as u32);
+
+    // Template ID -> declared slots
+    let mut templates: HashMap<u16, HashSet<u16>> = HashMap::new();
+    for template in &payload.templates {
+        checker.location = Location::Template(template.id.into());
+        check_template(&mut checker, template);
+        if templates.insert(template.id, declared_slots(template)).is_some() {
+            checker.report(ProblemKind::DuplicateTemplate(template.id.into()));
+        }
+    }
+
+    // Instance ID -> template ID
+    let mut instances: HashMap<u32, u16> = HashMap::new();
+
+    for (index, op) in payload.operations.iter().enumerate() {
+        checker.location = Location::Op(index);
+
+        match op {
+            Operation::TemplateDef(template) => {
+                check_template(&mut checker, template);
+                templates.insert(template.id, declared_slots(template));
+            }
+            Operation::Instantiate(op) => {
+                if !templates.contains_key(&op.template_id) {
+                    checker.report(ProblemKind::UnknownTemplate(op.template_id.into()));
+                }
+                if op.parent_id != 0 {
+                    check_instance(&mut checker, &instances, op.parent_id);
+                }
+                if op.instance_id == 0 || instances.contains_key(&op.instance_id) {
+                    checker.report(ProblemKind::DuplicateInstance(op.instance_id));
+                } else {
+                    instances.insert(op.instance_id, op.template_id);
+                }
+            }
+            Operation::PatchText(op) => {
+                check_slot(&mut checker, &instances, &templates, op.instance_id, op.slot_id);
+                checker.string(op.string_id);
+            }
+            Operation::PatchAttr(op) => {
+                check_slot(&mut checker, &instances, &templates, op.instance_id, op.slot_id);
+                checker.string(op.attr_name_id);
+                checker.string(op.value_id);
+            }
+            Operation::PatchClassToggle(op) => {
+                check_instance(&mut checker, &instances, op.instance_id);
+                checker.string(op.class_name_id);
+            }
+            Operation::AttachEvent(op) => {
+                check_instance(&mut checker, &instances, op.instance_id);
+                checker.string(op.event_type_id);
+            }
+            Operation::RemoveNode(op) => {
+                if instances.remove(&op.instance_id).is_none() {
+                    checker.report(ProblemKind::UnknownInstance(op.instance_id));
+                }
+            }
+            Operation::BatchStart(op) => checker.batch_start(op.batch_id, index),
+            Operation::BatchCommit(op) => checker.batch_commit(op.batch_id),
+            Operation::SetProperty(op) => {
+                check_instance(&mut checker, &instances, op.instance_id);
+                checker.string(op.prop_name_id);
+                if let PropertyValue::String(id) = op.value {
+                    checker.string(id);
+                }
+            }
+            Operation::AppendChild(op) => {
+                check_instance(&mut checker, &instances, op.parent_id);
+                check_instance(&mut checker, &instances, op.child_id);
+            }
+        }
+    }
+
+    checker.finish()
+}
+
+fn check_instance(checker: &mut Checker, instances: &HashMap<u32, u16>, id: u32) -> Option<u16> {
+    let template = instances.get(&id).copied();
+    if template.is_none() {
+        checker.report(ProblemKind::UnknownInstance(id));
+    }
+    template
+}
+
+fn check_slot(
+    checker: &mut Checker,
+    instances: &HashMap<u32, u16>,
+    templates: &HashMap<u16, HashSet<u16>>,
+    instance_id: u32,
+    slot_id: u16,
+) {
+    let Some(template_id) = check_instance(checker, instances, instance_id) else {
+        return;
+    };
+    if !templates.get(&template_id).is_some_and(|slots| slots.contains(&slot_id)) {
+        checker.report(ProblemKind::UndeclaredSlot {
+            instance_id,
+            template_id,
+            slot_id,
+        });
+    }
+}
+
+fn check_template(checker: &mut Checker, template: &TemplateDef) {
+    checker.string(template.html_string_id);
+    for binding in &template.bindings {
+        match binding.binding_type {
+            BindingType::Attribute { attr_name_id: id }
+            | BindingType::Property { prop_name_id: id }
+            | BindingType::Event { event_type_id: id } => checker.string(id),
+            BindingType::Text | BindingType::Class => {}
+        }
+    }
+}
+
+fn declared_slots(template: &TemplateDef) -> HashSet<u16> {
+    template.bindings.iter().map(|b| b.slot_id).collect()
+}
+
+/// Validate a v2 stream (as produced by dx-compiler's `generate_htip`)
+///
+/// Node IDs follow `dx_client::NodeRegistry`: clones get 1, 2, 3, ... in
+/// order, and 0 is the root. Batch ops carry their batch ID in `target_id`.
+pub fn validate_v2(stream: &[u8]) -> Vec<Problem> {
+    let mut checker = Checker::new(0);
+    let mut reader = Reader::new(stream);
+
+    macro_rules! read {
+        ($read:expr, $what:literal) => {
+            match $read {
+                Some(value) => value,
+                None => {
+                    checker.report(ProblemKind::Malformed(concat!("truncated ", $what)));
+                    return checker.problems;
+                }
+            }
+        };
+    }
+
+    // Header
+    let magic = read!(reader.u16(), "header");
+    let version = read!(reader.u8(), "header");
+    let flags = read!(reader.u8(), "header");
+    let template_count = read!(reader.u16(), "header");
+    let string_count = read!(reader.u16(), "header");
+    let opcode_count = read!(reader.u32(), "header");
+    let payload_size = read!(reader.u32(), "header");
+
+    if magic != dx_packet::HtipHeader::MAGIC {
+        checker.report(ProblemKind::Malformed("invalid magic"));
+        return checker.problems;
+    }
+    if version != dx_packet::HtipHeader::VERSION {
+        checker.report(ProblemKind::Malformed("unsupported version"));
+        return checker.problems;
+    }
+    if payload_size as usize != stream.len() - dx_packet::HtipHeader::SIZE {
+        checker.report(ProblemKind::Malformed("payload size does not match stream length"));
+    }
+    if template_count > MAX_TEMPLATES {
+        checker.report(ProblemKind::Malformed("too many templates"));
+    }
+    checker.string_count = string_count as u32;
+    let width = IdWidth::from_flags(flags);
+
+    // String table: entries, then data
+    let entries = read!(reader.bytes(string_count as usize * StringEntry::SIZE), "string table");
+    let spans: Vec<(usize, usize)> = entries
+        .chunks_exact(StringEntry::SIZE)
+        .map(|e| {
+            let offset = u32::from_le_bytes([e[0], e[1], e[2], e[3]]) as usize;
+            (offset, offset + u16::from_le_bytes([e[4], e[5]]) as usize)
+        })
+        .collect();
+    let data_len = spans.iter().map(|&(_, end)| end).max().unwrap_or(0);
+    let data = read!(reader.bytes(data_len), "string data");
+    for (id, &(start, end)) in spans.iter().enumerate() {
+        if std::str::from_utf8(&data[start..end]).is_err() {
+            checker.report(ProblemKind::InvalidString(id as u32));
+        }
+    }
+
+    // Template dictionary
+    let mut templates = HashSet::new();
+    for _ in 0..template_count {
+        let entry = read!(reader.bytes(TemplateEntry::SIZE), "template dictionary");
+        let id = read!(width.read_id(entry, 0), "template dictionary");
+        let html = width.id_size();
+        checker.location = Location::Template(id);
+        checker.string(u16::from_le_bytes([entry[html], entry[html + 1]]) as u32);
+        if !templates.insert(id) {
+            checker.report(ProblemKind::DuplicateTemplate(id));
+        }
+    }
+
+    // Opcodes
+    let mut nodes: HashSet<u32> = HashSet::new();
+    let mut next_node: u32 = 1;
+    for index in 0..opcode_count as usize {
+        checker.location = Location::Op(index);
+        let (op, target) = read!(reader.opcode_header(width), "opcode");
+
+        let Some(op_type) = OpType::from_u8(op) else {
+            // Payload size is unknown, nothing after this can be read
+            checker.report(ProblemKind::InvalidOpcode(op));
+            return checker.problems;
+        };
+
+        let node = |checker: &mut Checker, id: u32| {
+            if !nodes.contains(&id) {
+                checker.report(ProblemKind::UnknownInstance(id));
+            }
+        };
+
+        match op_type {
+            OpType::Clone => {
+                let template = read!(reader.id(width), "clone payload");
+                let parent = read!(reader.id(width), "clone payload");
+                if !templates.contains(&template) {
+                    checker.report(ProblemKind::UnknownTemplate(template));
+                }
+                if parent != 0 {
+                    node(&mut checker, parent);
+                }
+                if target != next_node {
+                    checker.report(ProblemKind::CloneTargetMismatch {
+                        expected: next_node,
+                        found: target,
+                    });
+                }
+                nodes.insert(next_node);
+                next_node += 1;
+            }
+            OpType::PatchText => {
+                let text = read!(reader.u16(), "patch_text payload");
+                read!(reader.u16(), "patch_text payload");
+                node(&mut checker, target);
+                checker.string(text as u32);
+            }
+            OpType::PatchAttr | OpType::SetStyle => {
+                let name = read!(reader.u16(), "attribute payload");
+                let value = read!(reader.u16(), "attribute payload");
+                node(&mut checker, target);
+                checker.string(name as u32);
+                checker.string(value as u32);
+            }
+            OpType::ClassToggle => {
+                let class = read!(reader.u16(), "class_toggle payload");
+                read!(reader.u16(), "class_toggle payload");
+                node(&mut checker, target);
+                checker.string(class as u32);
+            }
+            OpType::Remove => {
+                if !nodes.remove(&target) {
+                    checker.report(ProblemKind::UnknownInstance(target));
+                }
+            }
+            OpType::BatchStart => checker.batch_start(target, index),
+            OpType::BatchCommit => checker.batch_commit(target),
+            OpType::AttachEvent => {
+                let event = read!(reader.u16(), "attach_event payload");
+                read!(reader.u16(), "attach_event payload");
+                node(&mut checker, target);
+                checker.string(event as u32);
+            }
+            OpType::SetProperty => {
+                let name = read!(reader.u16(), "set_property payload");
+                let kind = read!(reader.u8(), "set_property payload");
+                read!(reader.u8(), "set_property payload");
+                let value = read!(reader.u16(), "set_property payload");
+                node(&mut checker, target);
+                checker.string(name as u32);
+                match PropKind::from_u8(kind) {
+                    Some(PropKind::String | PropKind::Number) => checker.string(value as u32),
+                    Some(PropKind::Bool | PropKind::Null) => {}
+                    None => checker.report(ProblemKind::Malformed("invalid property kind")),
+                }
+            }
+            OpType::AppendChild => {
+                let child = read!(reader.id(width), "append_child payload");
+                if width == IdWidth::Compact {
+                    read!(reader.u16(), "append_child payload");
+                }
+                if target != 0 {
+                    node(&mut checker, target);
+                }
+                node(&mut checker, child);
+            }
+        }
+    }
+
+    if reader.offset() != stream.len() {
+        checker.location = Location::Header;
+        checker.report(ProblemKind::Malformed("trailing bytes after the last opcode"));
+    }
+
+    checker.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::opcodes::*;
+
+    fn problems(payload: &HtipPayload) -> Vec<(Option<usize>, ProblemKind)> {
+        validate_payload(payload).into_iter().map(|p| (p.op_index(), p.kind)).collect()
+    }
+
+    fn text_template(id: u16, html_string_id: u32) -> TemplateDef {
+        TemplateDef {
+            id,
+            html_string_id,
+            bindings: vec![Binding {
+                slot_id: 0,
+                binding_type: BindingType::Text,
+                path: vec![0],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_valid_payload() {
+        let payload = HtipPayload {
+            strings: vec!["<p><!--SLOT_0--></p>".into(), "Hi".into()],
+            templates: vec![text_template(0, 0)],
+            operations: vec![
+                Operation::BatchStart(BatchStart { batch_id: 1 }),
+                Operation::Instantiate(Instantiate {
+                    instance_id: 1,
+                    template_id: 0,
+                    parent_id: 0,
+                }),
+                Operation::PatchText(PatchText {
+                    instance_id: 1,
+                    slot_id: 0,
+                    string_id: 1,
+                }),
+                Operation::BatchCommit(BatchCommit { batch_id: 1 }),
+                Operation::RemoveNode(RemoveNode { instance_id: 1 }),
+            ],
+        };
+
+        assert_eq!(validate_payload(&payload), vec![]);
+    }
+
+    #[test]
+    fn test_payload_reports_every_problem() {
+        let payload = HtipPayload {
+            strings: vec!["<p></p>".into()],
+            templates: vec![text_template(0, 0), text_template(0, 5)],
+            operations: vec![
+                Operation::BatchStart(BatchStart { batch_id: 1 }),
+                Operation::Instantiate(Instantiate {
+                    instance_id: 1,
+                    template_id: 3,
+                    parent_id: 9,
+                }),
+                Operation::Instantiate(Instantiate {
+                    instance_id: 2,
+                    template_id: 0,
+                    parent_id: 0,
+                }),
+                Operation::PatchText(PatchText {
+                    instance_id: 2,
+                    slot_id: 4,
+                    string_id: 2,
+                }),
+                Operation::BatchStart(BatchStart { batch_id: 1 }),
+                Operation::BatchCommit(BatchCommit { batch_id: 7 }),
+                Operation::RemoveNode(RemoveNode { instance_id: 2 }),
+                Operation::AppendChild(AppendChild {
+                    parent_id: 1,
+                    child_id: 2,
+                }),
+            ],
+        };
+
+        let found = validate_payload(&payload);
+        assert_eq!(found[0].location, Location::Template(0));
+        assert_eq!(found[0].kind, ProblemKind::StringOutOfRange(5));
+        assert_eq!(found[1].kind, ProblemKind::DuplicateTemplate(0));
+
+        assert_eq!(
+            problems(&payload)[2..],
+            [
+                (Some(1), ProblemKind::UnknownTemplate(3)),
+                (Some(1), ProblemKind::UnknownInstance(9)),
+                (
+                    Some(3),
+                    ProblemKind::UndeclaredSlot {
+                        instance_id: 2,
+                        template_id: 0,
+                        slot_id: 4
+                    }
+                ),
+                (Some(3), ProblemKind::StringOutOfRange(2)),
+                (Some(4), ProblemKind::BatchReopened(1)),
+                (Some(5), ProblemKind::BatchMismatch { open: 1, commit: 7 }),
+                (Some(7), ProblemKind::UnknownInstance(2)),
+                (Some(0), ProblemKind::BatchNotCommitted(1)),
+                (Some(4), ProblemKind::BatchNotCommitted(1)),
+            ]
+        );
+        assert_eq!(found[4].to_string(), "op 3: slot 4 of instance 2 not declared by template 0");
+    }
+
+    #[test]
+    fn test_v2_stream() {
+        let valid = assemble(
+            r#"
+.htip v2
+string s0 "<div></div>"
+string s1 "Hi"
+template id=0 html=s0 slots=1
+batch_start target=1
+clone target=1 template=0 parent=0
+clone target=2 template=0 parent=1
+patch_text target=2 text=s1
+batch_commit target=1
+remove target=2
+"#,
+        )
+        .unwrap();
+        assert_eq!(validate_v2(&valid), vec![]);
+
+        let broken = assemble(
+            r#"
+.htip v2
+string s0 "<div></div>"
+template id=0 html=s3 slots=0
+template id=0 html=s0 slots=0
+clone target=5 template=2 parent=0
+patch_text target=9 text=s1
+batch_commit target=1
+"#,
+        )
+        .unwrap();
+        let found: Vec<_> =
+            validate_v2(&broken).into_iter().map(|p| (p.location, p.kind)).collect();
+        assert_eq!(
+            found,
+            [
+                (Location::Template(0), ProblemKind::StringOutOfRange(3)),
+                (Location::Template(0), ProblemKind::DuplicateTemplate(0)),
+                (Location::Op(0), ProblemKind::UnknownTemplate(2)),
+                (
+                    Location::Op(0),
+                    ProblemKind::CloneTargetMismatch {
+                        expected: 1,
+                        found: 5
+                    }
+                ),
+                (Location::Op(1), ProblemKind::UnknownInstance(9)),
+                (Location::Op(1), ProblemKind::StringOutOfRange(1)),
+                (Location::Op(2), ProblemKind::BatchNotOpen(1)),
+            ]
+        );
+
+        // Structural damage stops validation
+        let found = validate_v2(&valid[..valid.len() - 2]);
+        assert_eq!(found.last().unwrap().kind, ProblemKind::Malformed("truncated opcode"));
+    }
+}
@@ -2,7 +2,12 @@
 //!
 //! Full round-trip testing: serialize → deserialize → verify
 
-use dx_binary::{deserializer::HtipStream, opcodes::*, serializer::HtipWriter};
+use dx_binary::{
+    deserializer::{HtipStream, HtipStreamDecoder},
+    opcodes::*,
+    serializer::HtipWriter,
+    signature::StreamContext,
+};
 use ed25519_dalek::SigningKey;
 
 #[test]
@@ -36,7 +41,7 @@ fn test_full_roundtrip() {
 
     // Deserialize
     let verifying_key = signing_key.verifying_key();
-    let mut stream = HtipStream::new(&binary, &verifying_key).unwrap();
+    let stream = HtipStream::new(&binary, &verifying_key, &StreamContext::new(0)).unwrap();
 
     assert!(stream.is_verified());
 
@@ -88,7 +93,7 @@ fn test_large_payload() {
 
     // Verify can deserialize
     let verifying_key = signing_key.verifying_key();
-    let mut stream = HtipStream::new(&binary, &verifying_key).unwrap();
+    let stream = HtipStream::new(&binary, &verifying_key, &StreamContext::new(0)).unwrap();
 
     assert!(stream.is_verified());
     assert_eq!(stream.remaining(), 300); // 100 templates + 100 instantiates + 100 patches
@@ -112,7 +117,7 @@ fn test_string_deduplication() {
 
     // Parse and verify
     let verifying_key = signing_key.verifying_key();
-    let stream = HtipStream::new(&binary, &verifying_key).unwrap();
+    let stream = HtipStream::new(&binary, &verifying_key, &StreamContext::new(0)).unwrap();
 
     // All patch operations should reference same string ID
     assert!(stream.is_verified());
@@ -136,7 +141,7 @@ fn test_batch_operations() {
 
     // Deserialize
     let verifying_key = signing_key.verifying_key();
-    let stream = HtipStream::new(&binary, &verifying_key).unwrap();
+    let stream = HtipStream::new(&binary, &verifying_key, &StreamContext::new(0)).unwrap();
 
     let ops = stream.operations();
 
@@ -170,7 +175,7 @@ fn test_all_opcode_types() {
 
     // Deserialize and verify all opcodes
     let verifying_key = signing_key.verifying_key();
-    let stream = HtipStream::new(&binary, &verifying_key).unwrap();
+    let stream = HtipStream::new(&binary, &verifying_key, &StreamContext::new(0)).unwrap();
 
     let opcodes: Vec<OpcodeV1> = stream.operations().iter().map(|op| op.opcode()).collect();
 
@@ -187,3 +192,89 @@ fn test_all_opcode_types() {
     assert!(opcodes.contains(&OpcodeV1::SetProperty));
     assert!(opcodes.contains(&OpcodeV1::AppendChild));
 }
+
+/// Synthetic benchmark stream: a hand-written page shaped like
+/// `examples/dashboard-complex.tsx`, with a table of `users` rows
+fn table_stream(users: u32, macros: bool) -> Vec<u8> {
+    let mut writer = HtipWriter::new();
+    writer.set_macro_compression(macros);
+
+    let text = |slot_id: u16, path: Vec<u8>| Binding {
+        slot_id,
+        binding_type: BindingType::Text,
+        path,
+    };
+    writer.write_template(0, "<div class=\"dashboard\"><main></main></div>", vec![]);
+    writer.write_template(1, "<header><h1>Users</h1></header>", vec![]);
+    writer.write_template(2, "<nav class=\"sidebar\"><a href=\"/\">Home</a></nav>", vec![]);
+    writer.write_template(3, "<input type=\"search\" placeholder=\"Search\">", vec![]);
+    writer.write_template(
+        4,
+        "<div class=\"filters\"><select><option>all</option><option>active</option></select></div>",
+        vec![],
+    );
+    writer.write_template(5, "<table class=\"users\"><tbody></tbody></table>", vec![]);
+    writer.write_template(
+        6,
+        "<tr><td><!--SLOT_0--></td><td><!--SLOT_1--></td><td><!--SLOT_2--></td></tr>",
+        vec![text(0, vec![0]), text(1, vec![1]), text(2, vec![2])],
+    );
+    writer.write_template(7, "<footer>© dx</footer>", vec![]);
+
+    writer.write_batch_start(1);
+    writer.write_instantiate(1, 0, 0);
+    for (instance, template) in [(2, 1), (3, 2), (4, 3), (5, 4), (6, 5)] {
+        writer.write_instantiate(instance, template, 1);
+    }
+    writer.write_attach_event(4, "input", 1);
+    writer.write_attach_event(5, "change", 2);
+
+    // UserTable rows
+    for i in 0..users {
+        let row = 100 + i;
+        writer.write_instantiate(row, 6, 6);
+        writer.write_patch_text(row, 0, &format!("User {i}"));
+        writer.write_patch_text(row, 1, &format!("user{i}@example.com"));
+        writer.write_patch_text(row, 2, if i % 4 == 0 { "inactive" } else { "active" });
+        writer.write_class_toggle(row, "selected", i == 3);
+        writer.write_attach_event(row, "click", 3);
+    }
+
+    writer.write_instantiate(7, 7, 1);
+    writer.write_batch_commit(1);
+
+    writer.finish_and_sign(&SigningKey::from_bytes(&[0u8; 32])).unwrap()
+}
+
+#[test]
+fn test_macro_compression_on_table() {
+    let verifying_key = SigningKey::from_bytes(&[0u8; 32]).verifying_key();
+    let context = StreamContext::new(0);
+
+    let plain = table_stream(50, false);
+    let compressed = table_stream(50, true);
+    let saved = 100 * (plain.len() - compressed.len()) / plain.len();
+    println!(
+        "Synthetic table (50 rows): {} bytes, {} with macros ({saved}% smaller)",
+        plain.len(),
+        compressed.len()
+    );
+    assert!(saved >= 20, "only {saved}% smaller");
+
+    // Runtimes see the same operations either way
+    let expected = HtipStream::new(&plain, &verifying_key, &context).unwrap();
+    let stream = HtipStream::new(&compressed, &verifying_key, &context).unwrap();
+    assert_eq!(format!("{:?}", stream.operations()), format!("{:?}", expected.operations()));
+    assert!(stream.validate().is_empty());
+
+    let mut decoder = HtipStreamDecoder::new(&verifying_key, &context);
+    let mut ops = Vec::new();
+    for chunk in compressed.chunks(7) {
+        decoder.feed(chunk);
+        while let Some(op) = decoder.next_operation().unwrap() {
+            ops.push(op);
+        }
+    }
+    decoder.finish().unwrap();
+    assert_eq!(format!("{ops:?}"), format!("{:?}", expected.operations()));
+}
@@ -69,6 +69,9 @@ const OP_PATCH_ATTR: u8 = 3;
 const OP_CLASS_TOGGLE: u8 = 4;
 const OP_REMOVE: u8 = 5;
 
+/// Header flag: node and template IDs are u32 (matches dx_packet::HtipHeader::FLAG_WIDE_IDS)
+const FLAG_WIDE_IDS: u8 = 0x04;
+
 // ============================================================================
 // WASM Exports (called by JavaScript)
 // ============================================================================
@@ -83,9 +86,12 @@ pub extern "C" fn init() -> u32 {
 ///
 /// # Format
 /// ```
-/// [4-byte header: magic + version]
+/// [4-byte header: magic (2) + version (1) + flags (1)]
 /// [opcode stream...]
 /// ```
+///
+/// Node IDs are 2 bytes and template IDs 1 byte, both 4 bytes with
+/// `FLAG_WIDE_IDS`.
 #[no_mangle]
 pub extern "C" fn render_stream(ptr: *const u8, len: u32) -> u32 {
     if len < 4 {
@@ -93,7 +99,8 @@ pub extern "C" fn render_stream(ptr: *const u8, len: u32) -> u32 {
     }
 
     unsafe {
-        // Skip 4-byte header
+        // Skip 4-byte header, keeping the ID width from its flags
+        let wide = *ptr.add(3) & FLAG_WIDE_IDS != 0;
         let mut offset = 4;
 
         // Root handle (body) is always 0
@@ -106,9 +113,13 @@ pub extern "C" fn render_stream(ptr: *const u8, len: u32) -> u32 {
 
             match op {
                 OP_CLONE => {
-                    // Read template ID (1 byte)
-                    let template_id = *ptr.add(offset as usize) as u32;
-                    offset += 1;
+                    // Read template ID (1 byte, 4 if wide)
+                    let template_id = if wide {
+                        read_u32(ptr, offset)
+                    } else {
+                        *ptr.add(offset as usize) as u32
+                    };
+                    offset += if wide { 4 } else { 1 };
 
                     // Clone and append to root
                     let node_handle = host_clone_template(template_id);
@@ -116,9 +127,8 @@ pub extern "C" fn render_stream(ptr: *const u8, len: u32) -> u32 {
                 }
 
                 OP_PATCH_TEXT => {
-                    // Read: node_id (2 bytes) + text_len (2 bytes) + text
-                    let node_id = read_u16(ptr, offset) as u32;
-                    offset += 2;
+                    // Read: node_id (2 or 4 bytes) + text_len (2 bytes) + text
+                    let node_id = read_node_id(ptr, &mut offset, wide);
                     let text_len = read_u16(ptr, offset) as u32;
                     offset += 2;
                     let text_ptr = ptr.add(offset as usize);
@@ -129,8 +139,7 @@ pub extern "C" fn render_stream(ptr: *const u8, len: u32) -> u32 {
 
                 OP_PATCH_ATTR => {
                     // Read: node_id + key_len + key + val_len + val
-                    let node_id = read_u16(ptr, offset) as u32;
-                    offset += 2;
+                    let node_id = read_node_id(ptr, &mut offset, wide);
                     let key_len = read_u16(ptr, offset) as u32;
                     offset += 2;
                     let key_ptr = ptr.add(offset as usize);
@@ -145,8 +154,7 @@ pub extern "C" fn render_stream(ptr: *const u8, len: u32) -> u32 {
 
                 OP_CLASS_TOGGLE => {
                     // Read: node_id + class_len + class + enable
-                    let node_id = read_u16(ptr, offset) as u32;
-                    offset += 2;
+                    let node_id = read_node_id(ptr, &mut offset, wide);
                     let class_len = read_u16(ptr, offset) as u32;
                     offset += 2;
                     let class_ptr = ptr.add(offset as usize);
@@ -158,8 +166,7 @@ pub extern "C" fn render_stream(ptr: *const u8, len: u32) -> u32 {
                 }
 
                 OP_REMOVE => {
-                    let node_id = read_u16(ptr, offset) as u32;
-                    offset += 2;
+                    let node_id = read_node_id(ptr, &mut offset, wide);
 
                     host_remove(node_id);
                 }
@@ -193,6 +200,25 @@ pub extern "C" fn reset() {
 // Utilities
 // ============================================================================
 
+#[inline]
+unsafe fn read_u32(ptr: *const u8, offset: u32) -> u32 {
+    read_u16(ptr, offset) as u32 | (read_u16(ptr, offset + 2) as u32) << 16
+}
+
+/// Read a node ID (u16, or u32 in wide-ID streams) and advance past it
+#[inline]
+unsafe fn read_node_id(ptr: *const u8, offset: &mut u32, wide: bool) -> u32 {
+    if wide {
+        let id = read_u32(ptr, *offset);
+        *offset += 4;
+        id
+    } else {
+        let id = read_u16(ptr, *offset) as u32;
+        *offset += 2;
+        id
+    }
+}
+
 #[inline]
 unsafe fn read_u16(ptr: *const u8, offset: u32) -> u16 {
     let b1 = *ptr.add(offset as usize) as u16;
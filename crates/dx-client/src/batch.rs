@@ -0,0 +1,330 @@
+//! Batch Stage: Transactional BatchStart/BatchCommit
+//!
+//! Ops between `BatchStart` and `BatchCommit` are copied out of the stream
+//! and held until the outermost batch commits. The renderer then checks the
+//! whole batch and applies it, so a bad op leaves the DOM untouched.
+//!
+//! Batch IDs travel in the opcode's `target_id`. Nested batches must commit
+//! innermost first, and an ID cannot be reopened while it is still open.
+//!
+//! Staged ops always hold `u32` IDs: ops of compact streams are widened when
+//! read, so the same op looks the same whatever the stream's `IdWidth`.
+
+use dx_packet::{ErrorCode, IdWidth, OpType, PropKind};
+
+/// An opcode copied out of the stream
+#[derive(Clone, Copy, Debug)]
+pub struct StagedOp {
+    pub op_type: u8,
+    /// Target node ID (batch ID for BatchStart/BatchCommit)
+    pub target_id: u32,
+    /// Payload bytes in the wide-ID layout (only the first `payload_size`
+    /// are meaningful)
+    pub payload: [u8; 8],
+}
+
+impl StagedOp {
+    /// Payload bytes of a staged op type (wide-ID layout)
+    pub fn payload_size(op_type: OpType) -> usize {
+        IdWidth::Wide.payload_size(op_type)
+    }
+
+    /// Read an op at `offset` of a stream with `width` IDs
+    ///
+    /// Advances `offset` past the op. Clone and AppendChild payloads are
+    /// widened to `ClonePayloadWide` and `AppendChildPayloadWide`.
+    pub fn read(data: &[u8], offset: &mut usize, width: IdWidth) -> Result<Self, u8> {
+        let header_size = width.opcode_header_size();
+        let header = data
+            .get(*offset..*offset + header_size)
+            .ok_or(ErrorCode::BufferTooSmall as u8)?;
+        let op_type = OpType::from_u8(header[0]).ok_or(ErrorCode::InvalidOpcode as u8)?;
+        let target_id = width
+            .read_id(header, header_size - width.id_size())
+            .ok_or(ErrorCode::BufferTooSmall as u8)?;
+
+        let start = *offset + header_size;
+        let size = width.payload_size(op_type);
+        let bytes = data.get(start..start + size).ok_or(ErrorCode::BufferTooSmall as u8)?;
+
+        let mut payload = [0u8; 8];
+        let id = |index: usize| width.read_id(bytes, index * width.id_size()).unwrap_or(0);
+        match op_type {
+            OpType::Clone => {
+                payload[0..4].copy_from_slice(&id(0).to_le_bytes());
+                payload[4..8].copy_from_slice(&id(1).to_le_bytes());
+            }
+            OpType::AppendChild => payload[0..4].copy_from_slice(&id(0).to_le_bytes()),
+            _ => payload[..size].copy_from_slice(bytes),
+        }
+        *offset = start + size;
+
+        Ok(Self {
+            op_type: op_type as u8,
+            target_id,
+            payload,
+        })
+    }
+
+    pub fn op_type(&self) -> Option<OpType> {
+        OpType::from_u8(self.op_type)
+    }
+
+    /// First u32 of the payload (Clone template, AppendChild child)
+    pub fn id0(&self) -> u32 {
+        u32::from_le_bytes([
+            self.payload[0],
+            self.payload[1],
+            self.payload[2],
+            self.payload[3],
+        ])
+    }
+
+    /// First u16 of the payload (template, string or name index)
+    pub fn arg0(&self) -> u16 {
+        u16::from_le_bytes([self.payload[0], self.payload[1]])
+    }
+
+    /// Second u16 of the payload (parent or value index)
+    pub fn arg1(&self) -> u16 {
+        u16::from_le_bytes([self.payload[2], self.payload[3]])
+    }
+
+    /// Third u16 of the payload (SetProperty value)
+    pub fn arg2(&self) -> u16 {
+        u16::from_le_bytes([self.payload[4], self.payload[5]])
+    }
+}
+
+/// Open batches and their staged ops
+pub struct BatchStage {
+    /// Open batch IDs, innermost last
+    open: Vec<u32>,
+    ops: Vec<StagedOp>,
+}
+
+impl BatchStage {
+    pub const fn new() -> Self {
+        Self {
+            open: Vec::new(),
+            ops: Vec::new(),
+        }
+    }
+
+    /// Check if ops are currently being staged
+    pub fn is_open(&self) -> bool {
+        !self.open.is_empty()
+    }
+
+    /// Number of open (nested) batches
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Open a batch (aborts everything if the ID is already open)
+    pub fn start(&mut self, id: u32) -> Result<(), u8> {
+        if self.open.contains(&id) {
+            self.abort();
+            return Err(ErrorCode::InvalidBatch as u8);
+        }
+        self.open.push(id);
+        Ok(())
+    }
+
+    /// Stage an op of the open batches
+    pub fn stage(&mut self, op: StagedOp) {
+        self.ops.push(op);
+    }
+
+    /// Commit the innermost batch
+    ///
+    /// Returns the ops to apply once the outermost batch commits, `None`
+    /// for nested commits. A commit that does not match the innermost open
+    /// batch aborts everything.
+    pub fn commit(&mut self, id: u32) -> Result<Option<Vec<StagedOp>>, u8> {
+        if self.open.last() != Some(&id) {
+            self.abort();
+            return Err(ErrorCode::InvalidBatch as u8);
+        }
+
+        self.open.pop();
+        if self.open.is_empty() {
+            Ok(Some(core::mem::take(&mut self.ops)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Drop all open batches and their staged ops
+    pub fn abort(&mut self) {
+        self.open.clear();
+        self.ops.clear();
+    }
+}
+
+impl Default for BatchStage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Check a committed batch before any of it is applied
+///
+/// Covers every case where applying an op returns an error: unknown op
+/// types and property kinds, unregistered templates and (with a string
+/// table) out of range string indices.
+pub fn check_batch(
+    ops: &[StagedOp],
+    has_template: impl Fn(u32) -> bool,
+    has_string: Option<impl Fn(u16) -> bool>,
+) -> Result<(), u8> {
+    let string = |idx: u16| match &has_string {
+        Some(has_string) if !has_string(idx) => Err(ErrorCode::StringIndexOutOfBounds as u8),
+        _ => Ok(()),
+    };
+
+    for op in ops {
+        match op.op_type().ok_or(ErrorCode::InvalidOpcode as u8)? {
+            OpType::Clone => {
+                if !has_template(op.id0()) {
+                    return Err(ErrorCode::TemplateNotFound as u8);
+                }
+            }
+            OpType::PatchText | OpType::ClassToggle | OpType::AttachEvent => string(op.arg0())?,
+            OpType::PatchAttr | OpType::SetStyle => {
+                string(op.arg0())?;
+                string(op.arg1())?;
+            }
+            OpType::SetProperty => {
+                string(op.arg0())?;
+                match PropKind::from_u8(op.payload[2]).ok_or(ErrorCode::InvalidOpcode as u8)? {
+                    PropKind::String | PropKind::Number => string(op.arg2())?,
+                    PropKind::Bool | PropKind::Null => {}
+                }
+            }
+            OpType::Remove | OpType::BatchStart | OpType::BatchCommit | OpType::AppendChild => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(op_type: OpType, target_id: u32, arg0: u16, arg1: u16) -> StagedOp {
+        let mut payload = [0u8; 8];
+        payload[0..2].copy_from_slice(&arg0.to_le_bytes());
+        payload[2..4].copy_from_slice(&arg1.to_le_bytes());
+        StagedOp {
+            op_type: op_type as u8,
+            target_id,
+            payload,
+        }
+    }
+
+    #[test]
+    fn test_read_widens_compact_ids() {
+        // Clone node 3 from template 7 under node 2, then append 3 to root
+        let compact = [1, 0, 3, 0, 7, 0, 2, 0, 11, 0, 0, 0, 3, 0, 0, 0];
+        let mut wide = vec![1, 0, 0, 0, 3, 0, 0, 0, 7, 0, 0, 0, 2, 0, 0, 0];
+        wide.extend([11, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0]);
+
+        for (data, width) in [(&compact[..], IdWidth::Compact), (&wide[..], IdWidth::Wide)] {
+            let mut offset = 0;
+            let clone = StagedOp::read(data, &mut offset, width).unwrap();
+            assert_eq!((clone.target_id, clone.id0()), (3, 7));
+            assert_eq!(&clone.payload[4..8], &2u32.to_le_bytes());
+
+            let append = StagedOp::read(data, &mut offset, width).unwrap();
+            assert_eq!((append.op_type(), append.target_id), (Some(OpType::AppendChild), 0));
+            assert_eq!(append.id0(), 3);
+            assert_eq!(offset, data.len());
+
+            assert_eq!(
+                StagedOp::read(data, &mut offset, width).unwrap_err(),
+                ErrorCode::BufferTooSmall as u8
+            );
+        }
+
+        // A wide template ID
+        let mut big = vec![1, 0, 0, 0, 1, 0, 0, 0];
+        big.extend(70_000u32.to_le_bytes());
+        big.extend(0u32.to_le_bytes());
+        let clone = StagedOp::read(&big, &mut 0, IdWidth::Wide).unwrap();
+        assert_eq!(clone.id0(), 70_000);
+    }
+
+    #[test]
+    fn test_nested_batches_release_at_outermost_commit() {
+        let mut stage = BatchStage::new();
+        stage.start(1).unwrap();
+        stage.stage(op(OpType::Clone, 1, 0, 0));
+        stage.start(2).unwrap();
+        stage.stage(op(OpType::PatchText, 1, 0, 0));
+
+        assert_eq!(stage.commit(2).unwrap().map(|ops| ops.len()), None);
+        assert_eq!(stage.depth(), 1);
+
+        let ops = stage.commit(1).unwrap().unwrap();
+        assert_eq!(ops.len(), 2);
+        assert!(!stage.is_open());
+    }
+
+    #[test]
+    fn test_invalid_batch_ids_abort() {
+        let mut stage = BatchStage::new();
+        stage.start(1).unwrap();
+        stage.stage(op(OpType::Remove, 3, 0, 0));
+        assert_eq!(stage.start(1), Err(ErrorCode::InvalidBatch as u8));
+        assert!(!stage.is_open());
+
+        stage.start(1).unwrap();
+        stage.start(2).unwrap();
+        assert!(stage.commit(1).is_err());
+        assert!(!stage.is_open());
+
+        assert!(stage.commit(5).is_err());
+    }
+
+    #[test]
+    fn test_check_batch() {
+        let templates = |id: u32| id < 2;
+        let strings = Some(|idx: u16| idx < 3);
+        let good = [
+            op(OpType::Clone, 1, 1, 0),
+            op(OpType::PatchAttr, 1, 0, 2),
+            op(OpType::Remove, 1, 0, 0),
+        ];
+        assert_eq!(check_batch(&good, templates, strings), Ok(()));
+
+        let missing_template = [op(OpType::PatchText, 1, 0, 0), op(OpType::Clone, 2, 7, 0)];
+        assert_eq!(
+            check_batch(&missing_template, templates, strings),
+            Err(ErrorCode::TemplateNotFound as u8)
+        );
+
+        // SetProperty: kind 1 (Number) in the low byte of arg1, value in arg2
+        let mut number = op(OpType::SetProperty, 1, 0, PropKind::Number as u16);
+        number.payload[4] = 3;
+        assert_eq!(
+            check_batch(&[number], templates, strings),
+            Err(ErrorCode::StringIndexOutOfBounds as u8)
+        );
+        let bad_kind = op(OpType::SetProperty, 1, 0, 9);
+        assert_eq!(
+            check_batch(&[bad_kind], templates, strings),
+            Err(ErrorCode::InvalidOpcode as u8)
+        );
+
+        let bad_string = [op(OpType::SetStyle, 1, 0, 3)];
+        assert_eq!(
+            check_batch(&bad_string, templates, strings),
+            Err(ErrorCode::StringIndexOutOfBounds as u8)
+        );
+
+        // Without a string table string ops are skipped, as when applied
+        assert_eq!(check_batch(&bad_string, templates, None::<fn(u16) -> bool>), Ok(()));
+    }
+}
@@ -32,17 +32,23 @@ use core::ptr;
 use dx_packet::*;
 use wasm_bindgen::prelude::*;
 
+mod batch;
 mod node_registry;
 mod patcher;
 mod renderer;
+mod state_store;
 mod stream_reader;
+mod stream_state;
 mod string_table;
 mod template_cache;
 
+pub use batch::{BatchStage, StagedOp};
 pub use node_registry::NodeRegistry;
 pub use patcher::{Patcher, PATCH_BLOCK_SIZE};
 pub use renderer::Renderer;
-pub use stream_reader::{ChunkDispatcher, StreamReader};
+pub use state_store::StateStore;
+pub use stream_reader::{ChunkAction, ChunkDispatcher, StreamReader};
+pub use stream_state::{OpSink, StreamState};
 pub use string_table::StringTableReader;
 pub use template_cache::TemplateCache;
 
@@ -55,6 +61,7 @@ use core::cell::RefCell;
 thread_local! {
     static RENDERER: RefCell<Option<Renderer>> = RefCell::new(None);
     static PATCHER: RefCell<Option<Patcher>> = RefCell::new(None);
+    static STATE_STORE: RefCell<StateStore> = const { RefCell::new(StateStore::new()) };
 }
 
 // ============================================================================
@@ -119,6 +126,7 @@ pub fn reset() {
     RENDERER.with(|r| {
         *r.borrow_mut() = None;
     });
+    STATE_STORE.with(|s| s.borrow_mut().clear());
     // Reset bump allocator to reclaim all memory
     unsafe {
         allocator::reset_heap();
@@ -177,12 +185,30 @@ pub fn poll_and_process_chunk() -> Result<bool, u8> {
     })?;
 
     if let Some((chunk_type, data)) = chunk {
-        CHUNK_DISPATCHER.with(|cd| {
+        let action = CHUNK_DISPATCHER.with(|cd| {
             let mut dispatcher = cd.borrow_mut();
             let dispatcher = dispatcher.as_mut().ok_or(10u8)?; // ErrorCode::NotInitialized
             dispatcher.handle_chunk(chunk_type, data)
         })?;
 
+        // Templates, initial state and opcodes go live now, not at EOF
+        match action {
+            ChunkAction::RegisterLayout(layout) => RENDERER.with(|r| {
+                let mut renderer = r.borrow_mut();
+                let renderer = renderer.as_mut().ok_or(10u8)?; // ErrorCode::NotInitialized
+                renderer.register_layout(&layout)
+            })?,
+            ChunkAction::LoadState(state) => {
+                STATE_STORE.with(|s| s.borrow_mut().load(&state))?;
+            }
+            ChunkAction::Render(ops) => RENDERER.with(|r| {
+                let mut renderer = r.borrow_mut();
+                let renderer = renderer.as_mut().ok_or(10u8)?; // ErrorCode::NotInitialized
+                renderer.process_chunk(&ops)
+            })?,
+            ChunkAction::None => {}
+        }
+
         return Ok(true);
     }
 
@@ -200,37 +226,46 @@ pub fn is_stream_finished() -> bool {
     })
 }
 
-/// Finalize streaming: Process accumulated chunks and render
+/// Check if the layout chunk has been registered
 ///
-/// Call this after is_stream_finished() returns true
+/// Once true, the first render can run without waiting for the WASM chunk
+#[wasm_bindgen]
+pub fn is_layout_ready() -> bool {
+    CHUNK_DISPATCHER.with(|cd| cd.borrow().as_ref().map(|d| d.is_layout_loaded()).unwrap_or(false))
+}
+
+/// Finalize streaming: Check that every required chunk arrived
+///
+/// Call this after is_stream_finished() returns true. Templates, initial
+/// state and Opcodes chunks were already applied by poll_and_process_chunk().
 #[wasm_bindgen]
 pub fn finalize_stream() -> Result<(), u8> {
-    // Get accumulated data
-    let (layout_data, _state_data, _wasm_data) = CHUNK_DISPATCHER.with(|cd| {
+    // A batch must commit within the stream
+    RENDERER.with(|r| r.borrow_mut().as_mut().map_or(Ok(()), |r| r.finish_chunks()))?;
+
+    CHUNK_DISPATCHER.with(|cd| {
         let mut dispatcher = cd.borrow_mut();
         let dispatcher = dispatcher.as_mut().ok_or(10u8)?; // ErrorCode::NotInitialized
 
+        if !dispatcher.is_layout_loaded() {
+            return Err(12u8); // ErrorCode::MissingLayout
+        }
         if !dispatcher.is_complete() {
             return Err(11u8); // ErrorCode::IncompleteStream
         }
 
-        Ok::<_, u8>((
-            dispatcher.take_layout(),
-            dispatcher.take_state(),
-            dispatcher.take_wasm(),
-        ))
-    })?;
-
-    // TODO: Process layout_data to register templates
-    // TODO: Process state_data to initialize memory
-    // TODO: WASM is handled by browser's WebAssembly.instantiateStreaming
-
-    // For now, just validate we got the data
-    if layout_data.is_none() {
-        return Err(12u8); // ErrorCode::MissingLayout
-    }
+        // WASM is handled by browser's WebAssembly.instantiateStreaming
+        let _wasm_data = dispatcher.take_wasm();
+        Ok(())
+    })
+}
 
-    Ok(())
+/// Get the State Region offset of a component's initial state
+///
+/// Returns -1 if the component has no initial state
+#[wasm_bindgen]
+pub fn get_state_offset(component_id: u16) -> i32 {
+    STATE_STORE.with(|s| s.borrow().offset_of(component_id).map(|o| o as i32).unwrap_or(-1))
 }
 
 // ============================================================================
@@ -1,10 +1,12 @@
 //! Node Registry: Track cloned nodes by ID
 //!
-//! Fixed-size array for predictable memory layout.
+//! Fixed-size array for predictable memory layout. IDs past it (wide-ID
+//! streams) grow the array on demand, up to `max_nodes`.
 
+use dx_packet::{DecodeLimits, ErrorCode};
 use web_sys::Node;
 
-/// Maximum nodes (matches dx_packet::MAX_NODES)
+/// Pre-allocated nodes (matches dx_packet::MAX_NODES)
 const MAX_NODES: usize = 65536;
 
 /// Node registry using fixed-size array
@@ -12,7 +14,9 @@ pub struct NodeRegistry {
     /// Registered nodes (None = slot empty)
     nodes: Vec<Option<Node>>,
     /// Next available ID
-    next_id: u16,
+    next_id: u32,
+    /// Highest ID handed out (IDs are not reused until `clear`)
+    max_nodes: usize,
 }
 
 impl NodeRegistry {
@@ -25,26 +29,39 @@ impl NodeRegistry {
         Self {
             nodes,
             next_id: 1, // 0 reserved for root
+            max_nodes: DecodeLimits::WIDE.max_nodes as usize,
         }
     }
 
+    /// Bound the IDs handed out (the renderer's `DecodeLimits::max_nodes`)
+    pub fn set_max_nodes(&mut self, max_nodes: usize) {
+        self.max_nodes = max_nodes;
+    }
+
     /// Register a node and return its ID
-    pub fn register(&mut self, node: Node) -> u16 {
+    ///
+    /// Fails with `TooManyNodes` once `max_nodes` IDs are taken.
+    pub fn register(&mut self, node: Node) -> Result<u32, u8> {
         let id = self.next_id;
-        if (id as usize) < MAX_NODES {
-            self.nodes[id as usize] = Some(node);
-            self.next_id = self.next_id.wrapping_add(1);
+        if id as usize > self.max_nodes {
+            return Err(ErrorCode::TooManyNodes as u8);
+        }
+        let next_id = id.checked_add(1).ok_or(ErrorCode::TooManyNodes as u8)?;
+        if id as usize >= self.nodes.len() {
+            self.nodes.resize_with(id as usize + 1, || None);
         }
-        id
+        self.nodes[id as usize] = Some(node);
+        self.next_id = next_id;
+        Ok(id)
     }
 
     /// Get a node by ID
-    pub fn get(&self, id: u16) -> Option<&Node> {
+    pub fn get(&self, id: u32) -> Option<&Node> {
         self.nodes.get(id as usize).and_then(|n| n.as_ref())
     }
 
     /// Remove a node by ID
-    pub fn remove(&mut self, id: u16) -> Option<Node> {
+    pub fn remove(&mut self, id: u32) -> Option<Node> {
         self.nodes.get_mut(id as usize).and_then(|n| n.take())
     }
 
@@ -55,9 +72,33 @@ impl NodeRegistry {
 
     /// Clear all nodes
     pub fn clear(&mut self) {
+        self.nodes.truncate(MAX_NODES);
         for slot in self.nodes.iter_mut() {
             *slot = None;
         }
         self.next_id = 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen::{JsCast, JsValue};
+
+    #[test]
+    fn test_register_stops_at_max_nodes() {
+        // Off the browser a reserved JS value stands in for a DOM node
+        let node = || JsValue::NULL.unchecked_into::<Node>();
+        let mut registry = NodeRegistry::new();
+        registry.set_max_nodes(MAX_NODES + 1);
+
+        for expected in 1..=MAX_NODES as u32 + 1 {
+            assert_eq!(registry.register(node()), Ok(expected));
+        }
+        assert_eq!(registry.register(node()), Err(ErrorCode::TooManyNodes as u8));
+        assert_eq!(registry.count() as usize, MAX_NODES + 1);
+
+        registry.clear();
+        assert_eq!(registry.register(node()), Ok(1));
+    }
+}
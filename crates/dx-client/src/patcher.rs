@@ -5,52 +5,36 @@
 ///
 /// # Algorithm
 ///
-/// 1. Old binary divided into 4KB blocks
-/// 2. Server sends XOR diff for changed blocks
+/// 1. Binary divided into 64-byte blocks (`dx_packet::BLOCK_SIZE`)
+/// 2. Server sends XOR diff for changed blocks (`dx_packet::PatchWriter`)
 /// 3. Client XORs each block: new = old ^ xor_data
-/// 4. Result is the updated binary
+/// 4. Result is the updated binary, resized to the target length
+///
+/// # Integrity
+///
+/// The old binary must hash to the header's `base_version_hash` and the
+/// result to its `target_version_hash` (`PatchHeader::version_hash`).
+/// On any failure the original binary is left untouched.
 ///
 /// # Performance
 ///
-/// - Block size: 4096 bytes (cache-friendly)
+/// - Block size: 64 bytes (only changed bytes go over the wire)
 /// - Operation: XOR (CPU-level instruction)
-/// - Target: < 1ms for 20KB patch (~5 blocks)
+/// - Target: < 1ms for 20KB patch
 
-use dx_packet::{PatchHeader, BLOCK_SIZE};
+use dx_packet::{ErrorCode, PatchReader, BLOCK_SIZE};
 
 /// Block size constant (re-exported from dx-packet)
 pub const PATCH_BLOCK_SIZE: usize = BLOCK_SIZE;
 
-/// Patch block entry
-///
-/// Represents a single block that needs to be patched
-#[derive(Debug, Clone)]
-pub struct PatchBlock {
-    /// Block index (multiply by BLOCK_SIZE to get byte offset)
-    pub index: u32,
-    /// XOR data to apply to this block
-    pub xor_data: Vec<u8>,
-}
-
-/// Complete patch operation
-///
-/// Contains header + all blocks to patch
-#[derive(Debug, Clone)]
-pub struct Patch {
-    /// Patch metadata
-    pub header: PatchHeader,
-    /// Blocks to patch
-    pub blocks: Vec<PatchBlock>,
-}
-
 /// Patcher state
 ///
 /// Holds the old binary and accumulated patch data
 pub struct Patcher {
     /// The original binary to patch
     pub(crate) old_binary: Option<Vec<u8>>,
-    /// Accumulated patch data
-    patch_data: Option<Patch>,
+    /// Accumulated patch data (validated by set_patch_data)
+    patch_data: Option<Vec<u8>>,
 }
 
 impl Patcher {
@@ -71,70 +55,50 @@ impl Patcher {
     ///
     /// # Format
     ///
+    /// See `dx_packet::PatchWriter`:
+    ///
     /// ```text
     /// [PatchHeader: 17 bytes]
+    /// [Target Length: 4 bytes]
     /// [Block Count: 4 bytes]
     /// [Block 1: index:4 + length:2 + data:N]
     /// [Block 2: index:4 + length:2 + data:N]
     /// ...
     /// ```
     pub fn set_patch_data(&mut self, data: &[u8]) -> Result<(), u8> {
-        if data.len() < 21 {
-            // Need at least header (17) + block count (4)
-            return Err(1); // ErrorCode::InvalidPatchData
+        for block in Self::read(data)? {
+            block.map_err(Self::error_code)?;
         }
 
-        // Parse header
-        let header = PatchHeader::from_bytes(&data[0..17])
-            .ok_or(2)?; // ErrorCode::InvalidPatchHeader
-
-        // Parse block count
-        let block_count = u32::from_le_bytes([data[17], data[18], data[19], data[20]]) as usize;
-
-        // Parse blocks
-        let mut blocks = Vec::with_capacity(block_count);
-        let mut offset = 21;
-
-        for _ in 0..block_count {
-            if offset + 6 > data.len() {
-                return Err(3); // ErrorCode::TruncatedPatch
-            }
-
-            // Read block index (4 bytes)
-            let index = u32::from_le_bytes([
-                data[offset],
-                data[offset + 1],
-                data[offset + 2],
-                data[offset + 3],
-            ]);
-
-            // Read XOR data length (2 bytes)
-            let length = u16::from_le_bytes([data[offset + 4], data[offset + 5]]) as usize;
-
-            offset += 6;
-
-            if offset + length > data.len() {
-                return Err(4); // ErrorCode::InvalidBlockData
-            }
+        self.patch_data = Some(data.to_vec());
+        Ok(())
+    }
 
-            // Read XOR data
-            let xor_data = data[offset..offset + length].to_vec();
-            offset += length;
+    /// Open a patch, mapping header errors to the patcher's codes
+    fn read(data: &[u8]) -> Result<PatchReader<'_>, u8> {
+        PatchReader::new(data).map_err(|e| match e {
+            ErrorCode::BufferTooSmall => 1, // ErrorCode::InvalidPatchData
+            _ => 2,                         // ErrorCode::InvalidPatchHeader
+        })
+    }
 
-            blocks.push(PatchBlock { index, xor_data });
+    /// Map a block or apply error to the patcher's codes
+    fn error_code(e: ErrorCode) -> u8 {
+        match e {
+            ErrorCode::BufferTooSmall => 3,      // ErrorCode::TruncatedPatch
+            ErrorCode::PatchBaseMismatch => 8,   // ErrorCode::BaseHashMismatch
+            ErrorCode::PatchTargetMismatch => 9, // ErrorCode::TargetHashMismatch
+            _ => 7,                              // ErrorCode::BlockOutOfBounds
         }
-
-        self.patch_data = Some(Patch { header, blocks });
-        Ok(())
     }
 
     /// Apply patch and return new binary
     ///
     /// # Performance
     ///
-    /// - O(n) where n = total XOR bytes
+    /// - O(n) where n = binary size (hashing) + total XOR bytes
     /// - XOR is CPU-level instruction (very fast)
-    /// - In-place modification minimizes allocations
+    /// - The old binary is never modified
     ///
     /// # Returns
     ///
@@ -144,28 +108,7 @@ impl Patcher {
         let old_binary = self.old_binary.as_ref().ok_or(5)?; // ErrorCode::NoBinarySet
         let patch = self.patch_data.as_ref().ok_or(6)?; // ErrorCode::NoPatchSet
 
-        // Clone old binary (we'll modify it in place)
-        let mut new_binary = old_binary.clone();
-
-        // Apply each block
-        for block in &patch.blocks {
-            let offset = (block.index as usize) * PATCH_BLOCK_SIZE;
-
-            // Bounds check
-            if offset >= new_binary.len() {
-                return Err(7); // ErrorCode::BlockOutOfBounds
-            }
-
-            let end = (offset + block.xor_data.len()).min(new_binary.len());
-            let target_len = end - offset;
-
-            // XOR in place
-            for i in 0..target_len.min(block.xor_data.len()) {
-                new_binary[offset + i] ^= block.xor_data[i];
-            }
-        }
-
-        Ok(new_binary)
+        Self::read(patch)?.apply(old_binary).map_err(Self::error_code)
     }
 
     /// Apply patch directly to a buffer without cloning
@@ -175,68 +118,13 @@ impl Patcher {
     /// Faster than apply_patch() as it modifies in-place.
     /// Use when you can mutate the original buffer.
     ///
-    /// # Safety
+    /// # Errors
     ///
-    /// Buffer must be large enough for all patch blocks
+    /// The patch must keep the binary's length. The buffer is only
+    /// modified if the whole patch applies and the result matches the
+    /// target hash.
     pub fn apply_patch_inplace(buffer: &mut [u8], patch_data: &[u8]) -> Result<(), u8> {
-        if patch_data.len() < 21 {
-            return Err(1); // ErrorCode::InvalidPatchData
-        }
-
-        // Parse header (we validate but don't use it for now)
-        let _header = PatchHeader::from_bytes(&patch_data[0..17])
-            .ok_or(2)?; // ErrorCode::InvalidPatchHeader
-
-        // Parse block count
-        let block_count =
-            u32::from_le_bytes([patch_data[17], patch_data[18], patch_data[19], patch_data[20]])
-                as usize;
-
-        // Parse and apply blocks
-        let mut offset = 21;
-
-        for _ in 0..block_count {
-            if offset + 6 > patch_data.len() {
-                return Err(3); // ErrorCode::TruncatedPatch
-            }
-
-            // Read block index
-            let index = u32::from_le_bytes([
-                patch_data[offset],
-                patch_data[offset + 1],
-                patch_data[offset + 2],
-                patch_data[offset + 3],
-            ]);
-
-            // Read length
-            let length =
-                u16::from_le_bytes([patch_data[offset + 4], patch_data[offset + 5]]) as usize;
-
-            offset += 6;
-
-            if offset + length > patch_data.len() {
-                return Err(4); // ErrorCode::InvalidBlockData
-            }
-
-            // Calculate buffer offset
-            let buf_offset = (index as usize) * PATCH_BLOCK_SIZE;
-
-            if buf_offset >= buffer.len() {
-                return Err(7); // ErrorCode::BlockOutOfBounds
-            }
-
-            // XOR in place
-            let end = (buf_offset + length).min(buffer.len());
-            let target_len = end - buf_offset;
-
-            for i in 0..target_len.min(length) {
-                buffer[buf_offset + i] ^= patch_data[offset + i];
-            }
-
-            offset += length;
-        }
-
-        Ok(())
+        Self::read(patch_data)?.apply_in_place(buffer).map_err(Self::error_code)
     }
 
     /// Get the patched binary (consumes patcher)
@@ -258,24 +146,16 @@ impl Default for Patcher {
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
     use super::*;
-
-    /// Helper: Create a simple patch for testing
-    fn create_test_patch(block_index: u32, xor_data: Vec<u8>) -> Vec<u8> {
-        let mut patch = Vec::new();
-
-        // Header (17 bytes)
-        let header = PatchHeader::new(0x1111, 0x2222, 1); // algo=1 for XOR
-        patch.extend_from_slice(&header.to_bytes());
-
-        // Block count (1 block)
-        patch.extend_from_slice(&1u32.to_le_bytes());
-
-        // Block data
-        patch.extend_from_slice(&block_index.to_le_bytes()); // index
-        patch.extend_from_slice(&(xor_data.len() as u16).to_le_bytes()); // length
-        patch.extend_from_slice(&xor_data); // data
-
-        patch
+    use dx_packet::{PatchHeader, PatchWriter};
+
+    /// Helper: Create a patch that XORs `xor_data` into `old` at a block
+    fn create_test_patch(old: &[u8], block_index: u32, xor_data: Vec<u8>) -> Vec<u8> {
+        let mut new = old.to_vec();
+        let offset = block_index as usize * PATCH_BLOCK_SIZE;
+        for (byte, xor) in new[offset..].iter_mut().zip(&xor_data) {
+            *byte ^= xor;
+        }
+        PatchWriter::diff(old, &new).unwrap()
     }
 
     #[test]
@@ -284,11 +164,11 @@ mod tests {
 
         // Old binary: 8KB of zeros
         let old_binary = vec![0u8; 8192];
-        patcher.set_old_binary(old_binary);
+        patcher.set_old_binary(old_binary.clone());
 
         // Patch: XOR first 10 bytes of block 0 with pattern
         let xor_data = vec![0xFF, 0xEE, 0xDD, 0xCC, 0xBB, 0xAA, 0x99, 0x88, 0x77, 0x66];
-        let patch = create_test_patch(0, xor_data.clone());
+        let patch = create_test_patch(&old_binary, 0, xor_data.clone());
 
         patcher.set_patch_data(&patch).unwrap();
 
@@ -311,36 +191,37 @@ mod tests {
 
         // Old binary: 12KB
         let old_binary = vec![0x00u8; 12288];
-        patcher.set_old_binary(old_binary);
-
-        // Patch with 2 blocks
-        let mut patch = Vec::new();
-
-        // Header
-        let header = PatchHeader::new(0x1111, 0x2222, 1);
-        patch.extend_from_slice(&header.to_bytes());
-
-        // Block count (2 blocks)
-        patch.extend_from_slice(&2u32.to_le_bytes());
-
-        // Block 0: Change first byte
-        patch.extend_from_slice(&0u32.to_le_bytes()); // index 0
-        patch.extend_from_slice(&1u16.to_le_bytes()); // length 1
-        patch.push(0xAA); // XOR data
+        patcher.set_old_binary(old_binary.clone());
 
-        // Block 1: Change byte at offset 4096 (second block)
-        patch.extend_from_slice(&1u32.to_le_bytes()); // index 1
-        patch.extend_from_slice(&1u16.to_le_bytes()); // length 1
-        patch.push(0xBB); // XOR data
+        // Change the first byte of block 0 and of block 1
+        let mut expected = old_binary.clone();
+        expected[0] = 0xAA;
+        expected[PATCH_BLOCK_SIZE] = 0xBB;
+        let patch = PatchWriter::diff(&old_binary, &expected).unwrap();
 
         patcher.set_patch_data(&patch).unwrap();
         let new_binary = patcher.apply_patch().unwrap();
 
         // Verify
         assert_eq!(new_binary[0], 0xAA); // Block 0
-        assert_eq!(new_binary[4096], 0xBB); // Block 1
+        assert_eq!(new_binary[PATCH_BLOCK_SIZE], 0xBB); // Block 1
         assert_eq!(new_binary[1], 0x00); // Unchanged
-        assert_eq!(new_binary[4097], 0x00); // Unchanged
+        assert_eq!(new_binary[PATCH_BLOCK_SIZE + 1], 0x00); // Unchanged
+    }
+
+    #[test]
+    fn test_patcher_resizes_binary() {
+        let old_binary = vec![0x11u8; 1000];
+        let mut grown = old_binary.clone();
+        grown.extend_from_slice(&[0x22; 100]);
+        let shrunk = old_binary[..900].to_vec();
+
+        for new in [grown, shrunk] {
+            let mut patcher = Patcher::new();
+            patcher.set_old_binary(old_binary.clone());
+            patcher.set_patch_data(&PatchWriter::diff(&old_binary, &new).unwrap()).unwrap();
+            assert_eq!(patcher.apply_patch().unwrap(), new);
+        }
     }
 
     #[test]
@@ -350,7 +231,7 @@ mod tests {
 
         // Create patch
         let xor_data = vec![0xFF, 0xEE, 0xDD];
-        let patch = create_test_patch(0, xor_data);
+        let patch = create_test_patch(&buffer, 0, xor_data);
 
         // Apply in-place
         Patcher::apply_patch_inplace(&mut buffer, &patch).unwrap();
@@ -376,7 +257,7 @@ mod tests {
         let mut patcher = Patcher::new();
         patcher.set_old_binary(old.clone());
 
-        let patch = create_test_patch(0, diff.clone());
+        let patch = create_test_patch(&old, 0, diff.clone());
         patcher.set_patch_data(&patch).unwrap();
 
         let result = patcher.apply_patch().unwrap();
@@ -384,10 +265,10 @@ mod tests {
         // Should equal new
         assert_eq!(&result[0..4], &new[..]);
 
-        // Reverse: Apply same patch to new, should get old
+        // Reverse: Apply same diff to new, should get old
         let mut patcher2 = Patcher::new();
-        patcher2.set_old_binary(new);
-        let patch2 = create_test_patch(0, diff);
+        patcher2.set_old_binary(new.clone());
+        let patch2 = create_test_patch(&new, 0, diff);
         patcher2.set_patch_data(&patch2).unwrap();
         let result2 = patcher2.apply_patch().unwrap();
 
@@ -401,10 +282,8 @@ mod tests {
         patcher.set_old_binary(old_binary.clone());
 
         // Patch with 0 blocks
-        let mut patch = Vec::new();
-        let header = PatchHeader::new(0x1111, 0x2222, 1);
-        patch.extend_from_slice(&header.to_bytes());
-        patch.extend_from_slice(&0u32.to_le_bytes()); // 0 blocks
+        let patch = PatchWriter::diff(&old_binary, &old_binary).unwrap();
+        assert_eq!(patch.len(), PatchWriter::PREFIX_SIZE);
 
         patcher.set_patch_data(&patch).unwrap();
         let new_binary = patcher.apply_patch().unwrap();
@@ -426,9 +305,9 @@ mod tests {
 
         patcher.set_old_binary(old_binary.clone());
 
-        // Create large XOR data (2KB)
+        // Create large change (2KB, spanning many blocks)
         let xor_data: Vec<u8> = (0..2048).map(|i| ((i * 3) % 256) as u8).collect();
-        let patch = create_test_patch(0, xor_data.clone());
+        let patch = create_test_patch(&old_binary, 0, xor_data.clone());
 
         patcher.set_patch_data(&patch).unwrap();
         let new_binary = patcher.apply_patch().unwrap();
@@ -442,4 +321,72 @@ mod tests {
         // Rest unchanged
         assert_eq!(new_binary[2048], old_binary[2048]);
     }
+
+    #[test]
+    fn test_patcher_rejects_wrong_base() {
+        let old_binary = vec![0x11u8; 4096];
+        let patch = create_test_patch(&old_binary, 0, vec![0xFF; 4]);
+
+        // A different (e.g. stale cached) binary than the patch was made for
+        let mut cached = old_binary.clone();
+        cached[100] = 0x22;
+
+        let mut patcher = Patcher::new();
+        patcher.set_old_binary(cached.clone());
+        patcher.set_patch_data(&patch).unwrap();
+        assert_eq!(patcher.apply_patch(), Err(8));
+        assert_eq!(patcher.old_binary.as_ref(), Some(&cached));
+
+        let mut buffer = cached.clone();
+        assert_eq!(Patcher::apply_patch_inplace(&mut buffer, &patch), Err(8));
+        assert_eq!(buffer, cached);
+    }
+
+    #[test]
+    fn test_patcher_rejects_wrong_target() {
+        let old_binary = vec![0x11u8; 4096];
+        let mut patch = create_test_patch(&old_binary, 0, vec![0xFF; 4]);
+
+        // Corrupt the XOR data: the result no longer matches the target hash
+        let last = patch.len() - 1;
+        patch[last] ^= 0x01;
+
+        let mut patcher = Patcher::new();
+        patcher.set_old_binary(old_binary.clone());
+        patcher.set_patch_data(&patch).unwrap();
+        assert_eq!(patcher.apply_patch(), Err(9));
+
+        let mut buffer = old_binary.clone();
+        assert_eq!(Patcher::apply_patch_inplace(&mut buffer, &patch), Err(9));
+        assert_eq!(buffer, old_binary);
+    }
+
+    #[test]
+    fn test_patcher_rejects_bad_blocks() {
+        let old_binary = vec![0x11u8; 4096];
+        let header = PatchHeader::new(
+            PatchHeader::version_hash(&old_binary),
+            PatchHeader::version_hash(&old_binary),
+            PatchWriter::ALGORITHM,
+        );
+        let mut patch = Vec::new();
+        patch.extend_from_slice(&header.to_bytes());
+        patch.extend_from_slice(&4096u32.to_le_bytes()); // target length
+        patch.extend_from_slice(&2u32.to_le_bytes());
+        patch.extend_from_slice(&0u32.to_le_bytes()); // valid block first
+        patch.extend_from_slice(&1u16.to_le_bytes());
+        patch.push(0xFF);
+        patch.extend_from_slice(&1000u32.to_le_bytes()); // then out of bounds
+        patch.extend_from_slice(&1u16.to_le_bytes());
+        patch.push(0xFF);
+
+        let mut patcher = Patcher::new();
+        assert_eq!(patcher.set_patch_data(&patch), Err(7));
+        assert_eq!(patcher.set_patch_data(&patch[..PatchWriter::PREFIX_SIZE + 6]), Err(3));
+        assert_eq!(patcher.set_patch_data(&patch[..20]), Err(1));
+
+        let mut buffer = old_binary.clone();
+        assert_eq!(Patcher::apply_patch_inplace(&mut buffer, &patch), Err(7));
+        assert_eq!(buffer, old_binary);
+    }
 }
@@ -8,8 +8,10 @@ use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{Document, Element, Node};
 
+use crate::batch::StagedOp;
 use crate::node_registry::NodeRegistry;
-use crate::string_table::StringTableReader;
+use crate::stream_state::{OpSink, StreamState};
+use crate::string_table::Strings;
 use crate::template_cache::TemplateCache;
 
 /// Main renderer
@@ -18,6 +20,8 @@ pub struct Renderer {
     node_registry: NodeRegistry,
     document: Document,
     root: Option<Element>,
+    /// Limits, open batches and chunked-stream totals
+    stream: StreamState,
 }
 
 impl Renderer {
@@ -31,9 +35,34 @@ impl Renderer {
             node_registry: NodeRegistry::new(),
             document,
             root: None,
+            stream: StreamState::new(),
         })
     }
 
+    /// Replace the limits applied to streams
+    ///
+    /// Until this is called, the limits follow each stream's ID width (see
+    /// `DecodeLimits::for_width`).
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.stream.set_limits(limits);
+        self.node_registry.set_max_nodes(limits.max_nodes as usize);
+    }
+
+    /// Register the templates of a Layout chunk
+    ///
+    /// Runs as soon as the chunk arrives, ahead of any opcodes.
+    pub fn register_layout(&mut self, layout: &[u8]) -> Result<(), u8> {
+        let reader = LayoutReader::new(layout).map_err(|e| e as u8)?;
+        self.stream.check_layout(reader.remaining() as usize)?;
+
+        for template in reader {
+            let template = template.map_err(|e| e as u8)?;
+            self.template_cache.register(template.id, template.html)?;
+        }
+
+        Ok(())
+    }
+
     /// Set root element for rendering
     pub fn set_root(&mut self, selector: &str) -> Result<(), u8> {
         self.root = self
@@ -53,133 +82,97 @@ impl Renderer {
 
     /// Process HTIP stream
     pub fn process_stream(&mut self, data: &[u8], header: &HtipHeader) -> Result<(), u8> {
-        let mut offset = HtipHeader::SIZE;
-
-        // Parse string table if present
-        let strings = if header.string_count > 0 {
-            Some(StringTableReader::new(data, offset, header.string_count))
-        } else {
-            None
-        };
-
-        // Skip past string table
-        if let Some(ref s) = strings {
-            // Calculate string table size: entries + data
-            offset += (header.string_count as usize) * StringEntry::SIZE;
-            // Note: string data size would need to be in header or calculated
-        }
-
-        // Parse templates if present
-        if header.template_count > 0 {
-            for _ in 0..header.template_count {
-                if offset + TemplateEntry::SIZE > data.len() {
-                    return Err(ErrorCode::BufferTooSmall as u8);
-                }
-
-                let entry = unsafe {
-                    ptr::read_unaligned(data.as_ptr().add(offset) as *const TemplateEntry)
-                };
-                offset += TemplateEntry::SIZE;
-
-                // Get HTML from string table
-                if let Some(ref strings) = strings {
-                    if let Some(html) = strings.get(entry.html_string_idx) {
-                        self.template_cache.register(entry.id, html)?;
-                    }
-                }
-            }
-        }
-
-        // Process opcodes
-        for _ in 0..header.opcode_count {
-            if offset + OpcodeHeader::SIZE > data.len() {
-                return Err(ErrorCode::BufferTooSmall as u8);
-            }
-
-            let op_header =
-                unsafe { ptr::read_unaligned(data.as_ptr().add(offset) as *const OpcodeHeader) };
-            offset += OpcodeHeader::SIZE;
+        // The stream state is moved out while it drives self
+        let mut stream = core::mem::take(&mut self.stream);
+        let result = stream.process_stream(data, header, self);
+        self.stream = stream;
+        result
+    }
 
-            // Execute opcode
-            offset = self.execute_op(&op_header, data, offset, &strings)?;
-        }
+    /// Process one Opcodes chunk of a chunked stream (see
+    /// `StreamState::process_chunk`)
+    pub fn process_chunk(&mut self, data: &[u8]) -> Result<(), u8> {
+        let mut stream = core::mem::take(&mut self.stream);
+        let result = stream.process_chunk(data, self);
+        self.stream = stream;
+        result
+    }
 
-        Ok(())
+    /// End a chunked stream
+    ///
+    /// A batch still open after the last Opcodes chunk is dropped.
+    pub fn finish_chunks(&mut self) -> Result<(), u8> {
+        self.stream.finish_chunks()
     }
 
     /// Execute a single opcode
-    fn execute_op(
-        &mut self,
-        header: &OpcodeHeader,
-        data: &[u8],
-        mut offset: usize,
-        strings: &Option<StringTableReader>,
-    ) -> Result<usize, u8> {
-        let op_type = OpType::from_u8(header.op_type).ok_or(ErrorCode::InvalidOpcode as u8)?;
+    fn execute_op(&mut self, op: &StagedOp, strings: Option<&dyn Strings>) -> Result<(), u8> {
+        let op_type = op.op_type().ok_or(ErrorCode::InvalidOpcode as u8)?;
+        let target_id = op.target_id;
 
         match op_type {
             OpType::Clone => {
-                let payload = self.read_payload::<ClonePayload>(data, &mut offset)?;
-                self.execute_clone(header.target_id, &payload)?;
+                self.execute_clone(target_id, &Self::payload::<ClonePayloadWide>(op))?;
             }
             OpType::PatchText => {
-                let payload = self.read_payload::<PatchTextPayload>(data, &mut offset)?;
-                if let Some(ref s) = strings {
-                    self.execute_patch_text(header.target_id, &payload, s)?;
+                if let Some(s) = strings {
+                    self.execute_patch_text(target_id, &Self::payload(op), s)?;
                 }
             }
             OpType::PatchAttr => {
-                let payload = self.read_payload::<PatchAttrPayload>(data, &mut offset)?;
-                if let Some(ref s) = strings {
-                    self.execute_patch_attr(header.target_id, &payload, s)?;
+                if let Some(s) = strings {
+                    self.execute_patch_attr(target_id, &Self::payload(op), s)?;
                 }
             }
             OpType::ClassToggle => {
-                let payload = self.read_payload::<ClassTogglePayload>(data, &mut offset)?;
-                if let Some(ref s) = strings {
-                    self.execute_class_toggle(header.target_id, &payload, s)?;
+                if let Some(s) = strings {
+                    self.execute_class_toggle(target_id, &Self::payload(op), s)?;
                 }
             }
             OpType::Remove => {
-                self.execute_remove(header.target_id)?;
+                self.execute_remove(target_id)?;
             }
             OpType::SetStyle => {
-                let payload = self.read_payload::<SetStylePayload>(data, &mut offset)?;
-                if let Some(ref s) = strings {
-                    self.execute_set_style(header.target_id, &payload, s)?;
+                if let Some(s) = strings {
+                    self.execute_set_style(target_id, &Self::payload(op), s)?;
+                }
+            }
+            OpType::AttachEvent => {
+                if let Some(s) = strings {
+                    self.execute_attach_event(target_id, &Self::payload(op), s)?;
+                }
+            }
+            OpType::SetProperty => {
+                if let Some(s) = strings {
+                    self.execute_set_property(target_id, &Self::payload(op), s)?;
                 }
             }
+            OpType::AppendChild => {
+                self.execute_append_child(target_id, &Self::payload(op))?;
+            }
             OpType::BatchStart | OpType::BatchCommit => {
-                // Batch markers are no-ops in this implementation
-                // Future: could defer DOM writes until commit
+                // Handled by StreamState::dispatch
             }
         }
 
-        Ok(offset)
+        Ok(())
     }
 
-    /// Read a payload struct from data
-    fn read_payload<T: Copy>(&self, data: &[u8], offset: &mut usize) -> Result<T, u8> {
-        let size = core::mem::size_of::<T>();
-        if *offset + size > data.len() {
-            return Err(ErrorCode::BufferTooSmall as u8);
-        }
-
-        let payload = unsafe { ptr::read_unaligned(data.as_ptr().add(*offset) as *const T) };
-        *offset += size;
-
-        Ok(payload)
+    /// Read a payload struct from a staged op
+    fn payload<T: Copy>(op: &StagedOp) -> T {
+        debug_assert!(core::mem::size_of::<T>() <= op.payload.len());
+        unsafe { ptr::read_unaligned(op.payload.as_ptr() as *const T) }
     }
 
     // ========================================================================
     // Opcode Executors
     // ========================================================================
 
-    fn execute_clone(&mut self, _target_id: u16, payload: &ClonePayload) -> Result<(), u8> {
+    fn execute_clone(&mut self, _target_id: u32, payload: &ClonePayloadWide) -> Result<(), u8> {
         let cloned = self.template_cache.clone_template(payload.template_id)?;
 
         // Register the cloned node
-        let _node_id = self.node_registry.register(cloned.clone());
+        let _node_id = self.node_registry.register(cloned.clone())?;
 
         // Append to parent
         if payload.parent_id == 0 {
@@ -196,9 +189,9 @@ impl Renderer {
 
     fn execute_patch_text(
         &mut self,
-        target_id: u16,
+        target_id: u32,
         payload: &PatchTextPayload,
-        strings: &StringTableReader,
+        strings: &dyn Strings,
     ) -> Result<(), u8> {
         let text =
             strings.get(payload.string_idx).ok_or(ErrorCode::StringIndexOutOfBounds as u8)?;
@@ -212,9 +205,9 @@ impl Renderer {
 
     fn execute_patch_attr(
         &mut self,
-        target_id: u16,
+        target_id: u32,
         payload: &PatchAttrPayload,
-        strings: &StringTableReader,
+        strings: &dyn Strings,
     ) -> Result<(), u8> {
         let name = strings
             .get(payload.attr_name_idx)
@@ -238,9 +231,9 @@ impl Renderer {
 
     fn execute_class_toggle(
         &mut self,
-        target_id: u16,
+        target_id: u32,
         payload: &ClassTogglePayload,
-        strings: &StringTableReader,
+        strings: &dyn Strings,
     ) -> Result<(), u8> {
         let class_name = strings
             .get(payload.class_name_idx)
@@ -254,7 +247,7 @@ impl Renderer {
         Ok(())
     }
 
-    fn execute_remove(&mut self, target_id: u16) -> Result<(), u8> {
+    fn execute_remove(&mut self, target_id: u32) -> Result<(), u8> {
         if let Some(node) = self.node_registry.remove(target_id) {
             if let Some(parent) = node.parent_node() {
                 let _ = parent.remove_child(&node);
@@ -265,9 +258,9 @@ impl Renderer {
 
     fn execute_set_style(
         &mut self,
-        target_id: u16,
+        target_id: u32,
         payload: &SetStylePayload,
-        strings: &StringTableReader,
+        strings: &dyn Strings,
     ) -> Result<(), u8> {
         let prop = strings
             .get(payload.prop_name_idx)
@@ -284,12 +277,93 @@ impl Renderer {
         Ok(())
     }
 
+    /// Listeners call `globalThis.dxHandleEvent(handler_id, event)`
+    fn execute_attach_event(
+        &mut self,
+        target_id: u32,
+        payload: &AttachEventPayload,
+        strings: &dyn Strings,
+    ) -> Result<(), u8> {
+        let event_type = strings
+            .get(payload.event_type_idx)
+            .ok_or(ErrorCode::StringIndexOutOfBounds as u8)?;
+
+        if let Some(node) = self.node_registry.get(target_id) {
+            attach_event(node, event_type, payload.handler_id);
+        }
+
+        Ok(())
+    }
+
+    fn execute_set_property(
+        &mut self,
+        target_id: u32,
+        payload: &SetPropertyPayload,
+        strings: &dyn Strings,
+    ) -> Result<(), u8> {
+        let name = strings
+            .get(payload.prop_name_idx)
+            .ok_or(ErrorCode::StringIndexOutOfBounds as u8)?;
+        let string = || strings.get(payload.value).ok_or(ErrorCode::StringIndexOutOfBounds as u8);
+
+        let value = match PropKind::from_u8(payload.kind).ok_or(ErrorCode::InvalidOpcode as u8)? {
+            PropKind::String => JsValue::from_str(string()?),
+            PropKind::Number => JsValue::from_f64(string()?.parse().unwrap_or(f64::NAN)),
+            PropKind::Bool => JsValue::from_bool(payload.value != 0),
+            PropKind::Null => JsValue::NULL,
+        };
+
+        if let Some(node) = self.node_registry.get(target_id) {
+            set_property(node, name, &value);
+        }
+
+        Ok(())
+    }
+
+    fn execute_append_child(
+        &mut self,
+        target_id: u32,
+        payload: &AppendChildPayloadWide,
+    ) -> Result<(), u8> {
+        let Some(child) = self.node_registry.get(payload.child_id) else {
+            return Ok(());
+        };
+
+        if target_id == 0 {
+            if let Some(ref root) = self.root {
+                let _ = root.append_child(child);
+            }
+        } else if let Some(parent) = self.node_registry.get(target_id) {
+            let _ = parent.append_child(child);
+        }
+
+        Ok(())
+    }
+
     /// Get node count
     pub fn node_count(&self) -> u32 {
         self.node_registry.count()
     }
 }
 
+impl OpSink for Renderer {
+    fn register_template(&mut self, id: u32, html: &str) -> Result<(), u8> {
+        self.template_cache.register(id, html)
+    }
+
+    fn has_template(&self, id: u32) -> bool {
+        self.template_cache.contains(id)
+    }
+
+    fn node_count(&self) -> usize {
+        self.node_registry.count() as usize
+    }
+
+    fn execute(&mut self, op: &StagedOp, strings: Option<&dyn Strings>) -> Result<(), u8> {
+        self.execute_op(op, strings)
+    }
+}
+
 // Inline JS snippets - fastest and smallest way to touch DOM
 #[wasm_bindgen(inline_js = "
     export function toggle_class(node, name, enable) {
@@ -303,8 +377,16 @@ impl Renderer {
             node.style.setProperty(prop, val);
         }
     }
+    export function attach_event(node, type, handler) {
+        node.addEventListener(type, (event) => globalThis.dxHandleEvent?.(handler, event));
+    }
+    export function set_property(node, name, val) {
+        node[name] = val;
+    }
 ")]
 extern "C" {
     fn toggle_class(node: &Node, name: &str, enable: bool);
     fn set_style(node: &Node, prop: &str, val: &str);
+    fn attach_event(node: &Node, event_type: &str, handler_id: u16);
+    fn set_property(node: &Node, name: &str, val: &JsValue);
 }
@@ -0,0 +1,131 @@
+//! State Store: Initial component state from the State chunk
+//!
+//! Mirrors dx-core's State Region: every component's image is bump-allocated
+//! into one contiguous buffer and addressed by offset.
+
+use dx_packet::StateReader;
+
+/// Entry in the component table: (component_id, offset, len)
+type Slot = (u16, u32, u32);
+
+/// Component state storage loaded before the first render
+pub struct StateStore {
+    /// Contiguous State Region image
+    region: Vec<u8>,
+    /// Component table, in snapshot order
+    slots: Vec<Slot>,
+}
+
+impl StateStore {
+    /// Create empty store
+    pub const fn new() -> Self {
+        Self {
+            region: Vec::new(),
+            slots: Vec::new(),
+        }
+    }
+
+    /// Decode a State chunk and allocate every component in the region
+    ///
+    /// An empty chunk means "no initial state" and is accepted.
+    /// On error, the store is left unchanged.
+    pub fn load(&mut self, data: &[u8]) -> Result<(), u8> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let reader = StateReader::new(data).map_err(|e| e as u8)?;
+
+        // Validate every record before touching the region
+        let mut components = Vec::with_capacity(reader.remaining() as usize);
+        for component in reader {
+            components.push(component.map_err(|e| e as u8)?);
+        }
+
+        for component in components {
+            let image = component.data();
+            self.slots
+                .push((component.component_id, self.region.len() as u32, image.len() as u32));
+            self.region.extend_from_slice(image);
+        }
+
+        Ok(())
+    }
+
+    /// Get a component's state image
+    pub fn get(&self, component_id: u16) -> Option<&[u8]> {
+        let &(_, offset, len) = self.slots.iter().find(|s| s.0 == component_id)?;
+        self.region.get(offset as usize..(offset + len) as usize)
+    }
+
+    /// Offset of a component's state in the region
+    pub fn offset_of(&self, component_id: u16) -> Option<u32> {
+        self.slots.iter().find(|s| s.0 == component_id).map(|s| s.1)
+    }
+
+    /// Number of components with initial state
+    pub fn component_count(&self) -> u32 {
+        self.slots.len() as u32
+    }
+
+    /// Raw State Region image
+    pub fn region(&self) -> &[u8] {
+        &self.region
+    }
+
+    /// Drop all state (for hot reload)
+    pub fn clear(&mut self) {
+        self.region.clear();
+        self.slots.clear();
+    }
+}
+
+impl Default for StateStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use dx_packet::{ErrorCode, StateValue, StateWriter};
+
+    #[test]
+    fn test_load_state_snapshot() {
+        let mut writer = StateWriter::new();
+        writer
+            .add_component(0, &[(0, StateValue::I32(7)), (1, StateValue::Str("hi"))])
+            .unwrap();
+        writer.add_component(3, &[(0, StateValue::Bool(true))]).unwrap();
+
+        let mut store = StateStore::new();
+        store.load(&writer.finish()).unwrap();
+
+        assert_eq!(store.component_count(), 2);
+        assert_eq!(store.offset_of(0), Some(0));
+        assert_eq!(store.get(0).unwrap()[0..4], 7i32.to_le_bytes());
+        assert_eq!(store.offset_of(3), Some(12));
+        assert_eq!(store.get(3).unwrap(), &1u32.to_le_bytes());
+        assert!(store.get(1).is_none());
+    }
+
+    #[test]
+    fn test_load_rejects_corrupt_snapshot() {
+        let mut writer = StateWriter::new();
+        writer.add_component(0, &[(0, StateValue::I32(1))]).unwrap();
+        let bytes = writer.finish();
+
+        let mut store = StateStore::new();
+        store.load(&bytes).unwrap();
+
+        let err = store.load(&bytes[..bytes.len() - 2]).unwrap_err();
+        assert_eq!(err, ErrorCode::BufferTooSmall as u8);
+        assert_eq!(store.component_count(), 1);
+        assert_eq!(store.region().len(), 4);
+
+        // Empty State chunk means no initial state
+        store.load(&[]).unwrap();
+        assert_eq!(store.component_count(), 1);
+    }
+}
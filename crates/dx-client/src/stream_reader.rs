@@ -6,8 +6,15 @@
 //! - Dispatches to appropriate handler
 //!
 //! **Performance:** Zero-copy where possible, handles partial chunks gracefully
+//!
+//! A chunk longer than `DecodeLimits::max_chunk_bytes` is rejected from its
+//! header, before any of its body is buffered.
+//!
+//! Layout, State and Opcodes chunks are handed back by `ChunkDispatcher` as
+//! soon as they complete, so templates, initial state and above-the-fold
+//! content are in place before the WASM chunk (or EOF) arrives.
 
-use dx_packet::{ChunkHeader, ChunkType};
+use dx_packet::{ChunkHeader, ChunkType, DecodeLimits, LayoutReader, Limit};
 
 /// State machine for incremental chunk processing
 pub struct StreamReader {
@@ -19,6 +26,8 @@ pub struct StreamReader {
     offset: usize,
     /// Queue of complete chunks ready for processing
     chunk_queue: Vec<(ChunkType, Vec<u8>)>,
+    /// Bounds for untrusted streams
+    limits: DecodeLimits,
 }
 
 /// Reader state machine
@@ -35,11 +44,17 @@ enum ReaderState {
 impl StreamReader {
     /// Create new stream reader
     pub fn new() -> Self {
+        Self::with_limits(DecodeLimits::DEFAULT)
+    }
+
+    /// Create a stream reader that rejects chunks exceeding `limits`
+    pub fn with_limits(limits: DecodeLimits) -> Self {
         Self {
             state: ReaderState::ReadingHeader,
             buffer: Vec::with_capacity(8192), // 8KB initial buffer
             offset: 0,
             chunk_queue: Vec::new(),
+            limits,
         }
     }
 
@@ -63,6 +78,10 @@ impl StreamReader {
                     let header = ChunkHeader::from_bytes(header_bytes)
                         .ok_or(1u8)?; // ErrorCode::InvalidHeader
 
+                    self.limits
+                        .check(Limit::ChunkBytes, header.length as usize)
+                        .map_err(|limit| limit.error_code() as u8)?;
+
                     self.offset += 5;
 
                     // Transition to reading body
@@ -147,12 +166,25 @@ impl StreamReader {
     }
 }
 
+/// What the runtime must do with a handled chunk right away
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChunkAction {
+    /// Nothing (the chunk was checked or stored)
+    None,
+    /// Register these templates (a validated layout dictionary)
+    RegisterLayout(Vec<u8>),
+    /// Load this initial state into the `StateStore`
+    LoadState(Vec<u8>),
+    /// Run these opcodes (see `Renderer::process_chunk`)
+    Render(Vec<u8>),
+}
+
 /// Chunk dispatcher: Routes chunks to appropriate handlers
 pub struct ChunkDispatcher {
-    /// Accumulated layout data (template dictionary)
-    layout_data: Option<Vec<u8>>,
-    /// Accumulated state data (initial state)
-    state_data: Option<Vec<u8>>,
+    /// Layout chunk received (and handed out for registration)
+    layout_loaded: bool,
+    /// State chunk received (and handed out for loading)
+    state_loaded: bool,
     /// Accumulated WASM data (runtime logic)
     wasm_data: Option<Vec<u8>>,
 }
@@ -160,14 +192,18 @@ pub struct ChunkDispatcher {
 impl ChunkDispatcher {
     pub fn new() -> Self {
         Self {
-            layout_data: None,
-            state_data: None,
+            layout_loaded: false,
+            state_loaded: false,
             wasm_data: None,
         }
     }
 
     /// Handle a chunk
-    pub fn handle_chunk(&mut self, chunk_type: ChunkType, data: Vec<u8>) -> Result<(), u8> {
+    pub fn handle_chunk(
+        &mut self,
+        chunk_type: ChunkType,
+        data: Vec<u8>,
+    ) -> Result<ChunkAction, u8> {
         match chunk_type {
             ChunkType::Header => {
                 // Validate header (64 bytes)
@@ -175,25 +211,35 @@ impl ChunkDispatcher {
                     return Err(3u8); // ErrorCode::InvalidHeader
                 }
                 // TODO: Verify magic bytes, version, signature
-                Ok(())
+                Ok(ChunkAction::None)
             }
 
             ChunkType::Layout => {
-                // Store layout data for template registration
-                self.layout_data = Some(data);
-                Ok(())
+                // Decode every entry now: registration must not fail halfway
+                for template in LayoutReader::new(&data).map_err(|e| e as u8)? {
+                    template.map_err(|e| e as u8)?;
+                }
+                self.layout_loaded = true;
+                Ok(ChunkAction::RegisterLayout(data))
             }
 
             ChunkType::State => {
-                // Store initial state data
-                self.state_data = Some(data);
-                Ok(())
+                self.state_loaded = true;
+                Ok(ChunkAction::LoadState(data))
+            }
+
+            ChunkType::Opcodes => {
+                // Clones need the templates of the Layout chunk
+                if !self.layout_loaded {
+                    return Err(12u8); // ErrorCode::MissingLayout
+                }
+                Ok(ChunkAction::Render(data))
             }
 
             ChunkType::Wasm => {
                 // Store WASM binary
                 self.wasm_data = Some(data);
-                Ok(())
+                Ok(ChunkAction::None)
             }
 
             ChunkType::Patch => {
@@ -203,24 +249,24 @@ impl ChunkDispatcher {
 
             ChunkType::Eof => {
                 // Stream complete
-                Ok(())
+                Ok(ChunkAction::None)
             }
         }
     }
 
-    /// Check if all required chunks received
-    pub fn is_complete(&self) -> bool {
-        self.layout_data.is_some() && self.wasm_data.is_some()
+    /// Check if the first render can run (templates are registered)
+    pub fn is_layout_loaded(&self) -> bool {
+        self.layout_loaded
     }
 
-    /// Get layout data
-    pub fn take_layout(&mut self) -> Option<Vec<u8>> {
-        self.layout_data.take()
+    /// Check if the initial state was loaded
+    pub fn is_state_loaded(&self) -> bool {
+        self.state_loaded
     }
 
-    /// Get state data
-    pub fn take_state(&mut self) -> Option<Vec<u8>> {
-        self.state_data.take()
+    /// Check if all required chunks received
+    pub fn is_complete(&self) -> bool {
+        self.layout_loaded && self.wasm_data.is_some()
     }
 
     /// Get WASM data
@@ -232,6 +278,9 @@ impl ChunkDispatcher {
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
     use super::*;
+    use crate::stream_state::tests::MockSink;
+    use crate::stream_state::StreamState;
+    use dx_packet::{ErrorCode, HtipWriter, IdWidth, LayoutWriter, OpType};
 
     #[test]
     fn test_stream_reader_single_chunk() {
@@ -317,28 +366,211 @@ mod tests {
         assert!(reader.is_finished());
     }
 
+    #[test]
+    fn test_oversized_chunk_rejected_from_header() {
+        let limits = DecodeLimits {
+            max_chunk_bytes: 16,
+            ..DecodeLimits::DEFAULT
+        };
+        let mut reader = StreamReader::with_limits(limits);
+
+        // Claims 4 GB: rejected without waiting for (or buffering) the body
+        let data = vec![0x02u8, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert_eq!(reader.feed(&data), Err(ErrorCode::ChunkTooLarge as u8));
+
+        let mut reader = StreamReader::with_limits(limits);
+        let mut data = vec![0x02u8, 16, 0, 0, 0];
+        data.extend(vec![0xBBu8; 16]);
+        assert_eq!(reader.feed(&data), Ok(1));
+    }
+
     #[test]
     fn test_chunk_dispatcher() {
         let mut dispatcher = ChunkDispatcher::new();
 
         // Handle header
-        dispatcher
-            .handle_chunk(ChunkType::Header, vec![0xDDu8; 64])
-            .unwrap();
-
-        // Handle layout
-        dispatcher
-            .handle_chunk(ChunkType::Layout, vec![0xEEu8; 100])
-            .unwrap();
+        dispatcher.handle_chunk(ChunkType::Header, vec![0xDDu8; 64]).unwrap();
+
+        // Layout and state are handed out as soon as they arrive
+        let mut writer = LayoutWriter::new();
+        writer.add_template(0, "<p></p>", 1).unwrap();
+        let layout = writer.finish();
+        assert_eq!(
+            dispatcher.handle_chunk(ChunkType::Layout, layout.clone()),
+            Ok(ChunkAction::RegisterLayout(layout))
+        );
+        assert!(dispatcher.is_layout_loaded());
+        assert_eq!(
+            dispatcher.handle_chunk(ChunkType::State, vec![]),
+            Ok(ChunkAction::LoadState(vec![]))
+        );
+        assert!(!dispatcher.is_complete());
 
         // Handle WASM
-        dispatcher
-            .handle_chunk(ChunkType::Wasm, vec![0xFFu8; 1000])
-            .unwrap();
+        dispatcher.handle_chunk(ChunkType::Wasm, vec![0xFFu8; 1000]).unwrap();
 
         assert!(dispatcher.is_complete());
+        assert_eq!(dispatcher.take_wasm().unwrap().len(), 1000);
+    }
+
+    /// Concatenate chunks the way the server streams them
+    fn record(chunks: &[(ChunkType, Vec<u8>)]) -> Vec<u8> {
+        let mut stream = Vec::new();
+        for (chunk_type, data) in chunks {
+            stream.extend_from_slice(&ChunkHeader::new(*chunk_type, data.len() as u32).to_bytes());
+            stream.extend_from_slice(data);
+        }
+        stream
+    }
+
+    /// Feed a recorded stream one byte at a time, polling after each byte
+    ///
+    /// Returns each action with the number of bytes fed when it came out.
+    fn replay_byte_by_byte(stream: &[u8]) -> Vec<(usize, ChunkAction)> {
+        let mut reader = StreamReader::new();
+        let mut dispatcher = ChunkDispatcher::new();
+        let mut actions = Vec::new();
+
+        for (fed, byte) in stream.iter().enumerate() {
+            reader.feed(core::slice::from_ref(byte)).unwrap();
+            while let Some((chunk_type, data)) = reader.poll_chunk() {
+                match dispatcher.handle_chunk(chunk_type, data).unwrap() {
+                    ChunkAction::None => {}
+                    action => actions.push((fed + 1, action)),
+                }
+            }
+        }
+
+        assert!(reader.is_finished());
+        assert!(dispatcher.is_complete());
+        actions
+    }
+
+    /// Above- and below-the-fold Opcodes chunks of one page
+    ///
+    /// The first carries every string and template, opens batch 1 and clones
+    /// the templates. The second has no strings of its own: its PatchText
+    /// ops point into the first chunk's table, and it commits the batch.
+    fn page_chunks(with_texts: bool) -> (Vec<u8>, Vec<u8>) {
+        let mut writer = HtipWriter::new(IdWidth::Compact);
+        let header = writer.add_string("<header><h1></h1></header>").unwrap();
+        let footer = writer.add_string("<footer><p></p></footer>").unwrap();
+        let (title, year) = if with_texts {
+            (
+                writer.add_string("{self.title}").unwrap(),
+                writer.add_string("{self.year}").unwrap(),
+            )
+        } else {
+            (2, 3)
+        };
+        writer.add_template(0, header, 1).unwrap();
+        writer.add_template(1, footer, 1).unwrap();
+        writer.batch_start(1).unwrap();
+        writer.clone_template(1, 0, 0).unwrap();
+        writer.clone_template(2, 1, 0).unwrap();
+        let above_fold = writer.finish();
+
+        let mut writer = HtipWriter::new(IdWidth::Compact);
+        writer.patch_text(1, title).unwrap();
+        writer.patch_text(2, year).unwrap();
+        writer.batch_commit(1).unwrap();
+        (above_fold, writer.finish())
+    }
+
+    #[test]
+    fn test_opcodes_render_before_eof() {
+        let (above_fold, below_fold) = page_chunks(true);
+
+        let mut writer = LayoutWriter::new();
+        writer.add_template(0, "<header><h1></h1></header>", 1).unwrap();
+        writer.add_template(1, "<footer><p></p></footer>", 1).unwrap();
+        let layout = writer.finish();
+
+        let stream = record(&[
+            (ChunkType::Header, vec![0u8; 64]),
+            (ChunkType::Layout, layout.clone()),
+            (ChunkType::State, vec![]),
+            (ChunkType::Opcodes, above_fold.clone()),
+            (ChunkType::Wasm, vec![0u8; 2000]),
+            (ChunkType::Opcodes, below_fold.clone()),
+            (ChunkType::Eof, vec![]),
+        ]);
+
+        let layout_len = layout.len();
+        let actions = replay_byte_by_byte(&stream);
+        let (positions, actions): (Vec<_>, Vec<_>) = actions.into_iter().unzip();
+        assert_eq!(
+            actions,
+            [
+                ChunkAction::RegisterLayout(layout),
+                ChunkAction::LoadState(vec![]),
+                ChunkAction::Render(above_fold.clone()),
+                ChunkAction::Render(below_fold.clone()),
+            ]
+        );
+
+        // Each chunk is handed out on its last byte, the first render long
+        // before the WASM chunk is through
+        assert_eq!(positions[0], 69 + 5 + layout_len);
+        assert_eq!(positions[2], positions[1] + 5 + above_fold.len());
+        assert!(positions[2] + 2005 < stream.len());
+        assert_eq!(positions[3], stream.len() - 5);
+
+        // The batch opened above the fold stays open across the WASM chunk
+        let mut state = StreamState::new();
+        let mut sink = MockSink::default();
+        state.process_chunk(&above_fold, &mut sink).unwrap();
+        assert!(state.is_batch_open());
+        assert_eq!(sink.templates.len(), 2);
+        assert!(sink.applied.is_empty());
+
+        // ...and commits below it, with string indices from the first chunk
+        state.process_chunk(&below_fold, &mut sink).unwrap();
+        state.finish_chunks().unwrap();
+        let text = |s: &str| Some(s.to_string());
+        assert_eq!(
+            sink.applied,
+            [
+                (OpType::Clone, 1, None),
+                (OpType::Clone, 2, None),
+                (OpType::PatchText, 1, text("{self.title}")),
+                (OpType::PatchText, 2, text("{self.year}")),
+            ]
+        );
+
+        // Without those strings the same indices are out of range, and
+        // nothing of the batch is applied
+        let (opener, _) = page_chunks(false);
+        let mut state = StreamState::new();
+        let mut sink = MockSink::default();
+        state.process_chunk(&opener, &mut sink).unwrap();
+        assert_eq!(
+            state.process_chunk(&below_fold, &mut sink),
+            Err(ErrorCode::StringIndexOutOfBounds as u8)
+        );
+        assert!(sink.applied.is_empty());
+    }
+
+    #[test]
+    fn test_opcodes_need_layout() {
+        let mut dispatcher = ChunkDispatcher::new();
+        let early = dispatcher.handle_chunk(ChunkType::Opcodes, vec![0u8; 12]);
+        assert_eq!(early, Err(12)); // MissingLayout
+    }
+
+    #[test]
+    fn test_chunk_dispatcher_rejects_bad_layout() {
+        let mut dispatcher = ChunkDispatcher::new();
+
+        let garbage = dispatcher.handle_chunk(ChunkType::Layout, vec![0xEEu8; 100]);
+        assert_eq!(garbage, Err(ErrorCode::InvalidMagic as u8));
 
-        let layout = dispatcher.take_layout().unwrap();
-        assert_eq!(layout.len(), 100);
+        let mut writer = LayoutWriter::new();
+        writer.add_template(0, "<p></p>", 1).unwrap();
+        let mut truncated = writer.finish();
+        truncated.pop();
+        let truncated = dispatcher.handle_chunk(ChunkType::Layout, truncated);
+        assert_eq!(truncated, Err(ErrorCode::BufferTooSmall as u8));
+        assert!(!dispatcher.is_layout_loaded());
     }
 }
@@ -0,0 +1,423 @@
+//! Stream State: the DOM-free half of the renderer
+//!
+//! Parses HTIP streams and Opcodes chunks, applies `DecodeLimits`, stages
+//! batches and checks each one before any of it is applied. Whatever
+//! touches the DOM (templates, nodes, the ops themselves) sits behind
+//! `OpSink`, so all of this also runs off the browser.
+
+use core::ptr;
+use dx_packet::*;
+
+use crate::batch::{check_batch, BatchStage, StagedOp};
+use crate::string_table::{StringPool, StringTableReader, Strings};
+
+/// The DOM side of rendering
+pub trait OpSink {
+    /// Register a template's HTML
+    fn register_template(&mut self, id: u32, html: &str) -> Result<(), u8>;
+    /// Check if a template is registered
+    fn has_template(&self, id: u32) -> bool;
+    /// Number of live nodes
+    fn node_count(&self) -> usize;
+    /// Apply one op (never BatchStart or BatchCommit)
+    fn execute(&mut self, op: &StagedOp, strings: Option<&dyn Strings>) -> Result<(), u8>;
+}
+
+/// Limits, open batches and chunked-stream totals of a renderer
+pub struct StreamState {
+    /// Open batches (ops are held until the outermost commit)
+    batch: BatchStage,
+    /// Bounds for untrusted streams (None = `DecodeLimits::for_width`)
+    limits: Option<DecodeLimits>,
+    /// ID width of the stream being processed
+    width: IdWidth,
+    /// Strings of the chunked stream so far (see `process_chunk`)
+    strings: StringPool,
+    /// Templates and ops of the chunked stream so far
+    templates: usize,
+    ops: usize,
+}
+
+impl StreamState {
+    pub fn new() -> Self {
+        Self {
+            batch: BatchStage::new(),
+            limits: None,
+            width: IdWidth::Compact,
+            strings: StringPool::new(),
+            templates: 0,
+            ops: 0,
+        }
+    }
+
+    /// Replace the limits applied to streams, whatever their ID width
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.limits = Some(limits);
+    }
+
+    /// Limits applied to streams with `width` IDs
+    pub fn limits(&self, width: IdWidth) -> DecodeLimits {
+        self.limits.unwrap_or(DecodeLimits::for_width(width))
+    }
+
+    /// Check an amount against the limits of the current stream
+    pub fn check_limit(&self, limit: Limit, found: usize) -> Result<(), u8> {
+        let limits = self.limits(self.width);
+        limits.check(limit, found).map_err(|limit| limit.error_code() as u8)
+    }
+
+    /// Check the size of a layout dictionary (its IDs are always `u32`)
+    pub fn check_layout(&self, template_count: usize) -> Result<(), u8> {
+        let limits = self.limits(IdWidth::Wide);
+        limits
+            .check(Limit::Templates, template_count)
+            .map_err(|limit| limit.error_code() as u8)
+    }
+
+    /// Check if ops are currently being staged
+    pub fn is_batch_open(&self) -> bool {
+        self.batch.is_open()
+    }
+
+    /// Process a whole HTIP stream
+    pub fn process_stream(
+        &mut self,
+        data: &[u8],
+        header: &HtipHeader,
+        sink: &mut impl OpSink,
+    ) -> Result<(), u8> {
+        // Reject oversized streams before touching any of them
+        self.width = header.id_width();
+        self.check_limit(Limit::Strings, header.string_count as usize)?;
+        self.check_limit(Limit::Templates, header.template_count as usize)?;
+        self.check_limit(Limit::Ops, header.opcode_count as usize)?;
+
+        // Parse string table if present
+        let strings = if header.string_count > 0 {
+            Some(StringTableReader::new(data, HtipHeader::SIZE, header.string_count))
+        } else {
+            None
+        };
+        if let Some(ref s) = strings {
+            self.check_limit(Limit::StringBytes, s.total_len())?;
+        }
+
+        // Templates start past the string entries and data
+        let offset = strings.as_ref().map_or(HtipHeader::SIZE, |s| s.end());
+        let table = strings.as_ref().map(|s| s as &dyn Strings);
+        self.run_sections(data, offset, header, table, sink)?;
+
+        // A batch left open at the end of the stream is dropped
+        if self.batch.is_open() {
+            self.batch.abort();
+            return Err(ErrorCode::InvalidBatch as u8);
+        }
+
+        Ok(())
+    }
+
+    /// Process one Opcodes chunk of a chunked stream
+    ///
+    /// The chunk is an HTIP stream whose string table extends the tables of
+    /// the chunks before it: string indices are stream-wide, so a batch can
+    /// start in one chunk and commit in a later one. Limits apply to the
+    /// whole stream so far, not to each chunk.
+    pub fn process_chunk(&mut self, data: &[u8], sink: &mut impl OpSink) -> Result<(), u8> {
+        if data.len() < HtipHeader::SIZE {
+            return Err(ErrorCode::BufferTooSmall as u8);
+        }
+        let header = unsafe { ptr::read_unaligned(data.as_ptr() as *const HtipHeader) };
+        if !header.is_valid() {
+            return Err(ErrorCode::InvalidMagic as u8);
+        }
+
+        self.width = header.id_width();
+        let table = StringTableReader::new(data, HtipHeader::SIZE, header.string_count);
+        let string_count = self.strings.count() + header.string_count as usize;
+        self.check_limit(Limit::Strings, string_count)?;
+        self.check_limit(Limit::StringBytes, self.strings.total_len() + table.total_len())?;
+        let template_count = self.templates + header.template_count as usize;
+        self.check_limit(Limit::Templates, template_count)?;
+        let op_count = self.ops + header.opcode_count as usize;
+        self.check_limit(Limit::Ops, op_count)?;
+        self.strings.extend(&table)?;
+        self.templates = template_count;
+        self.ops = op_count;
+
+        // The pool is moved out while the sections borrow self
+        let strings = core::mem::take(&mut self.strings);
+        let result = self.run_sections(data, table.end(), &header, Some(&strings), sink);
+        self.strings = strings;
+        result
+    }
+
+    /// End a chunked stream
+    ///
+    /// A batch still open after the last Opcodes chunk is dropped.
+    pub fn finish_chunks(&mut self) -> Result<(), u8> {
+        self.strings.clear();
+        self.templates = 0;
+        self.ops = 0;
+
+        if self.batch.is_open() {
+            self.batch.abort();
+            return Err(ErrorCode::InvalidBatch as u8);
+        }
+
+        Ok(())
+    }
+
+    /// Register the templates and run the opcodes that follow the strings
+    fn run_sections(
+        &mut self,
+        data: &[u8],
+        mut offset: usize,
+        header: &HtipHeader,
+        strings: Option<&dyn Strings>,
+        sink: &mut impl OpSink,
+    ) -> Result<(), u8> {
+        // Parse templates if present
+        let width = header.id_width();
+        for _ in 0..header.template_count {
+            if offset + TemplateEntry::SIZE > data.len() {
+                return Err(ErrorCode::BufferTooSmall as u8);
+            }
+
+            let (id, html_string_idx) = unsafe {
+                let entry = data.as_ptr().add(offset);
+                match width {
+                    IdWidth::Compact => {
+                        let entry = ptr::read_unaligned(entry as *const TemplateEntry);
+                        (entry.id as u32, entry.html_string_idx)
+                    }
+                    IdWidth::Wide => {
+                        let entry = ptr::read_unaligned(entry as *const TemplateEntryWide);
+                        (entry.id, entry.html_string_idx)
+                    }
+                }
+            };
+            offset += TemplateEntry::SIZE;
+
+            // Get HTML from string table
+            if let Some(html) = strings.and_then(|s| s.get(html_string_idx)) {
+                sink.register_template(id, html)?;
+            }
+        }
+
+        // Process opcodes
+        for _ in 0..header.opcode_count {
+            let op = StagedOp::read(data, &mut offset, width)?;
+            self.dispatch(&op, strings, sink)?;
+        }
+
+        Ok(())
+    }
+
+    /// Execute an opcode, or stage it while a batch is open
+    ///
+    /// At the outermost commit the whole batch is checked first: if any op
+    /// would fail, none of them is applied.
+    fn dispatch(
+        &mut self,
+        op: &StagedOp,
+        strings: Option<&dyn Strings>,
+        sink: &mut impl OpSink,
+    ) -> Result<(), u8> {
+        match op.op_type() {
+            Some(OpType::BatchStart) => {
+                if let Err(e) = self.check_limit(Limit::Nesting, self.batch.depth() + 1) {
+                    self.batch.abort();
+                    return Err(e);
+                }
+                self.batch.start(op.target_id)
+            }
+            Some(OpType::BatchCommit) => {
+                if let Some(ops) = self.batch.commit(op.target_id)? {
+                    let clones = ops.iter().filter(|op| op.op_type() == Some(OpType::Clone));
+                    self.check_limit(Limit::Nodes, sink.node_count() + clones.count())?;
+
+                    check_batch(
+                        &ops,
+                        |id| sink.has_template(id),
+                        strings.map(|s| move |idx| s.get(idx).is_some()),
+                    )?;
+
+                    for op in &ops {
+                        sink.execute(op, strings)?;
+                    }
+                }
+                Ok(())
+            }
+            _ if self.batch.is_open() => {
+                self.batch.stage(*op);
+                Ok(())
+            }
+            Some(OpType::Clone) => {
+                self.check_limit(Limit::Nodes, sink.node_count() + 1)?;
+                sink.execute(op, strings)
+            }
+            _ => sink.execute(op, strings),
+        }
+    }
+}
+
+impl Default for StreamState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Records what reaches the DOM side
+    #[derive(Default)]
+    pub(crate) struct MockSink {
+        pub templates: Vec<(u32, String)>,
+        pub nodes: usize,
+        /// Op type, target and (for PatchText) the text
+        pub applied: Vec<(OpType, u32, Option<String>)>,
+    }
+
+    impl OpSink for MockSink {
+        fn register_template(&mut self, id: u32, html: &str) -> Result<(), u8> {
+            self.templates.push((id, html.to_string()));
+            Ok(())
+        }
+
+        fn has_template(&self, id: u32) -> bool {
+            self.templates.iter().any(|(t, _)| *t == id)
+        }
+
+        fn node_count(&self) -> usize {
+            self.nodes
+        }
+
+        fn execute(&mut self, op: &StagedOp, strings: Option<&dyn Strings>) -> Result<(), u8> {
+            let op_type = op.op_type().ok_or(ErrorCode::InvalidOpcode as u8)?;
+            let text = match op_type {
+                OpType::Clone => {
+                    self.nodes += 1;
+                    None
+                }
+                OpType::Remove => {
+                    self.nodes = self.nodes.saturating_sub(1);
+                    None
+                }
+                OpType::PatchText => {
+                    let text = strings.and_then(|s| s.get(op.arg0()));
+                    let text = text.ok_or(ErrorCode::StringIndexOutOfBounds as u8)?;
+                    Some(text.to_string())
+                }
+                _ => None,
+            };
+            self.applied.push((op_type, op.target_id, text));
+            Ok(())
+        }
+    }
+
+    /// An Opcodes chunk with `strings` of its own and `clones` Clone ops
+    fn chunk(strings: &[&str], templates: u16, clones: u32) -> Vec<u8> {
+        let mut writer = HtipWriter::new(IdWidth::Compact);
+        for s in strings {
+            writer.add_string(s).unwrap();
+        }
+        for id in 0..templates {
+            writer.add_template(id as u32, 0, 0).unwrap();
+        }
+        for node in 0..clones {
+            writer.clone_template(node + 1, 0, 0).unwrap();
+        }
+        writer.finish()
+    }
+
+    #[test]
+    fn test_chunk_limits_cover_the_whole_stream() {
+        let limits = DecodeLimits {
+            max_strings: 3,
+            max_templates: 2,
+            max_ops: 4,
+            ..DecodeLimits::DEFAULT
+        };
+        let run = |chunks: &[Vec<u8>]| {
+            let mut state = StreamState::new();
+            state.set_limits(limits);
+            let mut sink = MockSink::default();
+            chunks.iter().try_for_each(|c| state.process_chunk(c, &mut sink))
+        };
+
+        // Every chunk fits on its own, the stream as a whole does not
+        let strings = chunk(&["<p></p>", "a"], 0, 0);
+        assert_eq!(run(&[strings.clone(), strings.clone()]), Err(ErrorCode::TooManyStrings as u8));
+        let templates = chunk(&["<p></p>"], 1, 0);
+        assert_eq!(
+            run(&[templates.clone(), templates.clone(), templates.clone()]),
+            Err(ErrorCode::TooManyTemplates as u8)
+        );
+        let ops = chunk(&["<p></p>"], 1, 3);
+        assert_eq!(run(&[ops.clone(), ops.clone()]), Err(ErrorCode::TooManyOps as u8));
+        assert_eq!(run(&[ops]), Ok(()));
+
+        // Totals start over with the next stream
+        let mut state = StreamState::new();
+        state.set_limits(limits);
+        let mut sink = MockSink::default();
+        state.process_chunk(&strings, &mut sink).unwrap();
+        state.finish_chunks().unwrap();
+        state.process_chunk(&strings, &mut sink).unwrap();
+    }
+
+    #[test]
+    fn test_finish_chunks_drops_open_batch() {
+        let mut writer = HtipWriter::new(IdWidth::Compact);
+        let html = writer.add_string("<p></p>").unwrap();
+        writer.add_template(0, html, 0).unwrap();
+        writer.batch_start(1).unwrap();
+        writer.clone_template(1, 0, 0).unwrap();
+        let open = writer.finish();
+
+        let mut state = StreamState::new();
+        let mut sink = MockSink::default();
+        state.process_chunk(&open, &mut sink).unwrap();
+        assert!(state.is_batch_open());
+        assert_eq!(state.finish_chunks(), Err(ErrorCode::InvalidBatch as u8));
+        assert!(!state.is_batch_open());
+        assert!(sink.applied.is_empty());
+    }
+
+    #[test]
+    fn test_node_limit_follows_id_width() {
+        let stream = |width: IdWidth, clones: u32| {
+            let mut writer = HtipWriter::new(width);
+            let html = writer.add_string("<li></li>").unwrap();
+            writer.add_template(0, html, 0).unwrap();
+            for node in 0..clones {
+                writer.clone_template(node & 0xFFFF, 0, 0).unwrap();
+            }
+            writer.finish()
+        };
+        let render = |data: &[u8]| {
+            let header = unsafe { ptr::read_unaligned(data.as_ptr() as *const HtipHeader) };
+            let mut sink = MockSink::default();
+            StreamState::new().process_stream(data, &header, &mut sink).map(|()| sink.nodes)
+        };
+
+        // Past 65,535 nodes under the default limits, as long as IDs are wide
+        assert_eq!(render(&stream(IdWidth::Wide, 70_000)), Ok(70_000));
+        let compact = stream(IdWidth::Compact, 70_000);
+        assert_eq!(render(&compact), Err(ErrorCode::TooManyNodes as u8));
+
+        // Limits that were set apply to both widths
+        let mut state = StreamState::new();
+        state.set_limits(DecodeLimits::DEFAULT);
+        let wide = stream(IdWidth::Wide, 70_000);
+        let header = unsafe { ptr::read_unaligned(wide.as_ptr() as *const HtipHeader) };
+        let result = state.process_stream(&wide, &header, &mut MockSink::default());
+        assert_eq!(result, Err(ErrorCode::TooManyNodes as u8));
+
+        // Layout IDs are u32: the layout gets the wide-ID template limit
+        let state = StreamState::new();
+        assert!(state.check_layout(MAX_TEMPLATES as usize + 1).is_ok());
+    }
+}
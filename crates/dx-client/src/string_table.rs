@@ -1,9 +1,17 @@
 //! String Table Reader: Zero-copy string access
 //!
 //! Reads strings directly from the binary payload without allocation.
+//!
+//! Chunked streams outlive each chunk's bytes, so their strings are copied
+//! into a `StringPool` that grows with every Opcodes chunk.
 
 use core::ptr;
-use dx_packet::StringEntry;
+use dx_packet::{ErrorCode, StringEntry};
+
+/// String lookup by index, for the executors
+pub trait Strings {
+    fn get(&self, idx: u16) -> Option<&str>;
+}
 
 /// Zero-copy string table reader
 pub struct StringTableReader<'a> {
@@ -38,30 +46,49 @@ impl<'a> StringTableReader<'a> {
 
     /// Get string by index (zero-copy)
     pub fn get(&self, idx: u16) -> Option<&'a str> {
+        let entry = self.entry(idx)?;
+
+        // Get string slice
+        let start = self.data_offset + entry.offset as usize;
+        let end = start + entry.len as usize;
+
+        if end > self.data.len() {
+            return None;
+        }
+
+        // Convert to str (UTF-8 assumed valid from server)
+        core::str::from_utf8(&self.data[start..end]).ok()
+    }
+
+    /// Read a string entry (zero-copy)
+    fn entry(&self, idx: u16) -> Option<StringEntry> {
         if idx >= self.count {
             return None;
         }
 
-        // Read entry (zero-copy)
         let entry_offset = self.entries_offset + (idx as usize * StringEntry::SIZE);
         if entry_offset + StringEntry::SIZE > self.data.len() {
             return None;
         }
 
-        let entry = unsafe {
+        Some(unsafe {
             ptr::read_unaligned(self.data.as_ptr().add(entry_offset) as *const StringEntry)
-        };
-
-        // Get string slice
-        let start = self.data_offset + entry.offset as usize;
-        let end = start + entry.len as usize;
+        })
+    }
 
-        if end > self.data.len() {
-            return None;
-        }
+    /// Total length of all strings, as declared by their entries
+    pub fn total_len(&self) -> usize {
+        (0..self.count).filter_map(|idx| self.entry(idx)).map(|e| e.len as usize).sum()
+    }
 
-        // Convert to str (UTF-8 assumed valid from server)
-        core::str::from_utf8(&self.data[start..end]).ok()
+    /// Offset just past the string data (where the next section starts)
+    pub fn end(&self) -> usize {
+        let data_len = (0..self.count)
+            .filter_map(|idx| self.entry(idx))
+            .map(|e| e.offset as usize + e.len as usize)
+            .max()
+            .unwrap_or(0);
+        self.data_offset + data_len
     }
 
     /// Get string count
@@ -69,3 +96,66 @@ impl<'a> StringTableReader<'a> {
         self.count
     }
 }
+
+impl Strings for StringTableReader<'_> {
+    fn get(&self, idx: u16) -> Option<&str> {
+        StringTableReader::get(self, idx)
+    }
+}
+
+/// Owned string table of a chunked stream
+///
+/// Each Opcodes chunk appends its table, so index N always names the same
+/// string for the rest of the stream.
+#[derive(Default)]
+pub struct StringPool {
+    /// All string bytes back to back
+    data: String,
+    /// (start, end) of each string in `data`
+    spans: Vec<(usize, usize)>,
+}
+
+impl StringPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append every string of a chunk's table
+    pub fn extend(&mut self, table: &StringTableReader) -> Result<(), u8> {
+        if self.spans.len() + table.count() as usize > u16::MAX as usize + 1 {
+            return Err(ErrorCode::StringIndexOutOfBounds as u8);
+        }
+
+        for idx in 0..table.count() {
+            let s = table.get(idx).ok_or(ErrorCode::StringIndexOutOfBounds as u8)?;
+            let start = self.data.len();
+            self.data.push_str(s);
+            self.spans.push((start, self.data.len()));
+        }
+
+        Ok(())
+    }
+
+    /// Number of strings received so far
+    pub fn count(&self) -> usize {
+        self.spans.len()
+    }
+
+    /// Total length of all strings received so far
+    pub fn total_len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Drop all strings (end of stream)
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.spans.clear();
+    }
+}
+
+impl Strings for StringPool {
+    fn get(&self, idx: u16) -> Option<&str> {
+        let &(start, end) = self.spans.get(idx as usize)?;
+        Some(&self.data[start..end])
+    }
+}
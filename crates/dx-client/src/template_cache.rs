@@ -2,6 +2,8 @@
 //!
 //! Templates are parsed ONCE and cloned via native `cloneNode()`.
 
+use std::collections::BTreeMap;
+
 use wasm_bindgen::JsCast;
 use web_sys::{Document, HtmlTemplateElement, Node};
 
@@ -9,9 +11,13 @@ use web_sys::{Document, HtmlTemplateElement, Node};
 const MAX_TEMPLATES: usize = 4096;
 
 /// Template cache using fixed-size array (no Vec allocation)
+///
+/// IDs of `MAX_TEMPLATES` and up (wide-ID streams) go to an overflow map.
 pub struct TemplateCache {
     /// Pre-parsed templates (None = not registered)
     templates: [Option<HtmlTemplateElement>; MAX_TEMPLATES],
+    /// Templates with IDs past the array
+    overflow: BTreeMap<u32, HtmlTemplateElement>,
     /// Count of registered templates
     count: u16,
     /// Cached document reference
@@ -26,6 +32,7 @@ impl TemplateCache {
 
         Ok(Self {
             templates: std::array::from_fn(|_| None),
+            overflow: BTreeMap::new(),
             count: 0,
             document,
         })
@@ -34,13 +41,9 @@ impl TemplateCache {
     /// Register a template from HTML string
     ///
     /// # Arguments
-    /// * `id` - Template ID (must be < MAX_TEMPLATES)
+    /// * `id` - Template ID
     /// * `html` - HTML string to parse
-    pub fn register(&mut self, id: u16, html: &str) -> Result<(), u8> {
-        if id as usize >= MAX_TEMPLATES {
-            return Err(4u8); // TemplateNotFound (out of range)
-        }
-
+    pub fn register(&mut self, id: u32, html: &str) -> Result<(), u8> {
         // Create template element
         let template = self
             .document
@@ -52,7 +55,12 @@ impl TemplateCache {
         // Parse HTML into template content
         template.set_inner_html(html);
 
-        self.templates[id as usize] = Some(template);
+        match self.templates.get_mut(id as usize) {
+            Some(slot) => *slot = Some(template),
+            None => {
+                self.overflow.insert(id, template);
+            }
+        }
         self.count += 1;
 
         Ok(())
@@ -61,14 +69,24 @@ impl TemplateCache {
     /// Clone a template's content
     ///
     /// Uses native `cloneNode(true)` for C++ speed
-    pub fn clone_template(&self, id: u16) -> Result<Node, u8> {
-        self.templates
-            .get(id as usize)
-            .and_then(|t| t.as_ref())
+    pub fn clone_template(&self, id: u32) -> Result<Node, u8> {
+        self.get(id)
             .and_then(|t| t.content().clone_node_with_deep(true).ok())
             .ok_or(4u8) // TemplateNotFound
     }
 
+    /// Check if a template is registered
+    pub fn contains(&self, id: u32) -> bool {
+        self.get(id).is_some()
+    }
+
+    fn get(&self, id: u32) -> Option<&HtmlTemplateElement> {
+        match self.templates.get(id as usize) {
+            Some(template) => template.as_ref(),
+            None => self.overflow.get(&id),
+        }
+    }
+
     /// Get template count
     pub fn count(&self) -> u16 {
         self.count
@@ -10,10 +10,11 @@
 //!
 //! The dx-client WASM (22KB) is the ONLY WASM. Apps are pure data.
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use dx_packet::{IdWidth, StateValue, StateWriter, TemplateEntry};
 use std::collections::HashMap;
 
-use crate::splitter::{Binding, StateSchema, Template};
+use crate::splitter::{Binding, StateField, StateSchema, Template};
 
 /// HTIP Header (matches dx_packet::HtipHeader)
 const MAGIC: u16 = 0x4458; // "DX"
@@ -63,18 +64,36 @@ pub fn generate_htip(
 
     let mut interner = StringInterner::new();
 
+    // IDs are u16 unless one of them does not fit
+    let node_id = |id: u32| id.checked_add(1).ok_or_else(|| anyhow!("Node ID {id} overflows u32"));
+    let max_id = templates
+        .iter()
+        .map(|t| node_id(t.id))
+        .chain(bindings.iter().map(|b| node_id(b.slot_id)))
+        .try_fold(0, |max, id| id.map(|id| max.max(id)))?;
+    let width = IdWidth::for_max_id(max_id);
+    let push_id = |bytes: &mut Vec<u8>, id: u32| match width {
+        IdWidth::Compact => bytes.extend(&(id as u16).to_le_bytes()),
+        IdWidth::Wide => bytes.extend(&id.to_le_bytes()),
+    };
+    let push_op = |bytes: &mut Vec<u8>, op_type: u8, target_id: u32| {
+        bytes.push(op_type);
+        match width {
+            IdWidth::Compact => bytes.push(0), // reserved
+            IdWidth::Wide => bytes.extend(&[0u8; 3]),
+        }
+        push_id(bytes, target_id);
+    };
+
     // Build opcodes
     let mut opcodes: Vec<u8> = Vec::new();
     let mut opcode_count: u32 = 0;
 
     // For each template, emit a Clone opcode (initial render)
     for template in templates {
-        let new_node_id = template.id as u16 + 1;
-        opcodes.push(1); // op_type = Clone
-        opcodes.push(0); // reserved
-        opcodes.extend(&new_node_id.to_le_bytes());
-        opcodes.extend(&(template.id as u16).to_le_bytes());
-        opcodes.extend(&0u16.to_le_bytes()); // parent_id = root
+        push_op(&mut opcodes, 1, node_id(template.id)?); // op_type = Clone
+        push_id(&mut opcodes, template.id);
+        push_id(&mut opcodes, 0); // parent_id = root
         opcode_count += 1;
     }
 
@@ -83,23 +102,26 @@ pub fn generate_htip(
         let text = format!("{{{}}}", binding.expression);
         let string_idx = interner.intern(&text);
 
-        let target_id = binding.slot_id as u16 + 1;
-        opcodes.push(2); // op_type = PatchText
-        opcodes.push(0); // reserved
-        opcodes.extend(&target_id.to_le_bytes());
+        push_op(&mut opcodes, 2, node_id(binding.slot_id)?); // op_type = PatchText
         opcodes.extend(&string_idx.to_le_bytes());
         opcodes.extend(&0u16.to_le_bytes());
         opcode_count += 1;
     }
 
     // Build template dictionary (intern HTML into string table)
+    // Sorted by id: the output must not depend on how templates were collected
+    let mut dictionary: Vec<&Template> = templates.iter().collect();
+    dictionary.sort_by_key(|t| t.id);
+
     let mut template_entries: Vec<u8> = Vec::new();
-    for template in templates {
+    for template in dictionary {
         let html_idx = interner.intern(&template.html);
-        template_entries.extend(&(template.id as u16).to_le_bytes());
+        push_id(&mut template_entries, template.id);
         template_entries.extend(&html_idx.to_le_bytes());
         template_entries.push(template.slots.len() as u8);
-        template_entries.extend(&[0u8; 3]);
+        // Reserved bytes (TemplateEntry and TemplateEntryWide are both 8 bytes)
+        let entry_end = template_entries.len().next_multiple_of(TemplateEntry::SIZE);
+        template_entries.resize(entry_end, 0);
     }
 
     // Build string table binary
@@ -124,7 +146,7 @@ pub fn generate_htip(
     let mut stream = Vec::new();
     stream.extend(&MAGIC.to_le_bytes());
     stream.push(VERSION);
-    stream.push(0x03); // flags: has_strings | has_templates
+    stream.push(0x03 | width.flags()); // flags: has_strings | has_templates (| wide IDs)
     stream.extend(&(templates.len() as u16).to_le_bytes());
     stream.extend(&(string_table.len() as u16).to_le_bytes());
     stream.extend(&opcode_count.to_le_bytes());
@@ -146,6 +168,46 @@ pub fn generate_htip(
     Ok((stream, string_table))
 }
 
+/// Generate the initial-state snapshot (State chunk payload)
+///
+/// Component ids follow the order of `schemas`, matching the generated
+/// state structs. Field types map like `codegen_macro::type_to_rust`.
+pub fn generate_state(schemas: &[StateSchema], verbose: bool) -> Result<Vec<u8>> {
+    let mut writer = StateWriter::new();
+
+    for (component_id, schema) in schemas.iter().enumerate() {
+        let component_id = u16::try_from(component_id)
+            .map_err(|_| anyhow!("Too many stateful components ({})", schemas.len()))?;
+
+        let fields: Vec<(u8, StateValue<'_>)> =
+            schema.fields.iter().map(|f| (f.dirty_bit, initial_state_value(f))).collect();
+
+        writer.add_component(component_id, &fields).map_err(|_| {
+            anyhow!("State for component {} does not fit in a snapshot", schema.component)
+        })?;
+    }
+
+    let snapshot = writer.finish();
+
+    if verbose {
+        println!("    State snapshot: {} bytes ({} components)", snapshot.len(), schemas.len());
+    }
+
+    Ok(snapshot)
+}
+
+/// Parse a field's initial value according to its TypeScript type
+fn initial_state_value(field: &StateField) -> StateValue<'_> {
+    let initial = field.initial_value.trim();
+
+    match field.type_name.as_str() {
+        "boolean" => StateValue::Bool(initial == "true"),
+        "string" => StateValue::Str(initial.trim_matches(|c| matches!(c, '"' | '\'' | '`'))),
+        // Numbers, arrays and handles are all i32 in the State Region
+        _ => StateValue::I32(initial.parse().unwrap_or(0)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -180,4 +242,94 @@ mod tests {
         assert!(!strings.is_empty());
         assert!(stream.len() < 500, "HTIP stream should be tiny, got {} bytes", stream.len());
     }
+
+    #[test]
+    fn test_htip_generation_is_deterministic() {
+        let template = |id: u32, html: &str| Template {
+            id,
+            html: html.to_string(),
+            slots: vec![],
+            hash: format!("h{id}"),
+        };
+        let templates = vec![
+            template(0, "<div/>"),
+            template(1, "<p/>"),
+            template(2, "<b/>"),
+        ];
+        let mut shuffled = templates.clone();
+        shuffled.swap(0, 2);
+
+        let (first, _) = generate_htip(&templates, &[], &[], false).unwrap();
+        let (second, _) = generate_htip(&templates, &[], &[], false).unwrap();
+        assert_eq!(first, second);
+
+        // Dictionary section is the same whatever the input order
+        let (_, strings) = generate_htip(&shuffled, &[], &[], false).unwrap();
+        assert_eq!(strings, ["<div/>", "<p/>", "<b/>"]);
+    }
+
+    #[test]
+    fn test_htip_generation_widens_ids_when_needed() {
+        let template = |id: u32| Template {
+            id,
+            html: "<i/>".to_string(),
+            slots: vec![],
+            hash: format!("h{id}"),
+        };
+
+        let (compact, _) = generate_htip(&[template(7)], &[], &[], false).unwrap();
+        assert_eq!(compact[3], 0x03);
+        assert_eq!(&compact[compact.len() - 8..], &[1, 0, 8, 0, 7, 0, 0, 0]);
+
+        let (wide, _) = generate_htip(&[template(70_000)], &[], &[], false).unwrap();
+        assert_eq!(wide[3], 0x03 | dx_packet::HtipHeader::FLAG_WIDE_IDS);
+        let clone = &wide[wide.len() - 16..];
+        assert_eq!(clone[0], 1);
+        assert_eq!(&clone[4..8], &70_001u32.to_le_bytes());
+        assert_eq!(&clone[8..12], &70_000u32.to_le_bytes());
+        assert_eq!(&clone[12..16], &0u32.to_le_bytes());
+
+        // Template entry: u32 id, html index, slot count, reserved
+        let entry = &wide[wide.len() - 24..wide.len() - 16];
+        assert_eq!(&entry[0..4], &70_000u32.to_le_bytes());
+        assert_eq!(entry.len(), dx_packet::TemplateEntry::SIZE);
+    }
+
+    #[test]
+    fn test_state_generation() {
+        let field = |name: &str, type_name: &str, initial: &str, dirty_bit| StateField {
+            name: name.to_string(),
+            type_name: type_name.to_string(),
+            initial_value: initial.to_string(),
+            dirty_bit,
+        };
+        let schemas = vec![
+            StateSchema {
+                component: "Counter".to_string(),
+                fields: vec![
+                    field("count", "number", "5", 0),
+                    field("open", "boolean", "true", 1),
+                    field("label", "string", "'Clicks'", 2),
+                    field("items", "any", "[]", 3),
+                ],
+            },
+            StateSchema {
+                component: "Empty".to_string(),
+                fields: vec![],
+            },
+        ];
+
+        let snapshot = generate_state(&schemas, false).unwrap();
+        let components: Vec<_> =
+            dx_packet::StateReader::new(&snapshot).unwrap().map(Result::unwrap).collect();
+
+        assert_eq!(components.len(), 2);
+        let counter = &components[0];
+        assert_eq!(counter.component_id, 0);
+        assert_eq!(counter.value(0), Some(StateValue::I32(5)));
+        assert_eq!(counter.value(1), Some(StateValue::Bool(true)));
+        assert_eq!(counter.value(2), Some(StateValue::Str("Clicks")));
+        assert_eq!(counter.value(3), Some(StateValue::I32(0)));
+        assert_eq!(components[1].field_count(), 0);
+    }
 }
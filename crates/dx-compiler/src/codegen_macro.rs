@@ -17,7 +17,8 @@
 //! - State → SharedArrayBuffer-backed struct
 //! - Updates → HTIP batch operations
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use dx_packet::LayoutWriter;
 use std::path::Path;
 
 use crate::splitter::{Binding, StateSchema, Template};
@@ -26,45 +27,18 @@ use crate::splitter::{Binding, StateSchema, Template};
 // Layout Binary Format
 // ============================================================================
 
-/// Binary layout header
-const LAYOUT_MAGIC: u32 = 0x4C415944; // "LAYD"
-const LAYOUT_VERSION: u8 = 1;
-
-/// Serialize templates to layout.bin
+/// Serialize templates to layout.bin (see `dx_packet::LayoutReader`)
 pub fn serialize_layout(templates: &[Template], output_dir: &Path) -> Result<()> {
     let layout_path = output_dir.join("layout.bin");
 
-    let mut data = Vec::new();
-
-    // Header
-    data.extend(&LAYOUT_MAGIC.to_le_bytes());
-    data.push(LAYOUT_VERSION);
-    data.push(0); // reserved
-    data.extend(&(templates.len() as u16).to_le_bytes());
-
-    // Template entries: [id: u16, html_offset: u32, html_len: u16, slot_count: u8, reserved: u8]
-    let mut html_data: Vec<u8> = Vec::new();
-    let mut entries: Vec<u8> = Vec::new();
-
+    let mut writer = LayoutWriter::new();
     for template in templates {
-        let offset = html_data.len() as u32;
-        let html_bytes = template.html.as_bytes();
-        let len = html_bytes.len() as u16;
-
-        entries.extend(&(template.id as u16).to_le_bytes());
-        entries.extend(&offset.to_le_bytes());
-        entries.extend(&len.to_le_bytes());
-        entries.push(template.slots.len() as u8);
-        entries.push(0); // reserved
-
-        html_data.extend(html_bytes);
+        writer
+            .add_template(template.id, &template.html, template.slots.len() as u8)
+            .map_err(|e| anyhow!("Template {} does not fit layout.bin: {e:?}", template.id))?;
     }
 
-    // Write entries then data
-    data.extend(&entries);
-    data.extend(&html_data);
-
-    std::fs::write(&layout_path, &data)?;
+    std::fs::write(&layout_path, writer.finish())?;
 
     Ok(())
 }
@@ -350,7 +324,7 @@ fn default_value(ts_type: &str, initial: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::splitter::{SlotDef, SlotType};
+    use crate::splitter::{SlotDef, SlotType, StateField};
 
     #[test]
     fn test_generate_macro_empty() {
@@ -402,6 +376,26 @@ mod tests {
         assert!(layout_path.exists());
 
         let data = std::fs::read(&layout_path).unwrap();
-        assert_eq!(&data[0..4], &LAYOUT_MAGIC.to_le_bytes());
+        let layout: Vec<_> = dx_packet::LayoutReader::new(&data).unwrap().collect();
+        assert_eq!(layout.len(), 1);
+        assert_eq!(layout[0].unwrap().html, "<div>Hello</div>");
+    }
+
+    #[test]
+    fn test_serialize_layout_wide_ids() {
+        let templates = vec![Template {
+            id: 70_000,
+            html: "<p>Wide</p>".to_string(),
+            slots: vec![],
+            hash: "wide".to_string(),
+        }];
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        serialize_layout(&templates, temp_dir.path()).unwrap();
+
+        let data = std::fs::read(temp_dir.path().join("layout.bin")).unwrap();
+        let template = dx_packet::LayoutReader::new(&data).unwrap().next().unwrap().unwrap();
+        assert_eq!(template.id, 70_000);
+        assert_eq!(template.html, "<p>Wide</p>");
     }
 }
@@ -84,6 +84,10 @@ pub fn compile_tsx(entry: &Path, output: &Path, verbose: bool) -> Result<Compile
     let htip_path = output.join("app.htip");
     std::fs::write(&htip_path, &htip_stream)?;
 
+    // Step 6b: Write initial state snapshot (streamed as the State chunk)
+    let state_snapshot = codegen::generate_state(&state_schema, verbose)?;
+    std::fs::write(output.join("state.bin"), &state_snapshot)?;
+
     // Step 7: Generate templates.json
     let templates_json = serde_json::to_string_pretty(&templates)?;
     let templates_path = output.join("templates.json");
@@ -178,6 +182,34 @@ export default function App() {
         assert!(compile_result.templates_path.exists());
     }
 
+    #[test]
+    fn test_compile_is_reproducible() {
+        let temp = TempDir::new().unwrap();
+        let entry = temp.path().join("App.tsx");
+
+        fs::write(&entry, r#"
+import { useState } from 'dx';
+export default function App() {
+    const [count, setCount] = useState(0);
+    const [label, setLabel] = useState("clicks");
+    return <div class="counter"><span>{label}</span><b>{count}</b></div>;
+}
+        "#).unwrap();
+
+        let first = temp.path().join("first");
+        let second = temp.path().join("second");
+        compile_tsx(&entry, &first, false).unwrap();
+        compile_tsx(&entry, &second, false).unwrap();
+
+        for artifact in ["app.htip", "app.dxb", "state.bin", "templates.json"] {
+            assert_eq!(
+                fs::read(first.join(artifact)).unwrap(),
+                fs::read(second.join(artifact)).unwrap(),
+                "{artifact} differs between builds"
+            );
+        }
+    }
+
     #[test]
     fn test_analyze_tsx() {
         let temp = TempDir::new().unwrap();
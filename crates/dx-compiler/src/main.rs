@@ -176,6 +176,10 @@ async fn build_project(
     let (htip_stream, _string_table) =
         codegen::generate_htip(&templates, &bindings, &state_schema, verbose)?;
 
+    // Initial state snapshot (streamed as the State chunk)
+    let state_snapshot = codegen::generate_state(&state_schema, verbose)?;
+    std::fs::write(output.join("state.bin"), &state_snapshot)?;
+
     // For Micro mode: generate raw Rust FFI code
     if runtime_variant == analyzer::RuntimeVariant::Micro {
         pb.set_message("Generating Micro Rust FFI code...");
@@ -20,6 +20,11 @@
 //! │  Opcode Stream (variable)              │
 //! └────────────────────────────────────────┘
 //! ```
+//!
+//! Node and template IDs are `u16` unless the header has
+//! `HtipHeader::FLAG_WIDE_IDS`, which widens them to `u32` (see `IdWidth`).
+//! Writers only set it when an ID does not fit, so small apps keep the
+//! compact form.
 
 #![no_std]
 extern crate alloc;
@@ -48,7 +53,7 @@ pub struct HtipHeader {
     pub magic: u16,
     /// Protocol version (currently 2)
     pub version: u8,
-    /// Flags: bit 0 = has_strings, bit 1 = has_templates
+    /// Flags: bit 0 = has_strings, bit 1 = has_templates, bit 2 = wide IDs
     pub flags: u8,
     /// Number of templates in dictionary
     pub template_count: u16,
@@ -65,11 +70,105 @@ impl HtipHeader {
     pub const VERSION: u8 = 2;
     pub const SIZE: usize = 16;
 
+    /// The stream has a string table
+    pub const FLAG_HAS_STRINGS: u8 = 0x01;
+    /// The stream has a template dictionary
+    pub const FLAG_HAS_TEMPLATES: u8 = 0x02;
+    /// Node and template IDs are `u32` (`OpcodeHeaderWide`, `ClonePayloadWide`, ...)
+    pub const FLAG_WIDE_IDS: u8 = 0x04;
+
     /// Validate header magic and version
     #[inline]
     pub fn is_valid(&self) -> bool {
         self.magic == Self::MAGIC && self.version == Self::VERSION
     }
+
+    /// Width of the stream's node and template IDs
+    #[inline]
+    pub const fn id_width(&self) -> IdWidth {
+        IdWidth::from_flags(self.flags)
+    }
+}
+
+/// Width of node and template IDs in a stream
+///
+/// Compact streams use `OpcodeHeader`, `ClonePayload`, `AppendChildPayload`
+/// and `TemplateEntry`; wide ones (`HtipHeader::FLAG_WIDE_IDS`) the `*Wide`
+/// variants. Only `OpcodeHeader` and `ClonePayload` change size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdWidth {
+    /// `u16` IDs, up to 65,535 nodes
+    Compact,
+    /// `u32` IDs
+    Wide,
+}
+
+impl IdWidth {
+    /// Width named by header flags
+    #[inline]
+    pub const fn from_flags(flags: u8) -> Self {
+        if flags & HtipHeader::FLAG_WIDE_IDS != 0 {
+            Self::Wide
+        } else {
+            Self::Compact
+        }
+    }
+
+    /// Narrowest width holding `max_id`
+    #[inline]
+    pub const fn for_max_id(max_id: u32) -> Self {
+        if max_id > u16::MAX as u32 {
+            Self::Wide
+        } else {
+            Self::Compact
+        }
+    }
+
+    /// Header flags to set for this width
+    #[inline]
+    pub const fn flags(self) -> u8 {
+        match self {
+            Self::Compact => 0,
+            Self::Wide => HtipHeader::FLAG_WIDE_IDS,
+        }
+    }
+
+    /// Size of an opcode header
+    #[inline]
+    pub const fn opcode_header_size(self) -> usize {
+        match self {
+            Self::Compact => OpcodeHeader::SIZE,
+            Self::Wide => OpcodeHeaderWide::SIZE,
+        }
+    }
+
+    /// Payload bytes that follow the opcode header
+    #[inline]
+    pub const fn payload_size(self, op_type: OpType) -> usize {
+        match (self, op_type) {
+            (Self::Wide, OpType::Clone) => 8,
+            _ => op_type.payload_size(),
+        }
+    }
+
+    /// Bytes of one ID
+    #[inline]
+    pub const fn id_size(self) -> usize {
+        match self {
+            Self::Compact => 2,
+            Self::Wide => 4,
+        }
+    }
+
+    /// Read an ID at `offset` (little-endian)
+    #[inline]
+    pub fn read_id(self, bytes: &[u8], offset: usize) -> Option<u32> {
+        let bytes = bytes.get(offset..offset + self.id_size())?;
+        Some(match self {
+            Self::Compact => u16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+            Self::Wide => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        })
+    }
 }
 
 // ============================================================================
@@ -96,6 +195,12 @@ pub enum OpType {
     BatchStart = 7,
     /// Batch commit marker
     BatchCommit = 8,
+    /// Attach event listener
+    AttachEvent = 9,
+    /// Set DOM property (e.g. input.value, checked)
+    SetProperty = 10,
+    /// Move node under a new parent
+    AppendChild = 11,
 }
 
 impl OpType {
@@ -110,9 +215,22 @@ impl OpType {
             6 => Some(Self::SetStyle),
             7 => Some(Self::BatchStart),
             8 => Some(Self::BatchCommit),
+            9 => Some(Self::AttachEvent),
+            10 => Some(Self::SetProperty),
+            11 => Some(Self::AppendChild),
             _ => None,
         }
     }
+
+    /// Payload bytes that follow the opcode header
+    #[inline]
+    pub const fn payload_size(self) -> usize {
+        match self {
+            Self::Remove | Self::BatchStart | Self::BatchCommit => 0,
+            Self::SetProperty => 6,
+            _ => 4,
+        }
+    }
 }
 
 /// Fixed-size opcode header (4 bytes)
@@ -141,6 +259,30 @@ impl OpcodeHeader {
     pub const SIZE: usize = 4;
 }
 
+/// Opcode header of wide-ID streams (8 bytes)
+///
+/// Memory Layout:
+/// ```text
+/// Offset  Size  Field
+/// 0       1     op_type
+/// 1       3     reserved (alignment)
+/// 4       4     target_id
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct OpcodeHeaderWide {
+    /// Operation type
+    pub op_type: u8,
+    /// Reserved for alignment
+    pub reserved: [u8; 3],
+    /// Target node ID (0 = root)
+    pub target_id: u32,
+}
+
+impl OpcodeHeaderWide {
+    pub const SIZE: usize = 8;
+}
+
 // ============================================================================
 // OPCODE PAYLOADS
 // ============================================================================
@@ -155,6 +297,16 @@ pub struct ClonePayload {
     pub parent_id: u16,
 }
 
+/// Clone operation of wide-ID streams
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ClonePayloadWide {
+    /// Template ID to clone
+    pub template_id: u32,
+    /// Parent node ID
+    pub parent_id: u32,
+}
+
 /// Text patch: update node text content
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -197,6 +349,75 @@ pub struct SetStylePayload {
     pub prop_value_idx: u16,
 }
 
+/// Event listener: the runtime calls back with `handler_id`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct AttachEventPayload {
+    /// String table index for event type (e.g. "click")
+    pub event_type_idx: u16,
+    /// Handler ID passed to the event callback
+    pub handler_id: u16,
+}
+
+/// Kind of value set by `SetProperty`
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropKind {
+    /// `value` is a string table index
+    String = 0,
+    /// `value` is a string table index holding the number in decimal
+    Number = 1,
+    /// `value` is 0 or 1
+    Bool = 2,
+    /// `value` is unused (0)
+    Null = 3,
+}
+
+impl PropKind {
+    #[inline]
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Self::String),
+            1 => Some(Self::Number),
+            2 => Some(Self::Bool),
+            3 => Some(Self::Null),
+            _ => None,
+        }
+    }
+}
+
+/// Property set: assign a DOM property (6 bytes)
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SetPropertyPayload {
+    /// String table index for property name
+    pub prop_name_idx: u16,
+    /// `PropKind` of the value
+    pub kind: u8,
+    /// Reserved
+    pub reserved: u8,
+    /// Value, interpreted according to `kind`
+    pub value: u16,
+}
+
+/// Append child: move `child_id` under the target node (0 = root)
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct AppendChildPayload {
+    /// Node to move
+    pub child_id: u16,
+    /// Reserved
+    pub reserved: u16,
+}
+
+/// Append child of wide-ID streams (same 4 bytes, no reserved half)
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct AppendChildPayloadWide {
+    /// Node to move
+    pub child_id: u32,
+}
+
 // ============================================================================
 // STRING TABLE
 // ============================================================================
@@ -239,6 +460,20 @@ impl TemplateEntry {
     pub const SIZE: usize = 8;
 }
 
+/// Template entry of wide-ID streams (same 8 bytes)
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TemplateEntryWide {
+    /// Template ID
+    pub id: u32,
+    /// String table index for HTML content
+    pub html_string_idx: u16,
+    /// Number of slots in template
+    pub slot_count: u8,
+    /// Reserved
+    pub reserved: u8,
+}
+
 // ============================================================================
 // ERROR CODES (No strings, just u8)
 // ============================================================================
@@ -263,6 +498,33 @@ pub enum ErrorCode {
     NodeNotFound = 6,
     /// Buffer too small
     BufferTooSmall = 7,
+    /// State snapshot is malformed
+    InvalidState = 8,
+    /// Batch reopened, committed out of order or never committed
+    InvalidBatch = 9,
+    // 10-12 are returned by the streaming API (not initialized, incomplete, no layout)
+    /// String table has more entries than `DecodeLimits::max_strings`
+    TooManyStrings = 13,
+    /// String data exceeds `DecodeLimits::max_string_bytes`
+    StringDataTooLarge = 14,
+    /// Template dictionary exceeds `DecodeLimits::max_templates`
+    TooManyTemplates = 15,
+    /// Opcode count exceeds `DecodeLimits::max_ops`
+    TooManyOps = 16,
+    /// Batches nested deeper than `DecodeLimits::max_nesting`
+    NestingTooDeep = 17,
+    /// Stream creates more nodes than `DecodeLimits::max_nodes`
+    TooManyNodes = 18,
+    /// Chunk body exceeds `DecodeLimits::max_chunk_bytes`
+    ChunkTooLarge = 19,
+    /// Layout dictionary is malformed
+    InvalidLayout = 20,
+    /// Delta patch is malformed
+    InvalidPatch = 21,
+    /// Binary does not match the patch's `base_version_hash`
+    PatchBaseMismatch = 22,
+    /// Patched binary does not match the patch's `target_version_hash`
+    PatchTargetMismatch = 23,
 }
 
 // ============================================================================
@@ -281,6 +543,116 @@ pub const MAX_STRINGS: u16 = 65535;
 /// Maximum nodes in registry
 pub const MAX_NODES: u16 = 65535;
 
+// ============================================================================
+// DECODE LIMITS
+// ============================================================================
+
+/// A resource bounded by `DecodeLimits`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Strings,
+    StringBytes,
+    Templates,
+    Ops,
+    Nesting,
+    Nodes,
+    ChunkBytes,
+}
+
+impl Limit {
+    /// Error code reported by dx-client when the limit is exceeded
+    pub const fn error_code(self) -> ErrorCode {
+        match self {
+            Limit::Strings => ErrorCode::TooManyStrings,
+            Limit::StringBytes => ErrorCode::StringDataTooLarge,
+            Limit::Templates => ErrorCode::TooManyTemplates,
+            Limit::Ops => ErrorCode::TooManyOps,
+            Limit::Nesting => ErrorCode::NestingTooDeep,
+            Limit::Nodes => ErrorCode::TooManyNodes,
+            Limit::ChunkBytes => ErrorCode::ChunkTooLarge,
+        }
+    }
+}
+
+/// Upper bounds applied while decoding an untrusted stream
+///
+/// Counts are checked against the lengths a stream declares, before
+/// anything is allocated for them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Entries in the string table
+    pub max_strings: u32,
+    /// Total bytes of string data
+    pub max_string_bytes: u32,
+    /// Templates in the dictionary
+    pub max_templates: u32,
+    /// Opcodes in the stream
+    pub max_ops: u32,
+    /// Depth of nested batches
+    pub max_nesting: u32,
+    /// Nodes created by the stream
+    pub max_nodes: u32,
+    /// Body length of a single chunk
+    pub max_chunk_bytes: u32,
+}
+
+impl DecodeLimits {
+    /// Limits matching the protocol constants
+    pub const DEFAULT: Self = Self {
+        max_strings: MAX_STRINGS as u32,
+        max_string_bytes: 16 * 1024 * 1024,
+        max_templates: MAX_TEMPLATES as u32,
+        max_ops: 1 << 20,
+        max_nesting: 64,
+        max_nodes: MAX_NODES as u32,
+        max_chunk_bytes: 16 * 1024 * 1024,
+    };
+
+    /// Limits for wide-ID streams: more templates and nodes than `u16` IDs
+    /// can name
+    pub const WIDE: Self = Self {
+        max_templates: 1 << 16,
+        max_nodes: 1 << 20,
+        ..Self::DEFAULT
+    };
+
+    /// Default limits for streams with `width` IDs
+    pub const fn for_width(width: IdWidth) -> Self {
+        match width {
+            IdWidth::Compact => Self::DEFAULT,
+            IdWidth::Wide => Self::WIDE,
+        }
+    }
+
+    /// Upper bound for a resource
+    pub const fn max(&self, limit: Limit) -> usize {
+        (match limit {
+            Limit::Strings => self.max_strings,
+            Limit::StringBytes => self.max_string_bytes,
+            Limit::Templates => self.max_templates,
+            Limit::Ops => self.max_ops,
+            Limit::Nesting => self.max_nesting,
+            Limit::Nodes => self.max_nodes,
+            Limit::ChunkBytes => self.max_chunk_bytes,
+        }) as usize
+    }
+
+    /// Check an amount against its limit
+    pub const fn check(&self, limit: Limit, found: usize) -> Result<(), Limit> {
+        if found > self.max(limit) {
+            Err(limit)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
 // ============================================================================
 // SHARED TYPES (Compiler <-> Server)
 // ============================================================================
@@ -358,6 +730,11 @@ pub enum ChunkType {
     Wasm = 0x04,
     /// Patch: Delta patch (binary diff)
     Patch = 0x05,
+    /// Opcodes: HTIP stream rendered as soon as it arrives
+    ///
+    /// May repeat and may be interleaved with other chunks after Layout.
+    /// Each chunk's string table continues the previous chunks' tables.
+    Opcodes = 0x06,
     /// End of stream marker
     Eof = 0xFF,
 }
@@ -370,14 +747,15 @@ impl ChunkType {
             0x03 => Some(ChunkType::State),
             0x04 => Some(ChunkType::Wasm),
             0x05 => Some(ChunkType::Patch),
+            0x06 => Some(ChunkType::Opcodes),
             0xFF => Some(ChunkType::Eof),
             _ => None,
         }
     }
 }
 
-/// Block size for XOR patching (4KB - cache friendly)
-pub const BLOCK_SIZE: usize = 4096;
+/// Block size for XOR patching (small blocks keep patches small)
+pub const BLOCK_SIZE: usize = 64;
 
 /// Chunk header for binary streaming
 /// Total size: 5 bytes (1 type + 4 length)
@@ -441,6 +819,17 @@ impl PatchHeader {
         }
     }
 
+    /// Hash of a binary version, as stored in `base_version_hash` and
+    /// `target_version_hash`
+    ///
+    /// 64-bit FNV-1a: catches a patch applied to the wrong base or a
+    /// corrupt result, not a forged patch (streams are signed separately).
+    pub fn version_hash(data: &[u8]) -> u64 {
+        data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    }
+
     /// Serialize to 17 bytes: [base:8][target:8][algo:1]
     pub fn to_bytes(&self) -> [u8; 17] {
         let mut bytes = [0u8; 17];
@@ -473,22 +862,1321 @@ impl PatchHeader {
 }
 
 // ============================================================================
-// SECURITY & CAPABILITIES
+// STATE SNAPSHOT (State chunk payload)
 // ============================================================================
 
-/// Capabilities manifest for security
-#[cfg_attr(
-    feature = "std",
-    derive(Serialize, Deserialize, bincode::Encode, bincode::Decode)
-)]
-#[derive(Debug, Clone)]
-#[derive(Default)]
-pub struct CapabilitiesManifest {
-    pub network: bool,
-    pub storage: bool,
-    pub geolocation: bool,
-    pub camera: bool,
-    pub microphone: bool,
-    pub signature: alloc::vec::Vec<u8>,
+/// State snapshot header - first 8 bytes of the State chunk
+///
+/// Memory Layout:
+/// ```text
+/// Offset  Size  Field
+/// 0       2     magic (0x5453 = "ST")
+/// 2       1     version
+/// 3       1     reserved
+/// 4       2     component_count
+/// 6       2     reserved
+/// ```
+///
+/// Followed by `component_count` component records:
+/// ```text
+/// StateComponentHeader (8 bytes)
+/// StateFieldEntry × field_count (4 bytes each)
+/// data (data_len bytes, copied verbatim into the State Region)
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateHeader {
+    /// Magic bytes: 0x5453 ("ST" in little-endian)
+    pub magic: u16,
+    /// Snapshot format version
+    pub version: u8,
+    /// Number of component records that follow
+    pub component_count: u16,
+}
+
+impl StateHeader {
+    pub const MAGIC: u16 = 0x5453; // "ST"
+    pub const VERSION: u8 = 1;
+    pub const SIZE: usize = 8;
+
+    pub fn new(component_count: u16) -> Self {
+        Self {
+            magic: Self::MAGIC,
+            version: Self::VERSION,
+            component_count,
+        }
+    }
+
+    /// Validate header magic and version
+    #[inline]
+    pub fn is_valid(&self) -> bool {
+        self.magic == Self::MAGIC && self.version == Self::VERSION
+    }
+
+    /// Serialize to 8 bytes
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0u8; 8];
+        bytes[0..2].copy_from_slice(&self.magic.to_le_bytes());
+        bytes[2] = self.version;
+        bytes[4..6].copy_from_slice(&self.component_count.to_le_bytes());
+        bytes
+    }
+
+    /// Deserialize from 8 bytes
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::SIZE {
+            return None;
+        }
+        Some(Self {
+            magic: u16::from_le_bytes([bytes[0], bytes[1]]),
+            version: bytes[2],
+            component_count: u16::from_le_bytes([bytes[4], bytes[5]]),
+        })
+    }
+}
+
+/// Per-component record header in a state snapshot
+///
+/// Memory Layout:
+/// ```text
+/// Offset  Size  Field
+/// 0       2     component_id
+/// 2       2     field_count
+/// 4       4     data_len
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateComponentHeader {
+    /// Component index (order of `StateSchema`s emitted by the compiler)
+    pub component_id: u16,
+    /// Number of field entries that follow
+    pub field_count: u16,
+    /// Size of the component's State Region image (multiple of 4)
+    pub data_len: u32,
+}
+
+impl StateComponentHeader {
+    pub const SIZE: usize = 8;
+
+    /// Serialize to 8 bytes
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0u8; 8];
+        bytes[0..2].copy_from_slice(&self.component_id.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.field_count.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.data_len.to_le_bytes());
+        bytes
+    }
+
+    /// Deserialize from 8 bytes
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::SIZE {
+            return None;
+        }
+        Some(Self {
+            component_id: u16::from_le_bytes([bytes[0], bytes[1]]),
+            field_count: u16::from_le_bytes([bytes[2], bytes[3]]),
+            data_len: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        })
+    }
+}
+
+/// Field value types in a state snapshot
+///
+/// Every value occupies a 4-byte aligned slot in the component's data:
+/// - `I32`: 4 bytes (little-endian)
+/// - `Bool`: 4 bytes (0 or 1)
+/// - `Str`: 4-byte length followed by UTF-8 bytes, padded to 4
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateValueType {
+    I32 = 1,
+    Bool = 2,
+    Str = 3,
+}
+
+impl StateValueType {
+    #[inline]
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(Self::I32),
+            2 => Some(Self::Bool),
+            3 => Some(Self::Str),
+            _ => None,
+        }
+    }
+}
+
+/// Field descriptor in a state snapshot
+///
+/// Memory Layout:
+/// ```text
+/// Offset  Size  Field
+/// 0       1     value_type (StateValueType)
+/// 1       1     dirty_bit
+/// 2       2     offset (into component data)
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateFieldEntry {
+    pub value_type: u8,
+    /// Bit in the component's dirty_mask
+    pub dirty_bit: u8,
+    /// Byte offset of the value inside the component data
+    pub offset: u16,
+}
+
+impl StateFieldEntry {
+    pub const SIZE: usize = 4;
+
+    /// Serialize to 4 bytes
+    pub fn to_bytes(&self) -> [u8; 4] {
+        let offset = self.offset.to_le_bytes();
+        [self.value_type, self.dirty_bit, offset[0], offset[1]]
+    }
+
+    /// Deserialize from 4 bytes
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::SIZE {
+            return None;
+        }
+        Some(Self {
+            value_type: bytes[0],
+            dirty_bit: bytes[1],
+            offset: u16::from_le_bytes([bytes[2], bytes[3]]),
+        })
+    }
+}
+
+/// Decoded state value (strings borrow from the snapshot)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateValue<'a> {
+    I32(i32),
+    Bool(bool),
+    Str(&'a str),
 }
 
+impl StateValue<'_> {
+    #[inline]
+    pub fn value_type(&self) -> StateValueType {
+        match self {
+            Self::I32(_) => StateValueType::I32,
+            Self::Bool(_) => StateValueType::Bool,
+            Self::Str(_) => StateValueType::Str,
+        }
+    }
+
+    /// Size of the value's slot in component data (4-byte aligned)
+    #[inline]
+    pub fn slot_size(&self) -> usize {
+        match self {
+            Self::I32(_) | Self::Bool(_) => 4,
+            Self::Str(s) => 4 + s.len().next_multiple_of(4),
+        }
+    }
+}
+
+/// Encoder for state snapshots (used by the compiler and server)
+pub struct StateWriter {
+    component_count: u16,
+    body: alloc::vec::Vec<u8>,
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self {
+            component_count: 0,
+            body: alloc::vec::Vec::new(),
+        }
+    }
+
+    /// Append a component record
+    ///
+    /// `fields` are `(dirty_bit, value)` pairs in schema order.
+    pub fn add_component(
+        &mut self,
+        component_id: u16,
+        fields: &[(u8, StateValue<'_>)],
+    ) -> Result<(), ErrorCode> {
+        let mut data = alloc::vec::Vec::new();
+        let mut entries = alloc::vec::Vec::with_capacity(fields.len() * StateFieldEntry::SIZE);
+
+        for (dirty_bit, value) in fields {
+            let offset = u16::try_from(data.len()).map_err(|_| ErrorCode::InvalidState)?;
+            let entry = StateFieldEntry {
+                value_type: value.value_type() as u8,
+                dirty_bit: *dirty_bit,
+                offset,
+            };
+            entries.extend_from_slice(&entry.to_bytes());
+
+            match value {
+                StateValue::I32(v) => data.extend_from_slice(&v.to_le_bytes()),
+                StateValue::Bool(v) => data.extend_from_slice(&(*v as u32).to_le_bytes()),
+                StateValue::Str(s) => {
+                    let len = u32::try_from(s.len()).map_err(|_| ErrorCode::InvalidState)?;
+                    data.extend_from_slice(&len.to_le_bytes());
+                    data.extend_from_slice(s.as_bytes());
+                    data.resize(data.len().next_multiple_of(4), 0);
+                }
+            }
+        }
+
+        let header = StateComponentHeader {
+            component_id,
+            field_count: u16::try_from(fields.len()).map_err(|_| ErrorCode::InvalidState)?,
+            data_len: u32::try_from(data.len()).map_err(|_| ErrorCode::InvalidState)?,
+        };
+        self.component_count =
+            self.component_count.checked_add(1).ok_or(ErrorCode::InvalidState)?;
+
+        self.body.extend_from_slice(&header.to_bytes());
+        self.body.extend_from_slice(&entries);
+        self.body.extend_from_slice(&data);
+        Ok(())
+    }
+
+    /// Finish the snapshot and return the encoded bytes
+    pub fn finish(self) -> alloc::vec::Vec<u8> {
+        let mut out = alloc::vec::Vec::with_capacity(StateHeader::SIZE + self.body.len());
+        out.extend_from_slice(&StateHeader::new(self.component_count).to_bytes());
+        out.extend_from_slice(&self.body);
+        out
+    }
+}
+
+/// One component record of a decoded state snapshot
+#[derive(Debug, Clone, Copy)]
+pub struct StateComponent<'a> {
+    pub component_id: u16,
+    entries: &'a [u8],
+    data: &'a [u8],
+}
+
+impl<'a> StateComponent<'a> {
+    /// Number of fields in this component
+    #[inline]
+    pub fn field_count(&self) -> usize {
+        self.entries.len() / StateFieldEntry::SIZE
+    }
+
+    /// Raw State Region image for this component (4-byte aligned fields)
+    #[inline]
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Field descriptor by index
+    pub fn field(&self, index: usize) -> Option<StateFieldEntry> {
+        let start = index.checked_mul(StateFieldEntry::SIZE)?;
+        StateFieldEntry::from_bytes(self.entries.get(start..)?)
+    }
+
+    /// Decoded field value by index
+    pub fn value(&self, index: usize) -> Option<StateValue<'a>> {
+        let field = self.field(index)?;
+        let at = field.offset as usize;
+        let word = self.data.get(at..at + 4)?;
+        let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+
+        match StateValueType::from_u8(field.value_type)? {
+            StateValueType::I32 => Some(StateValue::I32(word as i32)),
+            StateValueType::Bool => Some(StateValue::Bool(word != 0)),
+            StateValueType::Str => {
+                let bytes = self.data.get(at + 4..(at + 4).checked_add(word as usize)?)?;
+                core::str::from_utf8(bytes).ok().map(StateValue::Str)
+            }
+        }
+    }
+}
+
+/// Zero-copy decoder for state snapshots
+///
+/// Yields one `StateComponent` per record. Every field is validated
+/// before the component is returned, so `StateComponent::value` only
+/// returns `None` for out-of-range indices.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    remaining: u16,
+}
+
+impl<'a> StateReader<'a> {
+    /// Validate the header and start reading
+    pub fn new(data: &'a [u8]) -> Result<Self, ErrorCode> {
+        let header = StateHeader::from_bytes(data).ok_or(ErrorCode::BufferTooSmall)?;
+        if header.magic != StateHeader::MAGIC {
+            return Err(ErrorCode::InvalidMagic);
+        }
+        if header.version != StateHeader::VERSION {
+            return Err(ErrorCode::UnsupportedVersion);
+        }
+
+        Ok(Self {
+            data,
+            offset: StateHeader::SIZE,
+            remaining: header.component_count,
+        })
+    }
+
+    /// Number of component records not yet read
+    #[inline]
+    pub fn remaining(&self) -> u16 {
+        self.remaining
+    }
+
+    fn read_component(&mut self) -> Result<StateComponent<'a>, ErrorCode> {
+        let rest = &self.data[self.offset..];
+        let header = StateComponentHeader::from_bytes(rest).ok_or(ErrorCode::BufferTooSmall)?;
+
+        let entries_len = header.field_count as usize * StateFieldEntry::SIZE;
+        let data_len = header.data_len as usize;
+        let total = StateComponentHeader::SIZE + entries_len + data_len;
+        if rest.len() < total {
+            return Err(ErrorCode::BufferTooSmall);
+        }
+        if !data_len.is_multiple_of(4) {
+            return Err(ErrorCode::InvalidState);
+        }
+
+        let entries_end = StateComponentHeader::SIZE + entries_len;
+        let component = StateComponent {
+            component_id: header.component_id,
+            entries: &rest[StateComponentHeader::SIZE..entries_end],
+            data: &rest[entries_end..total],
+        };
+
+        for index in 0..component.field_count() {
+            let field = component.field(index).ok_or(ErrorCode::InvalidState)?;
+            if !field.offset.is_multiple_of(4) {
+                return Err(ErrorCode::InvalidState);
+            }
+            component.value(index).ok_or(ErrorCode::InvalidState)?;
+        }
+
+        self.offset += total;
+        Ok(component)
+    }
+}
+
+impl<'a> Iterator for StateReader<'a> {
+    type Item = Result<StateComponent<'a>, ErrorCode>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        match self.read_component() {
+            Ok(component) => {
+                self.remaining -= 1;
+                Some(Ok(component))
+            }
+            Err(e) => {
+                // Stop after the first error
+                self.remaining = 0;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// ============================================================================
+// LAYOUT DICTIONARY (Layout chunk payload)
+// ============================================================================
+
+/// Layout dictionary header - first 8 bytes of the Layout chunk (layout.bin)
+///
+/// Memory Layout:
+/// ```text
+/// Offset  Size  Field
+/// 0       4     magic (0x4C415944 = "LAYD")
+/// 4       1     version
+/// 5       1     reserved
+/// 6       2     template_count
+/// ```
+///
+/// Followed by `template_count` `LayoutEntry`s (12 bytes each), then the
+/// templates' HTML packed back to back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayoutHeader {
+    /// Magic bytes: 0x4C415944 ("LAYD")
+    pub magic: u32,
+    /// Layout format version
+    pub version: u8,
+    /// Number of template entries that follow
+    pub template_count: u16,
+}
+
+impl LayoutHeader {
+    pub const MAGIC: u32 = 0x4C415944; // "LAYD"
+    pub const VERSION: u8 = 1;
+    pub const SIZE: usize = 8;
+
+    pub fn new(template_count: u16) -> Self {
+        Self {
+            magic: Self::MAGIC,
+            version: Self::VERSION,
+            template_count,
+        }
+    }
+
+    /// Serialize to 8 bytes
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0u8; 8];
+        bytes[0..4].copy_from_slice(&self.magic.to_le_bytes());
+        bytes[4] = self.version;
+        bytes[6..8].copy_from_slice(&self.template_count.to_le_bytes());
+        bytes
+    }
+
+    /// Deserialize from 8 bytes
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::SIZE {
+            return None;
+        }
+        Some(Self {
+            magic: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            version: bytes[4],
+            template_count: u16::from_le_bytes([bytes[6], bytes[7]]),
+        })
+    }
+}
+
+/// Template entry in a layout dictionary
+///
+/// Memory Layout:
+/// ```text
+/// Offset  Size  Field
+/// 0       4     id (u32, like wide-ID HTIP streams)
+/// 4       4     html_offset (into the HTML data)
+/// 8       2     html_len
+/// 10      1     slot_count
+/// 11      1     reserved
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayoutEntry {
+    pub id: u32,
+    pub html_offset: u32,
+    pub html_len: u16,
+    pub slot_count: u8,
+}
+
+impl LayoutEntry {
+    pub const SIZE: usize = 12;
+
+    /// Serialize to 12 bytes
+    pub fn to_bytes(&self) -> [u8; 12] {
+        let mut bytes = [0u8; 12];
+        bytes[0..4].copy_from_slice(&self.id.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.html_offset.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.html_len.to_le_bytes());
+        bytes[10] = self.slot_count;
+        bytes
+    }
+
+    /// Deserialize from 12 bytes
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::SIZE {
+            return None;
+        }
+        Some(Self {
+            id: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            html_offset: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            html_len: u16::from_le_bytes([bytes[8], bytes[9]]),
+            slot_count: bytes[10],
+        })
+    }
+}
+
+/// Encoder for layout dictionaries (used by the compiler)
+pub struct LayoutWriter {
+    template_count: u16,
+    entries: alloc::vec::Vec<u8>,
+    html: alloc::vec::Vec<u8>,
+}
+
+impl Default for LayoutWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LayoutWriter {
+    pub fn new() -> Self {
+        Self {
+            template_count: 0,
+            entries: alloc::vec::Vec::new(),
+            html: alloc::vec::Vec::new(),
+        }
+    }
+
+    /// Append a template
+    pub fn add_template(&mut self, id: u32, html: &str, slot_count: u8) -> Result<(), ErrorCode> {
+        let entry = LayoutEntry {
+            id,
+            html_offset: u32::try_from(self.html.len()).map_err(|_| ErrorCode::InvalidLayout)?,
+            html_len: u16::try_from(html.len()).map_err(|_| ErrorCode::InvalidLayout)?,
+            slot_count,
+        };
+        self.template_count = self.template_count.checked_add(1).ok_or(ErrorCode::InvalidLayout)?;
+
+        self.entries.extend_from_slice(&entry.to_bytes());
+        self.html.extend_from_slice(html.as_bytes());
+        Ok(())
+    }
+
+    /// Finish the dictionary and return the encoded bytes
+    pub fn finish(self) -> alloc::vec::Vec<u8> {
+        let mut out = alloc::vec::Vec::with_capacity(
+            LayoutHeader::SIZE + self.entries.len() + self.html.len(),
+        );
+        out.extend_from_slice(&LayoutHeader::new(self.template_count).to_bytes());
+        out.extend_from_slice(&self.entries);
+        out.extend_from_slice(&self.html);
+        out
+    }
+}
+
+/// One template of a decoded layout dictionary (HTML borrows from it)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayoutTemplate<'a> {
+    pub id: u32,
+    pub html: &'a str,
+    pub slot_count: u8,
+}
+
+/// Zero-copy decoder for layout dictionaries
+///
+/// Yields one `LayoutTemplate` per entry, in dictionary order.
+pub struct LayoutReader<'a> {
+    entries: &'a [u8],
+    html: &'a [u8],
+    remaining: u16,
+}
+
+impl<'a> LayoutReader<'a> {
+    /// Validate the header and start reading
+    pub fn new(data: &'a [u8]) -> Result<Self, ErrorCode> {
+        let header = LayoutHeader::from_bytes(data).ok_or(ErrorCode::BufferTooSmall)?;
+        if header.magic != LayoutHeader::MAGIC {
+            return Err(ErrorCode::InvalidMagic);
+        }
+        if header.version != LayoutHeader::VERSION {
+            return Err(ErrorCode::UnsupportedVersion);
+        }
+
+        let entries_end = LayoutHeader::SIZE + header.template_count as usize * LayoutEntry::SIZE;
+        if data.len() < entries_end {
+            return Err(ErrorCode::BufferTooSmall);
+        }
+
+        Ok(Self {
+            entries: &data[LayoutHeader::SIZE..entries_end],
+            html: &data[entries_end..],
+            remaining: header.template_count,
+        })
+    }
+
+    /// Number of templates not yet read
+    #[inline]
+    pub fn remaining(&self) -> u16 {
+        self.remaining
+    }
+
+    fn read_template(&mut self) -> Result<LayoutTemplate<'a>, ErrorCode> {
+        let entry = LayoutEntry::from_bytes(self.entries).ok_or(ErrorCode::BufferTooSmall)?;
+        self.entries = &self.entries[LayoutEntry::SIZE..];
+
+        let start = entry.html_offset as usize;
+        let html = self
+            .html
+            .get(start..start + entry.html_len as usize)
+            .ok_or(ErrorCode::BufferTooSmall)?;
+        let html = core::str::from_utf8(html).map_err(|_| ErrorCode::InvalidLayout)?;
+
+        Ok(LayoutTemplate {
+            id: entry.id,
+            html,
+            slot_count: entry.slot_count,
+        })
+    }
+}
+
+impl<'a> Iterator for LayoutReader<'a> {
+    type Item = Result<LayoutTemplate<'a>, ErrorCode>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        match self.read_template() {
+            Ok(template) => {
+                self.remaining -= 1;
+                Some(Ok(template))
+            }
+            Err(e) => {
+                // Stop after the first error
+                self.remaining = 0;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// ============================================================================
+// HTIP STREAMS (Opcodes chunk payload)
+// ============================================================================
+
+/// Encoder for HTIP v2 streams (`HtipHeader`, string table, templates, ops)
+///
+/// Each Opcodes chunk of a chunked stream is one such stream. `add_string`
+/// returns indices counted from the stream's own first string: a later
+/// chunk refers to earlier chunks' strings by adding their counts.
+///
+/// IDs that do not fit the writer's `IdWidth` are rejected with
+/// `InvalidOpcode`.
+pub struct HtipWriter {
+    width: IdWidth,
+    string_count: u16,
+    string_entries: alloc::vec::Vec<u8>,
+    string_data: alloc::vec::Vec<u8>,
+    template_count: u16,
+    templates: alloc::vec::Vec<u8>,
+    op_count: u32,
+    ops: alloc::vec::Vec<u8>,
+}
+
+impl HtipWriter {
+    pub fn new(width: IdWidth) -> Self {
+        Self {
+            width,
+            string_count: 0,
+            string_entries: alloc::vec::Vec::new(),
+            string_data: alloc::vec::Vec::new(),
+            template_count: 0,
+            templates: alloc::vec::Vec::new(),
+            op_count: 0,
+            ops: alloc::vec::Vec::new(),
+        }
+    }
+
+    /// Append a string and return its index
+    pub fn add_string(&mut self, s: &str) -> Result<u16, ErrorCode> {
+        let idx = self.string_count;
+        let offset =
+            u32::try_from(self.string_data.len()).map_err(|_| ErrorCode::StringDataTooLarge)?;
+        let len = u16::try_from(s.len()).map_err(|_| ErrorCode::StringDataTooLarge)?;
+        self.string_count = idx.checked_add(1).ok_or(ErrorCode::TooManyStrings)?;
+
+        self.string_entries.extend_from_slice(&offset.to_le_bytes());
+        self.string_entries.extend_from_slice(&len.to_le_bytes());
+        self.string_entries.extend_from_slice(&[0, 0]); // reserved
+        self.string_data.extend_from_slice(s.as_bytes());
+        Ok(idx)
+    }
+
+    /// Append a template whose HTML is string `html_string_idx`
+    pub fn add_template(
+        &mut self,
+        id: u32,
+        html_string_idx: u16,
+        slot_count: u8,
+    ) -> Result<(), ErrorCode> {
+        let mut entry = alloc::vec::Vec::with_capacity(TemplateEntry::SIZE);
+        self.push_id(&mut entry, id)?;
+        entry.extend_from_slice(&html_string_idx.to_le_bytes());
+        entry.push(slot_count);
+        entry.resize(TemplateEntry::SIZE, 0); // reserved
+        self.template_count =
+            self.template_count.checked_add(1).ok_or(ErrorCode::TooManyTemplates)?;
+
+        self.templates.extend_from_slice(&entry);
+        Ok(())
+    }
+
+    /// Append an op whose payload is already laid out for the writer's width
+    pub fn op(&mut self, op_type: OpType, target_id: u32, payload: &[u8]) -> Result<(), ErrorCode> {
+        if payload.len() != self.width.payload_size(op_type) {
+            return Err(ErrorCode::InvalidOpcode);
+        }
+
+        let mut op = alloc::vec::Vec::with_capacity(self.width.opcode_header_size());
+        op.push(op_type as u8);
+        op.resize(self.width.opcode_header_size() - self.width.id_size(), 0); // reserved
+        self.push_id(&mut op, target_id)?;
+        self.op_count = self.op_count.checked_add(1).ok_or(ErrorCode::TooManyOps)?;
+
+        self.ops.extend_from_slice(&op);
+        self.ops.extend_from_slice(payload);
+        Ok(())
+    }
+
+    /// Append a Clone of `template_id` under `parent_id` (0 = root)
+    pub fn clone_template(
+        &mut self,
+        target_id: u32,
+        template_id: u32,
+        parent_id: u32,
+    ) -> Result<(), ErrorCode> {
+        let mut payload = alloc::vec::Vec::with_capacity(8);
+        self.push_id(&mut payload, template_id)?;
+        self.push_id(&mut payload, parent_id)?;
+        self.op(OpType::Clone, target_id, &payload)
+    }
+
+    /// Append a PatchText setting the target's text to string `string_idx`
+    pub fn patch_text(&mut self, target_id: u32, string_idx: u16) -> Result<(), ErrorCode> {
+        let [lo, hi] = string_idx.to_le_bytes();
+        self.op(OpType::PatchText, target_id, &[lo, hi, 0, 0])
+    }
+
+    /// Append a BatchStart opening batch `batch_id`
+    pub fn batch_start(&mut self, batch_id: u32) -> Result<(), ErrorCode> {
+        self.op(OpType::BatchStart, batch_id, &[])
+    }
+
+    /// Append a BatchCommit closing batch `batch_id`
+    pub fn batch_commit(&mut self, batch_id: u32) -> Result<(), ErrorCode> {
+        self.op(OpType::BatchCommit, batch_id, &[])
+    }
+
+    /// Finish the stream and return the encoded bytes
+    pub fn finish(self) -> alloc::vec::Vec<u8> {
+        let payload_size = self.string_entries.len()
+            + self.string_data.len()
+            + self.templates.len()
+            + self.ops.len();
+        let mut flags = self.width.flags();
+        if self.string_count > 0 {
+            flags |= HtipHeader::FLAG_HAS_STRINGS;
+        }
+        if self.template_count > 0 {
+            flags |= HtipHeader::FLAG_HAS_TEMPLATES;
+        }
+
+        let mut out = alloc::vec::Vec::with_capacity(HtipHeader::SIZE + payload_size);
+        out.extend_from_slice(&HtipHeader::MAGIC.to_le_bytes());
+        out.push(HtipHeader::VERSION);
+        out.push(flags);
+        out.extend_from_slice(&self.template_count.to_le_bytes());
+        out.extend_from_slice(&self.string_count.to_le_bytes());
+        out.extend_from_slice(&self.op_count.to_le_bytes());
+        out.extend_from_slice(&(payload_size as u32).to_le_bytes());
+        out.extend_from_slice(&self.string_entries);
+        out.extend_from_slice(&self.string_data);
+        out.extend_from_slice(&self.templates);
+        out.extend_from_slice(&self.ops);
+        out
+    }
+
+    fn push_id(&self, bytes: &mut alloc::vec::Vec<u8>, id: u32) -> Result<(), ErrorCode> {
+        match self.width {
+            IdWidth::Compact => {
+                let id = u16::try_from(id).map_err(|_| ErrorCode::InvalidOpcode)?;
+                bytes.extend_from_slice(&id.to_le_bytes());
+            }
+            IdWidth::Wide => bytes.extend_from_slice(&id.to_le_bytes()),
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// DELTA PATCHES (X-Dx-Patch responses)
+// ============================================================================
+
+/// Encoder for delta patches (used by the server)
+///
+/// Memory Layout:
+/// ```text
+/// Offset  Size  Field
+/// 0       17    PatchHeader (base/target `version_hash`, algorithm 1)
+/// 17      4     target_len (length of the patched binary)
+/// 21      4     block_count
+/// 25      ..    blocks: [index:4][len:2][xor:len]
+/// ```
+///
+/// Block `index` starts at byte `index * BLOCK_SIZE` of the target. Its
+/// `xor` is target ^ base over that range (base bytes past its end read as
+/// 0), with trailing zeros trimmed. Unchanged blocks within the base are
+/// left out; blocks reaching past it are always written, so the target is
+/// at most `BLOCK_SIZE` bytes per block longer than the base.
+pub struct PatchWriter;
+
+impl PatchWriter {
+    /// Size of the header, target length and block count
+    pub const PREFIX_SIZE: usize = 25;
+    /// `PatchHeader::patch_algorithm` for Block XOR
+    pub const ALGORITHM: u8 = 1;
+
+    /// Encode the patch turning `old` into `new`
+    pub fn diff(old: &[u8], new: &[u8]) -> Result<alloc::vec::Vec<u8>, ErrorCode> {
+        let target_len = u32::try_from(new.len()).map_err(|_| ErrorCode::InvalidPatch)?;
+        let header = PatchHeader::new(
+            PatchHeader::version_hash(old),
+            PatchHeader::version_hash(new),
+            Self::ALGORITHM,
+        );
+
+        let mut out = alloc::vec::Vec::with_capacity(Self::PREFIX_SIZE);
+        out.extend_from_slice(&header.to_bytes());
+        out.extend_from_slice(&target_len.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes()); // block_count, set below
+
+        let mut block_count = 0u32;
+        for (index, new_block) in new.chunks(BLOCK_SIZE).enumerate() {
+            let old_block = old.get(index * BLOCK_SIZE..).unwrap_or(&[]);
+            let mut xor = [0u8; BLOCK_SIZE];
+            for (i, byte) in new_block.iter().enumerate() {
+                xor[i] = byte ^ old_block.get(i).copied().unwrap_or(0);
+            }
+
+            let len = xor.iter().rposition(|&b| b != 0).map_or(0, |last| last + 1);
+            if len == 0 && index * BLOCK_SIZE + new_block.len() <= old.len() {
+                continue;
+            }
+
+            // index < target_len / BLOCK_SIZE, so it fits
+            out.extend_from_slice(&(index as u32).to_le_bytes());
+            out.extend_from_slice(&(len as u16).to_le_bytes());
+            out.extend_from_slice(&xor[..len]);
+            block_count += 1;
+        }
+
+        out[21..25].copy_from_slice(&block_count.to_le_bytes());
+        Ok(out)
+    }
+}
+
+/// One changed block of a decoded patch (XOR data borrows from it)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PatchBlock<'a> {
+    pub index: u32,
+    pub xor: &'a [u8],
+}
+
+/// Zero-copy decoder for delta patches (see `PatchWriter` for the format)
+#[derive(Clone)]
+pub struct PatchReader<'a> {
+    header: PatchHeader,
+    target_len: u32,
+    blocks: &'a [u8],
+    remaining: u32,
+}
+
+impl<'a> PatchReader<'a> {
+    /// Validate the header and start reading
+    pub fn new(data: &'a [u8]) -> Result<Self, ErrorCode> {
+        if data.len() < PatchWriter::PREFIX_SIZE {
+            return Err(ErrorCode::BufferTooSmall);
+        }
+        let header = PatchHeader::from_bytes(data).ok_or(ErrorCode::BufferTooSmall)?;
+        if header.patch_algorithm != PatchWriter::ALGORITHM {
+            return Err(ErrorCode::InvalidPatch);
+        }
+
+        Ok(Self {
+            header,
+            target_len: u32::from_le_bytes([data[17], data[18], data[19], data[20]]),
+            blocks: &data[PatchWriter::PREFIX_SIZE..],
+            remaining: u32::from_le_bytes([data[21], data[22], data[23], data[24]]),
+        })
+    }
+
+    /// Patch metadata
+    #[inline]
+    pub fn header(&self) -> &PatchHeader {
+        &self.header
+    }
+
+    /// Length of the patched binary
+    #[inline]
+    pub fn target_len(&self) -> u32 {
+        self.target_len
+    }
+
+    /// Number of blocks not yet read
+    #[inline]
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Patch `old` into a new buffer
+    ///
+    /// `old` must hash to the base hash and the result to the target hash.
+    pub fn apply(&self, old: &[u8]) -> Result<alloc::vec::Vec<u8>, ErrorCode> {
+        if PatchHeader::version_hash(old) != self.header.base_version_hash {
+            return Err(ErrorCode::PatchBaseMismatch);
+        }
+
+        // Each block adds at most BLOCK_SIZE bytes past the base
+        let growth = (self.remaining as usize).saturating_mul(BLOCK_SIZE);
+        if self.target_len as usize > old.len().saturating_add(growth) {
+            return Err(ErrorCode::InvalidPatch);
+        }
+
+        let mut new = old.to_vec();
+        new.resize(self.target_len as usize, 0);
+        self.xor_blocks(&mut new)?;
+
+        if PatchHeader::version_hash(&new) != self.header.target_version_hash {
+            return Err(ErrorCode::PatchTargetMismatch);
+        }
+        Ok(new)
+    }
+
+    /// Patch `buffer` in place (base and target must have the same length)
+    ///
+    /// On any error the buffer is left as it was.
+    pub fn apply_in_place(&self, buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        if buffer.len() != self.target_len as usize {
+            return Err(ErrorCode::InvalidPatch);
+        }
+        if PatchHeader::version_hash(buffer) != self.header.base_version_hash {
+            return Err(ErrorCode::PatchBaseMismatch);
+        }
+
+        self.xor_blocks(buffer)?;
+
+        if PatchHeader::version_hash(buffer) != self.header.target_version_hash {
+            // XOR is its own inverse: applying again restores the original
+            self.xor_blocks(buffer)?;
+            return Err(ErrorCode::PatchTargetMismatch);
+        }
+        Ok(())
+    }
+
+    /// XOR every block into a `target_len` buffer, or none if one is bad
+    fn xor_blocks(&self, buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        let mut blocks = self.clone();
+        for block in blocks.by_ref() {
+            block?;
+        }
+        if !blocks.blocks.is_empty() {
+            return Err(ErrorCode::InvalidPatch);
+        }
+
+        for block in self.clone().flatten() {
+            let start = block.index as usize * BLOCK_SIZE;
+            for (byte, xor) in buffer[start..].iter_mut().zip(block.xor) {
+                *byte ^= xor;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_block(&mut self) -> Result<PatchBlock<'a>, ErrorCode> {
+        if self.blocks.len() < 6 {
+            return Err(ErrorCode::BufferTooSmall);
+        }
+        let b = self.blocks;
+        let index = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        let len = u16::from_le_bytes([b[4], b[5]]) as usize;
+
+        // Each block stays inside its own BLOCK_SIZE range of the target
+        let start = (index as usize).checked_mul(BLOCK_SIZE).ok_or(ErrorCode::InvalidPatch)?;
+        if len > BLOCK_SIZE || start.saturating_add(len) > self.target_len as usize {
+            return Err(ErrorCode::InvalidPatch);
+        }
+
+        let xor = self.blocks.get(6..6 + len).ok_or(ErrorCode::BufferTooSmall)?;
+        self.blocks = &self.blocks[6 + len..];
+        Ok(PatchBlock { index, xor })
+    }
+}
+
+impl<'a> Iterator for PatchReader<'a> {
+    type Item = Result<PatchBlock<'a>, ErrorCode>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        match self.read_block() {
+            Ok(block) => {
+                self.remaining -= 1;
+                Some(Ok(block))
+            }
+            Err(e) => {
+                // Stop after the first error
+                self.remaining = 0;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// ============================================================================
+// SECURITY & CAPABILITIES
+// ============================================================================
+
+/// Capabilities manifest for security
+#[cfg_attr(
+    feature = "std",
+    derive(Serialize, Deserialize, bincode::Encode, bincode::Decode)
+)]
+#[derive(Debug, Clone)]
+#[derive(Default)]
+pub struct CapabilitiesManifest {
+    pub network: bool,
+    pub storage: bool,
+    pub geolocation: bool,
+    pub camera: bool,
+    pub microphone: bool,
+    pub signature: alloc::vec::Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wide_id_layouts() {
+        use core::mem::size_of;
+        assert_eq!(size_of::<OpcodeHeaderWide>(), OpcodeHeaderWide::SIZE);
+        assert_eq!(size_of::<ClonePayloadWide>(), IdWidth::Wide.payload_size(OpType::Clone));
+        assert_eq!(size_of::<AppendChildPayloadWide>(), size_of::<AppendChildPayload>());
+        assert_eq!(size_of::<TemplateEntryWide>(), TemplateEntry::SIZE);
+
+        assert_eq!(IdWidth::for_max_id(u16::MAX as u32), IdWidth::Compact);
+        assert_eq!(IdWidth::for_max_id(70_000), IdWidth::Wide);
+        assert_eq!(IdWidth::from_flags(0x03), IdWidth::Compact);
+        assert_eq!(IdWidth::from_flags(0x03 | IdWidth::Wide.flags()), IdWidth::Wide);
+
+        let bytes = 70_000u32.to_le_bytes();
+        assert_eq!(IdWidth::Wide.read_id(&bytes, 0), Some(70_000));
+        assert_eq!(IdWidth::Compact.read_id(&bytes, 0), Some(70_000 & 0xFFFF));
+        assert_eq!(IdWidth::Wide.read_id(&bytes, 1), None);
+
+        // Wide streams may use the IDs they can name
+        assert_eq!(DecodeLimits::for_width(IdWidth::Compact), DecodeLimits::DEFAULT);
+        let wide = DecodeLimits::for_width(IdWidth::Wide);
+        assert!(wide.max(Limit::Nodes) > u16::MAX as usize);
+        assert!(wide.max(Limit::Templates) > MAX_TEMPLATES as usize);
+    }
+
+    #[test]
+    fn test_state_snapshot_roundtrip() {
+        let mut writer = StateWriter::new();
+        writer
+            .add_component(
+                0,
+                &[
+                    (0, StateValue::I32(-42)),
+                    (1, StateValue::Bool(true)),
+                    (2, StateValue::Str("hello")),
+                ],
+            )
+            .unwrap();
+        writer.add_component(1, &[]).unwrap();
+        let bytes = writer.finish();
+
+        let mut reader = StateReader::new(&bytes).unwrap();
+        assert_eq!(reader.remaining(), 2);
+
+        let counter = reader.next().unwrap().unwrap();
+        assert_eq!(counter.component_id, 0);
+        assert_eq!(counter.field_count(), 3);
+        assert_eq!(counter.data().len() % 4, 0);
+        assert_eq!(counter.value(0), Some(StateValue::I32(-42)));
+        assert_eq!(counter.value(1), Some(StateValue::Bool(true)));
+        assert_eq!(counter.value(2), Some(StateValue::Str("hello")));
+        assert_eq!(counter.field(2).unwrap().dirty_bit, 2);
+        assert_eq!(counter.value(3), None);
+
+        let empty = reader.next().unwrap().unwrap();
+        assert_eq!(empty.component_id, 1);
+        assert_eq!(empty.field_count(), 0);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_layout_roundtrip() {
+        let mut writer = LayoutWriter::new();
+        writer.add_template(0, "<div class=\"card\"></div>", 2).unwrap();
+        writer.add_template(7, "<li>✓</li>", 0).unwrap();
+        let bytes = writer.finish();
+        assert_eq!(&bytes[0..4], &LayoutHeader::MAGIC.to_le_bytes());
+
+        let reader = LayoutReader::new(&bytes).unwrap();
+        assert_eq!(reader.remaining(), 2);
+        let templates: alloc::vec::Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(
+            templates,
+            [
+                LayoutTemplate {
+                    id: 0,
+                    html: "<div class=\"card\"></div>",
+                    slot_count: 2
+                },
+                LayoutTemplate {
+                    id: 7,
+                    html: "<li>✓</li>",
+                    slot_count: 0
+                },
+            ]
+        );
+
+        // HTML cut short: the entry that reaches past the end fails
+        let mut reader = LayoutReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert_eq!(reader.next(), Some(Err(ErrorCode::BufferTooSmall)));
+        assert_eq!(reader.next(), None);
+
+        assert_eq!(LayoutReader::new(&[0u8; 8]).err(), Some(ErrorCode::InvalidMagic));
+        assert_eq!(LayoutReader::new(&bytes[..12]).err(), Some(ErrorCode::BufferTooSmall));
+
+        // IDs past u16 (wide-ID apps) survive the round trip
+        let mut writer = LayoutWriter::new();
+        writer.add_template(70_000, "<p></p>", 1).unwrap();
+        let bytes = writer.finish();
+        let template = LayoutReader::new(&bytes).unwrap().next().unwrap().unwrap();
+        assert_eq!(template.id, 70_000);
+    }
+
+    #[test]
+    fn test_htip_writer() {
+        let mut writer = HtipWriter::new(IdWidth::Compact);
+        let html = writer.add_string("<p></p>").unwrap();
+        let text = writer.add_string("Hi").unwrap();
+        writer.add_template(3, html, 1).unwrap();
+        writer.batch_start(1).unwrap();
+        writer.clone_template(1, 3, 0).unwrap();
+        writer.patch_text(1, text).unwrap();
+        writer.batch_commit(1).unwrap();
+        let bytes = writer.finish();
+
+        let header = unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const HtipHeader) };
+        assert!(header.is_valid());
+        assert_eq!(header.flags, HtipHeader::FLAG_HAS_STRINGS | HtipHeader::FLAG_HAS_TEMPLATES);
+        assert_eq!((header.template_count, header.string_count), (1, 2));
+        assert_eq!(header.opcode_count, 4);
+        assert_eq!(header.payload_size as usize, bytes.len() - HtipHeader::SIZE);
+
+        // Entries, "<p></p>Hi", the template, then 4 + 4 + 4 + 4 + 4 + 4 op bytes
+        let templates = HtipHeader::SIZE + 2 * StringEntry::SIZE + 9;
+        assert_eq!(&bytes[templates..templates + 5], &[3, 0, 0, 0, 1]);
+        let ops = templates + TemplateEntry::SIZE;
+        assert_eq!(&bytes[ops..ops + 4], &[OpType::BatchStart as u8, 0, 1, 0]);
+        assert_eq!(&bytes[ops + 4..ops + 12], &[1, 0, 1, 0, 3, 0, 0, 0]);
+        assert_eq!(bytes.len(), ops + 24);
+
+        // Compact streams cannot carry IDs past u16
+        let mut writer = HtipWriter::new(IdWidth::Compact);
+        assert_eq!(writer.clone_template(70_000, 0, 0), Err(ErrorCode::InvalidOpcode));
+        let mut writer = HtipWriter::new(IdWidth::Wide);
+        writer.clone_template(70_000, 0, 0).unwrap();
+        let bytes = writer.finish();
+        assert_eq!(bytes[3], HtipHeader::FLAG_WIDE_IDS);
+        assert_eq!(bytes.len(), HtipHeader::SIZE + OpcodeHeaderWide::SIZE + 8);
+    }
+
+    #[test]
+    fn test_patch_roundtrip() {
+        let old: alloc::vec::Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+
+        let mut edited = old.clone();
+        edited[100] = 0;
+        edited[700..720].fill(0xEE);
+        let mut grown = edited.clone();
+        grown.extend_from_slice(&[1, 2, 3]);
+        let shrunk = old[..600].to_vec();
+
+        for new in [&edited, &grown, &shrunk, &old] {
+            let patch = PatchWriter::diff(&old, new).unwrap();
+            let reader = PatchReader::new(&patch).unwrap();
+            assert_eq!(reader.target_len() as usize, new.len());
+            assert_eq!(&reader.apply(&old).unwrap(), new);
+        }
+
+        // Only the changed blocks are sent
+        let patch = PatchWriter::diff(&old, &edited).unwrap();
+        let reader = PatchReader::new(&patch).unwrap();
+        let indices: alloc::vec::Vec<u32> = reader.map(|b| b.unwrap().index).collect();
+        assert_eq!(indices, [1, 10, 11]);
+
+        // Same length: in place
+        let mut buffer = old.clone();
+        PatchReader::new(&patch).unwrap().apply_in_place(&mut buffer).unwrap();
+        assert_eq!(buffer, edited);
+    }
+
+    #[test]
+    fn test_patch_rejects_mismatch() {
+        let old = alloc::vec![7u8; 300];
+        let mut new = old.clone();
+        new[10] = 8;
+        let patch = PatchWriter::diff(&old, &new).unwrap();
+        let reader = PatchReader::new(&patch).unwrap();
+
+        // Wrong base: untouched
+        let mut other = alloc::vec![6u8; 300];
+        assert_eq!(reader.apply(&other), Err(ErrorCode::PatchBaseMismatch));
+        assert_eq!(reader.apply_in_place(&mut other), Err(ErrorCode::PatchBaseMismatch));
+        assert_eq!(other, alloc::vec![6u8; 300]);
+
+        // Corrupt XOR data: reverted
+        let mut corrupt = patch.clone();
+        *corrupt.last_mut().unwrap() ^= 0x40;
+        let reader = PatchReader::new(&corrupt).unwrap();
+        let mut buffer = old.clone();
+        assert_eq!(reader.apply_in_place(&mut buffer), Err(ErrorCode::PatchTargetMismatch));
+        assert_eq!(buffer, old);
+
+        // Block past the target, truncated block
+        let mut outside = patch.clone();
+        outside[25..29].copy_from_slice(&100u32.to_le_bytes());
+        let reader = PatchReader::new(&outside).unwrap();
+        assert_eq!(reader.apply(&old), Err(ErrorCode::InvalidPatch));
+        let truncated = PatchReader::new(&patch[..patch.len() - 1]).unwrap();
+        assert_eq!(truncated.apply(&old), Err(ErrorCode::BufferTooSmall));
+
+        // Bytes past the last block
+        let mut trailing = patch.clone();
+        trailing.push(0);
+        let reader = PatchReader::new(&trailing).unwrap();
+        assert_eq!(reader.apply(&old), Err(ErrorCode::InvalidPatch));
+        let mut buffer = old.clone();
+        assert_eq!(reader.apply_in_place(&mut buffer), Err(ErrorCode::InvalidPatch));
+        assert_eq!(buffer, old);
+
+        // A target longer than the blocks can account for is not allocated
+        let mut huge = patch.clone();
+        huge[17..21].copy_from_slice(&u32::MAX.to_le_bytes());
+        let reader = PatchReader::new(&huge).unwrap();
+        assert_eq!(reader.apply(&old), Err(ErrorCode::InvalidPatch));
+
+        // Growth the XOR leaves as zeros still gets its blocks
+        let mut grown = old.clone();
+        grown.resize(old.len() + 3 * BLOCK_SIZE, 0);
+        let patch = PatchWriter::diff(&old, &grown).unwrap();
+        assert_eq!(PatchReader::new(&patch).unwrap().apply(&old), Ok(grown));
+    }
+
+    #[test]
+    fn test_state_snapshot_rejects_corruption() {
+        assert_eq!(StateReader::new(&[0u8; 4]).err(), Some(ErrorCode::BufferTooSmall));
+        assert_eq!(StateReader::new(&[0u8; 8]).err(), Some(ErrorCode::InvalidMagic));
+
+        let mut writer = StateWriter::new();
+        writer.add_component(0, &[(0, StateValue::Str("abc"))]).unwrap();
+        let mut bytes = writer.finish();
+
+        // Truncated record
+        let truncated = &bytes[..bytes.len() - 1];
+        let mut reader = StateReader::new(truncated).unwrap();
+        assert_eq!(reader.next().unwrap().err(), Some(ErrorCode::BufferTooSmall));
+        assert!(reader.next().is_none());
+
+        // String length pointing past the component data
+        let len_at = StateHeader::SIZE + StateComponentHeader::SIZE + StateFieldEntry::SIZE;
+        bytes[len_at] = 0xFF;
+        let mut reader = StateReader::new(&bytes).unwrap();
+        assert_eq!(reader.next().unwrap().err(), Some(ErrorCode::InvalidState));
+    }
+}
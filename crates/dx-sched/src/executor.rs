@@ -0,0 +1,393 @@
+//! Single-threaded futures executor on the frame loop
+//!
+//! Every poll is a scheduler task at the future's priority, so futures share
+//! the frame budget (and the yield rules) with plain callbacks.
+//! A waker re-enqueues its future's poll task at most once until it runs.
+//! Wakers may be sent to other threads: a wake there only records the
+//! future in its executor's wake queue, which the owning thread drains at
+//! the start of its next frame. So does a wake while the scheduler is
+//! borrowed (a spawn inside `with_scheduler` or `Scheduler::tick`).
+//!
+//! Polls run from the global frame loop (`start_scheduler` /
+//! `start_scheduler_with`), outside the scheduler borrow.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, ThreadId};
+
+use crate::{TaskPriority, try_with_scheduler, with_scheduler};
+
+type LocalFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+/// A spawned future (None while it is being polled)
+struct Spawned {
+    future: Option<LocalFuture>,
+    waker: Arc<FutureWaker>,
+}
+
+/// Futures woken since the last drain, shared with their wakers
+struct WakeQueue {
+    /// Thread whose scheduler polls the futures
+    owner: ThreadId,
+    woken: Mutex<Vec<(u32, TaskPriority)>>,
+}
+
+thread_local! {
+    static FUTURES: RefCell<HashMap<u32, Spawned>> = RefCell::new(HashMap::new());
+    static NEXT_ID: Cell<u32> = const { Cell::new(1) };
+    static WAKE_QUEUE: Arc<WakeQueue> = Arc::new(WakeQueue {
+        owner: thread::current().id(),
+        woken: Mutex::new(Vec::new()),
+    });
+}
+
+/// Waker that schedules a poll of one future
+struct FutureWaker {
+    id: u32,
+    priority: TaskPriority,
+    /// A poll is already requested
+    queued: AtomicBool,
+    wakes: Arc<WakeQueue>,
+}
+
+impl Wake for FutureWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let mut woken = self.wakes.woken.lock().unwrap_or_else(PoisonError::into_inner);
+        woken.push((self.id, self.priority));
+        drop(woken);
+
+        // Off-thread wakes wait for the owner's next frame
+        if thread::current().id() == self.wakes.owner {
+            drain_wakes();
+        }
+    }
+}
+
+/// Schedule a poll for every future woken since the last drain
+///
+/// Runs on the executor's thread, at the start of each frame and on wakes
+/// from that thread. If the scheduler is already borrowed the wakes stay
+/// queued for the next frame.
+pub(crate) fn drain_wakes() {
+    try_with_scheduler(|scheduler| {
+        let woken = WAKE_QUEUE.with(|queue| {
+            let mut woken = queue.woken.lock().unwrap_or_else(PoisonError::into_inner);
+            std::mem::take(&mut *woken)
+        });
+
+        for (id, priority) in woken {
+            scheduler.schedule(priority, Box::new(move || poll(id)));
+        }
+    });
+}
+
+/// Poll a future once (the body of its scheduler task)
+fn poll(id: u32) {
+    let taken = FUTURES.with(|futures| {
+        let mut futures = futures.borrow_mut();
+        let spawned = futures.get_mut(&id)?;
+        Some((spawned.future.take()?, spawned.waker.clone()))
+    });
+    let Some((mut future, waker)) = taken else {
+        return; // Cancelled
+    };
+
+    // Wakes from here on need a new poll
+    waker.queued.store(false, Ordering::Release);
+    let waker = Waker::from(waker);
+    let done = future.as_mut().poll(&mut Context::from_waker(&waker)).is_ready();
+
+    FUTURES.with(|futures| {
+        let mut futures = futures.borrow_mut();
+        if done {
+            futures.remove(&id);
+        } else if let Some(spawned) = futures.get_mut(&id) {
+            spawned.future = Some(future);
+        }
+    });
+}
+
+/// Handle to a spawned future
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FutureHandle(u32);
+
+impl FutureHandle {
+    pub fn id(&self) -> u32 {
+        self.0
+    }
+
+    /// Check if the future has not completed (or been cancelled) yet
+    pub fn is_pending(&self) -> bool {
+        FUTURES.with(|futures| futures.borrow().contains_key(&self.0))
+    }
+
+    /// Drop the future without polling it again
+    ///
+    /// Returns false if it already completed or was cancelled.
+    pub fn cancel(&self) -> bool {
+        // Drop outside the borrow: the future's destructor may spawn or cancel
+        let removed = FUTURES.with(|futures| futures.borrow_mut().remove(&self.0));
+        removed.is_some()
+    }
+}
+
+/// Run a future on the frame loop, polled at `priority`
+///
+/// The first poll is queued like any other task.
+pub fn spawn(priority: TaskPriority, future: impl Future<Output = ()> + 'static) -> FutureHandle {
+    let id = NEXT_ID.with(|next| {
+        let id = next.get();
+        next.set(id.wrapping_add(1).max(1));
+        id
+    });
+
+    let waker = Arc::new(FutureWaker {
+        id,
+        priority,
+        queued: AtomicBool::new(false),
+        wakes: WAKE_QUEUE.with(Arc::clone),
+    });
+    FUTURES.with(|futures| {
+        futures.borrow_mut().insert(
+            id,
+            Spawned {
+                future: Some(Box::pin(future)),
+                waker: waker.clone(),
+            },
+        )
+    });
+    waker.wake_by_ref();
+
+    FutureHandle(id)
+}
+
+/// Future returned by `yield_now` and `yield_if_needed`
+pub struct Yield {
+    /// Yield even if budget remains
+    always: bool,
+    polled: bool,
+}
+
+impl Future for Yield {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.polled {
+            return Poll::Ready(());
+        }
+        self.polled = true;
+
+        if self.always || with_scheduler(|scheduler| scheduler.should_yield()) {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+}
+
+/// Go to the back of the queue, letting other tasks run first
+pub fn yield_now() -> Yield {
+    Yield {
+        always: true,
+        polled: false,
+    }
+}
+
+/// Continue in the next frame if this frame's budget is spent
+pub fn yield_if_needed() -> Yield {
+    Yield {
+        always: false,
+        polled: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        FRAME_INTERVAL_MS, ManualDriver, Scheduler, VirtualClock, install_scheduler,
+        start_scheduler_with,
+    };
+    use std::rc::Rc;
+
+    fn virtual_loop() -> (VirtualClock, ManualDriver) {
+        let clock = VirtualClock::new();
+        let driver = ManualDriver::new();
+        install_scheduler(Scheduler::with_clock(clock.clone()));
+        assert!(start_scheduler_with(driver.clone()));
+        (clock, driver)
+    }
+
+    /// Resolves once `fire` has been called
+    #[derive(Default)]
+    struct Signal {
+        fired: Cell<bool>,
+        waker: RefCell<Option<Waker>>,
+    }
+
+    impl Signal {
+        fn fire(&self) {
+            self.fired.set(true);
+            if let Some(waker) = self.waker.borrow_mut().take() {
+                waker.wake();
+            }
+        }
+
+        async fn wait(&self) {
+            std::future::poll_fn(|cx| {
+                if self.fired.get() {
+                    Poll::Ready(())
+                } else {
+                    *self.waker.borrow_mut() = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            })
+            .await
+        }
+    }
+
+    #[test]
+    fn test_waker_reschedules_future() {
+        let (clock, driver) = virtual_loop();
+        let signal = Rc::new(Signal::default());
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        let handle = spawn(TaskPriority::Normal, {
+            let (signal, log) = (signal.clone(), log.clone());
+            async move {
+                log.borrow_mut().push("start");
+                signal.wait().await;
+                log.borrow_mut().push("done");
+            }
+        });
+
+        driver.step();
+        assert_eq!(*log.borrow(), ["start"]);
+        assert!(handle.is_pending());
+
+        // Nothing runs until the waker fires
+        clock.advance(FRAME_INTERVAL_MS);
+        driver.step();
+        assert_eq!(with_scheduler(|s| s.last_frame().executed), 0);
+
+        signal.fire();
+        clock.advance(FRAME_INTERVAL_MS);
+        driver.step();
+        assert_eq!(*log.borrow(), ["start", "done"]);
+        assert!(!handle.is_pending());
+        assert!(!handle.cancel());
+    }
+
+    #[test]
+    fn test_future_yields_when_budget_spent() {
+        let (clock, driver) = virtual_loop();
+        let done = Rc::new(Cell::new(0));
+
+        spawn(TaskPriority::Normal, {
+            let (clock, done) = (clock.clone(), done.clone());
+            async move {
+                for _ in 0..10 {
+                    clock.advance(1.0);
+                    done.set(done.get() + 1);
+                    yield_if_needed().await;
+                }
+            }
+        });
+
+        // 4ms budget, yields past 3.5ms: four 1ms chunks per frame
+        driver.step();
+        assert_eq!(done.get(), 4);
+
+        clock.set(FRAME_INTERVAL_MS);
+        driver.step();
+        assert!(done.get() >= 8);
+
+        clock.set(2.0 * FRAME_INTERVAL_MS);
+        driver.step();
+        assert_eq!(done.get(), 10);
+    }
+
+    #[test]
+    fn test_wake_from_another_thread_polls_on_next_frame() {
+        let (clock, driver) = virtual_loop();
+        let fired = Arc::new(AtomicBool::new(false));
+        let waker = Arc::new(Mutex::new(None::<Waker>));
+        let done = Rc::new(Cell::new(false));
+
+        spawn(TaskPriority::Normal, {
+            let (fired, waker, done) = (fired.clone(), waker.clone(), done.clone());
+            async move {
+                std::future::poll_fn(|cx| {
+                    if fired.load(Ordering::Acquire) {
+                        Poll::Ready(())
+                    } else {
+                        *waker.lock().unwrap() = Some(cx.waker().clone());
+                        Poll::Pending
+                    }
+                })
+                .await;
+                done.set(true);
+            }
+        });
+        driver.step();
+        assert!(!done.get());
+
+        thread::spawn(move || {
+            fired.store(true, Ordering::Release);
+            waker.lock().unwrap().take().unwrap().wake();
+        })
+        .join()
+        .unwrap();
+        assert!(with_scheduler(|s| s.task_queue.is_empty()));
+
+        clock.advance(FRAME_INTERVAL_MS);
+        driver.step();
+        assert!(done.get());
+    }
+
+    #[test]
+    fn test_spawn_inside_scheduler_borrow() {
+        let (_clock, driver) = virtual_loop();
+        let done = Rc::new(Cell::new(false));
+
+        let handle = with_scheduler(|_| {
+            let done = done.clone();
+            spawn(TaskPriority::Normal, async move { done.set(true) })
+        });
+        assert!(with_scheduler(|s| s.task_queue.is_empty()));
+
+        driver.step();
+        assert!(done.get());
+        assert!(!handle.is_pending());
+    }
+
+    #[test]
+    fn test_cancelled_future_is_not_polled() {
+        let (_clock, driver) = virtual_loop();
+        let polled = Rc::new(Cell::new(false));
+
+        let handle = spawn(TaskPriority::Idle, {
+            let polled = polled.clone();
+            async move { polled.set(true) }
+        });
+        assert!(handle.cancel());
+
+        driver.step();
+        assert!(!polled.get());
+        assert!(!handle.is_pending());
+    }
+}
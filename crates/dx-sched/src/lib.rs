@@ -5,71 +5,381 @@
 //!
 //! **ARCHITECTURE:**
 //! - RAF Loop: Driven by browser's vsync
-//! - Frame Budget: Max 4ms WASM execution per frame (16.67ms - 12ms for layout/paint)
-//! - Priority Queue: Input events > RAF callbacks > Idle callbacks
+//! - Frame Budget: Adaptive, starts at 4ms WASM per frame (16.67ms - 12ms for layout/paint)
+//!   and follows the measured refresh rate and dropped frames
+//! - Priority Queue: Input events > RAF callbacks > Idle callbacks (FIFO within a priority)
+//! - Aging: Idle tasks are guaranteed to run within a bounded number of frames
 //! - Yield Strategy: If budget exceeded, defer to next frame
+//! - Telemetry: rolling per-frame stats and long-task reports
+//! - Futures: `spawn` polls futures as tasks, wakers re-enqueue them
+//! - Clock & FrameDriver: performance.now()/RAF in the browser, a virtual clock and
+//!   manually stepped frames in native tests
 //!
 //! **ACID TEST COMPLIANCE:**
 //! - No allocations in hot loop
 //! - Use Performance API for nanosecond timing
 //! - Event queue uses ring buffer (from dx-core)
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
 use std::rc::Rc;
 use wasm_bindgen::JsCast;
 use wasm_bindgen::prelude::*;
 use web_sys::{Performance, window};
 
+mod executor;
+mod telemetry;
+pub use executor::{FutureHandle, spawn, yield_if_needed, yield_now};
+pub use telemetry::{
+    DEFAULT_LONG_TASK_THRESHOLD_MS, FRAME_STATS_CAPACITY, FrameStats, LONG_TASK_CAPACITY, LongTask,
+    Telemetry,
+};
+
 // ============================================================================
 // FRAME BUDGET CONFIGURATION
 // ============================================================================
 
-/// Maximum WASM execution time per frame (in milliseconds)
+/// Initial WASM execution time per frame (in milliseconds)
 /// Target: 60 FPS = 16.67ms per frame
 /// Budget: 4ms for WASM (leaving 12ms for layout, paint, composite)
 pub const FRAME_BUDGET_MS: f64 = 4.0;
 
-/// If we're within this threshold of budget, start yielding
-pub const YIELD_THRESHOLD_MS: f64 = 3.5;
+/// Start yielding once this fraction of the budget is used (3.5ms of 4ms)
+pub const YIELD_FRACTION: f64 = 0.875;
+
+/// Idle tasks are not started once this fraction of the budget is used (2ms of 4ms)
+pub const IDLE_CUTOFF_FRACTION: f64 = 0.5;
+
+/// Nominal frame interval (60 FPS), the refresh estimate before any frame is measured
+pub const FRAME_INTERVAL_MS: f64 = 16.67;
+
+/// Default maximum age (in frames) of an Idle task before it is forced to run
+/// 30 frames = 0.5s at 60 FPS
+pub const DEFAULT_IDLE_MAX_AGE_FRAMES: u32 = 30;
 
 // ============================================================================
-// PERFORMANCE TIMER
+// ADAPTIVE FRAME BUDGET
 // ============================================================================
 
-pub struct FrameTimer {
+/// Scheduler tuning knobs (set from the runtime config via `configure_scheduler`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SchedulerConfig {
+    /// Lower bound of the WASM budget (ms)
+    pub min_budget_ms: f64,
+    /// Upper bound of the WASM budget (ms)
+    pub max_budget_ms: f64,
+    /// Share of the refresh interval WASM may use (0.25 = 4.17ms at 60Hz)
+    pub budget_fraction: f64,
+    /// Maximum age (in frames) of an Idle task before it is forced to run
+    pub idle_max_age_frames: u32,
+}
+
+impl SchedulerConfig {
+    /// Clamp nonsensical values instead of failing (config comes from JS)
+    fn sanitized(self) -> Self {
+        let min_budget_ms = if self.min_budget_ms.is_finite() {
+            self.min_budget_ms.max(0.1)
+        } else {
+            0.1
+        };
+        let max_budget_ms = if self.max_budget_ms.is_finite() {
+            self.max_budget_ms.max(min_budget_ms)
+        } else {
+            min_budget_ms
+        };
+        let budget_fraction = if self.budget_fraction.is_finite() {
+            self.budget_fraction.clamp(0.05, 1.0)
+        } else {
+            0.25
+        };
+
+        Self {
+            min_budget_ms,
+            max_budget_ms,
+            budget_fraction,
+            idle_max_age_frames: self.idle_max_age_frames,
+        }
+    }
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            min_budget_ms: 1.0,
+            max_budget_ms: 8.0,
+            budget_fraction: 0.25,
+            idle_max_age_frames: DEFAULT_IDLE_MAX_AGE_FRAMES,
+        }
+    }
+}
+
+/// Frame budget that follows measured frame times
+///
+/// - Refresh rate: moving average of on-time frame intervals. A much shorter
+///   interval (faster display) is adopted at once; a run of long intervals
+///   (slower display) replaces the estimate.
+/// - Dropped frame (interval > 1.5x refresh): layout/paint didn't fit, the
+///   budget shrinks by 25%.
+/// - On-time frame: the budget grows back by 5% of its ceiling.
+/// - Ceiling: `budget_fraction` of the refresh interval, lowered further when
+///   the host reports its layout/paint cost.
+#[derive(Debug, Clone)]
+pub struct FrameBudget {
+    config: SchedulerConfig,
+    refresh_interval: f64,
+    budget: f64,
+    /// Moving average of reported layout/paint cost (ms)
+    render_cost: Option<f64>,
+    /// Consecutive intervals classified as dropped frames
+    slow_streak: u32,
+}
+
+impl FrameBudget {
+    /// Weight of a new sample in the moving averages
+    const SMOOTHING: f64 = 0.1;
+    /// An interval this many times the refresh interval is a dropped frame
+    const DROPPED_RATIO: f64 = 1.5;
+    /// This many dropped frames in a row means the display got slower
+    const SLOW_STREAK_FRAMES: u32 = 8;
+    /// Intervals longer than this (ms) are pauses (background tab), not frames
+    const MAX_INTERVAL_MS: f64 = 1000.0;
+    /// Headroom kept between WASM + layout/paint and the next vsync (ms)
+    const SAFETY_MARGIN_MS: f64 = 1.0;
+
+    pub fn new(config: SchedulerConfig) -> Self {
+        let mut budget = Self {
+            config: config.sanitized(),
+            refresh_interval: FRAME_INTERVAL_MS,
+            budget: FRAME_BUDGET_MS,
+            render_cost: None,
+            slow_streak: 0,
+        };
+        budget.budget = budget.budget.clamp(budget.config.min_budget_ms, budget.ceiling());
+        budget
+    }
+
+    /// Replace the tuning knobs, keeping the measurements
+    pub fn set_config(&mut self, config: SchedulerConfig) {
+        self.config = config.sanitized();
+        self.budget = self.budget.clamp(self.config.min_budget_ms, self.ceiling());
+    }
+
+    pub fn config(&self) -> &SchedulerConfig {
+        &self.config
+    }
+
+    /// Feed the measured interval between two consecutive frame starts (ms)
+    pub fn record_interval(&mut self, interval_ms: f64) {
+        if !(interval_ms > 0.0 && interval_ms <= Self::MAX_INTERVAL_MS) {
+            return;
+        }
+
+        if interval_ms > self.refresh_interval * Self::DROPPED_RATIO {
+            self.slow_streak += 1;
+            if self.slow_streak >= Self::SLOW_STREAK_FRAMES {
+                // Not dropped frames, just a slower display
+                self.refresh_interval = interval_ms;
+                self.slow_streak = 0;
+            } else {
+                self.budget = (self.budget * 0.75).max(self.config.min_budget_ms);
+            }
+        } else {
+            self.slow_streak = 0;
+            if interval_ms < self.refresh_interval * 0.75 {
+                // Faster display
+                self.refresh_interval = interval_ms;
+            } else {
+                self.refresh_interval += (interval_ms - self.refresh_interval) * Self::SMOOTHING;
+            }
+            self.budget += self.ceiling() * 0.05;
+        }
+
+        self.budget = self.budget.clamp(self.config.min_budget_ms, self.ceiling());
+    }
+
+    /// Feed the host's layout/paint cost for the last frame (ms)
+    pub fn record_render_cost(&mut self, cost_ms: f64) {
+        if !(0.0..=Self::MAX_INTERVAL_MS).contains(&cost_ms) {
+            return;
+        }
+
+        self.render_cost = Some(match self.render_cost {
+            Some(avg) => avg + (cost_ms - avg) * Self::SMOOTHING,
+            None => cost_ms,
+        });
+        self.budget = self.budget.clamp(self.config.min_budget_ms, self.ceiling());
+    }
+
+    /// Highest budget allowed at the current refresh rate and render cost
+    fn ceiling(&self) -> f64 {
+        let mut ceiling = self.refresh_interval * self.config.budget_fraction;
+        if let Some(cost) = self.render_cost {
+            ceiling = ceiling.min(self.refresh_interval - cost - Self::SAFETY_MARGIN_MS);
+        }
+        ceiling.clamp(self.config.min_budget_ms, self.config.max_budget_ms)
+    }
+
+    /// Chosen WASM budget for the next frame (ms)
+    pub fn budget_ms(&self) -> f64 {
+        self.budget
+    }
+
+    /// Detected refresh interval (ms)
+    pub fn refresh_interval_ms(&self) -> f64 {
+        self.refresh_interval
+    }
+
+    /// Elapsed time after which the scheduler yields (ms)
+    pub fn yield_threshold_ms(&self) -> f64 {
+        self.budget * YIELD_FRACTION
+    }
+
+    /// Elapsed time after which Idle tasks are no longer started (ms)
+    pub fn idle_cutoff_ms(&self) -> f64 {
+        self.budget * IDLE_CUTOFF_FRACTION
+    }
+}
+
+impl Default for FrameBudget {
+    fn default() -> Self {
+        Self::new(SchedulerConfig::default())
+    }
+}
+
+// ============================================================================
+// CLOCKS
+// ============================================================================
+
+/// Source of monotonic time (in ms)
+pub trait Clock {
+    fn now(&self) -> f64;
+}
+
+/// Browser clock backed by `performance.now()`
+pub struct PerformanceClock {
     performance: Performance,
-    frame_start: f64,
 }
 
-impl FrameTimer {
+impl PerformanceClock {
     pub fn new() -> Self {
         let window = window().expect("no window");
         let performance = window.performance().expect("no performance API");
 
+        Self { performance }
+    }
+}
+
+impl Default for PerformanceClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for PerformanceClock {
+    fn now(&self) -> f64 {
+        self.performance.now()
+    }
+}
+
+/// Manually driven clock for deterministic runs
+///
+/// Clones share the same time, so a test (or a task simulating its own cost)
+/// can keep a handle and advance the clock the scheduler reads.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    now: Rc<Cell<f64>>,
+}
+
+impl VirtualClock {
+    /// Create a clock starting at 0ms
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move time forward by `ms`
+    pub fn advance(&self, ms: f64) {
+        self.now.set(self.now.get() + ms);
+    }
+
+    /// Jump to an absolute time (ms)
+    pub fn set(&self, ms: f64) {
+        self.now.set(ms);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> f64 {
+        self.now.get()
+    }
+}
+
+// ============================================================================
+// PERFORMANCE TIMER
+// ============================================================================
+
+pub struct FrameTimer {
+    clock: Box<dyn Clock>,
+    frame_start: Option<f64>,
+    budget: FrameBudget,
+}
+
+impl FrameTimer {
+    /// Timer on the browser's `performance.now()`
+    pub fn new() -> Self {
+        Self::with_clock(PerformanceClock::new())
+    }
+
+    /// Timer on any clock (e.g. `VirtualClock` in tests)
+    pub fn with_clock(clock: impl Clock + 'static) -> Self {
         Self {
-            performance,
-            frame_start: 0.0,
+            clock: Box::new(clock),
+            frame_start: None,
+            budget: FrameBudget::default(),
         }
     }
 
-    /// Mark the start of a frame
+    /// Current time (in ms)
+    pub fn now(&self) -> f64 {
+        self.clock.now()
+    }
+
+    /// Mark the start of a frame (and measure the interval since the last one)
     pub fn start_frame(&mut self) {
-        self.frame_start = self.performance.now();
+        let now = self.clock.now();
+        if let Some(previous) = self.frame_start {
+            self.budget.record_interval(now - previous);
+        }
+        self.frame_start = Some(now);
+    }
+
+    /// Timestamp of the current frame start (in ms)
+    pub fn frame_start(&self) -> f64 {
+        self.frame_start.unwrap_or(0.0)
     }
 
     /// Get elapsed time since frame start (in ms)
     pub fn elapsed(&self) -> f64 {
-        self.performance.now() - self.frame_start
+        self.clock.now() - self.frame_start()
     }
 
     /// Check if we've exceeded the frame budget
     pub fn should_yield(&self) -> bool {
-        self.elapsed() > YIELD_THRESHOLD_MS
+        self.elapsed() > self.budget.yield_threshold_ms()
     }
 
     /// Get remaining budget (for logging)
     pub fn remaining_budget(&self) -> f64 {
-        FRAME_BUDGET_MS - self.elapsed()
+        self.budget.budget_ms() - self.elapsed()
+    }
+
+    /// Adaptive budget driving this timer
+    pub fn budget(&self) -> &FrameBudget {
+        &self.budget
+    }
+
+    pub fn budget_mut(&mut self) -> &mut FrameBudget {
+        &mut self.budget
     }
 }
 
@@ -88,122 +398,592 @@ pub enum TaskPriority {
     Idle = 2,
 }
 
+impl TaskPriority {
+    /// Number of priority levels
+    pub const COUNT: usize = 3;
+
+    #[inline]
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Self::Immediate),
+            1 => Some(Self::Normal),
+            2 => Some(Self::Idle),
+            _ => None,
+        }
+    }
+}
+
 pub type TaskCallback = Box<dyn FnOnce()>;
 
+/// A dequeued task, ready to run
 pub struct Task {
     priority: TaskPriority,
     callback: TaskCallback,
 }
 
+impl Task {
+    pub fn priority(&self) -> TaskPriority {
+        self.priority
+    }
+
+    /// Run the task, consuming it
+    pub fn run(self) {
+        (self.callback)();
+    }
+}
+
+/// Handle to a scheduled task
+///
+/// Handles are plain ids (never 0), so they can cross the JS boundary as numbers.
+/// Operations on a task that already ran or was cancelled are no-ops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskHandle(u32);
+
+impl TaskHandle {
+    /// Numeric id (for JS)
+    pub fn id(self) -> u32 {
+        self.0
+    }
+
+    /// Rebuild a handle from a numeric id
+    pub fn from_id(id: u32) -> Self {
+        Self(id)
+    }
+
+    /// Cancel the task on the global scheduler
+    ///
+    /// Returns false if the task already ran or was cancelled.
+    pub fn cancel(self) -> bool {
+        with_scheduler(|scheduler| scheduler.cancel(self))
+    }
+
+    /// Move the task to another priority on the global scheduler
+    ///
+    /// Returns false if the task already ran or was cancelled.
+    pub fn reprioritize(self, priority: TaskPriority) -> bool {
+        with_scheduler(|scheduler| scheduler.reprioritize(self, priority))
+    }
+}
+
+/// Pending task, owned by the queue until it runs or is cancelled
+struct TaskEntry {
+    priority: TaskPriority,
+    callback: TaskCallback,
+    /// Queue frame at which the task was scheduled (for aging)
+    enqueued_frame: u64,
+    /// Bumped on every reprioritize; ring entries with an older ticket are stale
+    ticket: u32,
+}
+
+/// Ring entry: (task id, ticket)
+type RingEntry = (u32, u32);
+
+/// Per-priority FIFO ring queues
+///
+/// - `schedule`: O(1) push to the back of the priority's ring
+/// - Dequeue: O(1) amortized pop from the front of the highest non-empty ring
+/// - `cancel` / `reprioritize`: O(1), stale ring entries are skipped on dequeue
+/// - Deadlines: O(log n) min-heap, checked once per frame
+/// - Aging: an Idle task waiting `idle_max_age` frames runs at the start of
+///   the next frame, even under constant Immediate/Normal load (one per
+///   frame, so a backlog of aged tasks can't blow the budget)
 pub struct TaskQueue {
-    /// Tasks sorted by priority (Vec used as heap)
-    tasks: Vec<Task>,
+    /// One ring per priority, indexed by `TaskPriority as usize`
+    queues: [VecDeque<RingEntry>; TaskPriority::COUNT],
+    /// Live tasks by id
+    tasks: HashMap<u32, TaskEntry>,
+    /// Live task count per priority
+    counts: [usize; TaskPriority::COUNT],
+    /// Deadlines as (deadline in µs, task id), earliest first
+    deadlines: BinaryHeap<Reverse<(u64, u32)>>,
+    /// Next task id (0 is never handed out)
+    next_id: u32,
+    /// Number of frames started so far
+    frame: u64,
+    /// Maximum age of an Idle task (in frames) before it is forced to run
+    idle_max_age: u32,
+    /// An overdue Idle task was already forced in the current frame
+    aged_this_frame: bool,
 }
 
 impl TaskQueue {
     pub fn new() -> Self {
+        Self::with_idle_max_age(DEFAULT_IDLE_MAX_AGE_FRAMES)
+    }
+
+    /// Create a queue with a custom Idle aging limit (minimum 1 frame)
+    pub fn with_idle_max_age(frames: u32) -> Self {
         Self {
-            tasks: Vec::with_capacity(64),
+            queues: [
+                VecDeque::with_capacity(64),
+                VecDeque::with_capacity(64),
+                VecDeque::with_capacity(16),
+            ],
+            tasks: HashMap::with_capacity(128),
+            counts: [0; TaskPriority::COUNT],
+            deadlines: BinaryHeap::new(),
+            next_id: 1,
+            frame: 0,
+            idle_max_age: frames.max(1),
+            aged_this_frame: false,
         }
     }
 
+    /// Change the Idle aging limit (minimum 1 frame)
+    pub fn set_idle_max_age(&mut self, frames: u32) {
+        self.idle_max_age = frames.max(1);
+    }
+
+    /// Get the Idle aging limit (in frames)
+    pub fn idle_max_age(&self) -> u32 {
+        self.idle_max_age
+    }
+
     /// Schedule a task with given priority
-    pub fn schedule(&mut self, priority: TaskPriority, callback: TaskCallback) {
-        self.tasks.push(Task { priority, callback });
-        // Keep sorted by priority (Immediate first)
-        self.tasks.sort_by_key(|t| t.priority);
+    pub fn schedule(&mut self, priority: TaskPriority, callback: TaskCallback) -> TaskHandle {
+        let id = self.allocate_id();
+
+        self.tasks.insert(
+            id,
+            TaskEntry {
+                priority,
+                callback,
+                enqueued_frame: self.frame,
+                ticket: 0,
+            },
+        );
+        self.counts[priority as usize] += 1;
+        self.queues[priority as usize].push_back((id, 0));
+
+        TaskHandle(id)
+    }
+
+    /// Schedule a task that is promoted to Immediate if it would otherwise
+    /// miss `deadline` (ms, on the frame timer's clock)
+    pub fn schedule_with_deadline(
+        &mut self,
+        priority: TaskPriority,
+        deadline: f64,
+        callback: TaskCallback,
+    ) -> TaskHandle {
+        let handle = self.schedule(priority, callback);
+        self.deadlines.push(Reverse((to_micros(deadline), handle.0)));
+        handle
+    }
+
+    /// Cancel a pending task
+    ///
+    /// Returns false if the task already ran or was cancelled.
+    pub fn cancel(&mut self, handle: TaskHandle) -> bool {
+        match self.tasks.remove(&handle.0) {
+            Some(entry) => {
+                self.counts[entry.priority as usize] -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Move a pending task to another priority (it goes to the back of that ring)
+    ///
+    /// Returns false if the task already ran or was cancelled.
+    pub fn reprioritize(&mut self, handle: TaskHandle, priority: TaskPriority) -> bool {
+        let Some(entry) = self.tasks.get_mut(&handle.0) else {
+            return false;
+        };
+
+        if entry.priority != priority {
+            self.counts[entry.priority as usize] -= 1;
+            self.counts[priority as usize] += 1;
+            entry.priority = priority;
+            entry.ticket = entry.ticket.wrapping_add(1);
+            self.queues[priority as usize].push_back((handle.0, entry.ticket));
+        }
+
+        true
+    }
+
+    /// Check if a task is still pending
+    pub fn is_pending(&self, handle: TaskHandle) -> bool {
+        self.tasks.contains_key(&handle.0)
+    }
+
+    /// Start a new frame at `now` (ms), the next one expected `frame_interval` ms later
+    ///
+    /// Promotes every task whose deadline falls before the next frame.
+    pub fn begin_frame(&mut self, now: f64, frame_interval: f64) {
+        self.frame += 1;
+        self.aged_this_frame = false;
+
+        let horizon = to_micros(now + frame_interval);
+        while let Some(&Reverse((deadline, id))) = self.deadlines.peek() {
+            if deadline > horizon {
+                break;
+            }
+            self.deadlines.pop();
+            self.reprioritize(TaskHandle(id), TaskPriority::Immediate);
+        }
+    }
+
+    /// Dequeue the next task to run in the current frame
+    ///
+    /// Returns None once the budget is spent or nothing is runnable.
+    pub fn next_task(&mut self, timer: &FrameTimer) -> Option<Task> {
+        if timer.should_yield() {
+            return None;
+        }
+
+        // One starved Idle task per frame runs ahead of everything else
+        if !self.aged_this_frame
+            && let Some(task) = self.pop_overdue_idle()
+        {
+            self.aged_this_frame = true;
+            return Some(task);
+        }
+
+        // Don't start Idle work once we're running low
+        self.pop_next(timer.elapsed() <= timer.budget().idle_cutoff_ms())
     }
 
     /// Execute tasks until budget is exhausted
     pub fn drain_until_budget(&mut self, timer: &FrameTimer) -> usize {
+        self.begin_frame(timer.frame_start(), timer.budget().refresh_interval_ms());
+
         let mut executed = 0;
+        while let Some(task) = self.next_task(timer) {
+            task.run();
+            executed += 1;
+        }
 
-        while !self.tasks.is_empty() {
-            if timer.should_yield() {
-                break;
+        executed
+    }
+
+    fn allocate_id(&mut self) -> u32 {
+        loop {
+            let id = self.next_id;
+            self.next_id = self.next_id.wrapping_add(1).max(1);
+            if !self.tasks.contains_key(&id) {
+                return id;
             }
+        }
+    }
 
-            if let Some(task) = self.tasks.first() {
-                // If next task is Idle priority and we're running low, skip it
-                if task.priority == TaskPriority::Idle && timer.elapsed() > 2.0 {
-                    break;
+    /// Drop stale entries from the front of a ring and peek the first live task
+    fn front_live(&mut self, level: usize) -> Option<&TaskEntry> {
+        while let Some(&(id, ticket)) = self.queues[level].front() {
+            match self.tasks.get(&id) {
+                Some(entry) if entry.ticket == ticket => break,
+                _ => {
+                    self.queues[level].pop_front();
                 }
             }
+        }
 
-            if let Some(task) = self.tasks.drain(0..1).next() {
-                (task.callback)();
-                executed += 1;
-            }
+        let &(id, _) = self.queues[level].front()?;
+        self.tasks.get(&id)
+    }
+
+    /// Take the (live) task at the front of a ring
+    fn pop_level(&mut self, level: usize) -> Option<Task> {
+        self.front_live(level)?;
+        let (id, _) = self.queues[level].pop_front()?;
+        let entry = self.tasks.remove(&id)?;
+        self.counts[level] -= 1;
+
+        Some(Task {
+            priority: entry.priority,
+            callback: entry.callback,
+        })
+    }
+
+    /// Pop the oldest Idle task if it has waited `idle_max_age` frames
+    fn pop_overdue_idle(&mut self) -> Option<Task> {
+        let level = TaskPriority::Idle as usize;
+        let age = self.frame - self.front_live(level)?.enqueued_frame;
+
+        if age >= self.idle_max_age as u64 {
+            self.pop_level(level)
+        } else {
+            None
         }
+    }
 
-        executed
+    /// Pop the next task in priority order (FIFO within a priority)
+    fn pop_next(&mut self, allow_idle: bool) -> Option<Task> {
+        let levels = if allow_idle {
+            TaskPriority::COUNT
+        } else {
+            TaskPriority::COUNT - 1
+        };
+        (0..levels).find_map(|level| self.pop_level(level))
     }
 
     /// Clear all tasks
     pub fn clear(&mut self) {
+        self.queues.iter_mut().for_each(VecDeque::clear);
         self.tasks.clear();
+        self.counts = [0; TaskPriority::COUNT];
+        self.deadlines.clear();
     }
 
     /// Get number of pending tasks
     pub fn len(&self) -> usize {
         self.tasks.len()
     }
+
+    /// Check if no tasks are pending
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Get number of pending tasks at a priority
+    pub fn len_at(&self, priority: TaskPriority) -> usize {
+        self.counts[priority as usize]
+    }
+}
+
+impl Default for TaskQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Convert a millisecond timestamp to whole microseconds (for heap ordering)
+fn to_micros(ms: f64) -> u64 {
+    (ms.max(0.0) * 1000.0) as u64
 }
 
 // ============================================================================
 // SCHEDULER (Main Loop Controller)
 // ============================================================================
 
+/// What happened in one frame
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FrameReport {
+    /// Frame number (1-based)
+    pub frame: u64,
+    /// Tasks run in this frame
+    pub executed: usize,
+    /// Tasks left pending for later frames
+    pub deferred: usize,
+    /// The frame ended because the budget ran out
+    pub yielded: bool,
+    /// Time spent in the frame (ms)
+    pub elapsed_ms: f64,
+}
+
 pub struct Scheduler {
     timer: FrameTimer,
     task_queue: TaskQueue,
     frame_count: u64,
+    /// Tasks executed in the current frame, per priority
+    frame_tasks: [u16; TaskPriority::COUNT],
+    /// The current frame ran out of budget
+    frame_yielded: bool,
+    /// Task handed out by `next_task` and its start time (ms)
+    running_task: Option<(TaskPriority, f64)>,
+    last_frame: FrameReport,
+    telemetry: Telemetry,
     is_running: bool,
 }
 
 impl Scheduler {
     pub fn new() -> Self {
+        Self::with_timer(FrameTimer::new())
+    }
+
+    /// Scheduler on any clock (e.g. `VirtualClock` in tests)
+    pub fn with_clock(clock: impl Clock + 'static) -> Self {
+        Self::with_timer(FrameTimer::with_clock(clock))
+    }
+
+    fn with_timer(timer: FrameTimer) -> Self {
         Self {
-            timer: FrameTimer::new(),
+            timer,
             task_queue: TaskQueue::new(),
             frame_count: 0,
+            frame_tasks: [0; TaskPriority::COUNT],
+            frame_yielded: false,
+            running_task: None,
+            last_frame: FrameReport::default(),
+            telemetry: Telemetry::new(),
             is_running: false,
         }
     }
 
     /// Schedule a task
-    pub fn schedule(&mut self, priority: TaskPriority, callback: TaskCallback) {
-        self.task_queue.schedule(priority, callback);
+    pub fn schedule(&mut self, priority: TaskPriority, callback: TaskCallback) -> TaskHandle {
+        self.task_queue.schedule(priority, callback)
+    }
+
+    /// Schedule a task that must start within `timeout_ms` from now
+    ///
+    /// The task runs at `priority` but is promoted to Immediate in the last
+    /// frame before its deadline.
+    pub fn schedule_with_deadline(
+        &mut self,
+        priority: TaskPriority,
+        timeout_ms: f64,
+        callback: TaskCallback,
+    ) -> TaskHandle {
+        let deadline = self.timer.now() + timeout_ms;
+        self.task_queue.schedule_with_deadline(priority, deadline, callback)
+    }
+
+    /// Cancel a pending task
+    pub fn cancel(&mut self, handle: TaskHandle) -> bool {
+        self.task_queue.cancel(handle)
+    }
+
+    /// Move a pending task to another priority
+    pub fn reprioritize(&mut self, handle: TaskHandle, priority: TaskPriority) -> bool {
+        self.task_queue.reprioritize(handle, priority)
+    }
+
+    /// Check if a task is still pending
+    pub fn is_pending(&self, handle: TaskHandle) -> bool {
+        self.task_queue.is_pending(handle)
+    }
+
+    /// Check if the current frame's budget is spent
+    pub fn should_yield(&self) -> bool {
+        self.timer.should_yield()
     }
 
     /// Process one frame
-    pub fn tick(&mut self) {
+    pub fn tick(&mut self) -> FrameReport {
+        self.begin_frame();
+        while let Some(task) = self.next_task() {
+            task.run();
+        }
+        self.end_frame()
+    }
+
+    /// Start a frame (first step of `tick`)
+    pub fn begin_frame(&mut self) {
         self.timer.start_frame();
         self.frame_count += 1;
+        self.frame_tasks = [0; TaskPriority::COUNT];
+        self.frame_yielded = false;
+        let interval = self.timer.budget().refresh_interval_ms();
+        self.task_queue.begin_frame(self.timer.frame_start(), interval);
+    }
+
+    /// Dequeue the next task for the current frame (None = yield)
+    ///
+    /// The caller runs the task. This lets the RAF loop release the
+    /// scheduler borrow while a task runs, so tasks can schedule, cancel
+    /// or reprioritize other tasks.
+    pub fn next_task(&mut self) -> Option<Task> {
+        self.finish_running_task();
 
-        // Execute queued tasks
-        let executed = self.task_queue.drain_until_budget(&self.timer);
+        let Some(task) = self.task_queue.next_task(&self.timer) else {
+            self.frame_yielded = !self.task_queue.is_empty() && self.timer.should_yield();
+            return None;
+        };
 
+        let count = &mut self.frame_tasks[task.priority as usize];
+        *count = count.saturating_add(1);
+        self.running_task = Some((task.priority, self.timer.now()));
+        Some(task)
+    }
+
+    /// Time the task handed out last, now that the caller is done with it
+    fn finish_running_task(&mut self) {
+        let Some((priority, started)) = self.running_task.take() else {
+            return;
+        };
+
+        let duration = self.timer.now() - started;
+        if self.telemetry.record_task(self.frame_count, priority, duration) {
+            #[cfg(target_arch = "wasm32")]
+            web_sys::console::warn_1(
+                &format!(
+                    "dx-sched: long {:?} task, {:.1}ms in frame {}",
+                    priority, duration, self.frame_count
+                )
+                .into(),
+            );
+        }
+    }
+
+    /// Finish a frame (last step of `tick`)
+    pub fn end_frame(&mut self) -> FrameReport {
         // Flush pending DOM operations
         #[cfg(target_arch = "wasm32")]
         {
             dx_dom::flush_queue();
         }
 
+        self.finish_running_task();
+
+        let report = FrameReport {
+            frame: self.frame_count,
+            executed: self.frame_tasks.iter().map(|&n| n as usize).sum(),
+            deferred: self.task_queue.len(),
+            yielded: self.frame_yielded,
+            elapsed_ms: self.timer.elapsed(),
+        };
+        self.last_frame = report;
+        self.telemetry.record_frame(FrameStats {
+            frame: report.frame,
+            wasm_ms: report.elapsed_ms,
+            tasks: self.frame_tasks,
+            deferred: report.deferred,
+            overrun: report.elapsed_ms > self.timer.budget().budget_ms(),
+        });
+
         // Log performance stats (every 60 frames = 1 second at 60fps)
+        #[cfg(target_arch = "wasm32")]
         if self.frame_count % 60 == 0 {
-            let elapsed = self.timer.elapsed();
-            let remaining = self.timer.remaining_budget();
-
-            #[cfg(target_arch = "wasm32")]
             web_sys::console::log_1(
                 &format!(
                     "Frame {}: {}ms used, {}ms budget remaining, {} tasks executed",
-                    self.frame_count, elapsed, remaining, executed
+                    report.frame,
+                    report.elapsed_ms,
+                    self.timer.remaining_budget(),
+                    report.executed
                 )
                 .into(),
             );
         }
+
+        report
+    }
+
+    /// Report of the last finished frame
+    pub fn last_frame(&self) -> FrameReport {
+        self.last_frame
+    }
+
+    /// Recorded frame stats and long tasks
+    pub fn telemetry(&self) -> &Telemetry {
+        &self.telemetry
+    }
+
+    pub fn telemetry_mut(&mut self) -> &mut Telemetry {
+        &mut self.telemetry
+    }
+
+    /// Apply new tuning knobs
+    pub fn configure(&mut self, config: SchedulerConfig) {
+        self.timer.budget_mut().set_config(config);
+        self.task_queue.set_idle_max_age(config.idle_max_age_frames);
+    }
+
+    /// Chosen WASM budget for the next frame (ms)
+    pub fn budget_ms(&self) -> f64 {
+        self.timer.budget().budget_ms()
+    }
+
+    /// Detected refresh interval (ms)
+    pub fn refresh_interval_ms(&self) -> f64 {
+        self.timer.budget().refresh_interval_ms()
+    }
+
+    /// Feed the host's layout/paint cost for the last frame (ms)
+    pub fn report_render_cost(&mut self, cost_ms: f64) {
+        self.timer.budget_mut().record_render_cost(cost_ms);
     }
 
     /// Check if scheduler is running
@@ -222,22 +1002,118 @@ impl Scheduler {
 // ============================================================================
 
 thread_local! {
-    static SCHEDULER: RefCell<Scheduler> = RefCell::new(Scheduler::new());
+    static SCHEDULER: RefCell<Option<Scheduler>> = const { RefCell::new(None) };
 }
 
+/// Access the global scheduler (created on the browser clock on first use)
 pub fn with_scheduler<F, R>(f: F) -> R
 where
     F: FnOnce(&mut Scheduler) -> R,
 {
-    SCHEDULER.with(|sched| f(&mut sched.borrow_mut()))
+    SCHEDULER.with(|sched| f(sched.borrow_mut().get_or_insert_with(Scheduler::new)))
+}
+
+/// Access the global scheduler unless it is already borrowed
+pub(crate) fn try_with_scheduler<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut Scheduler) -> R,
+{
+    SCHEDULER.with(|sched| {
+        let mut sched = sched.try_borrow_mut().ok()?;
+        Some(f(sched.get_or_insert_with(Scheduler::new)))
+    })
+}
+
+/// Replace the global scheduler (e.g. with one on a `VirtualClock`)
+pub fn install_scheduler(scheduler: Scheduler) {
+    SCHEDULER.with(|sched| *sched.borrow_mut() = Some(scheduler));
+}
+
+/// Run one frame on the global scheduler
+///
+/// Tasks run outside the scheduler borrow, so they may call back into it.
+fn run_frame() {
+    executor::drain_wakes();
+    with_scheduler(Scheduler::begin_frame);
+    while let Some(task) = with_scheduler(Scheduler::next_task) {
+        task.run();
+    }
+    with_scheduler(Scheduler::end_frame);
 }
 
 // ============================================================================
-// RAF LOOP (WASM Entry Point)
+// FRAME DRIVERS
 // ============================================================================
 
-#[wasm_bindgen]
-pub fn start_scheduler() {
+/// Callback run once on a frame
+pub type FrameCallback = Box<dyn FnOnce()>;
+
+/// Source of frame callbacks
+pub trait FrameDriver {
+    /// Call `frame` once, on the next frame
+    fn request_frame(&self, frame: FrameCallback);
+}
+
+/// Browser driver: one `requestAnimationFrame` per frame
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RafDriver;
+
+impl FrameDriver for RafDriver {
+    fn request_frame(&self, frame: FrameCallback) {
+        let window = window().expect("no window");
+        let callback = Closure::once_into_js(frame);
+
+        window
+            .request_animation_frame(callback.unchecked_ref())
+            .expect("failed to request animation frame");
+    }
+}
+
+/// Manually stepped driver for deterministic runs
+///
+/// Clones share the same frame queue.
+#[derive(Clone, Default)]
+pub struct ManualDriver {
+    pending: Rc<RefCell<Vec<FrameCallback>>>,
+}
+
+impl ManualDriver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run the frames requested so far, returns how many ran
+    ///
+    /// Frames requested while stepping wait for the next `step`.
+    pub fn step(&self) -> usize {
+        let frames = std::mem::take(&mut *self.pending.borrow_mut());
+        let count = frames.len();
+        for frame in frames {
+            frame();
+        }
+        count
+    }
+
+    /// Number of frames waiting for `step`
+    pub fn pending(&self) -> usize {
+        self.pending.borrow().len()
+    }
+}
+
+impl FrameDriver for ManualDriver {
+    fn request_frame(&self, frame: FrameCallback) {
+        self.pending.borrow_mut().push(frame);
+    }
+}
+
+// ============================================================================
+// FRAME LOOP (WASM Entry Point)
+// ============================================================================
+
+/// Start the global scheduler's frame loop on `driver`
+///
+/// Returns false if the scheduler was already running.
+pub fn start_scheduler_with<D: FrameDriver + Clone + 'static>(driver: D) -> bool {
     let already_running = with_scheduler(|scheduler| {
         if scheduler.is_running() {
             true
@@ -247,17 +1123,21 @@ pub fn start_scheduler() {
         }
     });
 
-    if already_running {
+    if !already_running {
+        request_next_frame(driver);
+    }
+    !already_running
+}
+
+#[wasm_bindgen]
+pub fn start_scheduler() {
+    if start_scheduler_with(RafDriver) {
+        #[cfg(target_arch = "wasm32")]
+        web_sys::console::log_1(&"dx-sched: Starting RAF loop".into());
+    } else {
         #[cfg(target_arch = "wasm32")]
         web_sys::console::warn_1(&"Scheduler already running".into());
-        return;
     }
-
-    #[cfg(target_arch = "wasm32")]
-    web_sys::console::log_1(&"dx-sched: Starting RAF loop".into());
-
-    // Kick off the RAF loop
-    request_next_frame();
 }
 
 #[wasm_bindgen]
@@ -268,70 +1148,143 @@ pub fn stop_scheduler() {
     web_sys::console::log_1(&"dx-sched: Stopping RAF loop".into());
 }
 
-/// Request the next animation frame
-fn request_next_frame() {
-    let window = window().expect("no window");
-
-    // Create closure for RAF callback
-    let closure = Rc::new(RefCell::new(None::<Closure<dyn FnMut()>>));
-    let closure_clone = closure.clone();
-
-    *closure.borrow_mut() = Some(Closure::new(move || {
+/// Request the next frame from the driver
+fn request_next_frame<D: FrameDriver + Clone + 'static>(driver: D) {
+    let next = driver.clone();
+    driver.request_frame(Box::new(move || {
         // Process this frame
-        let should_continue = with_scheduler(|scheduler| {
-            if !scheduler.is_running() {
-                false // Stop loop
-            } else {
-                scheduler.tick();
-                true
-            }
-        });
-
-        if !should_continue {
-            return;
+        if !with_scheduler(|scheduler| scheduler.is_running()) {
+            return; // Stop loop
         }
+        run_frame();
 
         // Schedule next frame
-        request_next_frame();
+        request_next_frame(next);
     }));
-
-    window
-        .request_animation_frame(closure_clone.borrow().as_ref().unwrap().as_ref().unchecked_ref())
-        .expect("failed to request animation frame");
 }
 
 // ============================================================================
 // PUBLIC API (Task Scheduling)
 // ============================================================================
 
-#[wasm_bindgen]
-pub fn schedule_immediate(callback: &js_sys::Function) {
+/// Wrap a JS function as a task callback
+fn js_task(callback: &js_sys::Function) -> TaskCallback {
     let callback_clone = callback.clone();
-    let task = Box::new(move || {
+    Box::new(move || {
         callback_clone.call0(&JsValue::NULL).ok();
-    });
+    })
+}
 
-    with_scheduler(|scheduler| scheduler.schedule(TaskPriority::Immediate, task));
+/// Schedule an Immediate task, returns its handle
+#[wasm_bindgen]
+pub fn schedule_immediate(callback: &js_sys::Function) -> u32 {
+    with_scheduler(|scheduler| scheduler.schedule(TaskPriority::Immediate, js_task(callback))).id()
 }
 
+/// Schedule a Normal task, returns its handle
 #[wasm_bindgen]
-pub fn schedule_normal(callback: &js_sys::Function) {
-    let callback_clone = callback.clone();
-    let task = Box::new(move || {
-        callback_clone.call0(&JsValue::NULL).ok();
-    });
+pub fn schedule_normal(callback: &js_sys::Function) -> u32 {
+    with_scheduler(|scheduler| scheduler.schedule(TaskPriority::Normal, js_task(callback))).id()
+}
+
+/// Schedule an Idle task, returns its handle
+#[wasm_bindgen]
+pub fn schedule_idle(callback: &js_sys::Function) -> u32 {
+    with_scheduler(|scheduler| scheduler.schedule(TaskPriority::Idle, js_task(callback))).id()
+}
 
-    with_scheduler(|scheduler| scheduler.schedule(TaskPriority::Normal, task));
+/// Schedule a task that must start within `timeout_ms`, returns its handle
+///
+/// `priority`: 0 = Immediate, 1 = Normal, 2 = Idle (anything else = Idle)
+#[wasm_bindgen]
+pub fn schedule_with_deadline(callback: &js_sys::Function, priority: u8, timeout_ms: f64) -> u32 {
+    let priority = TaskPriority::from_u8(priority).unwrap_or(TaskPriority::Idle);
+    with_scheduler(|scheduler| {
+        scheduler.schedule_with_deadline(priority, timeout_ms, js_task(callback))
+    })
+    .id()
 }
 
+/// Cancel a scheduled task by handle
+///
+/// Returns false if the task already ran or was cancelled
 #[wasm_bindgen]
-pub fn schedule_idle(callback: &js_sys::Function) {
-    let callback_clone = callback.clone();
-    let task = Box::new(move || {
-        callback_clone.call0(&JsValue::NULL).ok();
-    });
+pub fn cancel_task(handle: u32) -> bool {
+    TaskHandle::from_id(handle).cancel()
+}
 
-    with_scheduler(|scheduler| scheduler.schedule(TaskPriority::Idle, task));
+/// Move a scheduled task to another priority (0 = Immediate, 1 = Normal, 2 = Idle)
+///
+/// Returns false if the task already ran, was cancelled, or the priority is invalid
+#[wasm_bindgen]
+pub fn reprioritize_task(handle: u32, priority: u8) -> bool {
+    TaskPriority::from_u8(priority)
+        .map(|priority| TaskHandle::from_id(handle).reprioritize(priority))
+        .unwrap_or(false)
+}
+
+// ============================================================================
+// PUBLIC API (Frame Budget)
+// ============================================================================
+
+/// Tune the frame budget from the runtime config
+///
+/// Out-of-range values are clamped (min >= 0.1ms, max >= min, fraction in 0.05..=1).
+#[wasm_bindgen]
+pub fn configure_scheduler(
+    min_budget_ms: f64,
+    max_budget_ms: f64,
+    budget_fraction: f64,
+    idle_max_age_frames: u32,
+) {
+    let config = SchedulerConfig {
+        min_budget_ms,
+        max_budget_ms,
+        budget_fraction,
+        idle_max_age_frames,
+    };
+    with_scheduler(|scheduler| scheduler.configure(config));
+}
+
+/// WASM budget (ms) the scheduler will use for the next frame
+#[wasm_bindgen]
+pub fn get_frame_budget() -> f64 {
+    with_scheduler(|scheduler| scheduler.budget_ms())
+}
+
+/// Refresh interval (ms) detected from measured frame times
+#[wasm_bindgen]
+pub fn get_refresh_interval() -> f64 {
+    with_scheduler(|scheduler| scheduler.refresh_interval_ms())
+}
+
+/// Report the layout/paint cost of the last frame (e.g. from a PerformanceObserver)
+#[wasm_bindgen]
+pub fn report_render_cost(cost_ms: f64) {
+    with_scheduler(|scheduler| scheduler.report_render_cost(cost_ms));
+}
+
+// ============================================================================
+// PUBLIC API (Telemetry)
+// ============================================================================
+
+/// Recent frame stats and long tasks in the compact binary format
+/// (see the `telemetry` module docs for the layout)
+#[wasm_bindgen]
+pub fn get_frame_stats() -> Vec<u8> {
+    with_scheduler(|scheduler| scheduler.telemetry().to_bytes())
+}
+
+/// Summary of recent frame stats and long tasks as JSON
+#[wasm_bindgen]
+pub fn get_frame_stats_json() -> String {
+    with_scheduler(|scheduler| scheduler.telemetry().summary_json())
+}
+
+/// Report single tasks longer than `threshold_ms`
+#[wasm_bindgen]
+pub fn set_long_task_threshold(threshold_ms: f64) {
+    with_scheduler(|scheduler| scheduler.telemetry_mut().set_long_task_threshold_ms(threshold_ms));
 }
 
 // ============================================================================
@@ -345,3 +1298,347 @@ pub fn init_dx_sched() {
         web_sys::console::log_1(&"dx-sched: Frame Scheduler Initialized".into());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorder(log: &Rc<RefCell<Vec<&'static str>>>, name: &'static str) -> TaskCallback {
+        let log = log.clone();
+        Box::new(move || log.borrow_mut().push(name))
+    }
+
+    #[test]
+    fn test_priority_then_fifo_order() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut queue = TaskQueue::new();
+
+        queue.schedule(TaskPriority::Idle, recorder(&log, "idle"));
+        queue.schedule(TaskPriority::Normal, recorder(&log, "normal-1"));
+        queue.schedule(TaskPriority::Immediate, recorder(&log, "input-1"));
+        queue.schedule(TaskPriority::Normal, recorder(&log, "normal-2"));
+        queue.schedule(TaskPriority::Immediate, recorder(&log, "input-2"));
+        assert_eq!(queue.len(), 5);
+        assert_eq!(queue.len_at(TaskPriority::Normal), 2);
+
+        while let Some(task) = queue.pop_next(true) {
+            task.run();
+        }
+
+        assert_eq!(*log.borrow(), ["input-1", "input-2", "normal-1", "normal-2", "idle"]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_idle_cutoff_skips_idle_tasks() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut queue = TaskQueue::new();
+
+        queue.schedule(TaskPriority::Idle, recorder(&log, "idle"));
+        assert!(queue.pop_next(false).is_none());
+        assert_eq!(queue.len_at(TaskPriority::Idle), 1);
+    }
+
+    #[test]
+    fn test_idle_aging_under_constant_load() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut queue = TaskQueue::with_idle_max_age(3);
+        queue.schedule(TaskPriority::Idle, recorder(&log, "idle"));
+
+        // Simulate frames where the budget is always consumed by input
+        let mut idle_ran_at = None;
+        for frame in 1..=5 {
+            queue.begin_frame(frame as f64 * FRAME_INTERVAL_MS, FRAME_INTERVAL_MS);
+            queue.schedule(TaskPriority::Immediate, recorder(&log, "input"));
+
+            if let Some(task) = queue.pop_overdue_idle() {
+                task.run();
+                idle_ran_at.get_or_insert(frame);
+            }
+            queue.pop_next(false).unwrap().run();
+        }
+
+        assert_eq!(idle_ran_at, Some(3));
+        assert_eq!(log.borrow().iter().filter(|&&n| n == "idle").count(), 1);
+    }
+
+    #[test]
+    fn test_idle_aging_promotes_one_task_per_frame() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let clock = VirtualClock::new();
+        let mut scheduler = Scheduler::with_clock(clock.clone());
+        scheduler.configure(SchedulerConfig {
+            idle_max_age_frames: 1,
+            ..SchedulerConfig::default()
+        });
+        for _ in 0..20 {
+            scheduler.schedule(TaskPriority::Idle, costly(&log, &clock, "idle", 1.0));
+        }
+
+        // Normal work fills every frame's budget, so only aging runs Idle tasks
+        for frame in 1..=5 {
+            for _ in 0..8 {
+                scheduler.schedule(TaskPriority::Normal, costly(&log, &clock, "normal", 1.0));
+            }
+            log.borrow_mut().clear();
+            clock.set(frame as f64 * FRAME_INTERVAL_MS);
+            let report = scheduler.tick();
+
+            let idle = log.borrow().iter().filter(|&&n| n == "idle").count();
+            assert_eq!(idle, 1, "frame {frame}");
+            assert!(report.yielded);
+        }
+        assert_eq!(scheduler.task_queue.len_at(TaskPriority::Idle), 15);
+    }
+
+    #[test]
+    fn test_cancel_task() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut queue = TaskQueue::new();
+
+        let first = queue.schedule(TaskPriority::Normal, recorder(&log, "first"));
+        let second = queue.schedule(TaskPriority::Normal, recorder(&log, "second"));
+        assert_ne!(first.id(), 0);
+
+        assert!(queue.cancel(first));
+        assert!(!queue.cancel(first));
+        assert!(!queue.is_pending(first));
+        assert_eq!(queue.len_at(TaskPriority::Normal), 1);
+
+        while let Some(task) = queue.pop_next(true) {
+            task.run();
+        }
+        assert_eq!(*log.borrow(), ["second"]);
+        assert!(!queue.cancel(second));
+    }
+
+    #[test]
+    fn test_reprioritize_task() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut queue = TaskQueue::new();
+
+        queue.schedule(TaskPriority::Normal, recorder(&log, "normal"));
+        let idle = queue.schedule(TaskPriority::Idle, recorder(&log, "idle"));
+        let input = queue.schedule(TaskPriority::Immediate, recorder(&log, "input"));
+
+        assert!(queue.reprioritize(idle, TaskPriority::Immediate));
+        assert!(queue.reprioritize(input, TaskPriority::Idle));
+        assert_eq!(queue.len_at(TaskPriority::Immediate), 1);
+        assert_eq!(queue.len_at(TaskPriority::Idle), 1);
+
+        while let Some(task) = queue.pop_next(true) {
+            task.run();
+        }
+        assert_eq!(*log.borrow(), ["idle", "normal", "input"]);
+        assert!(!queue.reprioritize(idle, TaskPriority::Normal));
+    }
+
+    #[test]
+    fn test_deadline_promotes_to_immediate() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut queue = TaskQueue::new();
+
+        queue.schedule(TaskPriority::Normal, recorder(&log, "normal"));
+        let late = queue.schedule_with_deadline(TaskPriority::Idle, 50.0, recorder(&log, "late"));
+
+        // Deadline is more than one frame away: nothing changes
+        queue.begin_frame(0.0, FRAME_INTERVAL_MS);
+        assert_eq!(queue.len_at(TaskPriority::Idle), 1);
+
+        // Next frame would start after the deadline: promote now
+        queue.begin_frame(40.0, FRAME_INTERVAL_MS);
+        assert_eq!(queue.len_at(TaskPriority::Immediate), 1);
+        assert!(queue.is_pending(late));
+
+        while let Some(task) = queue.pop_next(false) {
+            task.run();
+        }
+        assert_eq!(*log.borrow(), ["late", "normal"]);
+    }
+
+    #[test]
+    fn test_deadline_of_cancelled_task_is_ignored() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut queue = TaskQueue::new();
+
+        let task = queue.schedule_with_deadline(TaskPriority::Idle, 10.0, recorder(&log, "x"));
+        assert!(queue.cancel(task));
+
+        queue.begin_frame(0.0, FRAME_INTERVAL_MS);
+        assert!(queue.is_empty());
+        assert!(queue.pop_next(true).is_none());
+    }
+
+    #[test]
+    fn test_budget_follows_refresh_rate() {
+        let mut budget = FrameBudget::default();
+        assert_eq!(budget.budget_ms(), FRAME_BUDGET_MS);
+
+        for _ in 0..100 {
+            budget.record_interval(16.67);
+        }
+        assert!((budget.budget_ms() - 16.67 * 0.25).abs() < 0.01);
+
+        // 120Hz display: estimate snaps down, budget halves
+        for _ in 0..100 {
+            budget.record_interval(8.33);
+        }
+        assert!((budget.refresh_interval_ms() - 8.33).abs() < 0.01);
+        assert!((budget.budget_ms() - 8.33 * 0.25).abs() < 0.01);
+
+        // Back to 60Hz: a run of long intervals is a slower display, not jank
+        for _ in 0..100 {
+            budget.record_interval(16.67);
+        }
+        assert!((budget.refresh_interval_ms() - 16.67).abs() < 0.01);
+        assert!((budget.budget_ms() - 16.67 * 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_budget_shrinks_on_dropped_frames() {
+        let mut budget = FrameBudget::default();
+        let full = budget.budget_ms();
+
+        budget.record_interval(33.3);
+        budget.record_interval(33.3);
+        assert!(budget.budget_ms() < full * 0.6);
+        assert!((budget.refresh_interval_ms() - FRAME_INTERVAL_MS).abs() < 0.01);
+        assert!(budget.yield_threshold_ms() < budget.budget_ms());
+        assert!(budget.idle_cutoff_ms() < budget.yield_threshold_ms());
+
+        // Recovers on on-time frames, and pauses are ignored
+        budget.record_interval(5000.0);
+        for _ in 0..40 {
+            budget.record_interval(16.67);
+        }
+        assert!(budget.budget_ms() > full);
+    }
+
+    #[test]
+    fn test_budget_config_and_render_cost() {
+        let mut budget = FrameBudget::new(SchedulerConfig {
+            min_budget_ms: 2.0,
+            max_budget_ms: 3.0,
+            budget_fraction: 0.5,
+            ..SchedulerConfig::default()
+        });
+        for _ in 0..100 {
+            budget.record_interval(16.67);
+        }
+        assert_eq!(budget.budget_ms(), 3.0);
+
+        // Heavy layout/paint leaves no room: the floor wins
+        budget.record_render_cost(15.0);
+        assert_eq!(budget.budget_ms(), 2.0);
+
+        // Garbage from JS is clamped
+        budget.set_config(SchedulerConfig {
+            min_budget_ms: f64::NAN,
+            max_budget_ms: -1.0,
+            budget_fraction: 7.0,
+            idle_max_age_frames: 1,
+        });
+        assert_eq!(budget.config().budget_fraction, 1.0);
+        assert!(budget.config().max_budget_ms >= budget.config().min_budget_ms);
+        assert!(budget.budget_ms() > 0.0);
+    }
+
+    /// Task that takes `cost_ms` of (virtual) time
+    fn costly(
+        log: &Rc<RefCell<Vec<&'static str>>>,
+        clock: &VirtualClock,
+        name: &'static str,
+        cost_ms: f64,
+    ) -> TaskCallback {
+        let (log, clock) = (log.clone(), clock.clone());
+        Box::new(move || {
+            clock.advance(cost_ms);
+            log.borrow_mut().push(name);
+        })
+    }
+
+    #[test]
+    fn test_virtual_clock_frames_are_deterministic() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let clock = VirtualClock::new();
+        let mut scheduler = Scheduler::with_clock(clock.clone());
+
+        for name in ["n1", "n2", "n3", "n4", "n5"] {
+            scheduler.schedule(TaskPriority::Normal, costly(&log, &clock, name, 1.0));
+        }
+        scheduler.schedule(TaskPriority::Idle, costly(&log, &clock, "idle", 0.5));
+
+        // 4ms budget: yields after the 4th 1ms task, idle is past its cutoff
+        let report = scheduler.tick();
+        assert_eq!(report.frame, 1);
+        assert_eq!(report.executed, 4);
+        assert_eq!(report.deferred, 2);
+        assert!(report.yielded);
+        assert_eq!(report.elapsed_ms, 4.0);
+        assert_eq!(*log.borrow(), ["n1", "n2", "n3", "n4"]);
+
+        clock.set(FRAME_INTERVAL_MS);
+        let report = scheduler.tick();
+        assert_eq!(report.executed, 2);
+        assert_eq!(report.deferred, 0);
+        assert!(!report.yielded);
+        assert_eq!(scheduler.last_frame(), report);
+        assert_eq!(*log.borrow(), ["n1", "n2", "n3", "n4", "n5", "idle"]);
+    }
+
+    #[test]
+    fn test_manual_driver_steps_global_loop() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let clock = VirtualClock::new();
+        let driver = ManualDriver::new();
+        install_scheduler(Scheduler::with_clock(clock.clone()));
+
+        assert!(start_scheduler_with(driver.clone()));
+        assert!(!start_scheduler_with(driver.clone()));
+        assert_eq!(driver.pending(), 1);
+
+        with_scheduler(|s| s.schedule(TaskPriority::Normal, costly(&log, &clock, "a", 3.0)));
+        with_scheduler(|s| s.schedule(TaskPriority::Normal, costly(&log, &clock, "b", 3.0)));
+
+        assert_eq!(driver.step(), 1);
+        assert_eq!(*log.borrow(), ["a", "b"]);
+        assert_eq!(with_scheduler(|s| s.last_frame().executed), 2);
+
+        // A stopped loop runs its pending frame as a no-op and requests no more
+        clock.set(FRAME_INTERVAL_MS);
+        stop_scheduler();
+        assert_eq!(driver.step(), 1);
+        assert_eq!(driver.pending(), 0);
+    }
+
+    #[test]
+    fn test_telemetry_records_frames_and_long_tasks() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let clock = VirtualClock::new();
+        let mut scheduler = Scheduler::with_clock(clock.clone());
+        scheduler.telemetry_mut().set_long_task_threshold_ms(2.0);
+
+        scheduler.schedule(TaskPriority::Immediate, costly(&log, &clock, "input", 0.5));
+        scheduler.schedule(TaskPriority::Normal, costly(&log, &clock, "render", 5.0));
+        scheduler.schedule(TaskPriority::Normal, costly(&log, &clock, "late", 1.0));
+        scheduler.tick();
+
+        let stats = *scheduler.telemetry().frames().last().unwrap();
+        assert_eq!(stats.frame, 1);
+        assert_eq!(stats.tasks, [1, 1, 0]);
+        assert_eq!(stats.executed(), 2);
+        assert_eq!(stats.deferred, 1);
+        assert_eq!(stats.wasm_ms, 5.5);
+        assert!(stats.overrun);
+
+        let long: Vec<_> = scheduler.telemetry().long_tasks().copied().collect();
+        assert_eq!(
+            long,
+            [LongTask {
+                frame: 1,
+                priority: TaskPriority::Normal,
+                duration_ms: 5.0,
+            }]
+        );
+    }
+}
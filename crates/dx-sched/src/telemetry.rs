@@ -0,0 +1,316 @@
+//! Frame telemetry: rolling per-frame stats and long-task reports
+//!
+//! Both rings are preallocated, so recording a frame never allocates.
+//!
+//! **BINARY EXPORT (little-endian):**
+//! ```text
+//! Header (7 bytes):
+//! ┌──────────┬─────────┬─────────────┬─────────────────┐
+//! │ magic:2  │ ver:1   │ frames:2    │ long_tasks:2    │
+//! │ "FS"     │ 1       │ u16         │ u16             │
+//! └──────────┴─────────┴─────────────┴─────────────────┘
+//!
+//! Frame record (18 bytes), oldest first:
+//! ┌──────────┬──────────┬────────────┬──────────────────────┬─────────┬──────┐
+//! │ frame:4  │ wasm:4   │ deferred:2 │ tasks:6              │ flags:1 │ rsv:1│
+//! │ u32      │ u32 (µs) │ u16 (sat.) │ u16 × (Imm,Norm,Idle)│ bit0=OVR│      │
+//! └──────────┴──────────┴────────────┴──────────────────────┴─────────┴──────┘
+//!
+//! Long task record (9 bytes), oldest first:
+//! ┌──────────┬─────────────┬────────────┐
+//! │ frame:4  │ duration:4  │ priority:1 │
+//! │ u32      │ u32 (µs)    │ u8         │
+//! └──────────┴─────────────┴────────────┘
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+use crate::TaskPriority;
+
+/// Frames kept in the stats ring (2s at 60 FPS)
+pub const FRAME_STATS_CAPACITY: usize = 120;
+
+/// Long tasks kept in the report ring
+pub const LONG_TASK_CAPACITY: usize = 32;
+
+/// A single task longer than this (ms) is reported (one full 60 FPS frame)
+pub const DEFAULT_LONG_TASK_THRESHOLD_MS: f64 = 16.0;
+
+/// Stats of one finished frame
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FrameStats {
+    /// Frame number
+    pub frame: u64,
+    /// Time spent running tasks (ms)
+    pub wasm_ms: f64,
+    /// Tasks run, indexed by `TaskPriority`
+    pub tasks: [u16; TaskPriority::COUNT],
+    /// Tasks left pending for later frames
+    pub deferred: usize,
+    /// The frame used more than its budget
+    pub overrun: bool,
+}
+
+impl FrameStats {
+    /// Size of one record in the binary export
+    pub const SIZE: usize = 18;
+
+    /// Total tasks run in the frame
+    pub fn executed(&self) -> usize {
+        self.tasks.iter().map(|&n| n as usize).sum()
+    }
+}
+
+/// A task that ran longer than the long-task threshold
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LongTask {
+    /// Frame the task ran in
+    pub frame: u64,
+    pub priority: TaskPriority,
+    pub duration_ms: f64,
+}
+
+impl LongTask {
+    /// Size of one record in the binary export
+    pub const SIZE: usize = 9;
+}
+
+/// Rolling frame stats and long-task reports
+pub struct Telemetry {
+    frames: VecDeque<FrameStats>,
+    long_tasks: VecDeque<LongTask>,
+    long_task_threshold_ms: f64,
+}
+
+impl Telemetry {
+    /// Magic number of the binary export ("FS")
+    pub const MAGIC: u16 = 0x5346;
+    pub const VERSION: u8 = 1;
+    /// Size of the binary export header
+    pub const HEADER_SIZE: usize = 7;
+
+    pub fn new() -> Self {
+        Self {
+            frames: VecDeque::with_capacity(FRAME_STATS_CAPACITY),
+            long_tasks: VecDeque::with_capacity(LONG_TASK_CAPACITY),
+            long_task_threshold_ms: DEFAULT_LONG_TASK_THRESHOLD_MS,
+        }
+    }
+
+    pub fn long_task_threshold_ms(&self) -> f64 {
+        self.long_task_threshold_ms
+    }
+
+    pub fn set_long_task_threshold_ms(&mut self, threshold_ms: f64) {
+        self.long_task_threshold_ms = threshold_ms.max(0.0);
+    }
+
+    /// Record a finished frame (drops the oldest once the ring is full)
+    pub fn record_frame(&mut self, stats: FrameStats) {
+        if self.frames.len() == FRAME_STATS_CAPACITY {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(stats);
+    }
+
+    /// Record a finished task, keeping it if it was a long task
+    ///
+    /// Returns true if the task was reported as long.
+    pub fn record_task(&mut self, frame: u64, priority: TaskPriority, duration_ms: f64) -> bool {
+        if duration_ms <= self.long_task_threshold_ms {
+            return false;
+        }
+
+        if self.long_tasks.len() == LONG_TASK_CAPACITY {
+            self.long_tasks.pop_front();
+        }
+        self.long_tasks.push_back(LongTask {
+            frame,
+            priority,
+            duration_ms,
+        });
+        true
+    }
+
+    /// Recorded frames, oldest first
+    pub fn frames(&self) -> impl Iterator<Item = &FrameStats> {
+        self.frames.iter()
+    }
+
+    /// Reported long tasks, oldest first
+    pub fn long_tasks(&self) -> impl Iterator<Item = &LongTask> {
+        self.long_tasks.iter()
+    }
+
+    /// Number of frames over budget in the ring
+    pub fn overruns(&self) -> usize {
+        self.frames.iter().filter(|f| f.overrun).count()
+    }
+
+    /// Drop all recorded stats
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.long_tasks.clear();
+    }
+
+    /// Export the rings in the compact binary format (see module docs)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(
+            Self::HEADER_SIZE
+                + self.frames.len() * FrameStats::SIZE
+                + self.long_tasks.len() * LongTask::SIZE,
+        );
+
+        out.extend_from_slice(&Self::MAGIC.to_le_bytes());
+        out.push(Self::VERSION);
+        out.extend_from_slice(&(self.frames.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(self.long_tasks.len() as u16).to_le_bytes());
+
+        for stats in &self.frames {
+            out.extend_from_slice(&(stats.frame as u32).to_le_bytes());
+            out.extend_from_slice(&to_micros(stats.wasm_ms).to_le_bytes());
+            out.extend_from_slice(&(stats.deferred.min(u16::MAX as usize) as u16).to_le_bytes());
+            for count in stats.tasks {
+                out.extend_from_slice(&count.to_le_bytes());
+            }
+            out.push(stats.overrun as u8);
+            out.push(0);
+        }
+
+        for task in &self.long_tasks {
+            out.extend_from_slice(&(task.frame as u32).to_le_bytes());
+            out.extend_from_slice(&to_micros(task.duration_ms).to_le_bytes());
+            out.push(task.priority as u8);
+        }
+
+        out
+    }
+
+    /// Summarize the rings as JSON
+    ///
+    /// `{"frames","avg_ms","max_ms","overruns","deferred_max","tasks":[imm,norm,idle],
+    /// "long_tasks":[{"frame","priority","ms"}]}`
+    pub fn summary_json(&self) -> String {
+        let count = self.frames.len();
+        let total_ms: f64 = self.frames.iter().map(|f| f.wasm_ms).sum();
+        let max_ms = self.frames.iter().map(|f| f.wasm_ms).fold(0.0, f64::max);
+        let avg_ms = if count == 0 {
+            0.0
+        } else {
+            total_ms / count as f64
+        };
+        let deferred_max = self.frames.iter().map(|f| f.deferred).max().unwrap_or(0);
+
+        let mut tasks = [0usize; TaskPriority::COUNT];
+        for stats in &self.frames {
+            for (total, &n) in tasks.iter_mut().zip(&stats.tasks) {
+                *total += n as usize;
+            }
+        }
+
+        let mut json = String::with_capacity(128 + self.long_tasks.len() * 48);
+        let _ = write!(
+            json,
+            "{{\"frames\":{},\"avg_ms\":{:.3},\"max_ms\":{:.3},\"overruns\":{},\"deferred_max\":{},\
+             \"tasks\":[{},{},{}],\"long_tasks\":[",
+            count,
+            avg_ms,
+            max_ms,
+            self.overruns(),
+            deferred_max,
+            tasks[0],
+            tasks[1],
+            tasks[2]
+        );
+        for (i, task) in self.long_tasks.iter().enumerate() {
+            let _ = write!(
+                json,
+                "{}{{\"frame\":{},\"priority\":\"{}\",\"ms\":{:.3}}}",
+                if i == 0 { "" } else { "," },
+                task.frame,
+                priority_name(task.priority),
+                task.duration_ms
+            );
+        }
+        json.push_str("]}");
+
+        json
+    }
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn priority_name(priority: TaskPriority) -> &'static str {
+    match priority {
+        TaskPriority::Immediate => "immediate",
+        TaskPriority::Normal => "normal",
+        TaskPriority::Idle => "idle",
+    }
+}
+
+/// Milliseconds to whole microseconds, saturating at u32::MAX
+fn to_micros(ms: f64) -> u32 {
+    (ms.max(0.0) * 1000.0).min(u32::MAX as f64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_keeps_latest_frames() {
+        let mut telemetry = Telemetry::new();
+        for frame in 1..=(FRAME_STATS_CAPACITY as u64 + 5) {
+            telemetry.record_frame(FrameStats {
+                frame,
+                wasm_ms: 1.0,
+                tasks: [1, 0, 0],
+                deferred: 0,
+                overrun: frame % 10 == 0,
+            });
+        }
+
+        assert_eq!(telemetry.frames().count(), FRAME_STATS_CAPACITY);
+        assert_eq!(telemetry.frames().next().unwrap().frame, 6);
+        assert_eq!(telemetry.overruns(), 12);
+    }
+
+    #[test]
+    fn test_binary_and_json_export() {
+        let mut telemetry = Telemetry::new();
+        telemetry.record_frame(FrameStats {
+            frame: 7,
+            wasm_ms: 2.5,
+            tasks: [1, 2, 3],
+            deferred: 4,
+            overrun: true,
+        });
+        assert!(!telemetry.record_task(7, TaskPriority::Normal, 3.0));
+        assert!(telemetry.record_task(7, TaskPriority::Idle, 20.0));
+
+        let bytes = telemetry.to_bytes();
+        assert_eq!(bytes.len(), Telemetry::HEADER_SIZE + FrameStats::SIZE + LongTask::SIZE);
+        assert_eq!(&bytes[0..7], &[0x46, 0x53, 1, 1, 0, 1, 0]);
+
+        let frame = &bytes[7..7 + FrameStats::SIZE];
+        assert_eq!(u32::from_le_bytes(frame[0..4].try_into().unwrap()), 7);
+        assert_eq!(u32::from_le_bytes(frame[4..8].try_into().unwrap()), 2500);
+        assert_eq!(&frame[8..16], &[4, 0, 1, 0, 2, 0, 3, 0]);
+        assert_eq!(frame[16], 1);
+
+        let long = &bytes[7 + FrameStats::SIZE..];
+        assert_eq!(u32::from_le_bytes(long[4..8].try_into().unwrap()), 20_000);
+        assert_eq!(long[8], TaskPriority::Idle as u8);
+
+        assert_eq!(
+            telemetry.summary_json(),
+            "{\"frames\":1,\"avg_ms\":2.500,\"max_ms\":2.500,\"overruns\":1,\"deferred_max\":4,\
+             \"tasks\":[1,2,3],\"long_tasks\":[{\"frame\":7,\"priority\":\"idle\",\"ms\":20.000}]}"
+        );
+    }
+}
@@ -4,8 +4,12 @@
 //!
 //! **Algorithm:** 64-byte block comparison with sparse encoding
 //! **Target:** 1KB deltas for typical component updates (99% bandwidth reduction)
+//!
+//! Block patches use the `dx_packet::PatchWriter` format, which
+//! `dx_client::Patcher` applies.
 
 use blake3;
+use dx_packet::{PatchReader, PatchWriter};
 use std::collections::HashMap;
 
 /// Calculate hash of binary data
@@ -82,79 +86,23 @@ impl DeltaInfo {
 // BLOCK-BASED DELTA PATCHING (For Day 17)
 // ============================================================================
 
-const BLOCK_SIZE: usize = 64; // 64-byte blocks
-
 /// Create a sparse block-based patch
 ///
-/// Format: [OFFSET:4][LENGTH:2][DATA:N]...
-/// Only includes blocks that changed
+/// Format: `dx_packet::PatchWriter` (header with version hashes, new length,
+/// then [INDEX:4][LENGTH:2][XOR:N] for each changed 64-byte block)
 ///
 /// **Performance:** Typical 50KB app → 1KB patch (98% reduction)
-pub fn create_block_patch(old: &[u8], new: &[u8]) -> Vec<u8> {
-    let mut patch = Vec::new();
-
-    // Header: New length (4 bytes)
-    patch.extend_from_slice(&(new.len() as u32).to_le_bytes());
-
-    let block_count = (new.len() + BLOCK_SIZE - 1) / BLOCK_SIZE;
-
-    for i in 0..block_count {
-        let start = i * BLOCK_SIZE;
-        let end = std::cmp::min(start + BLOCK_SIZE, new.len());
-        let new_block = &new[start..end];
-
-        // Check if block changed
-        let changed = if start < old.len() {
-            let old_end = std::cmp::min(start + BLOCK_SIZE, old.len());
-            let old_block = &old[start..old_end];
-            old_block != new_block
-        } else {
-            true // New block beyond old length
-        };
-
-        if changed {
-            // Emit: [OFFSET:4][LENGTH:2][DATA:N]
-            patch.extend_from_slice(&(start as u32).to_le_bytes());
-            patch.extend_from_slice(&(new_block.len() as u16).to_le_bytes());
-            patch.extend_from_slice(new_block);
-        }
-    }
-
-    patch
+pub fn create_block_patch(old: &[u8], new: &[u8]) -> Result<Vec<u8>, String> {
+    PatchWriter::diff(old, new).map_err(|e| format!("Cannot encode patch: {:?}", e))
 }
 
 /// Apply a block-based patch
+///
+/// Fails if `old` is not the version the patch was made from.
 pub fn apply_block_patch(old: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
-    if patch.len() < 4 {
-        return Err("Patch too short".to_string());
-    }
-
-    // Read new length
-    let new_len = u32::from_le_bytes([patch[0], patch[1], patch[2], patch[3]]) as usize;
-    let mut result = old.to_vec();
-    result.resize(new_len, 0);
-
-    // Apply patches
-    let mut i = 4;
-    while i < patch.len() {
-        if i + 6 > patch.len() {
-            break;
-        }
-
-        let offset = u32::from_le_bytes([patch[i], patch[i+1], patch[i+2], patch[i+3]]) as usize;
-        let length = u16::from_le_bytes([patch[i+4], patch[i+5]]) as usize;
-        i += 6;
-
-        if i + length > patch.len() {
-            return Err("Patch data truncated".to_string());
-        }
-
-        let data = &patch[i..i+length];
-        result[offset..offset+length].copy_from_slice(data);
-        i += length;
-    }
-
-    Ok(result)
+    PatchReader::new(patch)
+        .and_then(|reader| reader.apply(old))
+        .map_err(|e| format!("Cannot apply patch: {:?}", e))
 }
 
 /// Version storage for delta calculation
@@ -194,7 +142,7 @@ impl VersionStore {
     /// Create patch from old to new version
     pub fn create_patch(&self, old_hash: &str, new_data: &[u8]) -> Option<Vec<u8>> {
         let old_data = self.get(old_hash)?;
-        Some(create_block_patch(old_data, new_data))
+        create_block_patch(old_data, new_data).ok()
     }
 }
 
@@ -269,7 +217,7 @@ mod tests {
         new[100] = 99; // Change one byte
         new[500] = 88; // Change another
 
-        let patch = create_block_patch(&old, &new);
+        let patch = create_block_patch(&old, &new).unwrap();
         let result = apply_block_patch(&old, &patch).unwrap();
 
         assert_eq!(result, new);
@@ -283,7 +231,7 @@ mod tests {
         let mut new = vec![0xAAu8; 50_000];
         new[1000..1064].fill(0xBB); // Change one 64-byte block
 
-        let patch = create_block_patch(&old, &new);
+        let patch = create_block_patch(&old, &new).unwrap();
         
         // Patch should be much smaller than full binary
         // Note: The exact size depends on which blocks differ
@@ -310,6 +258,37 @@ mod tests {
         assert_eq!(result, v2);
     }
 
+    #[test]
+    fn test_block_patch_rejects_wrong_base() {
+        let old = vec![1u8; 1000];
+        let mut new = old.clone();
+        new[10] = 2;
+        let patch = create_block_patch(&old, &new).unwrap();
+
+        assert!(apply_block_patch(&new, &patch).is_err());
+    }
+
+    #[test]
+    fn test_patch_applies_on_client() {
+        let mut store = VersionStore::new(5);
+
+        // What a client has cached, and the rebuilt app
+        let v1: Vec<u8> = (0..5000u32).map(|i| (i % 253) as u8).collect();
+        let mut v2 = v1.clone();
+        v2[10..20].fill(0);
+        v2[3000] ^= 0xFF;
+        v2.extend_from_slice(b"new code");
+        let hash1 = store.store(v1.clone());
+
+        // X-Dx-Patch body
+        let patch = store.create_patch(&hash1, &v2).unwrap();
+
+        let mut patcher = dx_client::Patcher::new();
+        patcher.set_old_binary(v1);
+        patcher.set_patch_data(&patch).unwrap();
+        assert_eq!(patcher.apply_patch().unwrap(), v2);
+    }
+
     #[test]
     fn test_version_store_eviction() {
         let mut store = VersionStore::new(2); // Max 2 versions
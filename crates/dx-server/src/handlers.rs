@@ -186,10 +186,24 @@ pub async fn serve_binary_stream(
         .get("layout.bin")
         .map(|entry| entry.value().clone())
         .unwrap_or_else(|| {
-            tracing::warn!("⚠️ layout.bin not in cache, generating mock");
-            vec![0u8; 100] // Mock layout
+            tracing::warn!("⚠️ layout.bin not in cache, sending empty layout");
+            dx_packet::LayoutWriter::new().finish()
         });
 
+    // Initial state snapshot (empty = no initial state)
+    let state_bin = state
+        .binary_cache
+        .get("state.bin")
+        .map(|entry| entry.value().clone())
+        .unwrap_or_default();
+
+    // Initial render opcodes (empty = render after load)
+    let opcodes_bin = state
+        .binary_cache
+        .get("app.htip")
+        .map(|entry| entry.value().clone())
+        .unwrap_or_default();
+
     let wasm_bin = state
         .binary_cache
         .get("app.wasm")
@@ -208,7 +222,8 @@ pub async fn serve_binary_stream(
     };
 
     // Create streaming body
-    let stream = crate::stream::create_stream(&artifact, layout_bin, wasm_bin);
+    let stream =
+        crate::stream::create_stream(&artifact, layout_bin, state_bin, opcodes_bin, wasm_bin);
     let body = Body::from_stream(stream);
 
     // Build response with streaming headers
@@ -233,7 +248,7 @@ pub async fn serve_binary_stream(
 /// Serve a simple SVG favicon (prevents 404 errors)
 pub async fn serve_favicon() -> impl IntoResponse {
     // Simple SVG favicon with "dx" text
-    let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 32 32"><rect width="32" height="32" rx="4" fill="#667eea"/><text x="16" y="22" text-anchor="middle" fill="white" font-family="Arial" font-size="14" font-weight="bold">dx</text></svg>"#;
+    let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 32 32"><rect width="32" height="32" rx="4" fill="#667eea"/><text x="16" y="22" text-anchor="middle" fill="white" font-family="Arial" font-size="14" font-weight="bold">dx</text></svg>"##;
     
     (
         [(header::CONTENT_TYPE, "image/svg+xml")],
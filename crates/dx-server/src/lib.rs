@@ -112,6 +112,22 @@ impl ServerState {
                 &hash[..8]);
         }
 
+        // Load state.bin (initial state snapshot for the State chunk)
+        let state_path = path.join("state.bin");
+        if state_path.exists() {
+            let bytes = std::fs::read(&state_path)?;
+            tracing::debug!("  ✓ Cached state.bin ({} bytes)", bytes.len());
+            self.binary_cache.insert("state.bin".to_string(), bytes);
+        }
+
+        // Load app.htip (initial render for the Opcodes chunk)
+        let htip_path = path.join("app.htip");
+        if htip_path.exists() {
+            let bytes = std::fs::read(&htip_path)?;
+            tracing::debug!("  ✓ Cached app.htip ({} bytes)", bytes.len());
+            self.binary_cache.insert("app.htip".to_string(), bytes);
+        }
+
         // Load app.wasm
         let wasm_path = path.join("app.wasm");
         if wasm_path.exists() {
@@ -19,6 +19,7 @@
 //! - Direct slot injection
 //! - Smart bot detection
 
+use dx_binary::HtipStream;
 use dx_packet::Template;
 use std::collections::HashMap;
 
@@ -86,6 +87,15 @@ pub fn inflate_html(template: &Template, state: &StateData) -> String {
     result
 }
 
+/// Render a verified HTIP stream to HTML
+///
+/// Unlike `inflate_html`, runs the whole operation stream (nested
+/// instances, attributes, class toggles, properties) through
+/// `dx_binary::ssr`, giving the markup the client would build.
+pub fn inflate_stream(stream: &HtipStream) -> dx_binary::Result<String> {
+    dx_binary::ssr::render_html(stream.payload())
+}
+
 /// Inflate full HTML page with DOCTYPE, metadata, and body
 ///
 /// # Arguments
@@ -263,6 +273,34 @@ mod tests {
         assert_eq!(result, "<div></div>"); // Should be empty, not crash
     }
 
+    #[test]
+    fn test_stream_inflation() {
+        use dx_binary::opcodes::{Binding, BindingType};
+        use dx_binary::{HtipWriter, StreamContext};
+        use ed25519_dalek::SigningKey;
+
+        let mut writer = HtipWriter::new();
+        writer.write_template(0, "<section></section>", vec![]);
+        let title = Binding {
+            slot_id: 0,
+            binding_type: BindingType::Text,
+            path: vec![],
+        };
+        writer.write_template(1, "<h2>Title</h2>", vec![title]);
+        writer.write_instantiate(1, 0, 0);
+        writer.write_instantiate(2, 1, 1);
+        writer.write_patch_text(2, 0, "Hello World");
+        writer.write_class_toggle(1, "hero", true);
+
+        let key = SigningKey::from_bytes(&[1u8; 32]);
+        let binary = writer.finish_and_sign(&key).unwrap();
+        let stream =
+            HtipStream::new(&binary, &key.verifying_key(), &StreamContext::new(0)).unwrap();
+
+        let html = inflate_stream(&stream).unwrap();
+        assert_eq!(html, "<section class=\"hero\"><h2>Hello World</h2></section>");
+    }
+
     #[test]
     fn test_bot_detection() {
         assert!(is_bot("Mozilla/5.0 (compatible; Googlebot/2.1)"));
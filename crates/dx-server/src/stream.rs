@@ -8,7 +8,8 @@
 //! Dx-www streaming is parallel:
 //! - Chunk 1 (Layout) → WASM creates templates **while downloading**
 //! - Chunk 2 (State) → Memory allocated **while downloading**
-//! - Chunk 3 (Logic) → Browser compiles **while downloading**
+//! - Chunk 3 (Opcodes) → Above-the-fold DOM rendered **while downloading**
+//! - Chunk 4 (Logic) → Browser compiles **while downloading**
 //!
 //! Result: Zero blocking time. Execution starts before download completes.
 
@@ -23,20 +24,33 @@ use std::pin::Pin;
 /// 1. Header (64 bytes) - Magic + Version + Signature
 /// 2. Layout (templates) - Client starts DOM prep immediately
 /// 3. State (initial data) - Client allocates memory
-/// 4. WASM (logic) - Client compiles in background
-/// 5. EOF marker
+/// 4. Opcodes (initial render) - Client renders before the WASM arrives
+/// 5. WASM (logic) - Client compiles in background
+/// 6. EOF marker
+///
+/// `state_bin` is a `dx_packet::StateWriter` snapshot (compiler's state.bin),
+/// or empty when the app has no initial state. `opcodes_bin` is an HTIP
+/// stream (compiler's app.htip); no Opcodes chunk is sent when it is empty.
 pub fn create_stream(
     artifact: &DxbArtifact,
     layout_bin: Vec<u8>,
+    state_bin: Vec<u8>,
+    opcodes_bin: Vec<u8>,
     wasm_bin: Vec<u8>,
 ) -> Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send>> {
-    let chunks = build_chunks(artifact, layout_bin, wasm_bin);
+    let chunks = build_chunks(artifact, layout_bin, state_bin, opcodes_bin, wasm_bin);
 
     Box::pin(stream::iter(chunks.into_iter().map(Ok)))
 }
 
 /// Build the complete chunk sequence
-fn build_chunks(artifact: &DxbArtifact, layout_bin: Vec<u8>, wasm_bin: Vec<u8>) -> Vec<Bytes> {
+fn build_chunks(
+    artifact: &DxbArtifact,
+    layout_bin: Vec<u8>,
+    state_bin: Vec<u8>,
+    opcodes_bin: Vec<u8>,
+    wasm_bin: Vec<u8>,
+) -> Vec<Bytes> {
     let mut chunks = Vec::new();
 
     // Chunk 0: Header (Magic + Version + Signature)
@@ -46,12 +60,17 @@ fn build_chunks(artifact: &DxbArtifact, layout_bin: Vec<u8>, wasm_bin: Vec<u8>)
     chunks.push(create_layout_chunk(layout_bin));
 
     // Chunk 2: State (Initial Memory Snapshot)
-    chunks.push(create_state_chunk());
+    chunks.push(create_state_chunk(state_bin));
 
-    // Chunk 3: WASM (Runtime Logic)
+    // Chunk 3: Opcodes (Initial Render)
+    if !opcodes_bin.is_empty() {
+        chunks.push(create_opcodes_chunk(opcodes_bin));
+    }
+
+    // Chunk 4: WASM (Runtime Logic)
     chunks.push(create_wasm_chunk(wasm_bin));
 
-    // Chunk 4: EOF
+    // Chunk 5: EOF
     chunks.push(create_eof_chunk());
 
     chunks
@@ -90,12 +109,18 @@ fn create_layout_chunk(layout_bin: Vec<u8>) -> Bytes {
 
 /// Create State Chunk (Initial State Data)
 ///
-/// Contains initial state snapshot
-/// Client action: Allocate SharedArrayBuffer and populate
-fn create_state_chunk() -> Bytes {
-    // For now, empty state (future: serialize initial component state)
-    let state_data = vec![0u8; 0];
-    wrap_chunk(ChunkType::State, state_data)
+/// Contains the schema-driven initial state snapshot (see `dx_packet::StateReader`)
+/// Client action: Allocate State Region slots and populate
+fn create_state_chunk(state_bin: Vec<u8>) -> Bytes {
+    wrap_chunk(ChunkType::State, state_bin)
+}
+
+/// Create Opcodes Chunk (HTIP Stream)
+///
+/// Contains the initial render opcodes
+/// Client action: Execute immediately, ahead of the WASM chunk
+fn create_opcodes_chunk(opcodes_bin: Vec<u8>) -> Bytes {
+    wrap_chunk(ChunkType::Opcodes, opcodes_bin)
 }
 
 /// Create WASM Chunk (Runtime Logic)
@@ -129,13 +154,24 @@ fn wrap_chunk(chunk_type: ChunkType, data: Vec<u8>) -> Bytes {
 }
 
 /// Calculate total stream size (for Content-Length header)
-pub fn calculate_stream_size(layout_size: usize, wasm_size: usize) -> usize {
+pub fn calculate_stream_size(
+    layout_size: usize,
+    state_size: usize,
+    opcodes_size: usize,
+    wasm_size: usize,
+) -> usize {
     // Header: 5 (chunk header) + 64 (data) = 69
     // Layout: 5 (chunk header) + layout_size
-    // State: 5 (chunk header) + 0 (empty for now)
+    // State: 5 (chunk header) + state_size
+    // Opcodes: 5 (chunk header) + opcodes_size, if any
     // WASM: 5 (chunk header) + wasm_size
     // EOF: 5 (chunk header) + 0
-    69 + 5 + layout_size + 5 + 5 + wasm_size + 5
+    let opcodes = if opcodes_size > 0 {
+        5 + opcodes_size
+    } else {
+        0
+    };
+    69 + 5 + layout_size + 5 + state_size + opcodes + 5 + wasm_size + 5
 }
 
 #[cfg(test)]
@@ -193,13 +229,69 @@ mod tests {
     #[test]
     fn test_stream_size_calculation() {
         let layout_size = 1000;
+        let state_size = 24;
         let wasm_size = 50000;
 
-        let total = calculate_stream_size(layout_size, wasm_size);
+        let total = calculate_stream_size(layout_size, state_size, 0, wasm_size);
+
+        // Header (69) + Layout (5+1000) + State (5+24) + WASM (5+50000) + EOF (5+0)
+        assert_eq!(total, 69 + 1005 + 29 + 50005 + 5);
+        assert_eq!(total, 51113);
+
+        // Opcodes chunk (5+300) when there is an initial render
+        assert_eq!(calculate_stream_size(layout_size, state_size, 300, wasm_size), 51418);
+    }
+
+    #[test]
+    fn test_state_chunk_carries_snapshot() {
+        let mut writer = dx_packet::StateWriter::new();
+        writer.add_component(0, &[(0, dx_packet::StateValue::I32(42))]).unwrap();
+        let snapshot = writer.finish();
+
+        let artifact = DxbArtifact {
+            version: 1,
+            capabilities: CapabilitiesManifest::default(),
+            templates: vec![],
+            wasm_size: 0,
+        };
+        let chunks = build_chunks(&artifact, vec![1, 2, 3], snapshot.clone(), vec![], vec![]);
+
+        let state_chunk = &chunks[2];
+        assert_eq!(state_chunk[0], ChunkType::State as u8);
+        assert_eq!(&state_chunk[5..], &snapshot[..]);
+
+        let component =
+            dx_packet::StateReader::new(&state_chunk[5..]).unwrap().next().unwrap().unwrap();
+        assert_eq!(component.value(0), Some(dx_packet::StateValue::I32(42)));
+    }
+
+    #[test]
+    fn test_opcodes_chunk_precedes_wasm() {
+        let artifact = DxbArtifact {
+            version: 1,
+            capabilities: CapabilitiesManifest::default(),
+            templates: vec![],
+            wasm_size: 0,
+        };
 
-        // Header (69) + Layout (5+1000) + State (5+0) + WASM (5+50000) + EOF (5+0)
-        assert_eq!(total, 69 + 1005 + 5 + 50005 + 5);
-        assert_eq!(total, 51089);
+        let chunks = build_chunks(&artifact, vec![], vec![], vec![7; 16], vec![9; 4]);
+        let types: Vec<u8> = chunks.iter().map(|c| c[0]).collect();
+        assert_eq!(
+            types,
+            [
+                ChunkType::Header as u8,
+                ChunkType::Layout as u8,
+                ChunkType::State as u8,
+                ChunkType::Opcodes as u8,
+                ChunkType::Wasm as u8,
+                ChunkType::Eof as u8,
+            ]
+        );
+        assert_eq!(&chunks[3][5..], &[7; 16]);
+
+        // No initial render, no Opcodes chunk
+        let chunks = build_chunks(&artifact, vec![], vec![], vec![], vec![9; 4]);
+        assert_eq!(chunks.len(), 5);
     }
 
     #[test]
@@ -8,6 +8,7 @@
 use dx_binary::deserializer::HtipStream;
 use dx_binary::htip_bridge::HtipEngine;
 use dx_binary::serializer::HtipWriter;
+use dx_binary::signature::StreamContext;
 use ed25519_dalek::{SigningKey, VerifyingKey};
 use wasm_bindgen::prelude::*;
 use web_sys::{Performance, window};
@@ -60,7 +61,7 @@ impl HtipDemo {
 
         // Step 2: Deserialize HTIP (client-side)
         let t2 = self.perf.now();
-        let stream = HtipStream::new(&binary, &self.verifying_key)
+        let stream = HtipStream::new(&binary, &self.verifying_key, &now())
             .map_err(|e| format!("Deserialization failed: {:?}", e))?;
         let t3 = self.perf.now();
 
@@ -160,7 +161,7 @@ impl HtipDemo {
         let payload_size = binary.len();
 
         // Deserialize
-        let stream = HtipStream::new(&binary, &self.verifying_key)
+        let stream = HtipStream::new(&binary, &self.verifying_key, &now())
             .map_err(|e| format!("Failed: {:?}", e))?;
         let t2 = self.perf.now();
 
@@ -214,3 +215,8 @@ pub fn main() -> Result<(), JsValue> {
     web_sys::console::log_1(&"✨ dx-www HTIP Engine Ready".into());
     Ok(())
 }
+
+/// Stream context at the browser's current time
+fn now() -> StreamContext {
+    StreamContext::new((js_sys::Date::now() / 1000.0) as u64)
+}